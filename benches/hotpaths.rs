@@ -0,0 +1,101 @@
+// proj2-serv/benches/hotpaths.rs
+// Microbenchmarks of the per-packet hot paths: control framing, payload
+// generation, and the accounting every UDP upload datagram goes through.
+// Run with `cargo bench --bench hotpaths [filter]`. Criterion reports each
+// benchmark's time per call and the throughput it implies, and compares a run
+// with the previous one on the same machine. The modules under test are
+// compiled straight from src/, as the server has no library target.
+
+#[allow(dead_code)]
+#[path = "../src/interval.rs"]
+mod interval;
+#[allow(dead_code)]
+#[path = "../src/payload.rs"]
+mod payload;
+#[allow(dead_code)]
+#[path = "../src/protocol.rs"]
+mod protocol;
+#[allow(dead_code)]
+#[path = "../src/rng.rs"]
+mod rng;
+
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use interval::Measured;
+use payload::{PayloadSource, WriteBatch};
+use protocol::Command;
+
+const DATAGRAM: usize = 1400;
+
+fn framing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("framing");
+    for line in ["START_DOWNLOAD", "START_DOWNLOAD TENANT=acme PAYLOAD=random SEED=42 OMIT=2 BYTES=1G DSCP=46 PORTS=4"] {
+        group.throughput(Throughput::Bytes(line.len() as u64));
+        group.bench_function(format!("parse/{}", line.split_whitespace().count()), |b| b.iter(|| Command::parse(black_box(line))));
+    }
+    let data = vec![0x5au8; DATAGRAM];
+    group.throughput(Throughput::Bytes(DATAGRAM as u64));
+    group.bench_function("looks_like_command/1400B", |b| {
+        b.iter(|| protocol::looks_like_command(black_box(std::str::from_utf8(&data).unwrap())))
+    });
+    group.finish();
+}
+
+fn payloads(c: &mut Criterion) {
+    let mut group = c.benchmark_group("payload");
+    let mut buf = vec![0u8; DATAGRAM];
+    let mut sources: Vec<(&str, Box<dyn PayloadSource>)> = vec![
+        ("zeros", Box::new(payload::Zeros)),
+        ("random", Box::new(payload::Random::seeded(42))),
+        ("file", Box::new(payload::FileBacked::new(Arc::from(vec![7u8; 1 << 20])))),
+    ];
+    group.throughput(Throughput::Bytes(DATAGRAM as u64));
+    for (kind, source) in &mut sources {
+        group.bench_function(format!("{}/1400B", kind), |b| b.iter(|| source.fill(black_box(&mut buf))));
+    }
+    let mut batch = WriteBatch::new(8, 64 * 1024);
+    let mut random = payload::Random::seeded(42);
+    group.throughput(Throughput::Bytes(8 * 64 * 1024));
+    group.bench_function("write_batch/512K", |b| {
+        b.iter(|| {
+            batch.refill(&mut random, usize::MAX);
+            black_box(batch.slices().len());
+        })
+    });
+    group.finish();
+}
+
+/// What the UDP dispatch loop does with every upload datagram: the control
+/// parse it attempts first, the window lookup and the byte accounting.
+fn udp_accounting(c: &mut Criterion) {
+    let peers: Vec<SocketAddr> = (0..64).map(|i| SocketAddr::from(([10, 0, 0, i as u8], 40000 + i))).collect();
+    let start = Instant::now();
+    let mut windows: HashMap<SocketAddr, (u64, Measured)> =
+        peers.iter().map(|&addr| (addr, (0, Measured::new(start, Duration::ZERO)))).collect();
+    let data = vec![0u8; DATAGRAM];
+    let mut next = 0usize;
+    let mut group = c.benchmark_group("udp_accounting");
+    group.throughput(Throughput::Bytes(DATAGRAM as u64));
+    group.bench_function("datagram/1400B", |b| {
+        b.iter(|| {
+            let addr = peers[next % peers.len()];
+            next += 1;
+            let msg = String::from_utf8_lossy(black_box(&data)).trim().to_string();
+            let cmd = Command::parse(&msg);
+            black_box(&cmd.verb);
+            if let Some((total, measured)) = windows.get_mut(&addr) {
+                *total += DATAGRAM as u64;
+                measured.add(DATAGRAM as u64);
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(hotpaths, framing, payloads, udp_accounting);
+criterion_main!(hotpaths);
@@ -0,0 +1,236 @@
+// proj2-serv/benches/loopback.rs
+// End-to-end loopback throughput: starts the built server on spare ports and
+// runs fixed-size tests of each kind against it with `BYTES=`, timing each
+// from the client's side. Run with `cargo bench --bench loopback [filter]`;
+// Criterion reports the throughput and compares a run with the previous one.
+// Loopback figures bound what the hot paths can do on this host, so compare
+// them across builds on the same machine only. UDP figures include loss,
+// which is printed after the UDP group.
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream, UdpSocket};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, Criterion, SamplingMode, Throughput};
+
+const TCP_PORT: u16 = 18080;
+const UDP_PORT: u16 = 17070;
+/// Bytes moved by each TCP and UDP test.
+const TCP_BYTES: u64 = 64 << 20;
+const UDP_BYTES: u64 = 16 << 20;
+/// Silence after which a UDP download counts as over.
+const QUIET: Duration = Duration::from_millis(500);
+const DATAGRAM: usize = 1400;
+
+/// The server under test, stopped when dropped.
+struct Server(Child);
+
+impl Server {
+    fn start() -> std::io::Result<Self> {
+        let child = Command::new(env!("CARGO_BIN_EXE_proj2-serv"))
+            .args(["--tcp-ports", &TCP_PORT.to_string(), "--udp-ports", &UDP_PORT.to_string(), "--no-beacon"])
+            // Criterion starts tests far faster than any client should.
+            .args(["--control-rate", "100000", "--control-burst", "100000"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let server = Server(child);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while TcpStream::connect(tcp_addr()).is_err() {
+            if Instant::now() > deadline {
+                return Err(std::io::Error::new(ErrorKind::TimedOut, "server did not start listening"));
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        Ok(server)
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn tcp_addr() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], TCP_PORT))
+}
+
+fn udp_addr() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], UDP_PORT))
+}
+
+/// A TCP download of TCP_BYTES on `stream`, which stays open for the next.
+fn tcp_download(stream: &mut TcpStream, command: &str) -> std::io::Result<Duration> {
+    let start = Instant::now();
+    stream.write_all(format!("{} BYTES={}", command, TCP_BYTES).as_bytes())?;
+    let mut buf = vec![0u8; 256 * 1024];
+    let mut left = TCP_BYTES as usize;
+    while left > 0 {
+        let n = stream.read(&mut buf[..left.min(256 * 1024)])?;
+        if n == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        left -= n;
+    }
+    Ok(start.elapsed())
+}
+
+/// A TCP upload of TCP_BYTES, timed until the server has read it all and
+/// closed the connection behind the client's shutdown.
+fn tcp_upload() -> std::io::Result<Duration> {
+    let mut stream = TcpStream::connect(tcp_addr())?;
+    stream.write_all(format!("START_UPLOAD BYTES={}", TCP_BYTES).as_bytes())?;
+    // Keep the command out of the first data segment.
+    std::thread::sleep(Duration::from_millis(50));
+    let chunk = vec![0u8; 256 * 1024];
+    let start = Instant::now();
+    let mut sent = 0u64;
+    while sent < TCP_BYTES {
+        let n = chunk.len().min((TCP_BYTES - sent) as usize);
+        stream.write_all(&chunk[..n])?;
+        sent += n as u64;
+    }
+    stream.shutdown(Shutdown::Write)?;
+    let _ = stream.read_to_end(&mut Vec::new());
+    Ok(start.elapsed())
+}
+
+/// A UDP download of UDP_BYTES: the time until the server's FIN and the
+/// bytes that arrived.
+fn udp_download(command: &str) -> std::io::Result<(Duration, u64)> {
+    // Not connected: `PORTS=` stripes arrive from other server ports.
+    let sock = UdpSocket::bind("127.0.0.1:0")?;
+    sock.set_read_timeout(Some(QUIET))?;
+    let start = Instant::now();
+    sock.send_to(format!("{} BYTES={}", command, UDP_BYTES).as_bytes(), udp_addr())?;
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut bytes = 0u64;
+    loop {
+        let n = match sock.recv_from(&mut buf) {
+            Ok((n, _)) => n,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => break,
+            Err(e) => return Err(e),
+        };
+        let datagram = &buf[..n];
+        if datagram.starts_with(b"ACK_DOWNLOAD") {
+            // Echo the return-path cookie so the flood starts.
+            let ack = String::from_utf8_lossy(datagram);
+            if let Some(cookie) = ack.split_whitespace().find_map(|kv| kv.strip_prefix("COOKIE=")) {
+                sock.send_to(format!("CONFIRM COOKIE={}", cookie).as_bytes(), udp_addr())?;
+            }
+            continue;
+        }
+        if datagram.starts_with(b"FIN ") {
+            // The flood is over; stop the FIN bursts.
+            sock.send_to(b"FIN_ACK", udp_addr())?;
+            break;
+        }
+        bytes += n as u64;
+    }
+    Ok((start.elapsed(), bytes))
+}
+
+/// A UDP upload of UDP_BYTES sent as fast as the socket takes it: the time
+/// until the server acknowledges its end, and the bytes it counted.
+fn udp_upload() -> std::io::Result<(Duration, u64)> {
+    let sock = UdpSocket::bind("127.0.0.1:0")?;
+    sock.connect(udp_addr())?;
+    sock.set_read_timeout(Some(QUIET))?;
+    sock.send(b"START_UPLOAD")?;
+    let mut buf = vec![0u8; 2048];
+    // The ACK burst and NAT probe.
+    while sock.recv(&mut buf).is_ok() {}
+    let payload = vec![0u8; DATAGRAM];
+    let start = Instant::now();
+    let mut sent = 0u64;
+    while sent < UDP_BYTES {
+        if sock.send(&payload).is_ok() {
+            sent += payload.len() as u64;
+        }
+    }
+    sock.send(b"END_UPLOAD")?;
+    let deadline = Instant::now() + Duration::from_secs(3);
+    while Instant::now() < deadline {
+        let Ok(n) = sock.recv(&mut buf) else { continue };
+        let reply = String::from_utf8_lossy(&buf[..n]);
+        if let Some(bytes) = reply.strip_prefix("ACK_END_UPLOAD bytes=") {
+            return Ok((start.elapsed(), bytes.trim().parse().unwrap_or(0)));
+        }
+    }
+    Ok((start.elapsed(), 0))
+}
+
+/// Bytes expected and received over a benchmark's iterations.
+#[derive(Default)]
+struct Loss {
+    expected: u64,
+    received: u64,
+}
+
+impl Loss {
+    fn report(&self, name: &str) {
+        if self.expected > 0 {
+            let lost = 100.0 * self.expected.saturating_sub(self.received) as f64 / self.expected as f64;
+            println!("{}: {:.1}% of {} bytes lost", name, lost, self.expected);
+        }
+    }
+}
+
+fn loopback(c: &mut Criterion) {
+    let _server = Server::start().expect("server starts");
+
+    let mut tcp = c.benchmark_group("tcp");
+    tcp.sampling_mode(SamplingMode::Flat).sample_size(10).throughput(Throughput::Bytes(TCP_BYTES));
+    for (name, command) in [("download", "START_DOWNLOAD"), ("download/random", "START_DOWNLOAD PAYLOAD=random")] {
+        tcp.bench_function(name, |b| {
+            let mut stream = TcpStream::connect(tcp_addr()).expect("connect");
+            b.iter_custom(|iters| (0..iters).map(|_| tcp_download(&mut stream, command).expect("TCP download")).sum())
+        });
+    }
+    tcp.bench_function("upload", |b| b.iter_custom(|iters| (0..iters).map(|_| tcp_upload().expect("TCP upload")).sum()));
+    tcp.finish();
+
+    let mut udp = c.benchmark_group("udp");
+    udp.sampling_mode(SamplingMode::Flat).sample_size(10).throughput(Throughput::Bytes(UDP_BYTES));
+    let mut losses = Vec::new();
+    for (name, command) in [("download", "START_DOWNLOAD"), ("download/thread", "START_DOWNLOAD SENDER=thread"), ("download/ports=4", "START_DOWNLOAD PORTS=4")] {
+        let mut loss = Loss::default();
+        udp.bench_function(name, |b| {
+            b.iter_custom(|iters| {
+                (0..iters)
+                    .map(|_| {
+                        let (elapsed, bytes) = udp_download(command).expect("UDP download");
+                        loss.expected += UDP_BYTES;
+                        loss.received += bytes;
+                        elapsed
+                    })
+                    .sum()
+            })
+        });
+        losses.push((name, loss));
+    }
+    let mut loss = Loss::default();
+    udp.bench_function("upload", |b| {
+        b.iter_custom(|iters| {
+            (0..iters)
+                .map(|_| {
+                    let (elapsed, bytes) = udp_upload().expect("UDP upload");
+                    loss.expected += UDP_BYTES;
+                    loss.received += bytes;
+                    elapsed
+                })
+                .sum()
+        })
+    });
+    losses.push(("upload", loss));
+    udp.finish();
+    for (name, loss) in losses {
+        loss.report(&format!("udp/{}", name));
+    }
+}
+
+criterion_group!(benches, loopback);
+criterion_main!(benches);
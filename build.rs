@@ -0,0 +1,12 @@
+// proj2-serv/build.rs
+// Generates the gRPC admin service from proto/admin.proto with a vendored
+// protoc, so building needs no protoc on the host. The client is generated
+// too, for tests/grpc.rs.
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let protoc = protoc_bin_vendored::protoc_bin_path()?;
+    // SAFETY: build scripts are single-threaded.
+    unsafe { std::env::set_var("PROTOC", protoc) };
+    tonic_prost_build::configure().build_client(true).compile_protos(&["proto/admin.proto"], &["proto"])?;
+    Ok(())
+}
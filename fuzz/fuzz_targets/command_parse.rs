@@ -0,0 +1,48 @@
+// proj2-serv/fuzz/fuzz_targets/command_parse.rs
+// Command lines as a client could send them. Parsing must not panic, must
+// normalise verbs and option keys, must round-trip through its own canonical
+// form, and no option value may trip up the option parsers or let an
+// invalid tenant through.
+#![no_main]
+
+#[allow(dead_code)]
+#[path = "../../src/protocol.rs"]
+mod protocol;
+
+use libfuzzer_sys::fuzz_target;
+use protocol::Command;
+
+fuzz_target!(|line: &str| {
+    let cmd = Command::parse(line);
+    assert_eq!(cmd.verb, cmd.verb.to_ascii_uppercase());
+    assert!(!cmd.verb.contains(char::is_whitespace));
+    for (key, value) in &cmd.opts {
+        assert!(!key.is_empty());
+        assert_eq!(key, &key.to_ascii_uppercase());
+        assert!(!value.contains(char::is_whitespace));
+    }
+    assert!(protocol::valid_tenant(&cmd.tenant()));
+    assert!(cmd.run().is_none_or(|run| protocol::valid_tenant(&run)));
+
+    let mut canonical = cmd.verb.clone();
+    for arg in &cmd.args {
+        canonical.push(' ');
+        canonical.push_str(arg);
+    }
+    for (key, value) in &cmd.opts {
+        canonical.push_str(&format!(" {}={}", key, value));
+    }
+    let again = Command::parse(&canonical);
+    assert_eq!(again.verb, cmd.verb);
+    assert_eq!(again.args, cmd.args);
+    assert_eq!(again.opts, cmd.opts);
+
+    for value in cmd.opts.values() {
+        let _ = protocol::parse_version(Some(value));
+        let _ = protocol::parse_omit(value);
+        let _ = protocol::parse_stripe_ports(value);
+        let _ = protocol::parse_flag(value);
+        let _ = protocol::parse_echo_size(value);
+        let _ = protocol::parse_byte_count(value);
+    }
+});
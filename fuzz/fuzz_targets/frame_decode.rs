@@ -0,0 +1,28 @@
+// proj2-serv/fuzz/fuzz_targets/frame_decode.rs
+// Raw frames, any bytes a TCP read or UDP datagram can carry, through the
+// decoder both planes use. Decoding must not panic, must yield trimmed text,
+// and must agree with parsing that text; a frame taken for a mistyped command
+// must have a verb.
+#![no_main]
+
+#[allow(dead_code)]
+#[path = "../../src/protocol.rs"]
+mod protocol;
+
+use libfuzzer_sys::fuzz_target;
+use protocol::Command;
+
+fuzz_target!(|raw: &[u8]| {
+    let (text, cmd) = protocol::decode_frame(raw);
+    assert_eq!(text, text.trim());
+    let parsed = Command::parse(&text);
+    assert_eq!(parsed.verb, cmd.verb);
+    assert_eq!(parsed.args, cmd.args);
+    assert_eq!(parsed.opts, cmd.opts);
+    if protocol::looks_like_command(&text) {
+        assert!(!cmd.verb.is_empty());
+    }
+    if let Ok(valid) = std::str::from_utf8(raw) {
+        assert_eq!(text, valid.trim());
+    }
+});
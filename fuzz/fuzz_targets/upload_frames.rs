@@ -0,0 +1,43 @@
+// proj2-serv/fuzz/fuzz_targets/upload_frames.rs
+// Any byte stream, split into two reads at any point, through the framed upload
+// decoder. Decoding must not panic, must never report more payload than it was
+// given, and must see the same payload and lines however the stream is split.
+#![no_main]
+
+#[allow(dead_code)]
+#[path = "../../src/dataframe.rs"]
+mod dataframe;
+
+use dataframe::{Event, FrameDecoder};
+use libfuzzer_sys::fuzz_target;
+
+/// Payload bytes and lines up to the first framing error, and whether there was one.
+fn decode(reads: &[&[u8]]) -> (usize, Vec<String>, bool) {
+    let mut decoder = FrameDecoder::default();
+    let (mut data, mut lines) = (0, Vec::new());
+    for read in reads {
+        let mut input = *read;
+        while let Some(event) = decoder.next(&mut input) {
+            match event {
+                Ok(Event::Data(n)) => data += n,
+                Ok(Event::Line(line)) => lines.push(line),
+                Err(_) => return (data, lines, true),
+            }
+        }
+    }
+    (data, lines, false)
+}
+
+fuzz_target!(|input: (u16, &[u8])| {
+    let (split, bytes) = input;
+    let at = split as usize % (bytes.len() + 1);
+    let whole = decode(&[bytes]);
+    assert!(whole.0 <= bytes.len());
+    let (first, second) = bytes.split_at(at);
+    let split = decode(&[first, second]);
+    // An overlong line may be caught a read sooner when split; otherwise the
+    // split must not matter.
+    if !whole.2 && !split.2 {
+        assert_eq!(whole, split);
+    }
+});
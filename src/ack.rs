@@ -0,0 +1,154 @@
+// proj2-serv/src/ack.rs
+// Acknowledgement of UDP test starts (ACK_DOWNLOAD / ACK_UPLOAD). By default
+// the server sends a short burst of ACKs and starts at once. Bursts are resent
+// while the client stays silent, up to `--udp-ack-retries` times; silence means
+// no upload data, or no confirmation in handshake mode. With the three-way
+// handshake (`--udp-handshake` or `HANDSHAKE=1`) the client answers an ACK with
+// `CONFIRM` and the test only starts once it arrives, so neither side begins
+// before the other is ready.
+//
+// Return-path validation (on unless `--no-udp-validation`): a spoofed START
+// would turn the server into a flood amplifier aimed at the forged address, so
+// downloads and capacity probes always take the handshake, and their ACK
+// carries `COOKIE=<16 hex>`, fresh from the OS random source, that the client
+// must echo as `CONFIRM COOKIE=`. Only a client that can read what is sent to
+// its address learns the cookie.
+// Until it comes back the server sends no test traffic, and its ACKs stay
+// within AMPLIFICATION_LIMIT times the START datagram, as QUIC does; the first
+// ACK always goes out so the client can learn the cookie.
+//
+// A download's end is acknowledged the same way in reverse: the server sends
+// bursts of `FIN <bytes> PACKETS=<n>`, what it sent, until the client answers
+// `FIN_ACK` or FIN_BURSTS bursts go unanswered. The client can then tell the end
+// of the flood from loss and count its loss against the server's own figures.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::oneshot;
+
+use crate::impair::ImpairedSocket;
+use crate::rng;
+
+/// How long the client may stay silent after a burst before it is resent.
+pub const SILENCE_TIMEOUT: Duration = Duration::from_millis(250);
+/// Bursts of FIN sent at most when the client does not answer.
+pub const FIN_BURSTS: usize = 8;
+/// Bytes the server may send an unvalidated address per byte it received.
+pub const AMPLIFICATION_LIMIT: usize = 3;
+
+#[derive(Debug, Clone, Copy)]
+pub struct AckPolicy {
+    /// ACKs per burst.
+    pub count: usize,
+    /// Gap between the ACKs of a burst.
+    pub interval: Duration,
+    /// Extra bursts sent while the client stays silent.
+    pub retries: usize,
+    /// Wait for the client's `CONFIRM` before starting.
+    pub handshake: bool,
+    /// Make downloads and capacity probes echo a cookie before sending.
+    pub validate: bool,
+}
+
+impl Default for AckPolicy {
+    fn default() -> Self {
+        AckPolicy { count: 3, interval: Duration::from_millis(10), retries: 0, handshake: false, validate: true }
+    }
+}
+
+impl AckPolicy {
+    pub async fn send_burst(&self, sock: &ImpairedSocket, addr: SocketAddr, ack: &str) {
+        self.send_acks(sock, addr, ack, self.count).await;
+    }
+
+    async fn send_acks(&self, sock: &ImpairedSocket, addr: SocketAddr, ack: &str, count: usize) {
+        for i in 0..count {
+            if i > 0 {
+                tokio::time::sleep(self.interval).await;
+            }
+            if let Err(e) = sock.send_to(ack.as_bytes(), &addr).await {
+                eprintln!("UDP send {} failed to {}: {:?}", ack, addr, e);
+            }
+        }
+    }
+
+    /// Send bursts until `confirmed` fires; false if the client never confirms.
+    /// With a `budget`, at most that many bytes of ACKs go out (but always one).
+    pub async fn handshake(
+        &self,
+        sock: &ImpairedSocket,
+        addr: SocketAddr,
+        ack: &str,
+        confirmed: oneshot::Receiver<()>,
+        budget: Option<usize>,
+    ) -> bool {
+        tokio::pin!(confirmed);
+        let mut allowed = budget.map_or(usize::MAX, |b| (b / ack.len().max(1)).max(1));
+        for _ in 0..=self.retries {
+            let count = self.count.min(allowed);
+            allowed -= count;
+            self.send_acks(sock, addr, ack, count).await;
+            tokio::select! {
+                res = &mut confirmed => return res.is_ok(),
+                _ = tokio::time::sleep(SILENCE_TIMEOUT) => {}
+            }
+        }
+        false
+    }
+
+    /// Send `fin` bursts until `acked` fires; false if the client never answers.
+    pub async fn finish(&self, sock: &ImpairedSocket, addr: SocketAddr, fin: &str, acked: oneshot::Receiver<()>) -> bool {
+        tokio::pin!(acked);
+        for _ in 0..FIN_BURSTS {
+            self.send_burst(sock, addr, fin).await;
+            tokio::select! {
+                res = &mut acked => return res.is_ok(),
+                _ = tokio::time::sleep(SILENCE_TIMEOUT) => {}
+            }
+        }
+        false
+    }
+}
+
+/// A handshake's wake-up, and the cookie its `CONFIRM` must carry when the
+/// return path is being validated.
+type Pending = (oneshot::Sender<()>, Option<String>);
+
+/// Clients in handshake mode whose `CONFIRM` is awaited, or downloads whose
+/// `FIN_ACK` is.
+#[derive(Clone, Default)]
+pub struct PendingConfirms {
+    pending: Arc<Mutex<HashMap<SocketAddr, Pending>>>,
+}
+
+impl PendingConfirms {
+    /// A fresh cookie for an ACK, from the OS random source.
+    pub fn cookie(&self) -> String {
+        rng::token_hex()
+    }
+
+    /// Await a `CONFIRM` from `addr` (echoing `cookie`, if given), replacing
+    /// any earlier wait.
+    pub fn expect(&self, addr: SocketAddr, cookie: Option<String>) -> oneshot::Receiver<()> {
+        let (tx, rx) = oneshot::channel();
+        let mut pending = self.pending.lock().unwrap();
+        // Drop waits whose handshake has given up.
+        pending.retain(|_, (tx, _)| !tx.is_closed());
+        pending.insert(addr, (tx, cookie));
+        rx
+    }
+
+    /// A `CONFIRM` arrived from `addr`; false if none was expected or its
+    /// cookie does not match. A wrong cookie leaves the wait in place.
+    pub fn confirm(&self, addr: SocketAddr, cookie: Option<&str>) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        match pending.get(&addr) {
+            Some((_, Some(expected))) if cookie.is_none_or(|c| !c.eq_ignore_ascii_case(expected)) => false,
+            Some(_) => pending.remove(&addr).is_some_and(|(tx, _)| tx.send(()).is_ok()),
+            None => false,
+        }
+    }
+}
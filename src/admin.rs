@@ -0,0 +1,138 @@
+// proj2-serv/src/admin.rs
+// Admin queries over the TCP control channel: `ADMIN <query> [TENANT=<name>]`.
+// Only accepted from loopback peers. `STATUS` is a shorthand for `ADMIN STATUS`.
+// `ADMIN RESULTS FORMAT=iperf3|csv` renders results for iperf tooling.
+// `ADMIN LIMITS` lists the runtime limits and `ADMIN SET <limit> <value>`
+// changes one for tests started afterwards. `ADMIN MESH` gathers the peer
+// mesh's results matrix and `ADMIN MULTICAST` the loss of each multicast receiver.
+// `ADMIN DRAIN ON [RETRY_AFTER=<s>]` / `ADMIN DRAIN OFF` toggle drain mode.
+// `ADMIN SOCKETS` snapshots socket buffers and send errors (see sockstats.rs).
+// grpc.rs offers the session, limit, metric and result operations remotely.
+
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::clock;
+use crate::drain;
+use crate::iperf3;
+use crate::protocol::{error_frame, Command, ErrorCode};
+use crate::sockstats;
+use crate::state::ServerState;
+
+const LOOPBACK_ONLY: &str = "ERR UNAUTHORIZED admin commands are only accepted from loopback\n";
+
+pub async fn handle_admin(state: &ServerState, cmd: &Command, peer: SocketAddr) -> String {
+    if !peer.ip().is_loopback() {
+        return LOOPBACK_ONLY.to_string();
+    }
+    let tenant = cmd.opt("TENANT");
+    let query = cmd.args.first().map(|s| s.to_ascii_uppercase()).unwrap_or_default();
+    match query.as_str() {
+        "RESULTS" => {
+            let results = state.results.query(tenant);
+            let mut out = match cmd.opt("FORMAT").map(|f| f.to_ascii_lowercase()).as_deref() {
+                None | Some("line") => results.iter().map(|r| format!("{}\n", r.to_line())).collect(),
+                Some("iperf3") => iperf3::json_lines(&results),
+                Some("csv") => iperf3::csv_rows(&results),
+                Some(other) => {
+                    return format!("{}\n", error_frame(ErrorCode::InvalidOption, format!("unknown FORMAT={} (expected line, iperf3 or csv)", other)))
+                }
+            };
+            out.push_str("END\n");
+            out
+        }
+        "METRICS" => {
+            let mut out = state.metrics.render(tenant);
+            out.push_str("END\n");
+            out
+        }
+        "TENANTS" => {
+            let mut out = String::new();
+            for name in state.metrics.tenants() {
+                let _ = writeln!(out, "{}", name);
+            }
+            out.push_str("END\n");
+            out
+        }
+        "SESSIONS" | "STATUS" => render_status(state),
+        "SOCKETS" => sockstats::render(state),
+        "KILL" => match cmd.args.get(1).and_then(|id| id.parse::<u64>().ok()) {
+            Some(id) if state.sessions.kill(id) => format!("OK killed session {}\n", id),
+            Some(id) => format!("{}\n", error_frame(ErrorCode::NotFound, format!("no session {}", id))),
+            None => format!("{}\n", error_frame(ErrorCode::BadCommand, "usage: ADMIN KILL <session-id>")),
+        },
+        "MESH" => state.mesh.matrix().await,
+        "MULTICAST" => state.multicast.render(),
+        "DRAIN" => drain_command(state, cmd, peer),
+        "LIMITS" => {
+            let mut out = state.limits.render();
+            out.push_str("END\n");
+            out
+        }
+        "SET" => match (cmd.args.get(1), cmd.args.get(2)) {
+            (Some(name), Some(value)) => {
+                let name = name.to_ascii_lowercase();
+                match state.limits.set(&name, value) {
+                    Ok(()) => {
+                        println!("Admin {} set limit {}={}", peer, name, value);
+                        format!("OK {}={}\n", name, value)
+                    }
+                    Err(e) => format!("{}\n", error_frame(ErrorCode::InvalidOption, e)),
+                }
+            }
+            _ => format!("{}\n", error_frame(ErrorCode::BadCommand, "usage: ADMIN SET <limit> <value>")),
+        },
+        _ => format!("{}\n", error_frame(ErrorCode::BadCommand, format!("unknown admin query {:?}", query))),
+    }
+}
+
+fn drain_command(state: &ServerState, cmd: &Command, peer: SocketAddr) -> String {
+    match cmd.args.get(1).map(|s| s.to_ascii_uppercase()).as_deref() {
+        Some("ON") => {
+            let retry_after = match cmd.opt("RETRY_AFTER").map(|v| (v, v.parse::<u64>())) {
+                Some((_, Ok(secs))) => Duration::from_secs(secs),
+                Some((v, Err(_))) => return format!("{}\n", error_frame(ErrorCode::InvalidOption, format!("invalid RETRY_AFTER={}", v))),
+                None => drain::DEFAULT_RETRY_AFTER,
+            };
+            state.drain.start(retry_after);
+            println!("Admin {} started draining (retry after {}s)", peer, retry_after.as_secs());
+        }
+        Some("OFF") if state.drain.stop() => println!("Admin {} stopped draining", peer),
+        Some("OFF") => {}
+        Some(_) => return format!("{}\n", error_frame(ErrorCode::BadCommand, "usage: ADMIN DRAIN [ON [RETRY_AFTER=<s>]|OFF]")),
+        None => {}
+    }
+    state.drain.render(&state.sessions.list())
+}
+
+/// The top-level `STATUS` command, subject to the same loopback restriction.
+pub fn status(state: &ServerState, peer: SocketAddr) -> String {
+    if !peer.ip().is_loopback() {
+        return LOOPBACK_ONLY.to_string();
+    }
+    render_status(state)
+}
+
+/// One line per active session: protocol, peer, current direction, and bytes
+/// and packets so far.
+fn render_status(state: &ServerState) -> String {
+    let mut out = String::new();
+    for s in state.sessions.list() {
+        let _ = writeln!(
+            out,
+            "id={} proto={} peer={} tenant={} dir={} bytes={} age_ms={} packets={} idle_ms={}",
+            s.id,
+            s.protocol.as_str(),
+            s.peer,
+            s.tenant,
+            s.direction.map_or("idle", |d| d.as_str()),
+            s.bytes,
+            clock::elapsed(s.started).as_millis(),
+            s.packets,
+            clock::elapsed(s.last_activity).as_millis()
+        );
+    }
+    out.push_str("END\n");
+    out
+}
@@ -0,0 +1,143 @@
+// proj2-serv/src/auth.rs
+// Signed control messages (`--control-key <file>`). UDP source addresses are
+// trivially spoofed, so anyone who sniffs one START_DOWNLOAD datagram could
+// replay it with a victim's address and have the server flood the victim. With
+// a pre-shared key, commands that start work must end in
+//   ... NONCE=<16-64 hex> TS=<unix seconds> MAC=<hex HMAC-SHA256>
+// where the MAC covers the command text before ` MAC=`, using the key file's
+// contents (trailing newline stripped) as the key. HMAC-SHA256 comes from the
+// hmac and sha2 crates, tags are compared in constant time, and nonces are
+// drawn from the OS random source. The server rejects a bad or missing MAC, a
+// TS more than MAX_SKEW from its clock, and a nonce it has already accepted
+// within that window, with `ERR UNAUTHORIZED`; that reply is shorter than the
+// signed command, so it cannot amplify. A client retrying a start signs the
+// retry with a fresh nonce; a UDP start that repeats the running test's, its
+// signature aside, only needs a good MAC and TS to get that test's ACK again,
+// so a datagram resent as is is not refused as a replay. Scheduled and mesh tests sign their commands with
+// the same key. The UDS plane is local and guarded by file permissions, so it
+// does not ask for signatures.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{ensure, Context};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::protocol::{error_frame, Command, ErrorCode};
+use crate::ratelimit;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Largest difference between a command's TS and the server clock.
+pub const MAX_SKEW: Duration = Duration::from_secs(30);
+/// Accepted nonces remembered at once; a nonce is forgotten once its TS could
+/// no longer pass the skew check.
+const MAX_NONCES: usize = 65536;
+const MIN_KEY_LEN: usize = 16;
+
+pub struct ControlAuth {
+    key: Vec<u8>,
+    /// Accepted nonces and when they may be forgotten.
+    seen: Mutex<HashMap<String, Instant>>,
+}
+
+impl ControlAuth {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let mut key = std::fs::read(path).with_context(|| format!("reading control key {}", path.display()))?;
+        while key.last().is_some_and(|b| b.is_ascii_whitespace()) {
+            key.pop();
+        }
+        ensure!(key.len() >= MIN_KEY_LEN, "control key {} is shorter than {} bytes", path.display(), MIN_KEY_LEN);
+        Ok(ControlAuth { key, seen: Mutex::new(HashMap::new()) })
+    }
+
+    /// Check the signature on `line` if its command starts work, and take its
+    /// nonce; the error is a ready ERR frame.
+    pub fn verify(&self, line: &str, cmd: &Command) -> Result<(), String> {
+        self.authenticate(line, cmd)?;
+        let Some(nonce) = cmd.opt("NONCE").filter(|_| ratelimit::starts_work(&cmd.verb)).map(|n| n.to_ascii_lowercase()) else {
+            return Ok(());
+        };
+        let mut seen = self.seen.lock().unwrap();
+        let now = Instant::now();
+        if seen.len() >= MAX_NONCES {
+            seen.retain(|_, forget_at| *forget_at > now);
+        }
+        if seen.get(&nonce).is_some_and(|forget_at| *forget_at > now) {
+            return Err(error_frame(ErrorCode::Unauthorized, "replayed NONCE"));
+        }
+        if seen.len() >= MAX_NONCES {
+            return Err(error_frame(ErrorCode::Busy, "too many signed commands in flight"));
+        }
+        seen.insert(nonce, now + 2 * MAX_SKEW);
+        Ok(())
+    }
+
+    /// Check the MAC, NONCE and TS on `line` if its command starts work,
+    /// leaving the nonce untaken; the error is a ready ERR frame.
+    pub fn authenticate(&self, line: &str, cmd: &Command) -> Result<(), String> {
+        if !ratelimit::starts_work(&cmd.verb) {
+            return Ok(());
+        }
+        let refuse = |why: &str| Err(error_frame(ErrorCode::Unauthorized, why));
+        let Some((signed, mac)) = line.rsplit_once(" MAC=") else { return refuse("command must be signed (NONCE=, TS=, MAC=)") };
+        let Some(mac) = from_hex(mac.trim()) else { return refuse("malformed MAC") };
+        let mut expected = mac_with(&self.key);
+        expected.update(signed.as_bytes());
+        if expected.verify_slice(&mac).is_err() {
+            return refuse("bad MAC");
+        }
+        if !cmd.opt("NONCE").is_some_and(|n| (16..=64).contains(&n.len()) && n.bytes().all(|b| b.is_ascii_hexdigit())) {
+            return refuse("missing or malformed NONCE");
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        match cmd.opt("TS").and_then(|ts| ts.parse::<u64>().ok()) {
+            Some(ts) if ts.abs_diff(now) <= MAX_SKEW.as_secs() => Ok(()),
+            Some(_) => refuse("stale TS; check the client clock"),
+            None => refuse("missing or malformed TS"),
+        }
+    }
+
+    /// `line` with a fresh nonce, the current time and its MAC appended.
+    pub fn sign(&self, line: &str) -> String {
+        let mut nonce = [0u8; 16];
+        getrandom::fill(&mut nonce).expect("the OS random source is available");
+        let ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let signed = format!("{} NONCE={} TS={}", line, to_hex(&nonce), ts);
+        let mut mac = mac_with(&self.key);
+        mac.update(signed.as_bytes());
+        format!("{} MAC={}", signed, to_hex(&mac.finalize().into_bytes()))
+    }
+}
+
+/// `line` without its NONCE=, TS= and MAC= fields, so a start re-signed for a
+/// retry compares equal to the one it repeats.
+pub fn unsigned(line: &str) -> String {
+    line.split(' ').filter(|field| !["NONCE=", "TS=", "MAC="].iter().any(|key| field.starts_with(key))).collect::<Vec<_>>().join(" ")
+}
+
+/// `line` signed with `auth`, or as is without a control key.
+pub fn sign(auth: Option<&ControlAuth>, line: &str) -> String {
+    match auth {
+        Some(auth) => auth.sign(line),
+        None => line.to_string(),
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok()).collect()
+}
+
+fn mac_with(key: &[u8]) -> HmacSha256 {
+    HmacSha256::new_from_slice(key).expect("HMAC takes keys of any length")
+}
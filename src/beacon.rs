@@ -0,0 +1,105 @@
+// proj2-serv/src/beacon.rs
+// LAN discovery beacon. Unless `--no-beacon` is given, the server announces
+// itself every `--beacon-interval` seconds with one datagram to
+// `--beacon-addr` (broadcast 255.255.255.255:7171 by default, or a multicast
+// group), so clients can latch on without mDNS. The datagram is
+// `PROJ2_BEACON name=<name> version=<v> tcp=<port> udp=<port> degraded=<0|1>`,
+// plus `multicast=<group:port>` when the multicast stream runs; the address is
+// the datagram's source. Ports of planes that are down are sent as 0.
+// `proj2-serv discover` listens for beacons alongside its mDNS query
+// (`--beacon-addr` there too when the beacon goes to a multicast group).
+
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+
+use crate::state::ServerState;
+use crate::supervisor::Plane;
+
+pub const DEFAULT_ADDR: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::BROADCAST, 7171);
+const MAGIC: &str = "PROJ2_BEACON";
+
+fn beacon(state: &ServerState) -> String {
+    let port = |p| state.health.port(p).unwrap_or(0);
+    let mut line = format!(
+        "{} name={} version={} tcp={} udp={} degraded={}",
+        MAGIC,
+        state.config.mdns_name,
+        env!("CARGO_PKG_VERSION"),
+        port(Plane::Tcp),
+        port(Plane::Udp),
+        state.health.degraded() as u8
+    );
+    if let Some(group) = state.config.multicast {
+        line.push_str(&format!(" multicast={}", group));
+    }
+    line
+}
+
+fn bind_sender(dest: SocketAddrV4) -> anyhow::Result<UdpSocket> {
+    let s = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).context("creating beacon socket")?;
+    s.set_broadcast(true).context("SO_BROADCAST on beacon socket")?;
+    if dest.ip().is_multicast() {
+        // Beacons are for the local segment only.
+        s.set_multicast_ttl_v4(1)?;
+        s.set_multicast_loop_v4(true)?;
+    }
+    s.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)).into()).context("binding beacon socket")?;
+    s.set_nonblocking(true)?;
+    UdpSocket::from_std(s.into()).context("convert beacon socket")
+}
+
+/// Send a beacon every interval until the server stops.
+pub async fn run_beacon(state: Arc<ServerState>) -> anyhow::Result<()> {
+    let dest = state.config.beacon_addr;
+    let sock = bind_sender(dest)?;
+    println!("Beacon to {} every {:?}", dest, state.config.beacon_interval);
+    let mut ticker = tokio::time::interval(state.config.beacon_interval);
+    let mut failing = false;
+    loop {
+        ticker.tick().await;
+        // Nothing to announce until a plane has bound its port.
+        if !state.health.is_up(Plane::Tcp) && !state.health.is_up(Plane::Udp) {
+            continue;
+        }
+        match sock.send_to(beacon(&state).as_bytes(), dest).await {
+            Ok(_) => failing = false,
+            // Report once per outage rather than on every tick.
+            Err(e) if !failing => {
+                eprintln!("Beacon to {} failed: {}", dest, e);
+                failing = true;
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+/// Collect beacons sent to `addr` for `wait`, latest per sender.
+pub async fn listen(addr: SocketAddrV4, wait: Duration) -> anyhow::Result<BTreeMap<SocketAddr, String>> {
+    let s = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).context("creating beacon listener")?;
+    s.set_reuse_address(true)?;
+    #[cfg(unix)]
+    s.set_reuse_port(true)?;
+    s.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, addr.port())).into()).with_context(|| format!("binding beacon port {}", addr.port()))?;
+    if addr.ip().is_multicast() {
+        s.join_multicast_v4(addr.ip(), &Ipv4Addr::UNSPECIFIED).with_context(|| format!("joining beacon group {}", addr.ip()))?;
+    }
+    s.set_nonblocking(true)?;
+    let sock = UdpSocket::from_std(s.into())?;
+    let mut heard = BTreeMap::new();
+    let mut buf = vec![0u8; 2048];
+    let deadline = tokio::time::Instant::now() + wait;
+    while let Ok(recv) = tokio::time::timeout_at(deadline, sock.recv_from(&mut buf)).await {
+        let (n, from) = recv?;
+        let msg = String::from_utf8_lossy(&buf[..n]);
+        if let Some(fields) = msg.trim().strip_prefix(MAGIC) {
+            heard.insert(from, fields.trim().to_string());
+        }
+    }
+    Ok(heard)
+}
@@ -0,0 +1,172 @@
+// proj2-serv/src/bloat.rs
+// Latency under load (`BLOAT=1` on a UDP START_DOWNLOAD), the bufferbloat test
+// of home speed tests. The server pings the client with `PING <seq>` every
+// PING_INTERVAL from the plane's shared socket and the client answers each
+// with `PONG <seq>`. Pings run for IDLE with the link quiet before the flood
+// starts (the ACK says so with `BLOAT_IDLE_MS=`), then for the whole flood, so
+// the result can set the median round trip with the link loaded beside the one
+// with it idle. The difference is graded by the thresholds those tests use for
+// added latency: A+ under 5 ms, A under 30, B under 60, C under 200, D under
+// 400, F beyond. A ping left unanswered for LOST_AFTER counts as lost.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc;
+
+use crate::impair::ImpairedSocket;
+use crate::protocol::Command;
+
+/// Time between two pings.
+pub const PING_INTERVAL: Duration = Duration::from_millis(100);
+/// Pinging with the link quiet, before the flood starts.
+pub const IDLE: Duration = Duration::from_secs(1);
+/// A ping unanswered this long is lost.
+const LOST_AFTER: Duration = Duration::from_secs(1);
+/// Pongs waiting for their test to take them.
+const PONG_QUEUE: usize = 64;
+
+/// Grades by added latency, best first.
+const GRADES: [(Duration, &str); 5] = [
+    (Duration::from_millis(5), "A+"),
+    (Duration::from_millis(30), "A"),
+    (Duration::from_millis(60), "B"),
+    (Duration::from_millis(200), "C"),
+    (Duration::from_millis(400), "D"),
+];
+
+/// A pong's sequence number and when it arrived.
+type Pong = (u64, Instant);
+
+/// Where each client's `PONG`s go while a test pings it.
+#[derive(Clone, Default)]
+pub struct Pongs(Arc<Mutex<HashMap<SocketAddr, mpsc::Sender<Pong>>>>);
+
+impl Pongs {
+    fn expect(&self, addr: SocketAddr) -> mpsc::Receiver<Pong> {
+        let (tx, rx) = mpsc::channel(PONG_QUEUE);
+        self.0.lock().unwrap().insert(addr, tx);
+        rx
+    }
+
+    /// Hand `PONG <seq>` from `addr` to the test pinging it; false if it is
+    /// malformed or no test is.
+    pub fn pong(&self, addr: SocketAddr, cmd: &Command) -> bool {
+        let Some(seq) = cmd.args.first().and_then(|s| s.parse().ok()) else { return false };
+        let pongs = self.0.lock().unwrap();
+        pongs.get(&addr).is_some_and(|tx| tx.try_send((seq, Instant::now())).is_ok())
+    }
+}
+
+/// The pings of one test.
+pub struct Pinger {
+    sock: ImpairedSocket,
+    dest: SocketAddr,
+    pongs: Pongs,
+    replies: mpsc::Receiver<Pong>,
+    seq: u64,
+    /// Unanswered pings: when each went out and whether the link was loaded.
+    outstanding: HashMap<u64, (Instant, bool)>,
+    idle: Vec<Duration>,
+    loaded: Vec<Duration>,
+}
+
+impl Pinger {
+    pub fn new(sock: ImpairedSocket, dest: SocketAddr, pongs: &Pongs) -> Self {
+        let replies = pongs.expect(dest);
+        Pinger { sock, dest, pongs: pongs.clone(), replies, seq: 0, outstanding: HashMap::new(), idle: Vec::new(), loaded: Vec::new() }
+    }
+
+    /// Ping for IDLE with nothing else sent toward the client.
+    pub async fn idle(&mut self) {
+        self.ping_until(tokio::time::sleep(IDLE), false).await
+    }
+
+    /// Ping while `load` saturates the link; its output.
+    pub async fn loaded<F: Future>(&mut self, load: F) -> F::Output {
+        self.ping_until(load, true).await
+    }
+
+    async fn ping_until<F: Future>(&mut self, until: F, loaded: bool) -> F::Output {
+        tokio::pin!(until);
+        let mut tick = tokio::time::interval(PING_INTERVAL);
+        loop {
+            tokio::select! {
+                out = &mut until => return out,
+                _ = tick.tick() => {
+                    self.seq += 1;
+                    self.outstanding.insert(self.seq, (Instant::now(), loaded));
+                    if let Err(e) = self.sock.send_to(format!("PING {}", self.seq).as_bytes(), &self.dest).await {
+                        eprintln!("UDP send PING failed to {}: {:?}", self.dest, e);
+                    }
+                }
+                Some((seq, at)) = self.replies.recv() => {
+                    if let Some((sent, under_load)) = self.outstanding.remove(&seq) {
+                        let rtt = at.saturating_duration_since(sent);
+                        if under_load { self.loaded.push(rtt) } else { self.idle.push(rtt) }
+                    }
+                }
+            }
+        }
+    }
+
+    /// What the pings showed. Pings still within LOST_AFTER are left out.
+    pub fn finish(mut self) -> Latency {
+        let now = Instant::now();
+        let lost = self.outstanding.values().filter(|(sent, _)| now.duration_since(*sent) >= LOST_AFTER).count() as u64;
+        Latency { idle: median(&mut self.idle), loaded: median(&mut self.loaded), pings: self.seq, lost }
+    }
+}
+
+impl Drop for Pinger {
+    fn drop(&mut self) {
+        self.pongs.0.lock().unwrap().remove(&self.dest);
+    }
+}
+
+fn median(rtts: &mut [Duration]) -> Option<Duration> {
+    rtts.sort_unstable();
+    rtts.get(rtts.len() / 2).copied()
+}
+
+/// Median round trips with the link idle and loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Latency {
+    pub idle: Option<Duration>,
+    pub loaded: Option<Duration>,
+    pub pings: u64,
+    pub lost: u64,
+}
+
+impl Latency {
+    /// How much the load added to the round trip.
+    pub fn added(&self) -> Option<Duration> {
+        Some(self.loaded?.saturating_sub(self.idle?))
+    }
+
+    pub fn grade(&self) -> Option<&'static str> {
+        let added = self.added()?;
+        Some(GRADES.iter().find(|(below, _)| added < *below).map_or("F", |(_, grade)| grade))
+    }
+}
+
+impl fmt::Display for Latency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        write!(f, "pings={} pings_lost={}", self.pings, self.lost)?;
+        if let Some(idle) = self.idle {
+            write!(f, " rtt_idle_ms={:.1}", ms(idle))?;
+        }
+        if let Some(loaded) = self.loaded {
+            write!(f, " rtt_loaded_ms={:.1}", ms(loaded))?;
+        }
+        if let (Some(added), Some(grade)) = (self.added(), self.grade()) {
+            write!(f, " bloat_ms={:.1} bloat_grade={}", ms(added), grade)?;
+        }
+        Ok(())
+    }
+}
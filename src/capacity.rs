@@ -0,0 +1,114 @@
+// proj2-serv/src/capacity.rs
+// Packet-train capacity probing. `CAPACITY_PROBE` makes the server send short
+// trains of back-to-back datagrams; the bottleneck link spaces them out, so the
+// client's first-to-last arrival time per train (its dispersion) gives the link
+// capacity without saturating it. The client reports the dispersions with
+// `DISPERSION_REPORT <us>,<us>,...` and gets back `CAPACITY bps=<estimate>`.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::impair::ImpairedSocket;
+use crate::protocol::Command;
+
+/// Pause between trains so queues drain and trains do not interfere.
+const TRAIN_GAP: Duration = Duration::from_millis(50);
+/// How long a probe waits for its dispersion report.
+const REPORT_TTL: Duration = Duration::from_secs(60);
+/// Cap on probes awaiting a report.
+const MAX_PENDING: usize = 1024;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ProbeSpec {
+    pub trains: usize,
+    pub train_len: usize,
+    /// Datagram size in bytes.
+    pub size: usize,
+}
+
+impl ProbeSpec {
+    /// Read `TRAINS=`, `LEN=` and `SIZE=`; the error is the reason for an ERR reply.
+    pub fn from_command(cmd: &Command) -> Result<ProbeSpec, String> {
+        Ok(ProbeSpec {
+            trains: bounded(cmd, "TRAINS", 10, 1..=50)?,
+            train_len: bounded(cmd, "LEN", 8, 2..=100)?,
+            size: bounded(cmd, "SIZE", 1200, 64..=1472)?,
+        })
+    }
+
+    /// Median capacity over the reported trains. A train of n packets has n - 1
+    /// packets' worth of bits spread over its dispersion.
+    pub fn estimate_bps(&self, dispersions: &[Duration]) -> Option<f64> {
+        let bits = ((self.train_len - 1) * self.size * 8) as f64;
+        let mut estimates: Vec<f64> = dispersions
+            .iter()
+            .filter(|d| !d.is_zero())
+            .map(|d| bits / d.as_secs_f64())
+            .collect();
+        if estimates.is_empty() {
+            return None;
+        }
+        estimates.sort_by(f64::total_cmp);
+        Some(estimates[estimates.len() / 2])
+    }
+}
+
+/// Probes sent and awaiting the client's dispersion report, by client address.
+#[derive(Default)]
+pub struct PendingProbes {
+    probes: HashMap<SocketAddr, (ProbeSpec, Instant)>,
+}
+
+impl PendingProbes {
+    /// Remember a probe; false if too many are already waiting.
+    pub fn insert(&mut self, addr: SocketAddr, spec: ProbeSpec) -> bool {
+        let now = Instant::now();
+        self.probes.retain(|_, (_, at)| now.duration_since(*at) < REPORT_TTL);
+        if self.probes.len() >= MAX_PENDING && !self.probes.contains_key(&addr) {
+            return false;
+        }
+        self.probes.insert(addr, (spec, now));
+        true
+    }
+
+    pub fn take(&mut self, addr: SocketAddr) -> Option<ProbeSpec> {
+        self.probes.remove(&addr).map(|(spec, _)| spec)
+    }
+}
+
+fn bounded(cmd: &Command, key: &str, default: usize, range: std::ops::RangeInclusive<usize>) -> Result<usize, String> {
+    let Some(value) = cmd.opt(key) else { return Ok(default) };
+    match value.parse::<usize>() {
+        Ok(n) if range.contains(&n) => Ok(n),
+        _ => Err(format!("invalid {}={} (expected {}..={})", key, value, range.start(), range.end())),
+    }
+}
+
+/// Parse the microsecond dispersions of a `DISPERSION_REPORT`. Trains the client
+/// could not measure (loss) are sent as `-` and skipped.
+pub fn parse_report(cmd: &Command) -> Vec<Duration> {
+    cmd.args
+        .first()
+        .map(|list| list.split(',').filter_map(|us| us.parse().ok()).map(Duration::from_micros).collect())
+        .unwrap_or_default()
+}
+
+/// Send the trains. Each datagram starts with `TRAIN <train> <seq> <ns>` where
+/// `ns` is the send time relative to the start of the probe, padded to `size`.
+pub async fn send_trains(sock: ImpairedSocket, dest: SocketAddr, spec: ProbeSpec) {
+    let start = Instant::now();
+    let mut buf = vec![0u8; spec.size];
+    for train in 0..spec.trains {
+        for seq in 0..spec.train_len {
+            let header = format!("TRAIN {} {} {}", train, seq, start.elapsed().as_nanos());
+            buf.fill(0);
+            buf[..header.len()].copy_from_slice(header.as_bytes());
+            if let Err(e) = sock.send_to(&buf, &dest).await {
+                eprintln!("UDP capacity probe send to {} failed: {:?}", dest, e);
+                return;
+            }
+        }
+        tokio::time::sleep(TRAIN_GAP).await;
+    }
+}
@@ -0,0 +1,191 @@
+// proj2-serv/src/central.rs
+// The UDP plane's central sender. Async download floods do not write to their
+// sockets themselves: each fills a short queue of its own, and one task per
+// plane drains the queues in turn, `WEIGHT=` x 16 datagrams (1-16, default 1)
+// from each per round, so clients with equal weights get equal shares of the
+// socket. The round goes out in `sendmmsg` batches, one per run of datagrams
+// for the same socket, and only this task handles a full send buffer: it parks
+// on writability and resends the rest of the batch, so a flood just waits
+// while its queue is full and a slow round slows every flood alike. Floods
+// with sockets of their own (`CONNECTED=1`, `PORTS=`) queue through the same
+// task; an ICMP error on a connected socket is handed back to its flood.
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use tokio::sync::mpsc::{self, error::TryRecvError};
+use tokio::sync::oneshot;
+use tokio::sync::Notify;
+use tokio_util::task::AbortOnDropHandle;
+
+use crate::icmp;
+use crate::impair::ImpairedSocket;
+use crate::session::Counters;
+use crate::sockopt::Outgoing;
+
+/// Datagrams a flood may have waiting for its turn.
+const LANE_DEPTH: usize = 64;
+/// Datagrams per round for each unit of weight.
+const QUANTUM: usize = 16;
+/// Most datagrams handed to one `sendmmsg`.
+const MAX_BATCH: usize = 64;
+pub const MAX_WEIGHT: usize = 16;
+
+pub struct Datagram {
+    pub buf: Vec<u8>,
+    pub dest: SocketAddr,
+    pub tos: Option<u8>,
+    /// Which of the lane's sockets to send on.
+    pub sock: usize,
+}
+
+struct Lane {
+    rx: mpsc::Receiver<Datagram>,
+    weight: usize,
+    socks: Vec<ImpairedSocket>,
+    connected: bool,
+    failed: Arc<OnceLock<String>>,
+    /// The flood's session, charged with the full buffers and failed sends
+    /// of its datagrams.
+    counters: Arc<Counters>,
+    /// Dropped with the lane, once its last datagram has gone out.
+    _done: oneshot::Sender<()>,
+}
+
+/// Handle on a UDP plane's central sender.
+#[derive(Clone)]
+pub struct CentralSender {
+    /// Lanes opened since the sender last looked.
+    opened: Arc<Mutex<Vec<Lane>>>,
+    wake: Arc<Notify>,
+}
+
+/// One flood's queue into the central sender.
+pub struct LaneTx {
+    tx: mpsc::Sender<Datagram>,
+    wake: Arc<Notify>,
+    failed: Arc<OnceLock<String>>,
+    done: oneshot::Receiver<()>,
+}
+
+impl CentralSender {
+    /// Start the central sender; it stops when the handle is dropped.
+    pub fn spawn() -> (Self, AbortOnDropHandle<()>) {
+        let sender = CentralSender { opened: Arc::default(), wake: Arc::default() };
+        let task = tokio::spawn(run(sender.clone()));
+        (sender, AbortOnDropHandle::new(task))
+    }
+
+    /// Open a queue for one flood over `socks`, served `weight` shares per
+    /// round. `connected` sockets report ICMP errors back through the lane.
+    pub fn lane(&self, weight: usize, socks: Vec<ImpairedSocket>, connected: bool, counters: Arc<Counters>) -> LaneTx {
+        let (tx, rx) = mpsc::channel(LANE_DEPTH);
+        let failed = Arc::new(OnceLock::new());
+        let (done_tx, done) = oneshot::channel();
+        let lane = Lane { rx, weight: weight.clamp(1, MAX_WEIGHT), socks, connected, failed: failed.clone(), counters, _done: done_tx };
+        self.opened.lock().unwrap().push(lane);
+        self.wake.notify_one();
+        LaneTx { tx, wake: self.wake.clone(), failed, done }
+    }
+}
+
+impl LaneTx {
+    /// Queue a datagram, waiting for room; false if the sender has stopped.
+    pub async fn send(&self, datagram: Datagram) -> bool {
+        let queued = self.tx.send(datagram).await.is_ok();
+        self.wake.notify_one();
+        queued
+    }
+
+    /// The ICMP error that ended the client's connected socket, if any.
+    pub fn failed(&self) -> Option<&String> {
+        self.failed.get()
+    }
+
+    /// Close the queue and wait until what is in it has been sent.
+    pub async fn flush(self) {
+        let LaneTx { tx, wake, done, .. } = self;
+        drop(tx);
+        wake.notify_one();
+        let _ = done.await;
+    }
+}
+
+/// `WEIGHT=` of a download.
+pub fn parse_weight(value: &str) -> Option<usize> {
+    value.parse().ok().filter(|w| (1..=MAX_WEIGHT).contains(w))
+}
+
+async fn run(sender: CentralSender) {
+    let mut lanes: Vec<Lane> = Vec::new();
+    let mut round: Vec<(usize, Datagram)> = Vec::new();
+    let mut closed = Vec::new();
+    loop {
+        lanes.append(&mut sender.opened.lock().unwrap());
+        for (i, lane) in lanes.iter_mut().enumerate() {
+            for _ in 0..lane.weight * QUANTUM {
+                match lane.rx.try_recv() {
+                    Ok(datagram) => round.push((i, datagram)),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        closed.push(i);
+                        break;
+                    }
+                }
+            }
+        }
+        let sent_any = !round.is_empty();
+        let mut at = 0;
+        while at < round.len() {
+            let sock = socket(&lanes, &round[at]);
+            let run = round[at..].iter().take_while(|d| socket(&lanes, d).same_socket(sock)).count();
+            send_run(sock, &round[at..at + run], &lanes).await;
+            at += run;
+        }
+        round.clear();
+        // Removing in place, last first, keeps the order of turns.
+        for i in closed.drain(..).rev() {
+            lanes.remove(i);
+        }
+        if sent_any {
+            tokio::task::yield_now().await;
+        } else {
+            sender.wake.notified().await;
+        }
+    }
+}
+
+fn socket<'a>(lanes: &'a [Lane], (lane, datagram): &(usize, Datagram)) -> &'a ImpairedSocket {
+    let socks = &lanes[*lane].socks;
+    &socks[datagram.sock % socks.len()]
+}
+
+/// Send datagrams bound for one socket in batches, parking while its send
+/// buffer is full. A datagram the kernel refuses is skipped.
+async fn send_run(sock: &ImpairedSocket, run: &[(usize, Datagram)], lanes: &[Lane]) {
+    let mut at = 0;
+    while at < run.len() {
+        let batch: Vec<Outgoing<'_>> =
+            run[at..run.len().min(at + MAX_BATCH)].iter().map(|(_, d)| (&d.buf[..], d.dest, d.tos)).collect();
+        match sock.try_send_batch(&batch) {
+            Ok(n) => at += n.max(1),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                lanes[run[at].0].counters.would_block();
+                if sock.writable().await.is_err() {
+                    return;
+                }
+            }
+            Err(e) => {
+                let (lane, datagram) = &run[at];
+                let lane = &lanes[*lane];
+                lane.counters.send_error();
+                if lane.connected && icmp::is_peer_error(&e) {
+                    let _ = lane.failed.set(e.to_string());
+                } else {
+                    eprintln!("UDP central sender: send_to error to {}: {:?}", datagram.dest, e);
+                }
+                at += 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,34 @@
+// proj2-serv/src/clock.rs
+// The clock behind test windows, deadlines and idle timeouts. It reads tokio's
+// clock rather than std's, so a deadline and the timer that waits for it always
+// agree, and a test under tokio's paused time (`start_paused = true`) moves
+// both at once: a five-second window ends the moment the runtime has nothing
+// else to do, with no real sleep. tests/deadlines.rs drives the deadline logic
+// that way. Outside paused tests this is exactly `Instant::now()`.
+
+use std::time::{Duration, Instant};
+
+use tokio_util::sync::CancellationToken;
+
+pub fn now() -> Instant {
+    tokio::time::Instant::now().into_std()
+}
+
+pub fn elapsed(since: Instant) -> Duration {
+    now().saturating_duration_since(since)
+}
+
+/// Wait for the end of a window opened at `started` whose length, as `EXTEND`
+/// leaves it, `length` reads; a lengthened window is waited out in turn. False
+/// if `cancel` (an admin kill or shutdown) ends it first.
+pub async fn window_end(started: Instant, length: impl Fn() -> Duration, cancel: &CancellationToken) -> bool {
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep_until((started + length()).into()) => {}
+            _ = cancel.cancelled() => return false,
+        }
+        if started + length() <= now() {
+            return true;
+        }
+    }
+}
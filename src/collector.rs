@@ -0,0 +1,346 @@
+// proj2-serv/src/collector.rs
+// `proj2-serv collector --trust <hex public key> [--trust ...] [--port P]
+//                       [--store <file>]`
+// A central collector for signed results. Test servers run with
+// `--result-key` and `--export-results http://<collector>:<port>/results`, and
+// the collector takes the JSON object each POSTs, rebuilds the result line the
+// signature covers (its fields in order, `sig` left out) and keeps the result
+// only if one of the trusted servers' keys verifies it: 403 otherwise, 400
+// for a body that is not a flat result object. Kept results are appended to
+// the store file as `signer=<key> <result line> sig=<signature>` and read back,
+// checked again, at the next start. Queries over plain HTTP:
+//   GET /results   the kept result lines, oldest first
+//   GET /summary   one line per server, protocol and direction: results,
+//                  bytes and mean, median and highest throughput
+// both filtered by `?signer=<key prefix>&tenant=&proto=&dir=`.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io::Write;
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{ensure, Context};
+use ed25519_dalek::{Signature, VerifyingKey};
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::dashboard;
+
+const DEFAULT_PORT: u16 = 9100;
+/// Results kept in memory; the oldest go first.
+const MAX_COLLECTED: usize = 100_000;
+/// Largest request accepted, head and body.
+const MAX_REQUEST: usize = 64 * 1024;
+/// Limit on reading one request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Options {
+    port: u16,
+    trusted: Vec<VerifyingKey>,
+    store: Option<PathBuf>,
+}
+
+/// A verified result and the server that signed it.
+struct Collected {
+    signer: String,
+    line: String,
+    sig: String,
+}
+
+impl Collected {
+    /// The line's value of `key`.
+    fn field(&self, key: &str) -> Option<&str> {
+        self.line.split(' ').find_map(|kv| kv.strip_prefix(key)?.strip_prefix('='))
+    }
+
+    fn stored(&self) -> String {
+        format!("signer={} {} sig={}", self.signer, self.line, self.sig)
+    }
+}
+
+struct Collector {
+    trusted: Vec<VerifyingKey>,
+    results: Mutex<VecDeque<Collected>>,
+    store: Option<Mutex<std::fs::File>>,
+}
+
+impl Collector {
+    /// The hex key of the trusted server whose signature `sig` is over `line`.
+    fn verify(&self, line: &str, sig: &str) -> Option<String> {
+        let sig = Signature::from_slice(&from_hex(sig)?).ok()?;
+        let key = self.trusted.iter().find(|key| key.verify_strict(line.as_bytes(), &sig).is_ok())?;
+        Some(to_hex(key.as_bytes()))
+    }
+
+    fn keep(&self, collected: Collected) {
+        let mut results = self.results.lock().unwrap();
+        if results.len() == MAX_COLLECTED {
+            results.pop_front();
+        }
+        results.push_back(collected);
+    }
+
+    /// Take a result POSTed by a server; the error is the HTTP status.
+    fn submit(&self, body: &str) -> Result<(), &'static str> {
+        let fields = parse_object(body).ok_or("400 Bad Request")?;
+        let sig = fields.iter().find(|(k, _)| k == "sig").map(|(_, v)| v.clone()).ok_or("403 Forbidden")?;
+        let line: Vec<String> = fields.iter().filter(|(k, _)| k != "sig").map(|(k, v)| format!("{}={}", k, v)).collect();
+        let line = line.join(" ");
+        let signer = self.verify(&line, &sig).ok_or("403 Forbidden")?;
+        let collected = Collected { signer, line, sig };
+        if let Some(store) = &self.store
+            && let Err(e) = writeln!(store.lock().unwrap(), "{}", collected.stored())
+        {
+            eprintln!("Collector cannot append to its store: {}", e);
+        }
+        self.keep(collected);
+        Ok(())
+    }
+
+    /// Read back a store file, keeping the lines a trusted key still verifies.
+    fn load(&self, text: &str) -> usize {
+        let mut kept = 0;
+        for stored in text.lines() {
+            let Some((line, sig)) = stored.strip_prefix("signer=").and_then(|s| s.split_once(' ')).and_then(|(_, rest)| rest.rsplit_once(" sig="))
+            else {
+                continue;
+            };
+            if let Some(signer) = self.verify(line, sig) {
+                self.keep(Collected { signer, line: line.to_string(), sig: sig.to_string() });
+                kept += 1;
+            }
+        }
+        kept
+    }
+
+    /// Results matching every `key=value` of the query string.
+    fn query(&self, query: &str) -> Vec<String> {
+        let results = self.results.lock().unwrap();
+        results.iter().filter(|c| matches(c, query)).map(Collected::stored).collect()
+    }
+
+    fn summary(&self, query: &str) -> String {
+        let results = self.results.lock().unwrap();
+        let mut groups: BTreeMap<(String, &str, &str), (u64, Vec<f64>)> = BTreeMap::new();
+        for c in results.iter().filter(|c| matches(c, query)) {
+            let key = (c.signer[..16].to_string(), c.field("proto").unwrap_or("-"), c.field("dir").unwrap_or("-"));
+            let group = groups.entry(key).or_default();
+            group.0 += c.field("bytes").and_then(|b| b.parse::<u64>().ok()).unwrap_or(0);
+            group.1.push(c.field("bps").and_then(|b| b.parse().ok()).unwrap_or(0.0));
+        }
+        groups
+            .into_iter()
+            .map(|((signer, proto, dir), (bytes, mut rates))| {
+                rates.sort_by(f64::total_cmp);
+                let mean = rates.iter().sum::<f64>() / rates.len() as f64;
+                format!(
+                    "signer={} proto={} dir={} results={} bytes={} mean_bps={:.0} median_bps={:.0} max_bps={:.0}\n",
+                    signer,
+                    proto,
+                    dir,
+                    rates.len(),
+                    bytes,
+                    mean,
+                    rates[rates.len() / 2],
+                    rates[rates.len() - 1]
+                )
+            })
+            .collect()
+    }
+}
+
+/// Whether `c` matches every filter of `query`; `signer` matches a prefix of the key.
+fn matches(c: &Collected, query: &str) -> bool {
+    query.split('&').filter_map(|kv| kv.split_once('=')).all(|(key, value)| match key {
+        "signer" => c.signer.starts_with(value),
+        key => c.field(key) == Some(value),
+    })
+}
+
+pub async fn run(args: impl Iterator<Item = String>) -> anyhow::Result<()> {
+    let options = parse_options(args)?;
+    let store_text = match &options.store {
+        Some(path) if path.exists() => std::fs::read_to_string(path).with_context(|| format!("reading store {}", path.display()))?,
+        _ => String::new(),
+    };
+    let store = match &options.store {
+        Some(path) => Some(Mutex::new(
+            std::fs::OpenOptions::new().create(true).append(true).open(path).with_context(|| format!("opening store {}", path.display()))?,
+        )),
+        None => None,
+    };
+    let collector = Arc::new(Collector { trusted: options.trusted, results: Mutex::new(VecDeque::new()), store });
+    let loaded = collector.load(&store_text);
+    let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, options.port)).await.with_context(|| format!("binding collector port {}", options.port))?;
+    println!("Collector on http://0.0.0.0:{}/ trusting {} server(s), {} result(s) loaded", options.port, collector.trusted.len(), loaded);
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let collector = collector.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle(stream, &collector).await {
+                        eprintln!("Collector client {} error: {}", peer, e);
+                    }
+                });
+            }
+            Err(e) => {
+                eprintln!("Collector accept error: {:?}", e);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+    }
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> anyhow::Result<Options> {
+    let mut options = Options { port: DEFAULT_PORT, trusted: Vec::new(), store: None };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().with_context(|| format!("{} requires a value", arg));
+        match arg.as_str() {
+            "--port" => options.port = value()?.parse().context("invalid --port")?,
+            "--store" => options.store = Some(PathBuf::from(value()?)),
+            "--trust" => {
+                let hex = value()?;
+                let key = from_hex(&hex).and_then(|k| <[u8; 32]>::try_from(k).ok()).and_then(|k| VerifyingKey::from_bytes(&k).ok());
+                options.trusted.push(key.with_context(|| format!("invalid Ed25519 public key {:?} for --trust", hex))?);
+            }
+            _ => anyhow::bail!("unknown collector argument {:?}", arg),
+        }
+    }
+    ensure!(!options.trusted.is_empty(), "the collector needs at least one --trust <public key>");
+    Ok(options)
+}
+
+async fn handle(mut stream: TcpStream, collector: &Collector) -> std::io::Result<()> {
+    let request = tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream)).await.unwrap_or(Ok(None))?;
+    let Some((method, target, body)) = request else {
+        return dashboard::respond(&mut stream, "400 Bad Request", "text/plain", "bad request\n").await;
+    };
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    match (method.as_str(), path) {
+        ("POST", "/results") => match collector.submit(&body) {
+            Ok(()) => dashboard::respond(&mut stream, "200 OK", "text/plain", "stored\n").await,
+            Err(status) => {
+                println!("Collector refused a result: {}", status);
+                dashboard::respond(&mut stream, status, "text/plain", "refused\n").await
+            }
+        },
+        ("GET", "/results") => {
+            let body: String = collector.query(query).iter().map(|line| format!("{}\n", line)).collect();
+            dashboard::respond(&mut stream, "200 OK", "text/plain", &body).await
+        }
+        ("GET", "/summary") => dashboard::respond(&mut stream, "200 OK", "text/plain", &collector.summary(query)).await,
+        _ => dashboard::respond(&mut stream, "404 Not Found", "text/plain", "not found\n").await,
+    }
+}
+
+/// Method, target and body of a request; `None` if it is malformed or too large.
+async fn read_request(stream: &mut TcpStream) -> std::io::Result<Option<(String, String, String)>> {
+    let mut data = Vec::with_capacity(1024);
+    let mut buf = [0u8; 4096];
+    let head_end = loop {
+        if let Some(i) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+            break i + 4;
+        }
+        let n = stream.read(&mut buf).await?;
+        if n == 0 || data.len() + n > MAX_REQUEST {
+            return Ok(None);
+        }
+        data.extend_from_slice(&buf[..n]);
+    };
+    let head = String::from_utf8_lossy(&data[..head_end]).to_string();
+    let length = head
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    if head_end + length > MAX_REQUEST {
+        return Ok(None);
+    }
+    while data.len() < head_end + length {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        data.extend_from_slice(&buf[..n]);
+    }
+    let mut parts = head.lines().next().unwrap_or_default().split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else { return Ok(None) };
+    let body = String::from_utf8_lossy(&data[head_end..head_end + length]).to_string();
+    Ok(Some((method.to_string(), target.to_string(), body)))
+}
+
+/// The fields of a flat JSON object as exported results are written: string
+/// values unescaped, numbers as written.
+fn parse_object(body: &str) -> Option<Vec<(String, String)>> {
+    let mut chars = body.trim().strip_prefix('{')?.strip_suffix('}')?.chars().peekable();
+    let mut fields = Vec::new();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            return Some(fields);
+        }
+        if chars.next()? != '"' {
+            return None;
+        }
+        let key = parse_string(&mut chars)?;
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.next()? != ':' {
+            return None;
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let value = if chars.next_if_eq(&'"').is_some() {
+            parse_string(&mut chars)?
+        } else {
+            let mut number = String::new();
+            while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || "+-.eE".contains(*c)) {
+                number.push(c);
+            }
+            if number.is_empty() {
+                return None;
+            }
+            number
+        };
+        fields.push((key, value));
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        match chars.next() {
+            Some(',') => continue,
+            None => return Some(fields),
+            Some(_) => return None,
+        }
+    }
+}
+
+/// The rest of a JSON string whose opening quote has been read.
+fn parse_string(chars: &mut impl Iterator<Item = char>) -> Option<String> {
+    let mut out = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(out),
+            '\\' => match chars.next()? {
+                'u' => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    out.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+                }
+                'n' => out.push('\n'),
+                't' => out.push('\t'),
+                'r' => out.push('\r'),
+                c => out.push(c),
+            },
+            c => out.push(c),
+        }
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok()).collect()
+}
@@ -0,0 +1,510 @@
+// proj2-serv/src/config.rs
+// Command-line configuration. Flags accept both `--flag value` and `--flag=value`.
+
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+
+use crate::ack::AckPolicy;
+use crate::beacon;
+use crate::export::ExportTarget;
+use crate::flood::SenderMode;
+use crate::impair::Impairment;
+use crate::otel::OtlpEndpoint;
+use crate::hostres;
+use crate::protocol;
+use crate::rxstamp::RxTimestamps;
+use crate::sockopt::{self, Steering};
+
+/// What to do when a protocol plane (TCP or UDP) fails at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlanePolicy {
+    /// Rebind and restart the failed plane with exponential backoff.
+    Restart,
+    /// Leave the failed plane down; the other plane keeps serving.
+    StayDown,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub plane_policy: PlanePolicy,
+    /// TCP ports to try in order; later entries are fallbacks when earlier ones are taken.
+    pub tcp_ports: Vec<u16>,
+    /// UDP ports to try in order, as for `tcp_ports`.
+    pub udp_ports: Vec<u16>,
+    /// Idle time before TCP keepalive probes start; `None` disables keepalive.
+    pub tcp_keepalive: Option<Duration>,
+    /// Close TCP connections that send no command for this long; `None` disables.
+    pub idle_timeout: Option<Duration>,
+    /// How long a `RESUMABLE=1` test waits for its client to come back after
+    /// losing it; `None` disables resumption.
+    pub resume_grace: Option<Duration>,
+    /// Hard limit on byte-count (`BYTES=`) tests that never reach their target.
+    pub max_test_duration: Duration,
+    /// Longest window `EXTEND` may stretch a test to; `None` disables EXTEND.
+    pub max_total_duration: Option<Duration>,
+    /// Downloads and uploads allowed to run at once; 0 is unlimited.
+    pub max_tests: usize,
+    /// Tests that may wait for a slot beyond `max_tests`; 0 refuses them.
+    pub queue: usize,
+    /// How long a queued test waits for a slot.
+    pub queue_timeout: Duration,
+    /// Simulated loss, duplication and jitter on the UDP plane.
+    pub impairment: Impairment,
+    /// Content served for `PAYLOAD=file` downloads.
+    pub payload_file: Option<PathBuf>,
+    /// Pre-shared key that commands starting work must be signed with.
+    pub control_key: Option<PathBuf>,
+    /// Ed25519 seed that recorded results are signed with.
+    pub result_key: Option<PathBuf>,
+    /// MaxMind databases results are enriched from.
+    pub geoip: Vec<PathBuf>,
+    /// Directory for SEND_FILE/RECV_FILE; `None` disables file transfers.
+    pub file_dir: Option<PathBuf>,
+    /// Directory of pcap captures for `REPLAY=` downloads; `None` disables replay.
+    pub replay_dir: Option<PathBuf>,
+    /// Directory for per-packet traces of UDP tests started with `PKT_TRACE=1`;
+    /// `None` disables them.
+    pub packet_trace_dir: Option<PathBuf>,
+    /// Largest file that may be sent or received.
+    pub max_file_size: u64,
+    /// Size of each payload buffer in a TCP download write.
+    pub tcp_write_size: usize,
+    /// Payload buffers handed to one vectored write.
+    pub tcp_write_slices: usize,
+    /// A UDP upload or echo window that has received nothing after this long is
+    /// aborted; `None` waits for its deadline.
+    pub handshake_timeout: Option<Duration>,
+    /// A UDP upload or echo window whose client falls silent for this long is aborted.
+    pub inactivity_timeout: Option<Duration>,
+    /// How UDP test starts are acknowledged.
+    pub udp_acks: AckPolicy,
+    /// Default UDP download sender; clients may override it with `SENDER=`.
+    pub udp_sender: SenderMode,
+    /// Where UDP receive times come from (`--rx-timestamps off|kernel|hardware:<iface>`).
+    pub rx_timestamps: RxTimestamps,
+    /// Received UDP datagrams that may wait for dispatch (`--udp-rx-ring`).
+    pub udp_rx_ring: usize,
+    /// Largest UDP datagram received whole, and the largest `DATAGRAM=` a
+    /// download may ask for (`--udp-max-datagram`).
+    pub udp_max_datagram: usize,
+    /// Serve the stream protocol on this Unix domain socket path (`@name` for a
+    /// Linux abstract socket) as a local baseline.
+    pub uds: Option<String>,
+    /// Pin the TCP, UDP and per-test sockets to this interface (SO_BINDTODEVICE).
+    pub bind_device: Option<String>,
+    /// SO_PRIORITY and SO_MARK for every test socket (`--socket-priority`,
+    /// `--socket-mark`); a session's `PRIORITY=` and `MARK=` take precedence.
+    pub steering: Steering,
+    /// Marks clients may ask for with `MARK=` (`--client-marks`); none by default.
+    pub client_marks: Vec<u32>,
+    /// Serve the web dashboard on this loopback port.
+    pub dashboard_port: Option<u16>,
+    /// Serve `/healthz` and `/readyz` on this port, on all interfaces.
+    pub health_port: Option<u16>,
+    /// Serve the gRPC admin service on this port; loopback only unless
+    /// `grpc_token` is set.
+    pub grpc_port: Option<u16>,
+    /// File holding the bearer token remote gRPC admin calls must present.
+    pub grpc_token: Option<PathBuf>,
+    /// Tokio worker threads; `None` uses one per core.
+    pub worker_threads: Option<usize>,
+    /// CPUs runtime threads are pinned to, round-robin; empty leaves them unpinned.
+    pub cpu_affinity: Vec<usize>,
+    /// Run the UDP plane on a dedicated thread pinned to this CPU.
+    pub udp_cpu: Option<usize>,
+    /// Scheduled client-mode test campaigns to run against peer servers.
+    pub schedule: Option<PathBuf>,
+    /// Sinks every completed result is published to (`--export-results`, repeatable).
+    pub exports: Vec<ExportTarget>,
+    /// OTLP/HTTP collector to send traces and metrics to.
+    pub otlp_endpoint: Option<OtlpEndpoint>,
+    /// Take part in a peer mesh (`--mesh`, implied by `--mesh-peers`).
+    pub mesh: bool,
+    /// Static mesh members as `host:port` TCP control addresses.
+    pub mesh_peers: Vec<String>,
+    /// This instance's name in the mesh; unique across it.
+    pub mesh_name: String,
+    /// Time between measurement rounds against every mesh member.
+    pub mesh_interval: Duration,
+    /// Advertise the server on the LAN via mDNS/DNS-SD.
+    pub mdns: bool,
+    /// mDNS instance name, also used as the advertised `<name>.local` host.
+    pub mdns_name: String,
+    /// Stream sequenced datagrams to this multicast group (`--multicast group:port`).
+    pub multicast: Option<SocketAddrV4>,
+    /// Multicast stream rate in bits per second.
+    pub multicast_rate: u64,
+    /// Hop limit of the multicast stream.
+    pub multicast_ttl: u32,
+    /// Local interface address to send the multicast stream from.
+    pub multicast_if: Option<Ipv4Addr>,
+    /// Announce the server on the LAN with a periodic beacon; `--no-beacon` disables it.
+    pub beacon: bool,
+    /// Broadcast or multicast address the beacon is sent to.
+    pub beacon_addr: SocketAddrV4,
+    /// Time between beacons.
+    pub beacon_interval: Duration,
+    /// Work-starting commands (tests, transfers, probes) accepted per second
+    /// from one peer IP; 0 disables the limit.
+    pub control_rate: u32,
+    /// Such commands a peer may send in a burst above `control_rate`.
+    pub control_burst: u32,
+    /// Drop to this user (name or uid) once the listeners are bound.
+    pub user: Option<String>,
+    /// Group to drop to; defaults to the user's primary group.
+    pub group: Option<String>,
+    /// Apply the Landlock and seccomp sandbox (Linux).
+    pub sandbox: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            plane_policy: PlanePolicy::Restart,
+            tcp_ports: vec![8080],
+            udp_ports: vec![7070],
+            tcp_keepalive: Some(Duration::from_secs(30)),
+            idle_timeout: Some(Duration::from_secs(300)),
+            resume_grace: Some(Duration::from_secs(10)),
+            max_test_duration: Duration::from_secs(60),
+            max_total_duration: Some(Duration::from_secs(300)),
+            max_tests: 0,
+            queue: 0,
+            queue_timeout: Duration::from_secs(120),
+            impairment: Impairment::default(),
+            payload_file: None,
+            control_key: None,
+            result_key: None,
+            geoip: Vec::new(),
+            file_dir: None,
+            replay_dir: None,
+            packet_trace_dir: None,
+            max_file_size: 1 << 30,
+            tcp_write_size: 64 * 1024,
+            tcp_write_slices: 4,
+            handshake_timeout: Some(Duration::from_secs(2)),
+            inactivity_timeout: Some(Duration::from_secs(5)),
+            udp_acks: AckPolicy::default(),
+            udp_sender: SenderMode::Async,
+            rx_timestamps: RxTimestamps::default(),
+            udp_rx_ring: 8192,
+            udp_max_datagram: protocol::MAX_UDP_PAYLOAD,
+            uds: None,
+            bind_device: None,
+            steering: Steering::default(),
+            client_marks: Vec::new(),
+            dashboard_port: None,
+            health_port: None,
+            grpc_port: None,
+            grpc_token: None,
+            worker_threads: None,
+            cpu_affinity: Vec::new(),
+            udp_cpu: None,
+            schedule: None,
+            mesh: false,
+            mesh_peers: Vec::new(),
+            exports: Vec::new(),
+            otlp_endpoint: None,
+            mesh_name: hostres::hostname(),
+            mesh_interval: Duration::from_secs(60),
+            mdns: false,
+            mdns_name: hostres::hostname(),
+            multicast: None,
+            multicast_rate: 10_000_000,
+            multicast_ttl: 1,
+            multicast_if: None,
+            beacon: true,
+            beacon_addr: beacon::DEFAULT_ADDR,
+            beacon_interval: Duration::from_secs(5),
+            control_rate: 10,
+            control_burst: 32,
+            user: None,
+            group: None,
+            sandbox: false,
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> anyhow::Result<Config> {
+        let mut cfg = Config::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((f, v)) => (f.to_string(), Some(v.to_string())),
+                None => (arg.clone(), None),
+            };
+            let mut value = || -> anyhow::Result<String> {
+                match inline.clone() {
+                    Some(v) => Ok(v),
+                    None => args.next().with_context(|| format!("{} requires a value", flag)),
+                }
+            };
+            match flag.as_str() {
+                "--plane-policy" => {
+                    cfg.plane_policy = match value()?.as_str() {
+                        "restart" => PlanePolicy::Restart,
+                        "down" => PlanePolicy::StayDown,
+                        other => bail!("invalid --plane-policy {:?} (expected restart|down)", other),
+                    }
+                }
+                "--tcp-ports" => cfg.tcp_ports = parse_ports(&flag, &value()?)?,
+                "--udp-ports" => cfg.udp_ports = parse_ports(&flag, &value()?)?,
+                "--tcp-keepalive" => cfg.tcp_keepalive = parse_secs(&flag, &value()?)?,
+                "--idle-timeout" => cfg.idle_timeout = parse_secs(&flag, &value()?)?,
+                "--resume-grace" => cfg.resume_grace = parse_secs(&flag, &value()?)?,
+                "--max-test-duration" => {
+                    cfg.max_test_duration =
+                        parse_secs(&flag, &value()?)?.with_context(|| format!("{} must be positive", flag))?
+                }
+                "--max-total-duration" => cfg.max_total_duration = parse_secs(&flag, &value()?)?,
+                "--max-tests" => cfg.max_tests = parse_count(&flag, &value()?, 0..=100_000)?,
+                "--queue" => cfg.queue = parse_count(&flag, &value()?, 0..=100_000)?,
+                "--queue-timeout" => {
+                    cfg.queue_timeout =
+                        parse_secs(&flag, &value()?)?.with_context(|| format!("{} must be positive", flag))?
+                }
+                "--payload-file" => cfg.payload_file = Some(PathBuf::from(value()?)),
+                "--control-key" => cfg.control_key = Some(PathBuf::from(value()?)),
+                "--result-key" => cfg.result_key = Some(PathBuf::from(value()?)),
+                "--geoip" => cfg.geoip.push(PathBuf::from(value()?)),
+                "--file-dir" => cfg.file_dir = Some(PathBuf::from(value()?)),
+                "--replay-dir" => cfg.replay_dir = Some(PathBuf::from(value()?)),
+                "--packet-trace-dir" => cfg.packet_trace_dir = Some(PathBuf::from(value()?)),
+                "--max-file-size" => {
+                    let size = value()?;
+                    cfg.max_file_size = protocol::parse_byte_count(&size)
+                        .with_context(|| format!("invalid size {:?} for {} (e.g. 512M)", size, flag))?;
+                }
+                "--tcp-write-size" => {
+                    let size = value()?;
+                    cfg.tcp_write_size = protocol::parse_byte_count(&size)
+                        .filter(|&n| n <= 16 << 20)
+                        .with_context(|| format!("invalid size {:?} for {} (up to 16M)", size, flag))?
+                        as usize;
+                }
+                "--tcp-write-slices" => {
+                    let n = value()?;
+                    cfg.tcp_write_slices = n
+                        .parse()
+                        .ok()
+                        .filter(|n| (1..=64).contains(n))
+                        .with_context(|| format!("invalid count {:?} for {} (1-64)", n, flag))?;
+                }
+                "--handshake-timeout" => cfg.handshake_timeout = parse_secs(&flag, &value()?)?,
+                "--inactivity-timeout" => cfg.inactivity_timeout = parse_secs(&flag, &value()?)?,
+                "--udp-acks" => cfg.udp_acks.count = parse_count(&flag, &value()?, 1..=20)?,
+                "--udp-ack-interval" => cfg.udp_acks.interval = parse_millis(&flag, &value()?)?,
+                "--udp-ack-retries" => cfg.udp_acks.retries = parse_count(&flag, &value()?, 0..=20)?,
+                "--udp-handshake" => cfg.udp_acks.handshake = true,
+                "--no-udp-validation" => cfg.udp_acks.validate = false,
+                "--udp-sender" => {
+                    let mode = value()?;
+                    cfg.udp_sender =
+                        SenderMode::parse(&mode).with_context(|| format!("invalid sender {:?} for {} (expected async|thread)", mode, flag))?;
+                }
+                "--rx-timestamps" => {
+                    let mode = value()?;
+                    cfg.rx_timestamps = RxTimestamps::parse(&mode)
+                        .with_context(|| format!("invalid mode {:?} for {} (expected off|kernel|hardware:<iface>)", mode, flag))?;
+                }
+                "--udp-rx-ring" => cfg.udp_rx_ring = parse_count(&flag, &value()?, 16..=1_048_576)?,
+                "--udp-max-datagram" => cfg.udp_max_datagram = parse_count(&flag, &value()?, 576..=protocol::MAX_UDP_PAYLOAD)?,
+                "--uds" => cfg.uds = Some(value()?),
+                "--bind-device" => cfg.bind_device = Some(value()?),
+                "--socket-priority" => {
+                    let priority = value()?;
+                    cfg.steering.priority = Some(priority.parse().with_context(|| format!("invalid priority {:?} for {}", priority, flag))?);
+                }
+                "--socket-mark" => {
+                    let mark = value()?;
+                    cfg.steering.mark = Some(sockopt::parse_mark(&mark).with_context(|| format!("invalid mark {:?} for {}", mark, flag))?);
+                }
+                "--client-marks" => {
+                    cfg.client_marks = value()?
+                        .split(',')
+                        .map(|m| sockopt::parse_mark(m.trim()).with_context(|| format!("invalid mark {:?} in {}", m, flag)))
+                        .collect::<anyhow::Result<_>>()?;
+                }
+                "--dashboard-port" => {
+                    let port = value()?;
+                    cfg.dashboard_port = Some(port.parse().with_context(|| format!("invalid port {:?} for {}", port, flag))?);
+                }
+                "--health-port" => {
+                    let port = value()?;
+                    cfg.health_port = Some(port.parse().with_context(|| format!("invalid port {:?} for {}", port, flag))?);
+                }
+                "--grpc-port" => {
+                    let port = value()?;
+                    cfg.grpc_port = Some(port.parse().with_context(|| format!("invalid port {:?} for {}", port, flag))?);
+                }
+                "--grpc-token" => cfg.grpc_token = Some(PathBuf::from(value()?)),
+                "--worker-threads" => {
+                    let n = value()?;
+                    cfg.worker_threads = Some(
+                        n.parse().ok().filter(|&n| n > 0).with_context(|| format!("invalid count {:?} for {}", n, flag))?,
+                    );
+                }
+                "--cpu-affinity" => cfg.cpu_affinity = parse_cpu_list(&flag, &value()?)?,
+                "--udp-cpu" => {
+                    let cpu = value()?;
+                    cfg.udp_cpu = Some(cpu.parse().with_context(|| format!("invalid CPU {:?} for {}", cpu, flag))?);
+                }
+                "--schedule" => cfg.schedule = Some(PathBuf::from(value()?)),
+                "--export-results" => {
+                    let url = value()?;
+                    let target = ExportTarget::parse(&url).with_context(|| {
+                        format!("invalid sink {:?} for {} (expected http://host[:port]/path, kafka://host[:port]/topic or mqtt://host[:port]/topic)", url, flag)
+                    })?;
+                    cfg.exports.push(target);
+                }
+                "--otlp-endpoint" => {
+                    let url = value()?;
+                    let endpoint = OtlpEndpoint::parse(&url)
+                        .with_context(|| format!("invalid endpoint {:?} for {} (expected http://host[:port][/path])", url, flag))?;
+                    cfg.otlp_endpoint = Some(endpoint);
+                }
+                "--mesh" => cfg.mesh = true,
+                "--mesh-peers" => {
+                    cfg.mesh = true;
+                    cfg.mesh_peers = parse_peers(&flag, &value()?)?;
+                }
+                "--mesh-name" => {
+                    let name = value()?;
+                    if !protocol::valid_tenant(&name) {
+                        bail!("invalid name {:?} for {} (letters, digits, - and _, up to 32)", name, flag);
+                    }
+                    cfg.mesh_name = name;
+                }
+                "--mesh-interval" => {
+                    cfg.mesh_interval =
+                        parse_secs(&flag, &value()?)?.with_context(|| format!("{} must be positive", flag))?
+                }
+                "--mdns" => cfg.mdns = true,
+                "--mdns-name" => {
+                    let name = value()?;
+                    if !protocol::valid_tenant(&name) {
+                        bail!("invalid name {:?} for {} (letters, digits, - and _, up to 32)", name, flag);
+                    }
+                    cfg.mdns_name = name;
+                }
+                "--multicast" => {
+                    let group = value()?;
+                    cfg.multicast = Some(
+                        group.parse().ok().filter(|g: &SocketAddrV4| g.ip().is_multicast()).with_context(|| {
+                            format!("invalid group {:?} for {} (expected an IPv4 multicast group:port)", group, flag)
+                        })?,
+                    );
+                }
+                "--multicast-rate" => {
+                    let rate = value()?;
+                    cfg.multicast_rate = protocol::parse_byte_count(&rate).with_context(|| format!("invalid rate {:?} for {}", rate, flag))?;
+                }
+                "--multicast-ttl" => cfg.multicast_ttl = parse_count(&flag, &value()?, 1..=255)? as u32,
+                "--multicast-if" => {
+                    let iface = value()?;
+                    cfg.multicast_if = Some(iface.parse().with_context(|| format!("invalid address {:?} for {}", iface, flag))?);
+                }
+                "--no-beacon" => cfg.beacon = false,
+                "--beacon-addr" => {
+                    let addr = value()?;
+                    cfg.beacon_addr = addr.parse().with_context(|| format!("invalid address {:?} for {} (expected ip:port)", addr, flag))?;
+                }
+                "--beacon-interval" => {
+                    cfg.beacon_interval =
+                        parse_secs(&flag, &value()?)?.with_context(|| format!("{} must be positive", flag))?
+                }
+                "--control-rate" => cfg.control_rate = parse_count(&flag, &value()?, 0..=100_000)? as u32,
+                "--control-burst" => cfg.control_burst = parse_count(&flag, &value()?, 1..=100_000)? as u32,
+                "--user" => cfg.user = Some(value()?),
+                "--group" => cfg.group = Some(value()?),
+                "--sandbox" => cfg.sandbox = true,
+                "--impair-drop" => cfg.impairment.drop = parse_percent(&flag, &value()?)?,
+                "--impair-dup" => cfg.impairment.duplicate = parse_percent(&flag, &value()?)?,
+                "--impair-jitter" => cfg.impairment.jitter = parse_millis(&flag, &value()?)?,
+                _ => bail!("unknown argument {:?}", arg),
+            }
+        }
+        if cfg.group.is_some() && cfg.user.is_none() {
+            bail!("--group requires --user");
+        }
+        if cfg.grpc_token.is_some() && cfg.grpc_port.is_none() {
+            bail!("--grpc-token requires --grpc-port");
+        }
+        Ok(cfg)
+    }
+}
+
+/// Parse a comma-separated port list such as `8080,8081`.
+fn parse_ports(flag: &str, list: &str) -> anyhow::Result<Vec<u16>> {
+    let ports = list
+        .split(',')
+        .map(|p| p.trim().parse::<u16>().with_context(|| format!("invalid port {:?} in {}", p, flag)))
+        .collect::<anyhow::Result<Vec<u16>>>()?;
+    if ports.is_empty() {
+        bail!("{} requires at least one port", flag);
+    }
+    Ok(ports)
+}
+
+/// Parse a comma-separated list of `host:port` addresses.
+fn parse_peers(flag: &str, list: &str) -> anyhow::Result<Vec<String>> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|p| match p.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => Ok(p.to_string()),
+            _ => bail!("invalid peer {:?} in {} (expected host:port)", p, flag),
+        })
+        .collect()
+}
+
+/// Parse a CPU list such as `0-3,8,10-11`.
+fn parse_cpu_list(flag: &str, list: &str) -> anyhow::Result<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for part in list.split(',').map(str::trim) {
+        let parse = |s: &str| s.parse::<usize>().with_context(|| format!("invalid CPU {:?} in {}", s, flag));
+        match part.split_once('-') {
+            Some((lo, hi)) => {
+                let (lo, hi) = (parse(lo)?, parse(hi)?);
+                if lo > hi {
+                    bail!("invalid CPU range {:?} in {}", part, flag);
+                }
+                cpus.extend(lo..=hi);
+            }
+            None => cpus.push(parse(part)?),
+        }
+    }
+    Ok(cpus)
+}
+
+/// Parse a percentage (0-100, fractions allowed) into a probability.
+fn parse_percent(flag: &str, value: &str) -> anyhow::Result<f64> {
+    match value.parse::<f64>() {
+        Ok(pct) if (0.0..=100.0).contains(&pct) => Ok(pct / 100.0),
+        _ => bail!("invalid percentage {:?} for {} (expected 0-100)", value, flag),
+    }
+}
+
+/// Parse a count within `range`.
+fn parse_count(flag: &str, value: &str, range: std::ops::RangeInclusive<usize>) -> anyhow::Result<usize> {
+    match value.parse::<usize>() {
+        Ok(n) if range.contains(&n) => Ok(n),
+        _ => bail!("invalid count {:?} for {} ({}-{})", value, flag, range.start(), range.end()),
+    }
+}
+
+/// Parse a duration in whole milliseconds.
+fn parse_millis(flag: &str, value: &str) -> anyhow::Result<Duration> {
+    let ms: u64 = value.parse().with_context(|| format!("invalid milliseconds {:?} for {}", value, flag))?;
+    Ok(Duration::from_millis(ms))
+}
+
+/// Parse a duration in whole seconds; `0` means disabled.
+fn parse_secs(flag: &str, value: &str) -> anyhow::Result<Option<Duration>> {
+    let secs: u64 = value.parse().with_context(|| format!("invalid seconds {:?} for {}", value, flag))?;
+    Ok((secs > 0).then(|| Duration::from_secs(secs)))
+}
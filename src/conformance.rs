@@ -0,0 +1,683 @@
+// proj2-serv/src/conformance.rs
+// `proj2-serv conformance [--host H] [--tcp-port P] [--udp-port P]`
+// Drives a target server (this crate or another implementation of the protocol)
+// through every command and error path, then prints a pass/fail matrix. A check
+// of a feature the target has turned off (file transfers, resumption, EXTEND)
+// passes on `ERR DISABLED`, and the ADMIN one on the refusal a non-loopback
+// client gets. The `BYTES=` downloads of both planes are grouped under one
+// run of the `conformance` tenant, which the RUN and COMPARE checks read back.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, ensure, Context};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{lookup_host, TcpStream, UdpSocket};
+use tokio::time::timeout;
+
+use crate::owd;
+
+/// Server-side test window; transfers must stop shortly after it.
+const WINDOW: Duration = Duration::from_secs(5);
+const GRACE: Duration = Duration::from_secs(2);
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+/// Tenant of the tests the run checks group.
+const TENANT: &str = "conformance";
+/// Sizes of the `BYTES=` downloads.
+const TCP_BYTES: u64 = 1 << 20;
+const UDP_BYTES: u64 = 256 << 10;
+/// Round trips of the echo checks, and probes of the OWD check.
+const ECHOES: u64 = 10;
+const OWD_PROBES: u64 = 10;
+
+struct Target {
+    tcp: SocketAddr,
+    udp: SocketAddr,
+}
+
+struct Outcome {
+    name: &'static str,
+    elapsed: Duration,
+    result: anyhow::Result<String>,
+}
+
+pub async fn run(args: impl Iterator<Item = String>) -> anyhow::Result<()> {
+    let target = parse_target(args).await?;
+    println!("conformance: TCP {} / UDP {}", target.tcp, target.udp);
+
+    let mut outcomes = Vec::new();
+    let run = format!("conformance-{}", std::process::id());
+    outcomes.push(check("tcp.connect", tcp_connect(&target)).await);
+    outcomes.push(check("tcp.caps", tcp_caps(&target)).await);
+    outcomes.push(check("tcp.hello", tcp_hello(&target)).await);
+    outcomes.push(check("tcp.whoami", tcp_whoami(&target)).await);
+    outcomes.push(check("tcp.unknown_command", tcp_unknown_command(&target)).await);
+    outcomes.push(check("tcp.invalid_option", tcp_invalid_option(&target)).await);
+    outcomes.push(check("tcp.disabled", tcp_disabled(&target)).await);
+    outcomes.push(check("tcp.download", tcp_download(&target)).await);
+    outcomes.push(check("tcp.upload", tcp_upload(&target)).await);
+    outcomes.push(check("tcp.bytes", tcp_bytes(&target, &run)).await);
+    outcomes.push(check("tcp.echo", tcp_echo(&target)).await);
+    outcomes.push(check("tcp.resume", tcp_resume(&target)).await);
+    outcomes.push(check("tcp.cca_invalid", tcp_cca_invalid(&target)).await);
+    outcomes.push(check("tcp.end_download", tcp_end_download(&target)).await);
+    outcomes.push(check("tcp.admin", tcp_admin(&target)).await);
+    outcomes.push(check("udp.caps", udp_caps(&target)).await);
+    outcomes.push(check("udp.hello", udp_hello(&target)).await);
+    outcomes.push(check("udp.whoami", udp_whoami(&target)).await);
+    outcomes.push(check("udp.unknown_command", udp_unknown_command(&target)).await);
+    outcomes.push(check("udp.invalid_option", udp_invalid_option(&target)).await);
+    outcomes.push(check("udp.download", udp_download(&target)).await);
+    outcomes.push(check("udp.upload", udp_upload(&target)).await);
+    outcomes.push(check("udp.bytes", udp_bytes(&target, &run)).await);
+    outcomes.push(check("udp.echo", udp_echo(&target)).await);
+    outcomes.push(check("udp.owd", udp_owd(&target)).await);
+    outcomes.push(check("udp.capacity_probe", udp_capacity_probe(&target)).await);
+    outcomes.push(check("udp.extend", udp_extend(&target)).await);
+    outcomes.push(check("udp.end_download", udp_end_download(&target)).await);
+    outcomes.push(check("udp.end_upload", udp_end_upload(&target)).await);
+    outcomes.push(check("run.summary", run_summary(&target, &run)).await);
+    outcomes.push(check("run.compare", run_compare(&target, &run)).await);
+    println!();
+    println!("{:<24} {:<6} {:>8}  detail", "check", "result", "time");
+    let mut failed = 0;
+    for o in &outcomes {
+        let (status, detail) = match &o.result {
+            Ok(d) => ("PASS", d.clone()),
+            Err(e) => {
+                failed += 1;
+                ("FAIL", format!("{:#}", e))
+            }
+        };
+        println!("{:<24} {:<6} {:>6}ms  {}", o.name, status, o.elapsed.as_millis(), detail);
+    }
+    println!();
+    println!("{} passed, {} failed", outcomes.len() - failed, failed);
+    if failed > 0 {
+        bail!("{} conformance checks failed", failed);
+    }
+    Ok(())
+}
+
+async fn parse_target(mut args: impl Iterator<Item = String>) -> anyhow::Result<Target> {
+    let mut host = "127.0.0.1".to_string();
+    let mut tcp_port = 8080u16;
+    let mut udp_port = 7070u16;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().with_context(|| format!("{} requires a value", arg));
+        match arg.as_str() {
+            "--host" => host = value()?,
+            "--tcp-port" => tcp_port = value()?.parse().context("invalid --tcp-port")?,
+            "--udp-port" => udp_port = value()?.parse().context("invalid --udp-port")?,
+            _ => bail!("unknown conformance argument {:?}", arg),
+        }
+    }
+    let resolve = |port: u16| {
+        let host = host.clone();
+        async move {
+            lookup_host((host.as_str(), port))
+                .await
+                .with_context(|| format!("resolving {}", host))?
+                .next()
+                .with_context(|| format!("{} has no addresses", host))
+        }
+    };
+    Ok(Target { tcp: resolve(tcp_port).await?, udp: resolve(udp_port).await? })
+}
+
+async fn check(name: &'static str, fut: impl Future<Output = anyhow::Result<String>>) -> Outcome {
+    let start = Instant::now();
+    let result = fut.await;
+    let elapsed = start.elapsed();
+    println!("{:<24} {}", name, if result.is_ok() { "ok" } else { "FAILED" });
+    Outcome { name, elapsed, result }
+}
+
+async fn connect(target: &Target) -> anyhow::Result<TcpStream> {
+    timeout(REPLY_TIMEOUT, TcpStream::connect(target.tcp))
+        .await
+        .context("connect timed out")?
+        .context("connect failed")
+}
+
+/// Send a command and read one reply chunk.
+async fn tcp_request(stream: &mut TcpStream, cmd: &str) -> anyhow::Result<String> {
+    stream.write_all(cmd.as_bytes()).await?;
+    let mut buf = vec![0u8; 4096];
+    let n = timeout(REPLY_TIMEOUT, stream.read(&mut buf))
+        .await
+        .with_context(|| format!("no reply to {:?}", cmd))??;
+    ensure!(n > 0, "connection closed after {:?}", cmd);
+    Ok(String::from_utf8_lossy(&buf[..n]).trim().to_string())
+}
+
+/// Read one line, such as a reply that goes ahead of a test's data.
+async fn tcp_line(stream: &mut TcpStream) -> anyhow::Result<String> {
+    let mut line = Vec::new();
+    loop {
+        let byte = timeout(REPLY_TIMEOUT, stream.read_u8()).await.context("no reply line")??;
+        if byte == b'\n' {
+            return Ok(String::from_utf8_lossy(&line).trim().to_string());
+        }
+        line.push(byte);
+        ensure!(line.len() <= 4096, "reply line longer than 4096 bytes");
+    }
+}
+
+/// The value of `key=` in a reply.
+fn field<'a>(reply: &'a str, key: &str) -> Option<&'a str> {
+    reply.split_whitespace().find_map(|kv| kv.strip_prefix(key)?.strip_prefix('='))
+}
+
+fn check_hello(reply: &str) -> anyhow::Result<()> {
+    ensure!(reply.starts_with("HELLO "), "expected HELLO, got {:?}", reply);
+    for key in ["version", "features", "max_duration", "max_datagram"] {
+        ensure!(field(reply, key).is_some(), "HELLO lacks {}=: {:?}", key, reply);
+    }
+    Ok(())
+}
+
+fn check_whoami(reply: &str) -> anyhow::Result<()> {
+    let addr = reply.strip_prefix("YOUARE ").and_then(|a| a.parse::<SocketAddr>().ok());
+    ensure!(addr.is_some(), "expected YOUARE <address>, got {:?}", reply);
+    Ok(())
+}
+
+async fn tcp_connect(target: &Target) -> anyhow::Result<String> {
+    connect(target).await?;
+    Ok("connected".into())
+}
+
+async fn tcp_caps(target: &Target) -> anyhow::Result<String> {
+    let mut stream = connect(target).await?;
+    let reply = tcp_request(&mut stream, "CAPS").await?;
+    ensure!(reply.starts_with("CAPS"), "unexpected reply {:?}", reply);
+    Ok(reply)
+}
+
+async fn tcp_unknown_command(target: &Target) -> anyhow::Result<String> {
+    let mut stream = connect(target).await?;
+    let reply = tcp_request(&mut stream, "NOT_A_COMMAND").await?;
+    ensure!(reply.starts_with("ERR BAD_COMMAND"), "expected ERR BAD_COMMAND, got {:?}", reply);
+    // The connection must stay usable after an unknown command.
+    let reply = tcp_request(&mut stream, "CAPS").await?;
+    ensure!(reply.contains("CAPS"), "connection unusable after unknown command: {:?}", reply);
+    Ok("ERR BAD_COMMAND, connection survives".into())
+}
+
+/// Read until the server stops sending; returns (bytes, time of last data).
+async fn drain(stream: &mut TcpStream, start: Instant) -> anyhow::Result<(u64, Duration)> {
+    let mut buf = vec![0u8; 256 * 1024];
+    let mut total = 0u64;
+    let mut last = Duration::ZERO;
+    loop {
+        match timeout(Duration::from_millis(500), stream.read(&mut buf)).await {
+            Ok(Ok(0)) | Err(_) => return Ok((total, last)),
+            Ok(Ok(n)) => {
+                total += n as u64;
+                last = start.elapsed();
+                ensure!(last < WINDOW + GRACE, "server still sending after {:?}", last);
+            }
+            Ok(Err(e)) => return Err(e.into()),
+        }
+    }
+}
+
+async fn tcp_download(target: &Target) -> anyhow::Result<String> {
+    let mut stream = connect(target).await?;
+    let start = Instant::now();
+    stream.write_all(b"START_DOWNLOAD").await?;
+    let (bytes, last) = drain(&mut stream, start).await?;
+    ensure!(bytes > 0, "no download data received");
+    ensure!(last + GRACE >= WINDOW, "download stopped early at {:?}", last);
+    Ok(format!("{} bytes in {:?}", bytes, last))
+}
+
+async fn tcp_upload(target: &Target) -> anyhow::Result<String> {
+    let mut stream = connect(target).await?;
+    stream.write_all(b"START_UPLOAD").await?;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let chunk = vec![0u8; 64 * 1024];
+    let start = Instant::now();
+    let mut sent = 0u64;
+    // The server must keep accepting data for the whole window.
+    while start.elapsed() < WINDOW {
+        stream.write_all(&chunk).await.context("upload write failed")?;
+        sent += chunk.len() as u64;
+    }
+    Ok(format!("{} bytes sent", sent))
+}
+
+async fn tcp_hello(target: &Target) -> anyhow::Result<String> {
+    let mut stream = connect(target).await?;
+    let reply = tcp_request(&mut stream, "HELLO VERSION=1 AGENT=proj2-conformance").await?;
+    check_hello(&reply)?;
+    Ok(reply)
+}
+
+async fn tcp_whoami(target: &Target) -> anyhow::Result<String> {
+    let mut stream = connect(target).await?;
+    let reply = tcp_request(&mut stream, "WHOAMI").await?;
+    check_whoami(&reply)?;
+    Ok(reply)
+}
+
+async fn tcp_invalid_option(target: &Target) -> anyhow::Result<String> {
+    let mut stream = connect(target).await?;
+    // The download still runs, without a byte target; dropping the
+    // connection ends it.
+    stream.write_all(b"START_DOWNLOAD BYTES=lots").await?;
+    let reply = tcp_line(&mut stream).await?;
+    ensure!(reply.starts_with("ERR INVALID_OPTION"), "expected ERR INVALID_OPTION, got {:?}", reply.chars().take(40).collect::<String>());
+    Ok(reply)
+}
+
+async fn tcp_disabled(target: &Target) -> anyhow::Result<String> {
+    let mut stream = connect(target).await?;
+    let reply = tcp_request(&mut stream, "RECV_FILE conformance-missing").await?;
+    match reply.strip_prefix("ERR ").and_then(|r| r.split_whitespace().next()) {
+        Some("DISABLED") => Ok(reply),
+        // File transfers are on; the file is missing instead.
+        Some("NOT_FOUND") => Ok(format!("file transfers enabled: {}", reply)),
+        _ => bail!("expected ERR DISABLED, got {:?}", reply),
+    }
+}
+
+async fn tcp_bytes(target: &Target, run: &str) -> anyhow::Result<String> {
+    let mut stream = connect(target).await?;
+    let start = Instant::now();
+    stream.write_all(format!("START_DOWNLOAD BYTES={} TENANT={} RUN={}", TCP_BYTES, TENANT, run).as_bytes()).await?;
+    let (bytes, last) = drain(&mut stream, start).await?;
+    ensure!(bytes == TCP_BYTES, "asked for {} bytes, got {}", TCP_BYTES, bytes);
+    // The connection takes commands again once the target is reached.
+    let reply = tcp_request(&mut stream, "CAPS").await?;
+    ensure!(reply.starts_with("CAPS"), "connection unusable after the download: {:?}", reply);
+    Ok(format!("{} bytes in {:?}", bytes, last))
+}
+
+async fn tcp_echo(target: &Target) -> anyhow::Result<String> {
+    let mut stream = connect(target).await?;
+    stream.write_all(b"START_ECHO SIZE=64").await?;
+    let reply = tcp_line(&mut stream).await?;
+    ensure!(reply == "OK ECHO", "expected OK ECHO, got {:?}", reply);
+    let message: Vec<u8> = (0..64).collect();
+    let mut echo = vec![0u8; message.len()];
+    for seq in 0..ECHOES {
+        stream.write_all(&message).await?;
+        timeout(REPLY_TIMEOUT, stream.read_exact(&mut echo)).await.with_context(|| format!("no echo {}", seq))??;
+        ensure!(echo == message, "echo {} differs from the message", seq);
+    }
+    stream.shutdown().await?;
+    Ok(format!("{} echoes of {} bytes", ECHOES, message.len()))
+}
+
+async fn tcp_resume(target: &Target) -> anyhow::Result<String> {
+    let mut first = connect(target).await?;
+    first.write_all(b"START_DOWNLOAD RESUMABLE=1").await?;
+    let ticket = tcp_line(&mut first).await?;
+    if ticket.starts_with("ERR DISABLED") {
+        return Ok(format!("resumption disabled: {}", ticket));
+    }
+    ensure!(ticket.starts_with("RESUMABLE "), "expected a RESUMABLE ticket, got {:?}", ticket);
+    let (Some(session), Some(token)) = (field(&ticket, "SESSION"), field(&ticket, "TOKEN")) else {
+        bail!("malformed ticket {:?}", ticket);
+    };
+    let mut buf = vec![0u8; 64 * 1024];
+    let n = timeout(REPLY_TIMEOUT, first.read(&mut buf)).await.context("no data after the ticket")??;
+    ensure!(n > 0, "connection closed after the ticket");
+    // Carry on over a second connection, as a client that changed networks.
+    let mut second = connect(target).await?;
+    second.write_all(format!("RESUME SESSION={} TOKEN={}", session, token).as_bytes()).await?;
+    let reply = tcp_line(&mut second).await?;
+    ensure!(reply.starts_with(&format!("RESUMED SESSION={} ", session)) && field(&reply, "BYTES").is_some(), "expected RESUMED, got {:?}", reply);
+    let n = timeout(REPLY_TIMEOUT, second.read(&mut buf)).await.context("no data after RESUMED")??;
+    ensure!(n > 0, "connection closed after RESUMED");
+    // Read to the close, so the test ends here rather than waiting to resume.
+    second.write_all(b"END_DOWNLOAD").await?;
+    drain(&mut second, Instant::now()).await?;
+    Ok(reply)
+}
+
+async fn tcp_cca_invalid(target: &Target) -> anyhow::Result<String> {
+    let mut stream = connect(target).await?;
+    let reply = tcp_request(&mut stream, "START_DOWNLOAD CCA=__no_such_cca__").await?;
+    ensure!(reply.starts_with("ERR"), "expected ERR frame, got {:?}", reply.chars().take(40).collect::<String>());
+    Ok(reply.lines().next().unwrap_or_default().to_string())
+}
+
+async fn tcp_end_download(target: &Target) -> anyhow::Result<String> {
+    let mut stream = connect(target).await?;
+    let start = Instant::now();
+    stream.write_all(b"START_DOWNLOAD").await?;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    stream.write_all(b"END_DOWNLOAD").await?;
+    let (_, last) = drain(&mut stream, start).await?;
+    ensure!(last < WINDOW - Duration::from_secs(1), "download continued until {:?}", last);
+    Ok(format!("stopped after {:?}", last))
+}
+
+/// Send an ADMIN query and read its reply, several lines up to `END`.
+async fn tcp_admin_request(stream: &mut TcpStream, cmd: &str) -> anyhow::Result<String> {
+    stream.write_all(cmd.as_bytes()).await?;
+    let mut reply = String::new();
+    loop {
+        let line = tcp_line(stream).await?;
+        let done = line == "END" || (reply.is_empty() && line.starts_with("ERR "));
+        reply.push_str(&line);
+        reply.push('\n');
+        if done {
+            return Ok(reply);
+        }
+    }
+}
+
+async fn tcp_admin(target: &Target) -> anyhow::Result<String> {
+    let mut stream = connect(target).await?;
+    let reply = tcp_admin_request(&mut stream, &format!("ADMIN RESULTS TENANT={}", TENANT)).await?;
+    if reply.starts_with("ERR UNAUTHORIZED") {
+        return Ok(format!("refused for this client: {}", reply.trim()));
+    }
+    ensure!(reply.ends_with("END\n"), "expected result lines and END, got {:?}", reply);
+    let bad = tcp_admin_request(&mut stream, "ADMIN RESULTS FORMAT=__no_such_format__").await?;
+    ensure!(bad.starts_with("ERR INVALID_OPTION"), "expected ERR INVALID_OPTION, got {:?}", bad);
+    let unknown = tcp_admin_request(&mut stream, "ADMIN __NO_SUCH_QUERY__").await?;
+    ensure!(unknown.starts_with("ERR BAD_COMMAND"), "expected ERR BAD_COMMAND, got {:?}", unknown);
+    Ok(format!("{} result lines, errors for a bad format and query", reply.lines().count() - 1))
+}
+
+async fn udp_socket(target: &Target) -> anyhow::Result<UdpSocket> {
+    let bind: SocketAddr = if target.udp.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }.parse()?;
+    let sock = UdpSocket::bind(bind).await?;
+    sock.connect(target.udp).await?;
+    Ok(sock)
+}
+
+async fn udp_recv(sock: &UdpSocket, buf: &mut [u8]) -> anyhow::Result<usize> {
+    Ok(timeout(REPLY_TIMEOUT, sock.recv(buf)).await.context("no UDP reply")??)
+}
+
+async fn udp_caps(target: &Target) -> anyhow::Result<String> {
+    let sock = udp_socket(target).await?;
+    sock.send(b"CAPS").await?;
+    let mut buf = vec![0u8; 2048];
+    let n = udp_recv(&sock, &mut buf).await?;
+    let reply = String::from_utf8_lossy(&buf[..n]).to_string();
+    ensure!(reply.starts_with("CAPS"), "unexpected reply {:?}", reply);
+    Ok(reply)
+}
+
+async fn udp_unknown_command(target: &Target) -> anyhow::Result<String> {
+    let sock = udp_socket(target).await?;
+    sock.send(b"NOT_A_COMMAND").await?;
+    let mut buf = vec![0u8; 2048];
+    let n = udp_recv(&sock, &mut buf).await?;
+    let reply = String::from_utf8_lossy(&buf[..n]).to_string();
+    ensure!(reply.starts_with("ERR BAD_COMMAND"), "expected ERR BAD_COMMAND, got {:?}", reply);
+    Ok(reply)
+}
+
+async fn udp_download(target: &Target) -> anyhow::Result<String> {
+    let (bytes, sent, last) = udp_download_with(target, "START_DOWNLOAD").await?;
+    Ok(format!("{} of {} bytes in {:?}", bytes, sent, last))
+}
+
+/// A UDP download started with `command`: the bytes received, the bytes its
+/// FIN says were sent, and when the last data arrived.
+async fn udp_download_with(target: &Target, command: &str) -> anyhow::Result<(u64, u64, Duration)> {
+    let sock = udp_socket(target).await?;
+    let start = Instant::now();
+    sock.send(command.as_bytes()).await?;
+    let mut buf = vec![0u8; 64 * 1024];
+    udp_confirm_download(&sock, &mut buf).await?;
+    let mut bytes = 0u64;
+    let mut last = Duration::ZERO;
+    let mut fin = None;
+    while let Ok(Ok(n)) = timeout(Duration::from_millis(500), sock.recv(&mut buf)).await {
+        if buf[..n].starts_with(b"FIN ") {
+            let text = String::from_utf8_lossy(&buf[..n]).to_string();
+            fin = text.split_whitespace().nth(1).and_then(|b| b.parse::<u64>().ok());
+            ensure!(fin.is_some(), "malformed {:?}", text);
+            sock.send(b"FIN_ACK").await?;
+            break;
+        }
+        if !buf[..n].starts_with(b"ACK_DOWNLOAD") {
+            bytes += n as u64;
+            last = start.elapsed();
+        }
+        ensure!(start.elapsed() < WINDOW + GRACE, "server still sending after {:?}", start.elapsed());
+    }
+    ensure!(bytes > 0, "no download datagrams received");
+    let sent = fin.ok_or_else(|| anyhow::anyhow!("no FIN after the download"))?;
+    ensure!(bytes <= sent, "received {} bytes but FIN says {} were sent", bytes, sent);
+    Ok((bytes, sent, last))
+}
+
+/// Read the ACK_DOWNLOAD and echo its return-path cookie, if it has one.
+async fn udp_confirm_download(sock: &UdpSocket, buf: &mut [u8]) -> anyhow::Result<()> {
+    let n = udp_recv(sock, buf).await?;
+    let ack = String::from_utf8_lossy(&buf[..n]).to_string();
+    ensure!(ack.split_whitespace().next() == Some("ACK_DOWNLOAD"), "expected ACK_DOWNLOAD, got {} bytes", n);
+    if let Some(cookie) = ack.split_whitespace().find_map(|kv| kv.strip_prefix("COOKIE=")) {
+        sock.send(format!("CONFIRM COOKIE={}", cookie).as_bytes()).await?;
+    }
+    Ok(())
+}
+
+async fn udp_upload(target: &Target) -> anyhow::Result<String> {
+    let sock = udp_socket(target).await?;
+    sock.send(b"START_UPLOAD").await?;
+    let mut buf = vec![0u8; 2048];
+    let n = udp_recv(&sock, &mut buf).await?;
+    ensure!(&buf[..n] == b"ACK_UPLOAD", "expected ACK_UPLOAD, got {:?}", String::from_utf8_lossy(&buf[..n]));
+    // Remaining ACKs and the NAT probe follow.
+    let mut saw_probe = false;
+    while let Ok(Ok(n)) = timeout(Duration::from_millis(300), sock.recv(&mut buf)).await {
+        saw_probe |= &buf[..n] == b"P";
+    }
+    ensure!(saw_probe, "no NAT probe datagram after ACK_UPLOAD");
+    let payload = vec![0u8; 1400];
+    for _ in 0..100 {
+        sock.send(&payload).await?;
+    }
+    Ok("ACKs and probe received".into())
+}
+
+async fn udp_end_download(target: &Target) -> anyhow::Result<String> {
+    let sock = udp_socket(target).await?;
+    let start = Instant::now();
+    sock.send(b"START_DOWNLOAD").await?;
+    let mut buf = vec![0u8; 64 * 1024];
+    udp_confirm_download(&sock, &mut buf).await?;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    sock.send(b"END_DOWNLOAD").await?;
+    let mut acked = false;
+    while let Ok(Ok(n)) = timeout(Duration::from_millis(500), sock.recv(&mut buf)).await {
+        acked |= &buf[..n] == b"ACK_END_DOWNLOAD";
+        ensure!(start.elapsed() < WINDOW - Duration::from_secs(1), "download continued after END_DOWNLOAD");
+    }
+    ensure!(acked, "no ACK_END_DOWNLOAD");
+    Ok("stopped and acknowledged".into())
+}
+
+async fn udp_end_upload(target: &Target) -> anyhow::Result<String> {
+    let sock = udp_socket(target).await?;
+    sock.send(b"START_UPLOAD").await?;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let payload = vec![0u8; 1000];
+    for _ in 0..10 {
+        sock.send(&payload).await?;
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    sock.send(b"END_UPLOAD").await?;
+    let mut buf = vec![0u8; 2048];
+    loop {
+        let n = udp_recv(&sock, &mut buf).await?;
+        let reply = String::from_utf8_lossy(&buf[..n]).to_string();
+        if reply.starts_with("ACK_END_UPLOAD") {
+            ensure!(reply.contains("bytes="), "reply lacks byte count: {:?}", reply);
+            return Ok(reply);
+        }
+    }
+}
+
+/// Send a command and read the reply datagram.
+async fn udp_request(sock: &UdpSocket, cmd: &str) -> anyhow::Result<String> {
+    sock.send(cmd.as_bytes()).await?;
+    let mut buf = vec![0u8; 2048];
+    let n = udp_recv(sock, &mut buf).await?;
+    Ok(String::from_utf8_lossy(&buf[..n]).trim().to_string())
+}
+
+async fn udp_hello(target: &Target) -> anyhow::Result<String> {
+    let sock = udp_socket(target).await?;
+    let reply = udp_request(&sock, "HELLO VERSION=1 AGENT=proj2-conformance").await?;
+    check_hello(&reply)?;
+    Ok(reply)
+}
+
+async fn udp_whoami(target: &Target) -> anyhow::Result<String> {
+    let sock = udp_socket(target).await?;
+    let reply = udp_request(&sock, "WHOAMI").await?;
+    check_whoami(&reply)?;
+    Ok(reply)
+}
+
+async fn udp_invalid_option(target: &Target) -> anyhow::Result<String> {
+    let sock = udp_socket(target).await?;
+    let reply = udp_request(&sock, "CAPACITY_PROBE TRAINS=0").await?;
+    ensure!(reply.starts_with("ERR INVALID_OPTION"), "expected ERR INVALID_OPTION, got {:?}", reply);
+    Ok(reply)
+}
+
+async fn udp_bytes(target: &Target, run: &str) -> anyhow::Result<String> {
+    let command = format!("START_DOWNLOAD BYTES={} TENANT={} RUN={}", UDP_BYTES, TENANT, run);
+    let (bytes, sent, last) = udp_download_with(target, &command).await?;
+    ensure!(sent >= UDP_BYTES, "asked for {} bytes, FIN says {} were sent", UDP_BYTES, sent);
+    ensure!(last + GRACE < WINDOW, "download ran on to {:?} past its byte target", last);
+    Ok(format!("{} of {} bytes in {:?}", bytes, sent, last))
+}
+
+async fn udp_echo(target: &Target) -> anyhow::Result<String> {
+    let sock = udp_socket(target).await?;
+    let reply = udp_request(&sock, "START_ECHO").await?;
+    ensure!(reply == "ACK_ECHO", "expected ACK_ECHO, got {:?}", reply);
+    let mut buf = vec![0u8; 2048];
+    for seq in 0..ECHOES {
+        let message = format!("conformance echo {}", seq);
+        sock.send(message.as_bytes()).await?;
+        let n = udp_recv(&sock, &mut buf).await?;
+        ensure!(&buf[..n] == message.as_bytes(), "echo {} came back as {:?}", seq, String::from_utf8_lossy(&buf[..n]));
+    }
+    let reply = udp_request(&sock, "END_ECHO").await?;
+    let requests = field(&reply, "requests").and_then(|n| n.parse::<u64>().ok());
+    ensure!(reply.starts_with("ACK_END_ECHO") && requests == Some(ECHOES), "expected ACK_END_ECHO requests={}, got {:?}", ECHOES, reply);
+    Ok(reply)
+}
+
+async fn udp_owd(target: &Target) -> anyhow::Result<String> {
+    let sock = udp_socket(target).await?;
+    let bad = udp_request(&sock, "OWD_PROBE").await?;
+    ensure!(bad.starts_with("ERR BAD_COMMAND"), "expected ERR BAD_COMMAND for a bare OWD_PROBE, got {:?}", bad);
+    let mut report = Vec::new();
+    for seq in 0..OWD_PROBES {
+        let t1 = owd::now_us();
+        let reply = udp_request(&sock, &format!("OWD_PROBE {} {}", seq, t1)).await?;
+        let t4 = owd::now_us();
+        let parts: Vec<&str> = reply.split_whitespace().collect();
+        let echoed = parts.len() == 5 && parts[0] == "OWD_REPLY" && parts[1] == seq.to_string() && parts[2] == t1.to_string();
+        ensure!(echoed && parts[3..].iter().all(|t| t.parse::<i64>().is_ok()), "expected OWD_REPLY {} {} <t2> <t3>, got {:?}", seq, t1, reply);
+        report.push(format!("{}:{}", seq, t4));
+    }
+    let reply = udp_request(&sock, &format!("OWD_REPORT {}", report.join(","))).await?;
+    ensure!(reply.starts_with("OWD ") && ["offset_us", "rtt_us", "fwd_us", "rev_us"].iter().all(|k| field(&reply, k).is_some()), "unexpected OWD reply {:?}", reply);
+    Ok(reply)
+}
+
+async fn udp_capacity_probe(target: &Target) -> anyhow::Result<String> {
+    const TRAINS: usize = 3;
+    const SIZE: usize = 200;
+    let sock = udp_socket(target).await?;
+    let ack = udp_request(&sock, &format!("CAPACITY_PROBE TRAINS={} LEN=4 SIZE={}", TRAINS, SIZE)).await?;
+    ensure!(ack.starts_with("ACK_CAPACITY_PROBE"), "expected ACK_CAPACITY_PROBE, got {:?}", ack);
+    if let Some(cookie) = field(&ack, "COOKIE") {
+        sock.send(format!("CONFIRM COOKIE={}", cookie).as_bytes()).await?;
+    }
+    // First and last arrival of each train.
+    let mut arrivals: HashMap<usize, (Instant, Instant)> = HashMap::new();
+    let mut buf = vec![0u8; 2048];
+    while let Ok(Ok(n)) = timeout(Duration::from_millis(500), sock.recv(&mut buf)).await {
+        let header = String::from_utf8_lossy(&buf[..n.min(64)]).to_string();
+        // Repeated ACKs are skipped.
+        let Some(train) = header.strip_prefix("TRAIN ").and_then(|t| t.split_whitespace().next()?.parse().ok()) else { continue };
+        ensure!(n == SIZE, "train datagram of {} bytes, asked for {}", n, SIZE);
+        let now = Instant::now();
+        arrivals.entry(train).and_modify(|(_, last)| *last = now).or_insert((now, now));
+    }
+    ensure!(arrivals.len() == TRAINS, "received {} of {} trains", arrivals.len(), TRAINS);
+    let dispersions: Vec<String> = arrivals.values().map(|(first, last)| last.duration_since(*first).as_micros().max(1).to_string()).collect();
+    let reply = udp_request(&sock, &format!("DISPERSION_REPORT {}", dispersions.join(","))).await?;
+    ensure!(reply.starts_with("CAPACITY ") && field(&reply, "bps").is_some(), "expected CAPACITY bps=, got {:?}", reply);
+    Ok(reply)
+}
+
+async fn udp_extend(target: &Target) -> anyhow::Result<String> {
+    let sock = udp_socket(target).await?;
+    let idle = udp_request(&sock, "EXTEND 1").await?;
+    if idle.starts_with("ERR DISABLED") {
+        return Ok(format!("EXTEND disabled: {}", idle));
+    }
+    ensure!(idle.starts_with("ERR NOT_FOUND"), "expected ERR NOT_FOUND with no test running, got {:?}", idle);
+    let start = Instant::now();
+    sock.send(b"START_DOWNLOAD").await?;
+    let mut buf = vec![0u8; 64 * 1024];
+    udp_confirm_download(&sock, &mut buf).await?;
+    // Extend the test once it is running: its window opens with the data.
+    udp_recv(&sock, &mut buf).await?;
+    sock.send(b"EXTEND 1").await?;
+    // The reply arrives among the download's datagrams.
+    let reply = loop {
+        let n = udp_recv(&sock, &mut buf).await?;
+        if buf[..n].starts_with(b"EXTENDED") || buf[..n].starts_with(b"ERR ") {
+            break String::from_utf8_lossy(&buf[..n]).to_string();
+        }
+        ensure!(start.elapsed() < WINDOW, "no reply to EXTEND during the download");
+    };
+    sock.send(b"END_DOWNLOAD").await?;
+    // Stay until the flood stops, so no datagram meets a closed port.
+    while let Ok(Ok(_)) = timeout(Duration::from_millis(500), sock.recv(&mut buf)).await {}
+    let window = field(&reply, "window_ms").and_then(|ms| ms.parse::<u128>().ok());
+    ensure!(window.is_some_and(|ms| ms > WINDOW.as_millis()), "expected EXTENDED window_ms= beyond {:?}, got {:?}", WINDOW, reply);
+    Ok(reply)
+}
+
+async fn run_summary(target: &Target, run: &str) -> anyhow::Result<String> {
+    let mut stream = connect(target).await?;
+    let usage = tcp_request(&mut stream, "RUN").await?;
+    ensure!(usage.starts_with("ERR BAD_COMMAND"), "expected ERR BAD_COMMAND for a bare RUN, got {:?}", usage);
+    // The byte-target tests of both planes; results may take a moment to land.
+    let deadline = Instant::now() + REPLY_TIMEOUT;
+    loop {
+        let reply = tcp_request(&mut stream, &format!("RUN {} TENANT={}", run, TENANT)).await?;
+        if reply.starts_with("RUN ") && field(&reply, "streams") == Some("2") {
+            ensure!(field(&reply, "fairness").is_some(), "RUN lacks fairness=: {:?}", reply);
+            return Ok(reply);
+        }
+        ensure!(Instant::now() < deadline, "expected a run of 2 streams, got {:?}", reply);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+async fn run_compare(target: &Target, run: &str) -> anyhow::Result<String> {
+    let mut stream = connect(target).await?;
+    let reply = tcp_request(&mut stream, &format!("COMPARE {} TENANT={}", run, TENANT)).await?;
+    ensure!(
+        reply.starts_with(&format!("COMPARE name={} ", run)) && field(&reply, "download_udp_over_tcp").is_some(),
+        "expected a download comparison, got {:?}",
+        reply
+    );
+    let missing = tcp_request(&mut stream, &format!("COMPARE conformance-missing TENANT={}", TENANT)).await?;
+    ensure!(missing.starts_with("ERR NOT_FOUND"), "expected ERR NOT_FOUND for an unknown run, got {:?}", missing);
+    Ok(reply)
+}
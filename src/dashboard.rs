@@ -0,0 +1,150 @@
+// proj2-serv/src/dashboard.rs
+// Built-in web dashboard (`--dashboard-port`), served on loopback only like the
+// admin queries. The files under src/dashboard/ are embedded in the binary
+// with rust-embed and served by path, `GET /` being index.html; `GET /events`
+// is a Server-Sent Events stream with a snapshot of the session registry every
+// second, from which the page charts each session's throughput.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rust_embed::RustEmbed;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::clock;
+use crate::state::ServerState;
+
+#[derive(RustEmbed)]
+#[folder = "src/dashboard/"]
+struct Assets;
+
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(1);
+/// Largest request head accepted.
+const MAX_REQUEST: usize = 8 * 1024;
+
+pub async fn run_dashboard(listener: TcpListener, state: Arc<ServerState>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle(stream, peer, &state).await {
+                        eprintln!("Dashboard client {} error: {}", peer, e);
+                    }
+                });
+            }
+            Err(e) => {
+                eprintln!("Dashboard accept error: {:?}", e);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+    }
+}
+
+async fn handle(mut stream: TcpStream, peer: SocketAddr, state: &ServerState) -> std::io::Result<()> {
+    let Some(path) = read_request_path(&mut stream).await? else {
+        return respond(&mut stream, "400 Bad Request", "text/plain", "bad request\n").await;
+    };
+    match path.as_str() {
+        "/" => asset(&mut stream, "index.html").await,
+        "/events" => {
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n")
+                .await?;
+            let mut ticker = tokio::time::interval(SNAPSHOT_INTERVAL);
+            loop {
+                ticker.tick().await;
+                // A write error means the browser went away.
+                if stream.write_all(format!("data: {}\n\n", snapshot(state)).as_bytes()).await.is_err() {
+                    println!("Dashboard client {} disconnected", peer);
+                    return Ok(());
+                }
+            }
+        }
+        path => asset(&mut stream, path.trim_start_matches('/')).await,
+    }
+}
+
+/// Path of a `GET` request; `None` for anything else.
+pub async fn read_request_path(stream: &mut TcpStream) -> std::io::Result<Option<String>> {
+    let mut head = Vec::with_capacity(1024);
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 || head.len() + n > MAX_REQUEST {
+            return Ok(None);
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+    let head = String::from_utf8_lossy(&head);
+    let mut parts = head.lines().next().unwrap_or_default().split_whitespace();
+    match (parts.next(), parts.next()) {
+        (Some("GET"), Some(path)) => Ok(Some(path.split('?').next().unwrap_or(path).to_string())),
+        _ => Ok(None),
+    }
+}
+
+pub async fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: impl AsRef<[u8]>) -> std::io::Result<()> {
+    let body = body.as_ref();
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body).await
+}
+
+/// Serve the embedded file `name`, or 404.
+async fn asset(stream: &mut TcpStream, name: &str) -> std::io::Result<()> {
+    // Debug builds read assets from disk, so keep lookups inside the folder.
+    let inside = !name.split('/').any(|part| part == "..");
+    let Some(file) = inside.then(|| Assets::get(name)).flatten() else {
+        return respond(stream, "404 Not Found", "text/plain", "not found\n").await;
+    };
+    let content_type = match name.rsplit_once('.').map(|(_, ext)| ext) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        _ => "application/octet-stream",
+    };
+    respond(stream, "200 OK", content_type, file.data).await
+}
+
+/// The session registry as a JSON array.
+fn snapshot(state: &ServerState) -> String {
+    let sessions: Vec<String> = state
+        .sessions
+        .list()
+        .iter()
+        .map(|s| {
+            format!(
+                r#"{{"id":{},"proto":"{}","peer":"{}","tenant":"{}","dir":"{}","bytes":{},"packets":{},"age_ms":{},"idle_ms":{}}}"#,
+                s.id,
+                s.protocol.as_str(),
+                s.peer,
+                json_escape(&s.tenant),
+                s.direction.map_or("idle", |d| d.as_str()),
+                s.bytes,
+                s.packets,
+                clock::elapsed(s.started).as_millis(),
+                clock::elapsed(s.last_activity).as_millis()
+            )
+        })
+        .collect();
+    format!("[{}]", sessions.join(","))
+}
+
+pub fn json_escape(s: &str) -> String {
+    s.chars()
+        .flat_map(|c| match c {
+            '"' | '\\' => vec!['\\', c],
+            c if c.is_control() => format!("\\u{:04x}", c as u32).chars().collect(),
+            c => vec![c],
+        })
+        .collect()
+}
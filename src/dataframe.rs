@@ -0,0 +1,68 @@
+// proj2-serv/src/dataframe.rs
+// Length-delimited upload data on a TCP control connection (`START_UPLOAD
+// FRAMED=1`). The client sends its payload as `DATA <n>\n` followed by exactly
+// n bytes, and control lines such as `END_UPLOAD` or `EXTEND` between frames.
+// The decoder knows at every byte whether it is reading payload or a line, so
+// payload that happens to read `START_DOWNLOAD` is only ever counted, and a
+// connection stays in step after a window closes on frames still in flight.
+
+/// Longest line accepted between frames.
+pub const MAX_LINE: usize = 512;
+/// Largest frame a client may announce.
+pub const MAX_FRAME: u64 = 16 * 1024 * 1024;
+
+/// What the next bytes of the stream turned out to be.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Event {
+    /// Payload bytes of the current frame.
+    Data(usize),
+    /// A control line between frames, without its line end.
+    Line(String),
+}
+
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    /// Payload bytes left in the current frame; 0 between frames.
+    remaining: u64,
+    line: Vec<u8>,
+}
+
+impl FrameDecoder {
+    /// Decode the next event from the front of `input`, consuming what it
+    /// covers. None once `input` is used up; a partial line is kept for the
+    /// next read. Err if the stream cannot be framed, after which the decoder
+    /// must not be fed again.
+    pub fn next(&mut self, input: &mut &[u8]) -> Option<Result<Event, String>> {
+        loop {
+            if input.is_empty() {
+                return None;
+            }
+            if self.remaining > 0 {
+                let n = input.len().min(self.remaining.min(usize::MAX as u64) as usize);
+                *input = &input[n..];
+                self.remaining -= n as u64;
+                return Some(Ok(Event::Data(n)));
+            }
+            let Some(end) = input.iter().position(|&b| b == b'\n') else {
+                self.line.extend_from_slice(input);
+                *input = &[];
+                return (self.line.len() > MAX_LINE).then(|| Err(format!("line between frames longer than {} bytes", MAX_LINE)));
+            };
+            self.line.extend_from_slice(&input[..end]);
+            *input = &input[end + 1..];
+            let line = std::mem::take(&mut self.line);
+            if line.len() > MAX_LINE {
+                return Some(Err(format!("line between frames longer than {} bytes", MAX_LINE)));
+            }
+            let text = String::from_utf8_lossy(&line).trim().to_string();
+            match text.strip_prefix("DATA ") {
+                Some(len) => match len.trim().parse::<u64>() {
+                    Ok(len) if len <= MAX_FRAME => self.remaining = len,
+                    _ => return Some(Err(format!("invalid frame header {:?} (expected DATA <n> with n at most {})", text, MAX_FRAME))),
+                },
+                None if text.is_empty() => {}
+                None => return Some(Ok(Event::Line(text))),
+            }
+        }
+    }
+}
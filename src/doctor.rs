@@ -0,0 +1,283 @@
+// proj2-serv/src/doctor.rs
+// `proj2-serv doctor`
+// Reports host tuning that caps what any server can measure: socket buffer
+// sysctls, listen backlog, congestion control algorithms, NIC offloads and
+// firewall state. Each check prints OK, WARN or INFO with a hint on how to fix
+// it, so low numbers can be traced to the host before blaming the server.
+// The checks read /proc and /sys and are Linux-only.
+
+use anyhow::bail;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Level {
+    Ok,
+    Warn,
+    Info,
+}
+
+impl Level {
+    fn as_str(self) -> &'static str {
+        match self {
+            Level::Ok => "OK",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+        }
+    }
+}
+
+struct Finding {
+    name: String,
+    level: Level,
+    detail: String,
+    hint: Option<String>,
+}
+
+impl Finding {
+    fn new(name: impl Into<String>, level: Level, detail: impl Into<String>) -> Self {
+        Finding { name: name.into(), level, detail: detail.into(), hint: None }
+    }
+
+    fn hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+}
+
+pub fn run(mut args: impl Iterator<Item = String>) -> anyhow::Result<()> {
+    if let Some(arg) = args.next() {
+        bail!("unknown doctor argument {:?}", arg);
+    }
+    let findings = checks();
+    println!("{:<34} {:<6} detail", "check", "result");
+    for f in &findings {
+        println!("{:<34} {:<6} {}", f.name, f.level.as_str(), f.detail);
+        if let Some(hint) = &f.hint {
+            println!("{:<34} {:<6} hint: {}", "", "", hint);
+        }
+    }
+    let warnings = findings.iter().filter(|f| f.level == Level::Warn).count();
+    println!();
+    println!("{} checks, {} warnings", findings.len(), warnings);
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn checks() -> Vec<Finding> {
+    let mut findings = Vec::new();
+    findings.extend(buffer_sysctls());
+    findings.push(somaxconn());
+    findings.push(congestion_control());
+    findings.extend(interfaces());
+    findings.extend(firewall());
+    findings
+}
+
+#[cfg(not(target_os = "linux"))]
+fn checks() -> Vec<Finding> {
+    vec![Finding::new("platform", Level::Info, "host checks are only implemented for Linux")]
+}
+
+#[cfg(target_os = "linux")]
+fn sysctl(name: &str) -> Option<String> {
+    let path = format!("/proc/sys/{}", name.replace('.', "/"));
+    std::fs::read_to_string(path).ok().map(|v| v.trim().to_string())
+}
+
+#[cfg(target_os = "linux")]
+fn sysctl_num(name: &str) -> Option<u64> {
+    sysctl(name)?.parse().ok()
+}
+
+/// Largest value of a `min default max` triple such as net.ipv4.tcp_rmem.
+#[cfg(target_os = "linux")]
+fn sysctl_max(name: &str) -> Option<u64> {
+    sysctl(name)?.split_whitespace().last()?.parse().ok()
+}
+
+#[cfg(target_os = "linux")]
+fn at_least(name: &str, value: Option<u64>, want: u64) -> Finding {
+    match value {
+        Some(v) if v >= want => Finding::new(name, Level::Ok, v.to_string()),
+        Some(v) => Finding::new(name, Level::Warn, format!("{} (server asks for {})", v, want))
+            .hint(format!("sysctl -w {}={}", name, want)),
+        None => Finding::new(name, Level::Info, "unreadable"),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn buffer_sysctls() -> Vec<Finding> {
+    let udp = crate::UDP_BUFFER as u64;
+    let tcp = crate::TCP_BUFFER as u64;
+    let mut findings = vec![
+        at_least("net.core.rmem_max", sysctl_num("net.core.rmem_max"), udp),
+        at_least("net.core.wmem_max", sysctl_num("net.core.wmem_max"), udp),
+    ];
+    for name in ["net.ipv4.tcp_rmem", "net.ipv4.tcp_wmem"] {
+        let mut f = at_least(name, sysctl_max(name), tcp);
+        if f.level == Level::Warn {
+            f = f.hint(format!("raise the last field of {}, e.g. sysctl -w {}=\"4096 131072 {}\"", name, name, tcp));
+        }
+        findings.push(f);
+    }
+    findings.push(match sysctl_num("net.core.netdev_max_backlog") {
+        Some(v) if v < 5000 => Finding::new("net.core.netdev_max_backlog", Level::Info, v.to_string())
+            .hint("raise it (e.g. 16384) if UDP receive tests lose packets at high rates"),
+        Some(v) => Finding::new("net.core.netdev_max_backlog", Level::Ok, v.to_string()),
+        None => Finding::new("net.core.netdev_max_backlog", Level::Info, "unreadable"),
+    });
+    findings
+}
+
+#[cfg(target_os = "linux")]
+fn somaxconn() -> Finding {
+    let mut f = at_least("net.core.somaxconn", sysctl_num("net.core.somaxconn"), crate::LISTEN_BACKLOG as u64);
+    if f.level == Level::Warn {
+        f.detail.push_str("; bursts of connections may be refused");
+    }
+    f
+}
+
+#[cfg(target_os = "linux")]
+fn congestion_control() -> Finding {
+    let name = "net.ipv4.tcp_congestion_control";
+    let Some(available) = sysctl("net.ipv4.tcp_available_congestion_control") else {
+        return Finding::new(name, Level::Info, "unreadable");
+    };
+    let current = sysctl(name).unwrap_or_else(|| "-".to_string());
+    let f = Finding::new(name, Level::Info, format!("{} (available: {})", current, available));
+    if available.split_whitespace().any(|a| a == "bbr") {
+        f
+    } else {
+        f.hint("modprobe tcp_bbr to let clients request CCA=bbr")
+    }
+}
+
+/// MTU, speed and offloads of every interface that is up, loopback excepted.
+#[cfg(target_os = "linux")]
+fn interfaces() -> Vec<Finding> {
+    let Ok(entries) = std::fs::read_dir("/sys/class/net") else {
+        return vec![Finding::new("interfaces", Level::Info, "/sys/class/net unreadable")];
+    };
+    let mut names: Vec<String> = entries.filter_map(|e| e.ok()).map(|e| e.file_name().to_string_lossy().into_owned()).collect();
+    names.sort();
+    let mut findings = Vec::new();
+    for name in names {
+        let attr = |a: &str| std::fs::read_to_string(format!("/sys/class/net/{}/{}", name, a)).ok().map(|v| v.trim().to_string());
+        if name == "lo" || attr("operstate").as_deref() == Some("down") {
+            continue;
+        }
+        let mtu = attr("mtu").unwrap_or_else(|| "-".to_string());
+        let speed = match attr("speed").and_then(|s| s.parse::<i64>().ok()) {
+            Some(s) if s > 0 => format!("{}Mb/s", s),
+            _ => "unknown".to_string(),
+        };
+        findings.push(Finding::new(format!("{} link", name), Level::Info, format!("mtu={} speed={}", mtu, speed)));
+        findings.push(offloads(&name));
+    }
+    findings
+}
+
+#[cfg(target_os = "linux")]
+fn offloads(ifname: &str) -> Finding {
+    const OFFLOADS: [(&str, u32); 6] = [
+        ("rx-csum", ETHTOOL_GRXCSUM),
+        ("tx-csum", ETHTOOL_GTXCSUM),
+        ("sg", ETHTOOL_GSG),
+        ("tso", ETHTOOL_GTSO),
+        ("gso", ETHTOOL_GGSO),
+        ("gro", ETHTOOL_GGRO),
+    ];
+    let check = format!("{} offloads", ifname);
+    let mut states = Vec::new();
+    let mut off = Vec::new();
+    for (label, cmd) in OFFLOADS {
+        match ethtool_value(ifname, cmd) {
+            Ok(on) => {
+                states.push(format!("{}={}", label, if on { "on" } else { "off" }));
+                if !on {
+                    off.push(label);
+                }
+            }
+            Err(e) => return Finding::new(check, Level::Info, format!("unavailable: {}", e)),
+        }
+    }
+    let detail = states.join(" ");
+    // Without segmentation and receive offloads every 1400-byte datagram or
+    // segment costs a trip through the stack, which caps rates well below line speed.
+    let costly: Vec<&str> = off.iter().copied().filter(|o| ["gso", "gro", "tso"].contains(o)).collect();
+    if costly.is_empty() {
+        Finding::new(check, Level::Ok, detail)
+    } else {
+        Finding::new(check, Level::Warn, detail).hint(format!("ethtool -K {} {}", ifname, costly.iter().map(|o| format!("{} on", o)).collect::<Vec<_>>().join(" ")))
+    }
+}
+
+#[cfg(target_os = "linux")]
+const ETHTOOL_GRXCSUM: u32 = 0x14;
+#[cfg(target_os = "linux")]
+const ETHTOOL_GTXCSUM: u32 = 0x16;
+#[cfg(target_os = "linux")]
+const ETHTOOL_GSG: u32 = 0x18;
+#[cfg(target_os = "linux")]
+const ETHTOOL_GTSO: u32 = 0x1e;
+#[cfg(target_os = "linux")]
+const ETHTOOL_GGSO: u32 = 0x23;
+#[cfg(target_os = "linux")]
+const ETHTOOL_GGRO: u32 = 0x2b;
+
+/// One boolean ethtool setting, as `ethtool -k` reads it.
+#[cfg(target_os = "linux")]
+fn ethtool_value(ifname: &str, cmd: u32) -> std::io::Result<bool> {
+    use std::os::fd::AsRawFd;
+
+    #[repr(C)]
+    struct EthtoolValue {
+        cmd: u32,
+        data: u32,
+    }
+
+    let name = ifname.as_bytes();
+    if name.len() >= libc::IFNAMSIZ {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "interface name too long"));
+    }
+    let sock = std::net::UdpSocket::bind("0.0.0.0:0")?;
+    let mut value = EthtoolValue { cmd, data: 0 };
+    // SAFETY: ifreq is plain data; the name is NUL-terminated within IFNAMSIZ and
+    // ifru_data points at `value`, which outlives the ioctl.
+    let rc = unsafe {
+        let mut ifr: libc::ifreq = std::mem::zeroed();
+        for (dst, &src) in ifr.ifr_name.iter_mut().zip(name) {
+            *dst = src as libc::c_char;
+        }
+        ifr.ifr_ifru.ifru_data = &mut value as *mut EthtoolValue as *mut libc::c_char;
+        libc::ioctl(sock.as_raw_fd(), libc::SIOCETHTOOL, &mut ifr)
+    };
+    if rc < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(value.data != 0)
+}
+
+#[cfg(target_os = "linux")]
+fn firewall() -> Vec<Finding> {
+    let mut findings = Vec::new();
+    match (sysctl_num("net.netfilter.nf_conntrack_count"), sysctl_num("net.netfilter.nf_conntrack_max")) {
+        (Some(count), Some(max)) if max > 0 => {
+            let f = Finding::new("nf_conntrack", Level::Ok, format!("{} of {} entries", count, max));
+            findings.push(if count * 10 >= max * 8 {
+                Finding { level: Level::Warn, ..f }.hint("raise net.netfilter.nf_conntrack_max or exempt test ports with a NOTRACK rule")
+            } else {
+                f.hint("UDP floods and many short connections each take a conntrack entry")
+            });
+        }
+        _ => findings.push(Finding::new("nf_conntrack", Level::Ok, "not loaded")),
+    }
+    let tables = std::fs::read_to_string("/proc/net/ip_tables_names").unwrap_or_default();
+    let tables: Vec<&str> = tables.split_whitespace().collect();
+    let detail = if tables.is_empty() { "no iptables tables loaded".to_string() } else { format!("iptables tables: {}", tables.join(",")) };
+    findings.push(
+        Finding::new("firewall", Level::Info, detail)
+            .hint("make sure the TCP and UDP test ports are allowed (iptables -S / nft list ruleset)"),
+    );
+    findings
+}
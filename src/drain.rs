@@ -0,0 +1,61 @@
+// proj2-serv/src/drain.rs
+// Drain mode for maintenance. `ADMIN DRAIN ON [RETRY_AFTER=<s>]` makes every
+// plane refuse commands that start work with
+// `ERR BUSY server is draining RETRY_AFTER=<s>` while tests already running
+// finish normally; `ADMIN DRAIN OFF` takes new work again. `ADMIN DRAIN` shows
+// the mode and how many tests are still running, and CAPS reports `draining=1`,
+// so a load balancer can take the instance out of rotation and the operator can
+// restart it once `testing=0`.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::protocol::{error_frame, ErrorCode};
+use crate::ratelimit;
+use crate::session::SessionInfo;
+
+/// Hint sent to refused clients when `ADMIN DRAIN ON` gives none.
+pub const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Default)]
+pub struct Drain {
+    /// When draining began and the retry hint, while draining.
+    since: Mutex<Option<(Instant, Duration)>>,
+}
+
+impl Drain {
+    /// Start draining; a repeated call only updates the retry hint.
+    pub fn start(&self, retry_after: Duration) {
+        let mut since = self.since.lock().unwrap();
+        let started = since.map_or_else(Instant::now, |(at, _)| at);
+        *since = Some((started, retry_after));
+    }
+
+    /// Stop draining; false if the server was not draining.
+    pub fn stop(&self) -> bool {
+        self.since.lock().unwrap().take().is_some()
+    }
+
+    pub fn active(&self) -> bool {
+        self.since.lock().unwrap().is_some()
+    }
+
+    /// The reply refusing `verb` while draining, if it starts work.
+    pub fn refuse(&self, verb: &str) -> Option<String> {
+        let (_, retry_after) = (*self.since.lock().unwrap())?;
+        ratelimit::starts_work(verb)
+            .then(|| error_frame(ErrorCode::Busy, format!("server is draining RETRY_AFTER={}", retry_after.as_secs())))
+    }
+
+    /// `ADMIN DRAIN` reply: the mode and the sessions still open and testing.
+    pub fn render(&self, sessions: &[SessionInfo]) -> String {
+        let testing = sessions.iter().filter(|s| s.direction.is_some()).count();
+        let mode = match *self.since.lock().unwrap() {
+            Some((at, retry_after)) => {
+                format!("draining=1 since_s={} retry_after={}", at.elapsed().as_secs(), retry_after.as_secs())
+            }
+            None => "draining=0".to_string(),
+        };
+        format!("{} sessions={} testing={}\n", mode, sessions.len(), testing)
+    }
+}
@@ -0,0 +1,239 @@
+// proj2-serv/src/echo.rs
+// Echo tests: the server sends every message straight back, for request rate
+// and latency-under-load benchmarks rather than bulk throughput.
+//   TCP  START_ECHO [SIZE=<bytes>] [OMIT=<secs>]  server replies `OK ECHO` and
+//        echoes everything it reads; a request is one SIZE-byte message
+//        (default 64). The client ends early by closing its write side.
+//   UDP  START_ECHO [OMIT=<secs>]  server replies `ACK_ECHO` and echoes every
+//        datagram from the client until the window ends or `END_ECHO`
+//        (answered with `ACK_END_ECHO requests=<n>`).
+// Results carry the request count and requests per second, and percentiles of
+// the round trips the server sees: over TCP the time from echoing a request to
+// the first byte of the next, over UDP the time between consecutive requests.
+// For a client that waits for each echo before sending again, that is its RTT
+// plus its own turnaround; a pipelining client shows much shorter ones.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+use crate::clock;
+use crate::histogram::RoundTrips;
+use crate::hostres::{self, CpuSnapshot};
+use crate::interval::Measured;
+use crate::protocol::Command;
+use crate::results::{Direction, Protocol, TestResult};
+use crate::session::SessionGuard;
+use crate::state::ServerState;
+
+pub const DEFAULT_TCP_MESSAGE_SIZE: usize = 64;
+
+/// `START_ECHO` on a TCP connection, after its options have been read.
+pub async fn tcp_echo(
+    stream: &mut TcpStream,
+    cmd: &Command,
+    peer: SocketAddr,
+    state: &ServerState,
+    session: &SessionGuard,
+    size: usize,
+    omit: Duration,
+) -> anyhow::Result<()> {
+    let tenant = &cmd.tenant();
+    stream.write_all(b"OK ECHO\n").await?;
+    state.metrics.session_started(tenant);
+    session.begin(tenant, Direction::Echo);
+    let cpu = hostres::snapshot();
+    let start = clock::now();
+    let deadline = start + state.test_window(None, omit);
+    let mut measured = Measured::new(start, omit);
+    let (mut total, mut requests) = (0u64, 0u64);
+    let mut round_trips = RoundTrips::default();
+    // When the last whole request went back, until the next one starts.
+    let mut echoed_at: Option<Instant> = None;
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = tokio::select! {
+            res = stream.read(&mut buf) => match res {
+                Ok(n) => n,
+                Err(e) => {
+                    println!("[{}] TCP echo with {} stopped: {}", tenant, peer, e);
+                    break;
+                }
+            },
+            _ = tokio::time::sleep_until(deadline.into()) => break,
+            _ = session.token().cancelled() => {
+                println!("[{}] TCP echo with {} cancelled", tenant, peer);
+                break;
+            }
+        };
+        if n == 0 {
+            break;
+        }
+        if let Some(echoed) = echoed_at.take().filter(|_| measured.is_measuring()) {
+            round_trips.record(clock::elapsed(echoed).as_micros() as u64);
+        }
+        if let Err(e) = stream.write_all(&buf[..n]).await {
+            println!("[{}] TCP echo with {} stopped: {}", tenant, peer, e);
+            break;
+        }
+        // A request counts once its last byte has been echoed.
+        let completed = (total + n as u64) / size as u64 - total / size as u64;
+        total += n as u64;
+        if measured.is_measuring() {
+            requests += completed;
+        }
+        if total % size as u64 == 0 {
+            echoed_at = Some(clock::now());
+        }
+        measured.add(n as u64);
+        session.add_bytes(n as u64);
+    }
+    session.end();
+    println!("[{}] TCP server echoed {} bytes to {}", tenant, total, peer);
+    let result = TestResult::new(tenant, Protocol::Tcp, Direction::Echo, peer, measured.bytes(), measured.duration(clock::now()))
+        .with_requests(requests)
+        .with_round_trips(round_trips)
+        .with_intervals(measured.intervals())
+        .with_omit(omit)
+        .with_host_usage(cpu.and_then(|c| c.usage()))
+        .with_run(cmd.run());
+    state.record(result);
+    Ok(())
+}
+
+/// A running UDP echo window for one client address.
+struct EchoWindow {
+    session: SessionGuard,
+    tenant: String,
+    run: Option<String>,
+    deadline: Instant,
+    requests: u64,
+    round_trips: RoundTrips,
+    /// Arrival of the previous request.
+    last_request: Option<Instant>,
+    measured: Measured,
+    cpu: Option<CpuSnapshot>,
+}
+
+/// UDP echo windows by client address.
+#[derive(Clone, Default)]
+pub struct UdpEchoes(Arc<Mutex<HashMap<SocketAddr, EchoWindow>>>);
+
+impl UdpEchoes {
+    /// Open a window for `addr`, replacing any previous one, and finalize it at
+    /// its deadline.
+    pub async fn start(&self, state: &Arc<ServerState>, addr: SocketAddr, tenant: &str, run: Option<String>, omit: Duration) {
+        state.metrics.session_started(tenant);
+        let started = clock::now();
+        let deadline = started + state.test_window(None, omit);
+        let session = state.sessions.register(Protocol::Udp, addr, tenant, Some(Direction::Echo));
+        let (id, cancel) = (session.id(), session.token().clone());
+        let window = EchoWindow {
+            session,
+            tenant: tenant.to_string(),
+            run,
+            deadline,
+            requests: 0,
+            round_trips: RoundTrips::default(),
+            last_request: None,
+            measured: Measured::new(started, omit),
+            cpu: hostres::snapshot(),
+        };
+        self.0.lock().await.insert(addr, window);
+        tokio::spawn(self.clone().finalize_at_deadline(state.clone(), addr, id, deadline, cancel));
+    }
+
+    /// Count a datagram from `addr`; false if it has no running window, in which
+    /// case the datagram is not echo traffic.
+    pub async fn count(&self, addr: SocketAddr, len: usize) -> bool {
+        let mut map = self.0.lock().await;
+        let now = clock::now();
+        match map.get_mut(&addr) {
+            Some(window) if now <= window.deadline => {
+                if window.measured.is_measuring() {
+                    window.requests += 1;
+                    if let Some(last) = window.last_request {
+                        window.round_trips.record(now.duration_since(last).as_micros() as u64);
+                    }
+                }
+                window.last_request = Some(now);
+                window.measured.add(len as u64);
+                window.session.add_bytes(len as u64);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// `END_ECHO`: close the window early; returns its request count.
+    pub async fn end(&self, state: &ServerState, addr: SocketAddr) -> u64 {
+        let window = self.0.lock().await.remove(&addr);
+        window.map_or(0, |window| finish(state, addr, window, clock::now(), None))
+    }
+
+    async fn finalize_at_deadline(
+        self,
+        state: Arc<ServerState>,
+        addr: SocketAddr,
+        id: u64,
+        deadline: Instant,
+        cancel: CancellationToken,
+    ) {
+        tokio::select! {
+            _ = tokio::time::sleep_until(deadline.into()) => {}
+            _ = cancel.cancelled() => {}
+        }
+        let window = {
+            let mut map = self.0.lock().await;
+            match map.get(&addr) {
+                Some(w) if w.session.id() == id => map.remove(&addr),
+                _ => None,
+            }
+        };
+        if let Some(window) = window {
+            finish(&state, addr, window, clock::now(), None);
+        }
+    }
+
+    /// Drop windows whose client has gone quiet, recording them as aborted.
+    pub async fn collect_garbage(&self, state: &ServerState, now: Instant) {
+        let stale: Vec<(SocketAddr, EchoWindow, &'static str)> = {
+            let mut map = self.0.lock().await;
+            let dead: Vec<(SocketAddr, &'static str)> = map
+                .iter()
+                .filter_map(|(addr, w)| {
+                    let counts = w.session.counters();
+                    state.stale_reason(counts.packets > 0, counts.last_activity, now).map(|r| (*addr, r))
+                })
+                .collect();
+            dead.into_iter().filter_map(|(addr, reason)| map.remove(&addr).map(|w| (addr, w, reason))).collect()
+        };
+        for (addr, window, reason) in stale {
+            println!("[{}] UDP echo with {} aborted ({})", window.tenant, addr, reason);
+            let ended = window.session.counters().last_activity;
+            finish(state, addr, window, ended, Some(reason));
+        }
+    }
+}
+
+fn finish(state: &ServerState, peer: SocketAddr, window: EchoWindow, ended: Instant, aborted: Option<&'static str>) -> u64 {
+    let measured = window.measured;
+    println!("[{}] UDP server echoed {} requests to {}", window.tenant, window.requests, peer);
+    state.record(
+        TestResult::new(&window.tenant, Protocol::Udp, Direction::Echo, peer, measured.bytes(), measured.duration(ended.min(window.deadline)))
+            .with_requests(window.requests)
+            .with_round_trips(window.round_trips)
+            .with_intervals(measured.intervals())
+            .with_omit(measured.omit)
+            .with_host_usage(window.cpu.and_then(|c| c.usage()))
+            .with_aborted(aborted)
+            .with_run(window.run),
+    );
+    window.requests
+}
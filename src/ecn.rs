@@ -0,0 +1,87 @@
+// proj2-serv/src/ecn.rs
+// Explicit Congestion Notification on the UDP plane. A download started with
+// `ECN=1` goes out marked ECT(0), next to any DSCP, so ECN-capable queues on the
+// path may mark it CE rather than drop it. The plane's socket asks the kernel for
+// the TOS byte of every datagram it receives (IP_RECVTOS), and an upload window
+// counts the codepoints its data arrived with; CE counts in the result show an
+// AQM on the path signalling congestion by marking rather than dropping. Linux
+// only; elsewhere nothing is counted.
+
+use std::fmt;
+use std::io;
+
+use tokio::net::UdpSocket;
+
+/// ECN codepoints, the low two bits of the TOS byte.
+pub const NOT_ECT: u8 = 0b00;
+pub const ECT1: u8 = 0b01;
+pub const ECT0: u8 = 0b10;
+pub const CE: u8 = 0b11;
+
+/// The TOS byte for `dscp` with ECT(0) set when `ecn` is; None when neither
+/// leaves the kernel's default.
+pub fn tos(dscp: Option<u8>, ecn: bool) -> Option<u8> {
+    match (dscp, ecn) {
+        (None, false) => None,
+        (dscp, ecn) => Some((dscp.unwrap_or(0) << 2) | if ecn { ECT0 } else { NOT_ECT }),
+    }
+}
+
+/// Datagrams of an upload by the ECN codepoint they arrived with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EcnCounts {
+    pub not_ect: u64,
+    pub ect0: u64,
+    pub ect1: u64,
+    pub ce: u64,
+}
+
+impl EcnCounts {
+    /// Count a datagram received `copies` times with TOS byte `tos`; one the
+    /// kernel reported no TOS for is not counted.
+    pub fn add(&mut self, tos: Option<u8>, copies: usize) {
+        let Some(tos) = tos else { return };
+        let copies = copies as u64;
+        match tos & CE {
+            NOT_ECT => self.not_ect += copies,
+            ECT1 => self.ect1 += copies,
+            ECT0 => self.ect0 += copies,
+            _ => self.ce += copies,
+        }
+    }
+
+    /// Whether any datagram came in ECN-capable, CE-marked ones included.
+    pub fn capable(&self) -> bool {
+        self.ect0 + self.ect1 + self.ce > 0
+    }
+}
+
+impl fmt::Display for EcnCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ecn_ect0={} ecn_ect1={} ecn_ce={} ecn_not_ect={}", self.ect0, self.ect1, self.ce, self.not_ect)
+    }
+}
+
+/// Have the kernel pass up the TOS byte of every datagram received on `sock`.
+#[cfg(target_os = "linux")]
+pub fn enable_recv_tos(sock: &UdpSocket) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let on: libc::c_int = 1;
+    // SAFETY: passing a pointer to a live c_int together with its size.
+    let rc = unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            libc::IPPROTO_IP,
+            libc::IP_RECVTOS,
+            &on as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if rc != 0 { Err(io::Error::last_os_error()) } else { Ok(()) }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn enable_recv_tos(_sock: &UdpSocket) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "IP_RECVTOS not supported on this platform"))
+}
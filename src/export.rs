@@ -0,0 +1,253 @@
+// proj2-serv/src/export.rs
+// Result exporters (`--export-results <url>`, repeatable): every completed test
+// result, including scheduled and mesh ones, is also published as a JSON object
+// of its result-line fields to each configured sink:
+//   http://host[:port]/path    POSTed to a webhook (plain HTTP; put a local
+//                              proxy in front of an https endpoint)
+//   kafka://host[:port]/topic  produced to the topic with rdkafka, keyed by
+//                              tenant
+//   mqtt://host[:port]/topic   published at QoS 0 with rumqttc
+// Each sink has its own queue and task, so a slow or unreachable sink never
+// holds up a test or the other sinks; results that find its queue full are
+// dropped. A failed or timed-out publish is retried once, then dropped. Both
+// show in /metrics.
+
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{ensure, Context};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::util::Timeout;
+use rdkafka::ClientConfig;
+use rumqttc::{AsyncClient, MqttOptions, QoS};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio_util::task::AbortOnDropHandle;
+
+use crate::metrics::Metrics;
+use crate::results::TestResult;
+use crate::state::ServerState;
+
+/// Results waiting for a sink before new ones are dropped.
+const QUEUE_DEPTH: usize = 256;
+/// Limit on one publish, connecting included.
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(5);
+const CLIENT_ID: &str = "proj2-serv";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExportTarget {
+    Webhook { host: String, path: String },
+    Kafka { broker: String, topic: String },
+    Mqtt { broker: String, topic: String },
+}
+
+impl ExportTarget {
+    pub fn parse(url: &str) -> Option<Self> {
+        let (scheme, rest) = url.split_once("://")?;
+        let (authority, path) = rest.split_once('/')?;
+        let with_port = |default| with_port(authority, default);
+        match scheme {
+            "http" => Some(ExportTarget::Webhook { host: with_port(80)?, path: format!("/{}", path) }),
+            "kafka" if !path.is_empty() => Some(ExportTarget::Kafka { broker: with_port(9092)?, topic: path.to_string() }),
+            "mqtt" if !path.is_empty() => Some(ExportTarget::Mqtt { broker: with_port(1883)?, topic: path.to_string() }),
+            _ => None,
+        }
+    }
+}
+
+/// `host[:port]` as `host:port`, with `default` filled in.
+pub fn with_port(authority: &str, default: u16) -> Option<String> {
+    match authority.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => Some(authority.to_string()),
+        Some(_) => None,
+        None if !authority.is_empty() => Some(format!("{}:{}", authority, default)),
+        None => None,
+    }
+}
+
+/// POST `body` to `http://<host><path>` on a connection of its own; an error
+/// unless the endpoint answers 2xx.
+pub async fn http_post(host: &str, path: &str, content_type: &str, body: &str) -> anyhow::Result<()> {
+    let mut stream = TcpStream::connect(host).await.with_context(|| format!("connecting to {}", host))?;
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        host,
+        CLIENT_ID,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).await?;
+    let mut head = [0u8; 64];
+    let n = stream.read(&mut head).await?;
+    let status = String::from_utf8_lossy(&head[..n]);
+    let code = status.split(' ').nth(1).unwrap_or_default();
+    ensure!(status.starts_with("HTTP/1.") && code.starts_with('2'), "endpoint answered {:?}", status.lines().next().unwrap_or_default());
+    Ok(())
+}
+
+impl std::fmt::Display for ExportTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportTarget::Webhook { host, path } => write!(f, "http://{}{}", host, path),
+            ExportTarget::Kafka { broker, topic } => write!(f, "kafka://{}/{}", broker, topic),
+            ExportTarget::Mqtt { broker, topic } => write!(f, "mqtt://{}/{}", broker, topic),
+        }
+    }
+}
+
+/// A destination for completed test results.
+pub trait ResultSink: Send + 'static {
+    /// Deliver one result. On error the sink drops any connection it holds, so
+    /// the next call starts afresh.
+    fn publish(&mut self, result: &TestResult) -> impl Future<Output = anyhow::Result<()>> + Send;
+}
+
+/// Queues feeding the configured sinks.
+pub struct Exports {
+    queues: Vec<(ExportTarget, mpsc::Sender<TestResult>)>,
+    /// Receiving ends, taken when the sink tasks start.
+    pending: Mutex<Vec<(ExportTarget, mpsc::Receiver<TestResult>)>>,
+}
+
+impl Exports {
+    pub fn new(targets: &[ExportTarget]) -> Self {
+        let (queues, pending) = targets
+            .iter()
+            .map(|target| {
+                let (tx, rx) = mpsc::channel(QUEUE_DEPTH);
+                ((target.clone(), tx), (target.clone(), rx))
+            })
+            .unzip();
+        Exports { queues, pending: Mutex::new(pending) }
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.queues.is_empty()
+    }
+
+    /// Queue `result` for every sink.
+    pub fn export(&self, result: &TestResult, metrics: &Metrics) {
+        for (target, queue) in &self.queues {
+            if queue.try_send(result.clone()).is_err() {
+                eprintln!("Result export to {} is backed up; dropping a result", target);
+                metrics.export_dropped();
+            }
+        }
+    }
+}
+
+/// Run a task per configured sink until the server stops.
+pub async fn run_exports(state: Arc<ServerState>) {
+    let pending = std::mem::take(&mut *state.exports.pending.lock().unwrap());
+    let mut sinks = JoinSet::new();
+    for (target, queue) in pending {
+        println!("Exporting results to {}", target);
+        let state = state.clone();
+        let spawned = match target.clone() {
+            ExportTarget::Webhook { host, path } => Ok(sinks.spawn(run_sink(Webhook { host, path }, target.clone(), queue, state))),
+            ExportTarget::Kafka { broker, topic } => Kafka::new(&broker, topic).map(|sink| sinks.spawn(run_sink(sink, target.clone(), queue, state))),
+            ExportTarget::Mqtt { broker, topic } => Mqtt::new(&broker, topic).map(|sink| sinks.spawn(run_sink(sink, target.clone(), queue, state))),
+        };
+        if let Err(e) = spawned {
+            eprintln!("Not exporting results to {}: {:#}", target, e);
+        }
+    }
+    while sinks.join_next().await.is_some() {}
+}
+
+async fn run_sink<S: ResultSink>(mut sink: S, target: ExportTarget, mut queue: mpsc::Receiver<TestResult>, state: Arc<ServerState>) {
+    while let Some(result) = queue.recv().await {
+        let mut outcome = Ok(());
+        for _ in 0..2 {
+            outcome = match tokio::time::timeout(PUBLISH_TIMEOUT, sink.publish(&result)).await {
+                Ok(outcome) => outcome,
+                Err(_) => Err(anyhow::anyhow!("timed out after {:?}", PUBLISH_TIMEOUT)),
+            };
+            if outcome.is_ok() {
+                break;
+            }
+        }
+        if let Err(e) = outcome {
+            eprintln!("[{}] result export to {} failed: {:#}", result.tenant, target, e);
+            state.metrics.export_failed();
+        }
+    }
+}
+
+/// POST each result to an HTTP endpoint, one connection per result.
+struct Webhook {
+    host: String,
+    path: String,
+}
+
+impl ResultSink for Webhook {
+    async fn publish(&mut self, result: &TestResult) -> anyhow::Result<()> {
+        http_post(&self.host, &self.path, "application/json", &result.to_json()).await
+    }
+}
+
+/// Produce each result as one record keyed by tenant, through librdkafka.
+struct Kafka {
+    topic: String,
+    producer: FutureProducer,
+}
+
+impl Kafka {
+    fn new(broker: &str, topic: String) -> anyhow::Result<Self> {
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", broker)
+            .set("client.id", CLIENT_ID)
+            .set("message.timeout.ms", PUBLISH_TIMEOUT.as_millis().to_string())
+            .create()
+            .with_context(|| format!("creating a Kafka producer for {}", broker))?;
+        Ok(Kafka { topic, producer })
+    }
+}
+
+impl ResultSink for Kafka {
+    async fn publish(&mut self, result: &TestResult) -> anyhow::Result<()> {
+        let payload = result.to_json();
+        let record = FutureRecord::to(&self.topic).key(&result.tenant).payload(&payload);
+        self.producer.send(record, Timeout::After(PUBLISH_TIMEOUT)).await.map_err(|(e, _)| e)?;
+        Ok(())
+    }
+}
+
+/// Publish each result at QoS 0 on one long-lived MQTT session, which a task
+/// of its own keeps connected.
+struct Mqtt {
+    topic: String,
+    client: AsyncClient,
+    _connection: AbortOnDropHandle<()>,
+}
+
+impl Mqtt {
+    fn new(broker: &str, topic: String) -> anyhow::Result<Self> {
+        let (host, port) = broker.rsplit_once(':').context("broker has no port")?;
+        let options = MqttOptions::new(format!("{}-{}", CLIENT_ID, std::process::id()), host, port.parse()?);
+        let (client, mut events) = AsyncClient::new(options, QUEUE_DEPTH);
+        let broker = broker.to_string();
+        let connection = tokio::spawn(async move {
+            loop {
+                if let Err(e) = events.poll().await {
+                    // Polling again reconnects; until then publishes wait.
+                    eprintln!("MQTT connection to {} failed: {}", broker, e);
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        });
+        Ok(Mqtt { topic, client, _connection: AbortOnDropHandle::new(connection) })
+    }
+}
+
+impl ResultSink for Mqtt {
+    async fn publish(&mut self, result: &TestResult) -> anyhow::Result<()> {
+        self.client.publish(&self.topic, QoS::AtMostOnce, false, result.to_json()).await?;
+        Ok(())
+    }
+}
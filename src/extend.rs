@@ -0,0 +1,48 @@
+// proj2-serv/src/extend.rs
+// `EXTEND <secs>`: lengthen the window of a running test, for interactive
+// clients that keep testing while someone watches. A window grows to at most
+// `--max-total-duration`, counting any OMIT= warm-up; an EXTEND past that grows
+// it to the maximum, and one at the maximum is refused. On UDP the command is a
+// datagram from the test's address, extends every test running for that
+// address and is answered with `EXTENDED window_ms=<n>`, the new length. On TCP
+// it goes on the test's connection: a download reads it between writes, an
+// upload recognises it as the last line of a chunk, as it does END_UPLOAD.
+// There is no reply on TCP, where the connection carries the test's data; the
+// server logs the outcome.
+
+use std::time::Duration;
+
+use crate::protocol::{error_frame, Command, ErrorCode};
+use crate::session::Window;
+use crate::state::ServerState;
+
+/// Extend `windows` as `cmd` asks; the reply, `EXTENDED` or an ERR frame.
+pub fn extend<'a>(state: &ServerState, cmd: &Command, windows: impl IntoIterator<Item = &'a Window>) -> String {
+    let Some(max_total) = state.config.max_total_duration else {
+        return error_frame(ErrorCode::Disabled, "EXTEND is disabled (--max-total-duration 0)");
+    };
+    let Some(by) = cmd.args.first().and_then(|v| v.parse::<u64>().ok()).filter(|&secs| secs > 0) else {
+        return error_frame(ErrorCode::BadCommand, "usage: EXTEND <secs>");
+    };
+    let mut running = false;
+    let mut longest = None;
+    for window in windows {
+        running = true;
+        longest = longest.max(window.extend(Duration::from_secs(by), max_total));
+    }
+    match longest {
+        Some(len) => format!("EXTENDED window_ms={}", len.as_millis()),
+        None if running => error_frame(
+            ErrorCode::InvalidOption,
+            format!("test window is already at the maximum of {}s", max_total.as_secs()),
+        ),
+        None => error_frame(ErrorCode::NotFound, "no test running to extend"),
+    }
+}
+
+/// An `EXTEND` line ending an upload chunk: where the line starts, and the command.
+pub fn trailing(chunk: &[u8]) -> Option<(usize, Command)> {
+    let start = chunk.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+    let cmd = Command::parse(std::str::from_utf8(&chunk[start..]).ok()?);
+    (cmd.verb == "EXTEND").then_some((start, cmd))
+}
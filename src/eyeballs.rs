@@ -0,0 +1,148 @@
+// proj2-serv/src/eyeballs.rs
+// Happy Eyeballs for client-mode TCP tests (RFC 8305). When a scheduled job's
+// target resolves to both IPv6 and IPv4 addresses, the IPv6 connection is
+// tried first and the IPv4 one ATTEMPT_DELAY later, or as soon as IPv6 fails;
+// the test runs on whichever connects first. The losing attempt is left to
+// finish during the test so both handshake times are known, and the result
+// carries `happy_eyeballs=<winning family> he_ipv6_connect_ms=
+// he_ipv4_connect_ms= he_margin_ms=<loser minus winner>`, a `-` for an attempt
+// that failed. Comparing the two families' paths to a peer over time is then a
+// matter of querying the scheduler's history. Single-family targets connect as
+// before and carry none of this. Handshakes are timed on tokio's clock, so
+// tests/eyeballs.rs can race stand-in connects under paused time.
+
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use anyhow::Context;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::time::{timeout, Instant};
+
+/// Head start of the IPv6 attempt (RFC 8305's Connection Attempt Delay).
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+/// How long one connection attempt may take.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How a race between the two families went.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Race {
+    /// Family that connected first.
+    pub winner: &'static str,
+    /// Handshake time of each family's attempt; None where it failed.
+    pub ipv6: Option<Duration>,
+    pub ipv4: Option<Duration>,
+}
+
+/// A won race whose losing attempt may still be connecting.
+pub struct Racing<S = TcpStream> {
+    race: Race,
+    rest: mpsc::Receiver<Attempt<S>>,
+}
+
+type Attempt<S> = (&'static str, anyhow::Result<(S, Duration)>);
+
+impl<S> Racing<S> {
+    /// The race with the loser's handshake time, once it has finished.
+    pub async fn settle(mut self) -> Race {
+        if let Some((family, Ok((_, connect)))) = self.rest.recv().await {
+            self.race.set(family, connect);
+        }
+        self.race
+    }
+}
+
+impl Race {
+    fn set(&mut self, family: &str, connect: Duration) {
+        match family {
+            "ipv6" => self.ipv6 = Some(connect),
+            _ => self.ipv4 = Some(connect),
+        }
+    }
+
+    /// How much longer the loser's handshake took than the winner's.
+    fn margin(&self) -> Option<f64> {
+        let (winner, loser) = match self.winner {
+            "ipv6" => (self.ipv6?, self.ipv4?),
+            _ => (self.ipv4?, self.ipv6?),
+        };
+        Some((loser.as_secs_f64() - winner.as_secs_f64()) * 1000.0)
+    }
+}
+
+/// Connect to `addrs`, racing the families when both are present; also gives
+/// the handshake time of the connection used.
+pub async fn connect(addrs: &[SocketAddr]) -> anyhow::Result<(TcpStream, Duration, Option<Racing>)> {
+    race(addrs, TcpStream::connect).await
+}
+
+/// `connect` with `dial` making each connection.
+pub(crate) async fn race<S, F, Fut>(addrs: &[SocketAddr], dial: F) -> anyhow::Result<(S, Duration, Option<Racing<S>>)>
+where
+    S: Send + 'static,
+    F: Fn(SocketAddr) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = std::io::Result<S>> + Send,
+{
+    let ipv6 = addrs.iter().copied().find(SocketAddr::is_ipv6);
+    let ipv4 = addrs.iter().copied().find(SocketAddr::is_ipv4);
+    let (Some(ipv6), Some(ipv4)) = (ipv6, ipv4) else {
+        let peer = *addrs.first().context("no addresses to connect to")?;
+        let (stream, connect) = attempt(peer, dial).await?;
+        return Ok((stream, connect, None));
+    };
+    let (tx, mut rx) = mpsc::channel(2);
+    let (failed_tx, failed) = tokio::sync::oneshot::channel::<()>();
+    let v6 = tx.clone();
+    let dial6 = dial.clone();
+    tokio::spawn(async move {
+        let outcome = attempt(ipv6, dial6).await;
+        if outcome.is_err() {
+            let _ = failed_tx.send(());
+        }
+        let _ = v6.send(("ipv6", outcome)).await;
+    });
+    tokio::spawn(async move {
+        // Either the head start runs out or IPv6 has already failed.
+        let _ = timeout(ATTEMPT_DELAY, failed).await;
+        let _ = tx.send(("ipv4", attempt(ipv4, dial).await)).await;
+    });
+    let mut race = Race { winner: "", ipv6: None, ipv4: None };
+    let mut last_error = None;
+    while let Some((family, outcome)) = rx.recv().await {
+        match outcome {
+            Ok((stream, connect)) => {
+                race.winner = family;
+                race.set(family, connect);
+                return Ok((stream, connect, Some(Racing { race, rest: rx })));
+            }
+            Err(e) => last_error = Some(e.context(format!("{} attempt", family))),
+        }
+    }
+    Err(last_error.expect("both attempts report"))
+}
+
+/// One connection attempt and its handshake time.
+async fn attempt<S, Fut>(peer: SocketAddr, dial: impl Fn(SocketAddr) -> Fut) -> anyhow::Result<(S, Duration)>
+where
+    Fut: Future<Output = std::io::Result<S>>,
+{
+    let start = Instant::now();
+    let stream = timeout(CONNECT_TIMEOUT, dial(peer))
+        .await
+        .context("connect timed out")?
+        .context("connect failed")?;
+    Ok((stream, start.elapsed()))
+}
+
+impl fmt::Display for Race {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ms = |t: Option<Duration>| t.map_or("-".to_string(), |t| format!("{:.2}", t.as_secs_f64() * 1000.0));
+        write!(f, "happy_eyeballs={} he_ipv6_connect_ms={} he_ipv4_connect_ms={}", self.winner, ms(self.ipv6), ms(self.ipv4))?;
+        if let Some(margin) = self.margin() {
+            write!(f, " he_margin_ms={:.2}", margin)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,223 @@
+// proj2-serv/src/filexfer.rs
+// Real-file transfers over the TCP control connection, named from the client's
+// side like START_UPLOAD/START_DOWNLOAD:
+//   SEND_FILE <name> SIZE=<bytes>  client uploads; server replies `OK SEND_FILE`,
+//                                  reads exactly SIZE bytes, then `OK bytes=<n>`
+//   RECV_FILE <name>               client downloads; server replies
+//                                  `OK RECV_FILE size=<n>` and streams the file
+// Files live in `--file-dir`; names are a single plain path component, and
+// sizes are capped by `--max-file-size` (adjustable with `ADMIN SET`). The
+// client must wait for the OK line before sending file data. A name already
+// being uploaded gets `ERR BUSY`. Downloads use sendfile where available.
+
+use std::io::SeekFrom;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use anyhow::Context;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::hostres;
+use crate::protocol::{self, error_frame, Command, ErrorCode};
+use crate::results::{Direction, Protocol, TestResult};
+use crate::session::SessionGuard;
+use crate::state::ServerState;
+use crate::zerocopy;
+
+const BUF_SIZE: usize = 64 * 1024;
+/// Bytes handed to one sendfile call.
+const SENDFILE_CHUNK: usize = 1024 * 1024;
+
+/// Resolve a client-supplied name inside the configured directory.
+/// Errors are ready-made ERR frames.
+fn resolve(state: &ServerState, name: Option<&String>) -> Result<(PathBuf, String), String> {
+    let dir = state.config.file_dir.as_ref().ok_or_else(|| error_frame(ErrorCode::Disabled, "file transfers are disabled (no --file-dir)"))?;
+    let name = name.ok_or_else(|| error_frame(ErrorCode::BadCommand, "missing file name"))?;
+    if !valid_file_name(name) {
+        return Err(error_frame(ErrorCode::InvalidOption, format!("invalid file name {:?}", name)));
+    }
+    Ok((dir.join(name), name.clone()))
+}
+
+/// A single path component of safe characters, not hidden and not `.`/`..`.
+pub fn valid_file_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 255
+        && !name.starts_with('.')
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || b"._-".contains(&b))
+}
+
+/// `RECV_FILE <name>`: stream a file to the client.
+pub async fn recv_file(
+    stream: &mut TcpStream,
+    cmd: &Command,
+    peer: SocketAddr,
+    state: &ServerState,
+    session: &SessionGuard,
+) -> anyhow::Result<()> {
+    let tenant = cmd.tenant();
+    let (path, name) = match resolve(state, cmd.args.first()) {
+        Ok(resolved) => resolved,
+        Err(e) => return reply(stream, &e).await,
+    };
+    let mut file = match File::open(&path).await {
+        Ok(file) => file,
+        Err(e) => return reply(stream, &error_frame(open_error(&e), format!("cannot open {}: {}", name, e))).await,
+    };
+    let size = file.metadata().await?.len();
+    if size > state.limits.max_file_size() {
+        return reply(stream, &error_frame(ErrorCode::Failed, format!("{} exceeds the {} byte limit", name, state.limits.max_file_size()))).await;
+    }
+    reply(stream, &format!("OK RECV_FILE size={}", size)).await?;
+
+    state.metrics.session_started(&tenant);
+    session.begin(&tenant, Direction::Download);
+    let cpu = hostres::snapshot();
+    let start = Instant::now();
+    let mut sent = 0u64;
+    let mut buf = vec![0u8; BUF_SIZE];
+    let outcome: anyhow::Result<()> = async {
+        // sendfile works on its own offset, leaving `file`'s position for the fallback.
+        let mut zero_copy = Some(file.try_clone().await?.into_std().await);
+        while sent < size {
+            if let Some(std_file) = &zero_copy {
+                let count = SENDFILE_CHUNK.min((size - sent) as usize);
+                let res = tokio::select! {
+                    res = zerocopy::sendfile(stream, std_file, sent, count) => res,
+                    _ = session.token().cancelled() => anyhow::bail!("cancelled"),
+                };
+                match res {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        sent += n as u64;
+                        session.add_bytes(n as u64);
+                    }
+                    Err(e) if zerocopy::is_unsupported(&e) => {
+                        zero_copy = None;
+                        file.seek(SeekFrom::Start(sent)).await?;
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+                continue;
+            }
+            let n = tokio::select! {
+                res = file.read(&mut buf) => res.with_context(|| format!("reading {}", name))?,
+                _ = session.token().cancelled() => anyhow::bail!("cancelled"),
+            };
+            if n == 0 {
+                break;
+            }
+            let n = n.min((size - sent) as usize);
+            tokio::select! {
+                res = stream.write_all(&buf[..n]) => res?,
+                _ = session.token().cancelled() => anyhow::bail!("cancelled"),
+            }
+            sent += n as u64;
+            session.add_bytes(n as u64);
+        }
+        Ok(())
+    }
+    .await;
+    session.end();
+    if let Err(e) = &outcome {
+        println!("[{}] TCP file download {} to {} stopped: {:#}", tenant, name, peer, e);
+    }
+    let result = TestResult::new(&tenant, Protocol::Tcp, Direction::Download, peer, sent, start.elapsed())
+        .with_file(&name)
+        .with_host_usage(cpu.and_then(|c| c.usage()))
+        .with_run(cmd.run());
+    state.record(result);
+    Ok(())
+}
+
+/// `SEND_FILE <name> SIZE=<bytes>`: receive a file from the client. It is
+/// written under a temporary name, created exclusively so that one upload of a
+/// name runs at a time, and only renamed into place when complete.
+pub async fn send_file(
+    stream: &mut TcpStream,
+    cmd: &Command,
+    peer: SocketAddr,
+    state: &ServerState,
+    session: &SessionGuard,
+) -> anyhow::Result<()> {
+    let tenant = cmd.tenant();
+    let (path, name) = match resolve(state, cmd.args.first()) {
+        Ok(resolved) => resolved,
+        Err(e) => return reply(stream, &e).await,
+    };
+    let Some(size) = cmd.opt("SIZE").and_then(protocol::parse_byte_count) else {
+        return reply(stream, &error_frame(ErrorCode::BadCommand, "usage: SEND_FILE <name> SIZE=<bytes>")).await;
+    };
+    if size > state.limits.max_file_size() {
+        return reply(stream, &error_frame(ErrorCode::InvalidOption, format!("SIZE={} exceeds the {} byte limit", size, state.limits.max_file_size()))).await;
+    }
+    if tokio::fs::try_exists(&path).await.unwrap_or(true) {
+        return reply(stream, &error_frame(ErrorCode::Failed, format!("{} already exists", name))).await;
+    }
+    let partial = path.with_file_name(format!(".{}.partial", name));
+    let mut file = match OpenOptions::new().write(true).create_new(true).open(&partial).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            return reply(stream, &error_frame(ErrorCode::Busy, format!("{} is already being uploaded", name))).await;
+        }
+        Err(e) => return reply(stream, &error_frame(ErrorCode::Failed, format!("cannot create {}: {}", name, e))).await,
+    };
+    reply(stream, "OK SEND_FILE").await?;
+
+    state.metrics.session_started(&tenant);
+    session.begin(&tenant, Direction::Upload);
+    let cpu = hostres::snapshot();
+    let start = Instant::now();
+    let mut received = 0u64;
+    let mut buf = vec![0u8; BUF_SIZE];
+    let outcome: anyhow::Result<()> = async {
+        while received < size {
+            let want = BUF_SIZE.min((size - received) as usize);
+            let n = tokio::select! {
+                res = stream.read(&mut buf[..want]) => res?,
+                _ = session.token().cancelled() => anyhow::bail!("cancelled"),
+            };
+            if n == 0 {
+                anyhow::bail!("client closed after {} of {} bytes", received, size);
+            }
+            file.write_all(&buf[..n]).await.with_context(|| format!("writing {}", name))?;
+            received += n as u64;
+            session.add_bytes(n as u64);
+        }
+        file.flush().await?;
+        tokio::fs::rename(&partial, &path).await.with_context(|| format!("renaming {}", name))?;
+        Ok(())
+    }
+    .await;
+    session.end();
+    let elapsed = start.elapsed();
+    match &outcome {
+        Ok(()) => reply(stream, &format!("OK bytes={}", received)).await?,
+        Err(e) => {
+            println!("[{}] TCP file upload {} from {} failed: {:#}", tenant, name, peer, e);
+            let _ = tokio::fs::remove_file(&partial).await;
+        }
+    }
+    let result = TestResult::new(&tenant, Protocol::Tcp, Direction::Upload, peer, received, elapsed)
+        .with_file(&name)
+        .with_host_usage(cpu.and_then(|c| c.usage()))
+        .with_run(cmd.run());
+    state.record(result);
+    Ok(())
+}
+
+fn open_error(e: &std::io::Error) -> ErrorCode {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => ErrorCode::NotFound,
+        std::io::ErrorKind::PermissionDenied => ErrorCode::Unauthorized,
+        _ => ErrorCode::Failed,
+    }
+}
+
+async fn reply(stream: &mut TcpStream, line: &str) -> anyhow::Result<()> {
+    stream.write_all(format!("{}\n", line).as_bytes()).await?;
+    Ok(())
+}
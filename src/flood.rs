@@ -0,0 +1,518 @@
+// proj2-serv/src/flood.rs
+// UDP download floods. The default sender is an async task on the shared
+// runtime that queues datagrams for the plane's central sender (central.rs),
+// which batches them onto the sockets and handles a full send buffer;
+// `SENDER=thread` (or `--udp-sender thread`) instead busy-sends from a
+// dedicated OS thread, so packet rate is not bounded by task scheduling
+// latency. Both produce the same byte stream and accounting. With
+// `CONNECTED=1` the flood owns a socket connected to the client, on which an
+// ICMP error is the client's alone, so it ends the flood. With `REPLAY=` the
+// async sender paces datagrams by a capture's sizes and timings instead,
+// sending them itself, with `RAMP=` by a staircase of rates (ramp.rs), and
+// with `OVERHEAD=` it floods at each of a series of datagram sizes in turn
+// (overhead.rs). A `RATE=` the kernel does not pace is paced by the async
+// sender (pacing.rs).
+// `SENDER=fair` is accepted as the async sender.
+
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use crate::central::{CentralSender, Datagram};
+use crate::clock;
+use crate::icmp;
+use crate::impair::{ImpairedSocket, Impairment};
+use crate::interval::Measured;
+use crate::overhead::Overhead;
+use crate::pacing::{Mechanism, Pacing};
+use crate::payload::PayloadSource;
+use crate::pkttrace::PacketLog;
+use crate::ramp::{self, Ramp};
+use crate::replay::{self, Trace};
+use crate::resume::{Resumes, UdpMove};
+use crate::session::SessionGuard;
+use crate::sockopt;
+
+/// Datagram payload without `DATAGRAM=`; fits a 1500-byte MTU with room to spare.
+pub const PAYLOAD_SIZE: usize = 1400;
+const BURST: usize = 16; // tune 4..32
+const BACKOFF_US: u64 = 20; // microsecond backoff after a hard send error
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SenderMode {
+    Async,
+    Thread,
+}
+
+impl SenderMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            // The async sender has been the fair one since it moved to the
+            // central sender.
+            "async" | "fair" => Some(SenderMode::Async),
+            "thread" => Some(SenderMode::Thread),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SenderMode::Async => "async",
+            SenderMode::Thread => "thread",
+        }
+    }
+}
+
+/// One download flood toward `dest`.
+pub struct Flood {
+    pub dest: SocketAddr,
+    pub target: Option<u64>,
+    pub omit: Duration,
+    /// IP TOS byte to mark datagrams with.
+    pub tos: Option<u8>,
+    /// Sending on a per-session socket connected to `dest`.
+    pub connected: bool,
+    /// Capture whose packet sizes and spacing to replay.
+    pub replay: Option<Trace>,
+    /// Staircase of rates to pace the download through; counts what it sent.
+    pub ramp: Option<Ramp>,
+    /// Datagram sizes to flood at in turn; counts what it sent.
+    pub overhead: Option<Overhead>,
+    /// Rate cap; the flood spaces its datagrams when it is user-paced.
+    pub pacing: Option<Pacing>,
+    /// Per-packet log asked for with `PKT_TRACE=1`.
+    pub packets: Option<PacketLog>,
+    /// New destinations of a resumable download (`RESUME`).
+    pub moves: Option<mpsc::Receiver<UdpMove>>,
+    pub resumes: Resumes,
+    /// The plane's central sender, and this flood's share of it.
+    pub central: CentralSender,
+    pub weight: usize,
+    /// Payload bytes of each datagram (`DATAGRAM=`).
+    pub datagram: usize,
+    pub source: Box<dyn PayloadSource>,
+    pub session: SessionGuard,
+}
+
+pub struct Sent {
+    pub bytes: usize,
+    pub measured: Measured,
+    /// Sender that actually ran; a thread request can fall back to async.
+    pub mode: SenderMode,
+    /// ICMP error that stopped a connected flood.
+    pub unreachable: Option<String>,
+}
+
+impl Flood {
+    /// The test's window; `EXTEND` can lengthen it while the flood runs.
+    fn window(&self) -> Duration {
+        self.session.window().get()
+    }
+
+    fn running(&self, start: Instant, sent: usize) -> bool {
+        clock::elapsed(start) < self.window()
+            && self.target.is_none_or(|t| (sent as u64) < t)
+            && !self.session.token().is_cancelled()
+    }
+
+    /// Whether a send error means the client is gone; only a connected
+    /// socket's errors can be pinned on this flood's client.
+    fn peer_gone(&self, e: &std::io::Error) -> bool {
+        self.connected && icmp::is_peer_error(e)
+    }
+
+    /// Follow the client to the address it resumed the test from, if it moved.
+    fn follow_moves(&mut self) {
+        let Some(moves) = &mut self.moves else { return };
+        while let Ok((dest, gap)) = moves.try_recv() {
+            self.dest = dest;
+            self.resumes.add(gap);
+        }
+    }
+
+    fn log_packet(&mut self, len: usize) {
+        if let Some(log) = &mut self.packets {
+            log.record(len);
+        }
+    }
+
+    /// Length of the next datagram, short at the end of a `BYTES=` target.
+    fn next_len(&self, sent: usize) -> usize {
+        self.target.map_or(self.datagram, |t| self.datagram.min(t.saturating_sub(sent as u64) as usize))
+    }
+
+    /// Run with the requested sender, round-robin over `socks`.
+    pub async fn run(mut self, mode: SenderMode, socks: Vec<ImpairedSocket>, impairment: Impairment) -> (Self, Sent) {
+        if mode == SenderMode::Thread {
+            let user_paced = self.pacing.is_some_and(|p| p.mechanism == Mechanism::User);
+            if self.replay.is_some() || self.ramp.is_some() || self.overhead.is_some() || user_paced {
+                // Paced sends sleep on the runtime's timers.
+                eprintln!("UDP thread sender unavailable with REPLAY=, RAMP=, OVERHEAD= or user-paced RATE=; using async sender for {}", self.dest);
+            } else if !impairment.jitter.is_zero() {
+                // Delayed sends need the runtime's timers.
+                eprintln!("UDP thread sender unavailable with --impair-jitter; using async sender for {}", self.dest);
+            } else {
+                match spawn_sender_thread(&socks, impairment) {
+                    Ok((flood_tx, done)) => {
+                        let _ = flood_tx.send(self);
+                        return done.await.expect("UDP sender thread panicked");
+                    }
+                    Err(e) => eprintln!("UDP sender thread for {} unavailable: {}; using async sender", self.dest, e),
+                }
+            }
+        }
+        let sent = self.run_async(socks).await;
+        (self, sent)
+    }
+
+    /// Queue datagrams for the central sender, to go out in turn with other
+    /// floods'. A datagram counts as sent once queued; the flood returns once
+    /// its queue has gone out, so the FIN that follows cannot overtake it.
+    async fn run_async(&mut self, socks: Vec<ImpairedSocket>) -> Sent {
+        if let Some(trace) = self.replay.take() {
+            let sent = self.run_replay(&socks, &trace).await;
+            self.replay = Some(trace);
+            return sent;
+        }
+        if let Some(mut ramp) = self.ramp.take() {
+            let sent = self.run_ramp(&socks, &mut ramp).await;
+            self.ramp = Some(ramp);
+            return sent;
+        }
+        if let Some(mut overhead) = self.overhead.take() {
+            let sent = self.run_overhead(&socks, &mut overhead).await;
+            self.overhead = Some(overhead);
+            return sent;
+        }
+        let sock_count = socks.len();
+        let lane = self.central.lane(self.weight, socks, self.connected, self.session.shared_counters());
+        let start = clock::now();
+        let mut sent_bytes = 0usize;
+        let mut measured = Measured::new(start, self.omit);
+        let mut next_sock = 0usize;
+        let mut unreachable = None;
+        let mut unpaced = 0usize;
+
+        while self.running(start, sent_bytes) {
+            if let Some(e) = lane.failed() {
+                unreachable = Some(e.clone());
+                break;
+            }
+            if let Some(due) = self.pacing.and_then(|p| p.due(start, sent_bytes as u64))
+                && !pace(due, &mut unpaced, self.session.token()).await
+            {
+                break;
+            }
+            self.follow_moves();
+            let len = self.next_len(sent_bytes);
+            if len == 0 {
+                break;
+            }
+            let mut buf = vec![0u8; len];
+            self.source.fill(&mut buf);
+            let datagram = Datagram { buf, dest: self.dest, tos: self.tos, sock: next_sock % sock_count };
+            next_sock += 1;
+            let remaining = self.window().saturating_sub(clock::elapsed(start));
+            let queued = tokio::select! {
+                queued = lane.send(datagram) => queued,
+                _ = tokio::time::sleep(remaining) => break,
+                _ = self.session.token().cancelled() => break,
+            };
+            if !queued {
+                eprintln!("UDP central sender stopped; ending download to {}", self.dest);
+                break;
+            }
+            sent_bytes += len;
+            measured.add(len as u64);
+            self.session.add_bytes(len as u64);
+            self.log_packet(len);
+        }
+        lane.flush().await;
+        Sent { bytes: sent_bytes, measured, mode: SenderMode::Async, unreachable }
+    }
+
+    /// Send the capture's datagrams at their offsets from the start, looping it
+    /// until the flood ends. A datagram already due goes out at once, so the
+    /// schedule is caught up after a stall rather than shifted.
+    async fn run_replay(&mut self, socks: &[ImpairedSocket], trace: &Trace) -> Sent {
+        let start = clock::now();
+        let mut sent_bytes = 0usize;
+        let mut measured = Measured::new(start, self.omit);
+        let mut payload = vec![0u8; replay::MAX_DATAGRAM];
+        let mut unreachable = None;
+        let mut unpaced = 0usize;
+
+        for (i, packet) in trace.schedule().enumerate() {
+            if !self.running(start, sent_bytes) || packet.at >= self.window() {
+                break;
+            }
+            if !pace(start + packet.at, &mut unpaced, self.session.token()).await {
+                break;
+            }
+            self.follow_moves();
+            let len = self.target.map_or(packet.len, |t| packet.len.min(t.saturating_sub(sent_bytes as u64) as usize));
+            self.source.fill(&mut payload[..len]);
+            let sock = &socks[i % socks.len()];
+            let Some(result) = send_paced(sock, &payload[..len], self.dest, self.tos, &self.session, start).await else { break };
+            match result {
+                Ok(n) => {
+                    sent_bytes += n;
+                    measured.add(n as u64);
+                    self.session.add_bytes(n as u64);
+                    self.log_packet(n);
+                }
+                Err(e) if self.peer_gone(&e) => {
+                    unreachable = Some(e.to_string());
+                    break;
+                }
+                Err(e) => eprintln!("UDP send_to error to {}: {:?}", self.dest, e),
+            }
+        }
+        Sent { bytes: sent_bytes, measured, mode: SenderMode::Async, unreachable }
+    }
+
+    /// Send at each rate of the staircase in turn, every datagram stamped with
+    /// its step and sequence number for the client to count.
+    async fn run_ramp(&mut self, socks: &[ImpairedSocket], ramp: &mut Ramp) -> Sent {
+        let start = clock::now();
+        let mut sent_bytes = 0usize;
+        let mut measured = Measured::new(start, self.omit);
+        let mut payload = vec![0u8; self.datagram];
+        let mut unreachable = None;
+        let mut unpaced = 0usize;
+
+        for (seq, (step, at)) in ramp.schedule(self.datagram).enumerate() {
+            if !self.running(start, sent_bytes) || at >= self.window() {
+                break;
+            }
+            if !pace(start + at, &mut unpaced, self.session.token()).await {
+                break;
+            }
+            self.follow_moves();
+            let len = self.next_len(sent_bytes);
+            if len == 0 {
+                break;
+            }
+            self.source.fill(&mut payload[..len]);
+            ramp::stamp(&mut payload[..len], step, seq);
+            let sock = &socks[seq % socks.len()];
+            let Some(result) = send_paced(sock, &payload[..len], self.dest, self.tos, &self.session, start).await else { break };
+            match result {
+                Ok(n) => {
+                    sent_bytes += n;
+                    measured.add(n as u64);
+                    self.session.add_bytes(n as u64);
+                    self.log_packet(n);
+                    ramp.sent[step] += 1;
+                }
+                Err(e) if self.peer_gone(&e) => {
+                    unreachable = Some(e.to_string());
+                    break;
+                }
+                Err(e) => eprintln!("UDP send_to error to {}: {:?}", self.dest, e),
+            }
+        }
+        Sent { bytes: sent_bytes, measured, mode: SenderMode::Async, unreachable }
+    }
+
+    /// Flood at each datagram size in turn, one step each, every datagram
+    /// stamped like a ramp's for the client to count.
+    async fn run_overhead(&mut self, socks: &[ImpairedSocket], overhead: &mut Overhead) -> Sent {
+        let start = clock::now();
+        let mut sent_bytes = 0usize;
+        let mut measured = Measured::new(start, self.omit);
+        let mut payload = vec![0u8; overhead.sizes.iter().copied().max().unwrap_or(0)];
+        let mut unreachable = None;
+        let mut unpaced = 0usize;
+
+        for seq in 0.. {
+            let step = (clock::elapsed(start).as_secs_f64() / overhead.step.as_secs_f64()) as usize;
+            if step >= overhead.sizes.len() || !self.running(start, sent_bytes) {
+                break;
+            }
+            if !pace(start, &mut unpaced, self.session.token()).await {
+                break;
+            }
+            self.follow_moves();
+            let len = self.target.map_or(overhead.sizes[step], |t| overhead.sizes[step].min(t.saturating_sub(sent_bytes as u64) as usize));
+            if len == 0 {
+                break;
+            }
+            self.source.fill(&mut payload[..len]);
+            ramp::stamp(&mut payload[..len], step, seq);
+            let sock = &socks[seq % socks.len()];
+            let Some(result) = send_paced(sock, &payload[..len], self.dest, self.tos, &self.session, start).await else { break };
+            match result {
+                Ok(n) => {
+                    sent_bytes += n;
+                    measured.add(n as u64);
+                    self.session.add_bytes(n as u64);
+                    self.log_packet(n);
+                    overhead.sent[step] += 1;
+                }
+                Err(e) if self.peer_gone(&e) => {
+                    unreachable = Some(e.to_string());
+                    break;
+                }
+                Err(e) => eprintln!("UDP send_to error to {}: {:?}", self.dest, e),
+            }
+        }
+        Sent { bytes: sent_bytes, measured, mode: SenderMode::Async, unreachable }
+    }
+
+    /// Busy-send on the calling thread. The sockets stay non-blocking (they share
+    /// the runtime's file descriptions), so a full send buffer is retried in a
+    /// spin rather than parked. Drop and duplication are applied here.
+    fn run_blocking(&mut self, socks: &[std::net::UdpSocket], impairment: Impairment) -> Sent {
+        let start = clock::now();
+        let mut sent_bytes = 0usize;
+        let mut measured = Measured::new(start, self.omit);
+        let mut payload = vec![0u8; self.datagram];
+        let mut next_sock = 0usize;
+        let mut unreachable = None;
+
+        while self.running(start, sent_bytes) {
+            self.follow_moves();
+            let len = self.next_len(sent_bytes);
+            if len == 0 {
+                break;
+            }
+            self.source.fill(&mut payload[..len]);
+            let sock = &socks[next_sock % socks.len()];
+            next_sock += 1;
+            let copies = if impairment.is_active() { impairment.copies() } else { 1 };
+            let mut result = Ok(len);
+            for _ in 0..copies {
+                result = self.send_spinning(sock, &payload[..len], start);
+                if result.is_err() {
+                    break;
+                }
+            }
+            match result {
+                Ok(_) => {
+                    sent_bytes += len;
+                    measured.add(len as u64);
+                    self.session.add_bytes(len as u64);
+                    self.log_packet(len);
+                }
+                // Only returned once the flood is over.
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                Err(e) if self.peer_gone(&e) => {
+                    unreachable = Some(e.to_string());
+                    break;
+                }
+                Err(e) => {
+                    eprintln!("UDP send_to error to {}: {:?}", self.dest, e);
+                    std::thread::sleep(Duration::from_micros(BACKOFF_US));
+                }
+            }
+        }
+        Sent { bytes: sent_bytes, measured, mode: SenderMode::Thread, unreachable }
+    }
+
+    /// Send one datagram, spinning while the socket buffer is full. Gives up
+    /// with WouldBlock once the flood should stop.
+    fn send_spinning(&self, sock: &std::net::UdpSocket, datagram: &[u8], start: Instant) -> std::io::Result<usize> {
+        let mut blocked = false;
+        loop {
+            let res = match self.tos {
+                Some(tos) => sockopt::send_to_with_tos_std(sock, datagram, self.dest, tos),
+                None => sock.send_to(datagram, self.dest),
+            };
+            match res {
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    // One full buffer per datagram, however long the spin.
+                    if !blocked {
+                        blocked = true;
+                        self.session.would_block();
+                    }
+                    if clock::elapsed(start) >= self.window() || self.session.token().is_cancelled() {
+                        return Err(e);
+                    }
+                    std::hint::spin_loop();
+                }
+                Err(e) => {
+                    self.session.send_error();
+                    return Err(e);
+                }
+                sent => return sent,
+            }
+        }
+    }
+}
+
+/// Wait for a paced datagram's `due` time; false if the session was cancelled
+/// meanwhile. Datagrams already due go out at once, with a yield every BURST of
+/// them, so a stalled schedule is caught up without hogging the runtime.
+async fn pace(due: Instant, unpaced: &mut usize, cancel: &CancellationToken) -> bool {
+    if due > clock::now() {
+        *unpaced = 0;
+        tokio::select! {
+            _ = tokio::time::sleep_until(due.into()) => true,
+            _ = cancel.cancelled() => false,
+        }
+    } else {
+        *unpaced += 1;
+        if unpaced.is_multiple_of(BURST) {
+            tokio::task::yield_now().await;
+        }
+        true
+    }
+}
+
+/// Send one paced datagram of `session`'s flood, parking while the socket
+/// buffer is full; None if the flood should stop first.
+async fn send_paced(
+    sock: &ImpairedSocket,
+    datagram: &[u8],
+    dest: SocketAddr,
+    tos: Option<u8>,
+    session: &SessionGuard,
+    start: Instant,
+) -> Option<std::io::Result<usize>> {
+    loop {
+        match sock.try_send_marked(datagram, dest, tos) {
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                session.would_block();
+                let remaining = session.window().get().saturating_sub(clock::elapsed(start));
+                if !wait_writable(sock, remaining, session.token()).await {
+                    return None;
+                }
+            }
+            Err(e) => {
+                session.send_error();
+                return Some(Err(e));
+            }
+            sent => return Some(sent),
+        }
+    }
+}
+
+/// Park until `sock` is writable again; false if the flood should stop first
+/// (`remaining` of its window ran out or the session was cancelled).
+async fn wait_writable(sock: &ImpairedSocket, remaining: Duration, cancel: &CancellationToken) -> bool {
+    tokio::select! {
+        ready = sock.writable() => ready.is_ok(),
+        _ = tokio::time::sleep(remaining) => false,
+        _ = cancel.cancelled() => false,
+    }
+}
+
+type FloodHandoff = (std::sync::mpsc::Sender<Flood>, tokio::sync::oneshot::Receiver<(Flood, Sent)>);
+
+/// Start a sender thread over std handles on `socks`. The flood is handed to it
+/// through the returned channel once it is known to be running, and comes back
+/// with the outcome on the receiver.
+fn spawn_sender_thread(socks: &[ImpairedSocket], impairment: Impairment) -> std::io::Result<FloodHandoff> {
+    let std_socks = socks.iter().map(ImpairedSocket::try_clone_std).collect::<std::io::Result<Vec<_>>>()?;
+    let (flood_tx, flood_rx) = std::sync::mpsc::channel::<Flood>();
+    let (done_tx, done_rx) = tokio::sync::oneshot::channel();
+    std::thread::Builder::new().name("udp-sender".to_string()).spawn(move || {
+        let Ok(mut flood) = flood_rx.recv() else { return };
+        let sent = flood.run_blocking(&std_socks, impairment);
+        let _ = done_tx.send((flood, sent));
+    })?;
+    Ok((flood_tx, done_rx))
+}
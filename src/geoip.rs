@@ -0,0 +1,79 @@
+// proj2-serv/src/geoip.rs
+// GeoIP enrichment (`--geoip <file.mmdb>`, repeatable). Every recorded result
+// is looked up by its client address in local MaxMind databases, typically
+// GeoLite2-Country and GeoLite2-ASN, each asked for both the country and the
+// autonomous system so either kind serves; the first database to know a field
+// wins. The result line then carries `country=<ISO code> asn=<number>
+// as_org=<name>` (spaces in the name as underscores), which lands in ADMIN
+// RESULTS, the exports and the collector, whose queries can filter on them to
+// break reports down by network. The databases are read once at startup.
+
+use std::fmt;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use maxminddb::{geoip2, Reader};
+
+pub struct GeoIp {
+    readers: Vec<Reader<Vec<u8>>>,
+}
+
+/// What the databases know of a client address.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Geo {
+    pub country: Option<String>,
+    pub asn: Option<u32>,
+    pub as_org: Option<String>,
+}
+
+impl GeoIp {
+    /// None without databases.
+    pub fn load(paths: &[PathBuf]) -> anyhow::Result<Option<Self>> {
+        if paths.is_empty() {
+            return Ok(None);
+        }
+        let readers = paths.iter().map(|path| open(path)).collect::<anyhow::Result<_>>()?;
+        Ok(Some(GeoIp { readers }))
+    }
+
+    /// None when no database knows `ip`.
+    pub fn lookup(&self, ip: IpAddr) -> Option<Geo> {
+        let ip = ip.to_canonical();
+        let mut geo = Geo::default();
+        for reader in &self.readers {
+            if let Ok(asn) = reader.lookup::<geoip2::Asn>(ip) {
+                geo.asn = geo.asn.or(asn.autonomous_system_number);
+                geo.as_org = geo.as_org.or(asn.autonomous_system_organization.map(|org| org.replace(' ', "_")));
+            }
+            if let Ok(country) = reader.lookup::<geoip2::Country>(ip) {
+                geo.country = geo.country.or(country.country.and_then(|c| c.iso_code).map(str::to_string));
+            }
+        }
+        (geo != Geo::default()).then_some(geo)
+    }
+}
+
+fn open(path: &Path) -> anyhow::Result<Reader<Vec<u8>>> {
+    let reader = Reader::open_readfile(path).with_context(|| format!("opening GeoIP database {}", path.display()))?;
+    println!("GeoIP database {} ({})", path.display(), reader.metadata.database_type);
+    Ok(reader)
+}
+
+impl fmt::Display for Geo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut sep = "";
+        if let Some(country) = &self.country {
+            write!(f, "country={}", country)?;
+            sep = " ";
+        }
+        if let Some(asn) = self.asn {
+            write!(f, "{}asn={}", sep, asn)?;
+            sep = " ";
+        }
+        if let Some(org) = &self.as_org {
+            write!(f, "{}as_org={}", sep, org)?;
+        }
+        Ok(())
+    }
+}
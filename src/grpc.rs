@@ -0,0 +1,192 @@
+// proj2-serv/src/grpc.rs
+// gRPC admin service (`--grpc-port`, proto/admin.proto) for orchestrating a
+// fleet of servers: list and kill sessions, read and change the runtime
+// limits, and fetch metrics and results, the operations of the loopback ADMIN
+// queries. Without `--grpc-token` it listens on loopback only, like ADMIN;
+// with a token file it listens on every interface and each call must carry
+// `authorization: Bearer <token>`, which is compared in constant time.
+
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use anyhow::{bail, Context};
+use subtle::ConstantTimeEq;
+use tokio::net::TcpListener;
+use tonic::transport::server::TcpIncoming;
+use tonic::{Request, Response, Status};
+
+use crate::clock;
+use crate::state::ServerState;
+
+pub mod proto {
+    tonic::include_proto!("proj2serv.admin.v1");
+}
+
+use proto::admin_server::{Admin, AdminServer};
+use proto::{
+    GetLimitsRequest, GetMetricsRequest, GetMetricsResponse, KillSessionRequest, KillSessionResponse, Limits, ListResultsRequest,
+    ListResultsResponse, ListSessionsRequest, ListSessionsResponse, SetLimitRequest,
+};
+
+/// Shared secret a remote caller presents as a bearer token.
+#[derive(Clone)]
+pub struct Token(Vec<u8>);
+
+impl Token {
+    /// Read a token file; surrounding whitespace is ignored.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("reading gRPC token {}", path.display()))?;
+        let token = text.trim();
+        if token.is_empty() {
+            bail!("gRPC token {} is empty", path.display());
+        }
+        Ok(Token(token.as_bytes().to_vec()))
+    }
+
+    fn check<T>(&self, request: &Request<T>) -> Result<(), Status> {
+        let presented = request
+            .metadata()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .ok_or_else(|| Status::unauthenticated("missing bearer token"))?;
+        if bool::from(presented.as_bytes().ct_eq(&self.0)) {
+            Ok(())
+        } else {
+            Err(Status::unauthenticated("wrong bearer token"))
+        }
+    }
+}
+
+struct AdminService {
+    state: Arc<ServerState>,
+}
+
+#[tonic::async_trait]
+impl Admin for AdminService {
+    async fn list_sessions(&self, request: Request<ListSessionsRequest>) -> Result<Response<ListSessionsResponse>, Status> {
+        let tenant = request.into_inner().tenant;
+        let sessions = self
+            .state
+            .sessions
+            .list()
+            .into_iter()
+            .filter(|s| tenant.as_deref().is_none_or(|t| t == s.tenant))
+            .map(|s| proto::Session {
+                id: s.id,
+                protocol: s.protocol.as_str().to_string(),
+                peer: s.peer.to_string(),
+                tenant: s.tenant,
+                direction: s.direction.map_or("idle", |d| d.as_str()).to_string(),
+                bytes: s.bytes,
+                packets: s.packets,
+                age_ms: clock::elapsed(s.started).as_millis() as u64,
+                idle_ms: clock::elapsed(s.last_activity).as_millis() as u64,
+            })
+            .collect();
+        Ok(Response::new(ListSessionsResponse { sessions }))
+    }
+
+    async fn kill_session(&self, request: Request<KillSessionRequest>) -> Result<Response<KillSessionResponse>, Status> {
+        let peer = request.remote_addr();
+        let id = request.into_inner().id;
+        if !self.state.sessions.kill(id) {
+            return Err(Status::not_found(format!("no session {}", id)));
+        }
+        println!("gRPC admin {} killed session {}", display(peer), id);
+        Ok(Response::new(KillSessionResponse {}))
+    }
+
+    async fn get_limits(&self, _request: Request<GetLimitsRequest>) -> Result<Response<Limits>, Status> {
+        Ok(Response::new(self.limits()))
+    }
+
+    async fn set_limit(&self, request: Request<SetLimitRequest>) -> Result<Response<Limits>, Status> {
+        let peer = request.remote_addr();
+        let SetLimitRequest { name, value } = request.into_inner();
+        let name = name.to_ascii_lowercase();
+        self.state.limits.set(&name, &value).map_err(Status::invalid_argument)?;
+        println!("gRPC admin {} set limit {}={}", display(peer), name, value);
+        Ok(Response::new(self.limits()))
+    }
+
+    async fn get_metrics(&self, request: Request<GetMetricsRequest>) -> Result<Response<GetMetricsResponse>, Status> {
+        let tenant = request.into_inner().tenant;
+        let metrics = &self.state.metrics;
+        let tenants = metrics
+            .tenant_totals()
+            .into_iter()
+            .filter(|(name, _)| tenant.as_deref().is_none_or(|t| t == name))
+            .map(|(name, m)| proto::TenantMetrics {
+                tenant: name,
+                sessions_started: m.sessions_started,
+                sessions_finished: m.sessions_finished,
+                bytes_sent: m.bytes_sent,
+                bytes_received: m.bytes_received,
+                echo_requests: m.echo_requests,
+            })
+            .collect();
+        let server = metrics.server();
+        Ok(Response::new(GetMetricsResponse {
+            tenants,
+            task_panics: server.task_panics,
+            accept_exhausted: server.accept_exhausted,
+            accept_paused: server.accept_paused,
+            exports_failed: server.exports_failed,
+            exports_dropped: server.exports_dropped,
+        }))
+    }
+
+    async fn list_results(&self, request: Request<ListResultsRequest>) -> Result<Response<ListResultsResponse>, Status> {
+        let tenant = request.into_inner().tenant;
+        let results = self
+            .state
+            .results
+            .query(tenant.as_deref())
+            .iter()
+            .map(|r| proto::TestResult {
+                tenant: r.tenant.clone(),
+                protocol: r.protocol.as_str().to_string(),
+                direction: r.direction.as_str().to_string(),
+                peer: r.peer.to_string(),
+                bytes: r.bytes,
+                duration_ms: r.duration.as_millis() as u64,
+                bps: r.throughput_bps(),
+                finished_at: r.finished_at.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+                line: r.to_line(),
+            })
+            .collect();
+        Ok(Response::new(ListResultsResponse { results }))
+    }
+}
+
+impl AdminService {
+    fn limits(&self) -> Limits {
+        let limits = &self.state.limits;
+        let secs = |d: Option<std::time::Duration>| d.map_or(0, |d| d.as_secs());
+        Limits {
+            max_test_duration_s: limits.max_test_duration().as_secs(),
+            max_file_size: limits.max_file_size(),
+            handshake_timeout_s: secs(limits.handshake_timeout()),
+            inactivity_timeout_s: secs(limits.inactivity_timeout()),
+        }
+    }
+}
+
+fn display(peer: Option<SocketAddr>) -> String {
+    peer.map_or("-".to_string(), |p| p.to_string())
+}
+
+/// Serve the admin service on `listener` until the server stops.
+pub async fn run_grpc(listener: TcpListener, state: Arc<ServerState>, token: Option<Token>) {
+    let service = AdminServer::with_interceptor(AdminService { state }, move |request: Request<()>| match &token {
+        Some(token) => token.check(&request).map(|()| request),
+        None => Ok(request),
+    });
+    let served = tonic::transport::Server::builder().add_service(service).serve_with_incoming(TcpIncoming::from(listener)).await;
+    if let Err(e) = served {
+        eprintln!("gRPC admin service stopped: {}", e);
+    }
+}
@@ -0,0 +1,314 @@
+// proj2-serv/src/handover.rs
+// Zero-downtime restart. On SIGUSR2 the server execs a fresh copy of its binary
+// (same path and arguments) and passes it every listener (TCP, UDP, UDS,
+// dashboard, health, gRPC) over a Unix socket pair with SCM_RIGHTS. The new process
+// takes them in place of binding, like socket-activated ones, and answers READY
+// once both planes serve; only then does the old process stop accepting, refuse
+// new tests as if draining, and exit when its running tests are done or the
+// longest test could have finished. If the new process fails to start or to
+// report ready in time it is killed and the old one keeps serving. UDP uploads
+// in flight end at the handover, since the old process stops reading the shared
+// socket. Not available under `--sandbox`, which forbids exec.
+
+use std::io::Write;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, ensure, Context};
+
+use crate::state::ServerState;
+use crate::supervisor::Plane;
+use crate::systemd::{self, Inherited};
+
+/// Environment variable naming the new process's end of the socket pair.
+const ENV: &str = "PROJ2_HANDOVER_FD";
+/// How long the new process has to bring its planes up.
+const READY_TIMEOUT: Duration = Duration::from_secs(10);
+const READY: &[u8] = b"READY\n";
+/// Poll interval while waiting for planes or sessions.
+const POLL: Duration = Duration::from_millis(50);
+
+static HANDED_OVER: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Listener {
+    Tcp,
+    Udp,
+    Uds,
+    Dashboard,
+    Health,
+    Grpc,
+}
+
+impl Listener {
+    const ALL: [Listener; 6] = [Listener::Tcp, Listener::Udp, Listener::Uds, Listener::Dashboard, Listener::Health, Listener::Grpc];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Listener::Tcp => "tcp",
+            Listener::Udp => "udp",
+            Listener::Uds => "uds",
+            Listener::Dashboard => "dashboard",
+            Listener::Health => "health",
+            Listener::Grpc => "grpc",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Listener> {
+        Listener::ALL.into_iter().find(|l| l.as_str() == name)
+    }
+}
+
+/// Descriptors of the listeners in use, to pass on at a restart.
+pub struct Listeners {
+    fds: [AtomicI32; 6],
+}
+
+impl Default for Listeners {
+    fn default() -> Self {
+        Listeners { fds: std::array::from_fn(|_| AtomicI32::new(-1)) }
+    }
+}
+
+impl Listeners {
+    /// Record the socket now serving as `listener`, replacing any earlier one.
+    pub fn serving(&self, listener: Listener, sock: &impl AsRawFd) {
+        self.fds[listener as usize].store(sock.as_raw_fd(), Ordering::Relaxed);
+    }
+
+    /// Recorded listeners still in use; a plane that is down may have closed its socket.
+    pub fn live(&self, state: &ServerState) -> Vec<(Listener, RawFd)> {
+        Listener::ALL
+            .into_iter()
+            .filter(|&l| match l {
+                Listener::Tcp => state.health.is_up(Plane::Tcp),
+                Listener::Udp => state.health.is_up(Plane::Udp),
+                _ => true,
+            })
+            .filter_map(|l| {
+                let fd = self.fds[l as usize].load(Ordering::Relaxed);
+                (fd >= 0).then_some((l, fd))
+            })
+            .collect()
+    }
+}
+
+/// Whether this process handed its listeners to a successor; it must then
+/// leave shared resources such as the UDS path alone on exit.
+pub fn handed_over() -> bool {
+    HANDED_OVER.load(Ordering::Relaxed)
+}
+
+/// Take the listeners passed by the process this one replaces, if it is a
+/// successor. Must run before any other thread starts, since it clears ENV.
+pub fn take(inherited: &mut Inherited) -> anyhow::Result<()> {
+    let Ok(fd) = std::env::var(ENV) else { return Ok(()) };
+    // SAFETY: called from main before the runtime or any other thread exists.
+    unsafe { std::env::remove_var(ENV) };
+    let fd: RawFd = fd.parse().with_context(|| format!("invalid {}={:?}", ENV, fd))?;
+    // SAFETY: the previous process passed this end of the pair to us alone.
+    let channel = unsafe { UnixStream::from_raw_fd(fd) };
+    // Not for any process this one starts in turn.
+    // SAFETY: fcntl on a descriptor we own.
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+        return Err(std::io::Error::last_os_error()).context("setting FD_CLOEXEC on handover channel");
+    }
+    for (listener, fd) in recv_fds(&channel)? {
+        let sock = socket2::Socket::from(fd);
+        match listener {
+            Listener::Tcp => inherited.tcp = Some(sock.into()),
+            Listener::Udp => inherited.udp = Some(sock.into()),
+            Listener::Uds => inherited.uds = Some(sock.into()),
+            Listener::Dashboard => inherited.dashboard = Some(sock.into()),
+            Listener::Health => inherited.health = Some(sock.into()),
+            Listener::Grpc => inherited.grpc = Some(sock.into()),
+        }
+    }
+    println!("Took over listeners from the previous process");
+    inherited.handover = Some(channel);
+    Ok(())
+}
+
+/// In a successor: tell the previous process to stop accepting once both
+/// planes serve. Silence past READY_TIMEOUT makes it give up on us.
+pub async fn confirm(channel: UnixStream, state: std::sync::Arc<ServerState>) {
+    let deadline = Instant::now() + READY_TIMEOUT;
+    while !(state.health.is_up(Plane::Tcp) && state.health.is_up(Plane::Udp)) {
+        if Instant::now() >= deadline {
+            eprintln!("Planes not up after {:?}; not taking over from the previous process", READY_TIMEOUT);
+            return;
+        }
+        tokio::time::sleep(POLL).await;
+    }
+    if let Err(e) = (&channel).write_all(READY) {
+        eprintln!("Cannot confirm takeover to the previous process: {}", e);
+    }
+}
+
+/// Hand the listeners to a new process on every SIGUSR2 until one takes over.
+pub async fn serve_restarts(state: &ServerState) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut usr2 = match signal(SignalKind::user_defined2()) {
+        Ok(usr2) => usr2,
+        Err(e) => {
+            eprintln!("Hot restart unavailable: cannot listen for SIGUSR2: {}", e);
+            return std::future::pending().await;
+        }
+    };
+    while usr2.recv().await.is_some() {
+        if state.config.sandbox {
+            eprintln!("Hot restart requested but --sandbox forbids exec; still serving");
+            continue;
+        }
+        println!("Hot restart requested: starting a new process");
+        match restart(state).await {
+            Ok(pid) => {
+                println!("New process {} took over the listeners", pid);
+                systemd::notify(&format!("MAINPID={}", pid));
+                HANDED_OVER.store(true, Ordering::Relaxed);
+                return;
+            }
+            Err(e) => eprintln!("Hot restart failed, still serving: {:#}", e),
+        }
+    }
+    std::future::pending().await
+}
+
+/// Start the successor and pass it the listeners; its pid once it is ready.
+async fn restart(state: &ServerState) -> anyhow::Result<u32> {
+    let fds = state.listeners.live(state);
+    ensure!(!fds.is_empty(), "no listeners to hand over");
+    let (ours, theirs) = UnixStream::pair().context("creating handover socket pair")?;
+    let child_fd = theirs.as_raw_fd();
+    // argv[0] rather than current_exe(), which names the old file once an upgrade replaced it.
+    let mut args = std::env::args_os();
+    let program = args.next().context("no argv[0] to restart")?;
+    let mut command = std::process::Command::new(program);
+    command.args(args).env(ENV, child_fd.to_string());
+    // SAFETY: only fcntl runs between fork and exec, which is async-signal-safe.
+    unsafe {
+        command.pre_exec(move || match libc::fcntl(child_fd, libc::F_SETFD, 0) {
+            -1 => Err(std::io::Error::last_os_error()),
+            _ => Ok(()),
+        });
+    }
+    let mut child = command.spawn().context("starting new process")?;
+    drop(theirs);
+    let handed = async {
+        send_fds(&ours, &fds)?;
+        ours.set_nonblocking(true)?;
+        let mut ours = tokio::net::UnixStream::from_std(ours)?;
+        let mut reply = [0u8; READY.len()];
+        tokio::io::AsyncReadExt::read_exact(&mut ours, &mut reply).await.context("new process exited before it was ready")?;
+        ensure!(reply == READY, "unexpected reply {:?}", String::from_utf8_lossy(&reply));
+        anyhow::Ok(())
+    };
+    let outcome = match tokio::time::timeout(READY_TIMEOUT, handed).await {
+        Ok(outcome) => outcome,
+        Err(_) => Err(anyhow::anyhow!("new process not ready after {:?}", READY_TIMEOUT)),
+    };
+    if let Err(e) = outcome {
+        let _ = child.kill();
+        let _ = child.wait();
+        return Err(e);
+    }
+    Ok(child.id())
+}
+
+/// After a handover: let running tests finish, refusing new ones, then end
+/// what is left. Returns once no session remains or the grace period is over.
+pub async fn finish_sessions(state: &ServerState, grace: Duration) {
+    state.drain.start(Duration::from_secs(1));
+    let deadline = Instant::now() + state.limits.max_test_duration();
+    let testing = || state.sessions.list().iter().any(|s| s.direction.is_some());
+    while testing() && Instant::now() < deadline {
+        tokio::time::sleep(POLL).await;
+    }
+    state.sessions.shutdown();
+    let deadline = Instant::now() + grace;
+    while !state.sessions.is_empty() && Instant::now() < deadline {
+        tokio::time::sleep(POLL).await;
+    }
+}
+
+/// Send the listener names as one line, with the descriptors attached in the same order.
+fn send_fds(channel: &UnixStream, fds: &[(Listener, RawFd)]) -> anyhow::Result<()> {
+    let names: Vec<&str> = fds.iter().map(|(l, _)| l.as_str()).collect();
+    let line = format!("{}\n", names.join(" "));
+    let raw: Vec<RawFd> = fds.iter().map(|&(_, fd)| fd).collect();
+    let payload = std::mem::size_of_val(raw.as_slice()) as u32;
+    // SAFETY: CMSG_SPACE is a pure size computation.
+    let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(payload) } as usize];
+    let mut iov = libc::iovec { iov_base: line.as_ptr() as *mut libc::c_void, iov_len: line.len() };
+    // SAFETY: msghdr is plain data; every pointer set below outlives the sendmsg call.
+    let sent = unsafe {
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = control.len() as _;
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(payload) as _;
+        std::ptr::copy_nonoverlapping(raw.as_ptr(), libc::CMSG_DATA(cmsg) as *mut RawFd, raw.len());
+        libc::sendmsg(channel.as_raw_fd(), &msg, 0)
+    };
+    if sent < 0 {
+        return Err(std::io::Error::last_os_error()).context("sending listeners");
+    }
+    ensure!(sent as usize == line.len(), "short write of the listener list");
+    Ok(())
+}
+
+fn recv_fds(channel: &UnixStream) -> anyhow::Result<Vec<(Listener, OwnedFd)>> {
+    let mut line = [0u8; 256];
+    let payload = (Listener::ALL.len() * std::mem::size_of::<RawFd>()) as u32;
+    // SAFETY: CMSG_SPACE is a pure size computation.
+    let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(payload) } as usize];
+    let mut iov = libc::iovec { iov_base: line.as_mut_ptr() as *mut libc::c_void, iov_len: line.len() };
+    let mut fds = Vec::new();
+    // SAFETY: as in send_fds; the kernel fills at most the buffers given, and each
+    // received descriptor is new to this process and owned by us alone.
+    let received = unsafe {
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = control.len() as _;
+        let n = libc::recvmsg(channel.as_raw_fd(), &mut msg, 0);
+        if n >= 0 {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                    let count = ((*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize) / std::mem::size_of::<RawFd>();
+                    let data = libc::CMSG_DATA(cmsg) as *const RawFd;
+                    for i in 0..count {
+                        let fd = std::ptr::read_unaligned(data.add(i));
+                        libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+                        fds.push(OwnedFd::from_raw_fd(fd));
+                    }
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+        n
+    };
+    if received < 0 {
+        return Err(std::io::Error::last_os_error()).context("receiving listeners");
+    }
+    let line = String::from_utf8_lossy(&line[..received as usize]);
+    let names: Vec<&str> = line.split_whitespace().collect();
+    if names.len() != fds.len() {
+        bail!("got {} descriptors for listeners {:?}", fds.len(), names);
+    }
+    names
+        .into_iter()
+        .zip(fds)
+        .map(|(name, fd)| Ok((Listener::parse(name).with_context(|| format!("unknown listener {:?}", name))?, fd)))
+        .collect()
+}
@@ -0,0 +1,120 @@
+// proj2-serv/src/health.rs
+// Liveness and readiness endpoints for orchestrators (`--health-port`), served
+// on all interfaces since kubelet probes the pod address. Each request probes
+// the planes from inside the process over loopback: a TCP connect plus `CAPS`,
+// and a `CAPS` datagram to the UDP socket, so a wedged accept or receive loop
+// shows up even while its port is still bound.
+// `GET /healthz` is 200 unless a bound plane fails its probe; `GET /readyz` is
+// 200 only when both planes answer and the server is not draining. The body is
+// `tcp=<state> udp=<state> draining=<0|1>`, a state being `ok`, `down` (not
+// bound) or `unresponsive`.
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{ensure, Context};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+use crate::dashboard;
+use crate::state::ServerState;
+use crate::supervisor::Plane;
+
+/// How long a plane has to answer its probe.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PlaneState {
+    Ok,
+    Down,
+    Unresponsive,
+}
+
+impl PlaneState {
+    fn as_str(self) -> &'static str {
+        match self {
+            PlaneState::Ok => "ok",
+            PlaneState::Down => "down",
+            PlaneState::Unresponsive => "unresponsive",
+        }
+    }
+}
+
+pub async fn run_health(listener: TcpListener, state: Arc<ServerState>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle(stream, &state).await {
+                        eprintln!("Health client {} error: {}", peer, e);
+                    }
+                });
+            }
+            Err(e) => {
+                eprintln!("Health accept error: {:?}", e);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+    }
+}
+
+async fn handle(mut stream: TcpStream, state: &ServerState) -> std::io::Result<()> {
+    let Some(path) = dashboard::read_request_path(&mut stream).await? else {
+        return dashboard::respond(&mut stream, "400 Bad Request", "text/plain", "bad request\n").await;
+    };
+    let ready = match path.as_str() {
+        "/healthz" => false,
+        "/readyz" => true,
+        _ => return dashboard::respond(&mut stream, "404 Not Found", "text/plain", "not found\n").await,
+    };
+    let (tcp, udp) = tokio::join!(check(state, Plane::Tcp), check(state, Plane::Udp));
+    let draining = state.drain.active();
+    let healthy = tcp != PlaneState::Unresponsive && udp != PlaneState::Unresponsive;
+    let ok = if ready { tcp == PlaneState::Ok && udp == PlaneState::Ok && !draining } else { healthy };
+    let body = format!("tcp={} udp={} draining={}\n", tcp.as_str(), udp.as_str(), draining as u8);
+    let status = if ok { "200 OK" } else { "503 Service Unavailable" };
+    dashboard::respond(&mut stream, status, "text/plain", &body).await
+}
+
+async fn check(state: &ServerState, plane: Plane) -> PlaneState {
+    let Some(port) = state.health.port(plane) else { return PlaneState::Down };
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let probe = async {
+        match plane {
+            Plane::Tcp => probe_tcp(addr).await,
+            Plane::Udp => probe_udp(addr).await,
+        }
+    };
+    match tokio::time::timeout(PROBE_TIMEOUT, probe).await {
+        Ok(Ok(())) => PlaneState::Ok,
+        Ok(Err(e)) => {
+            eprintln!("Health probe of the {} plane failed: {:#}", plane.as_str(), e);
+            PlaneState::Unresponsive
+        }
+        Err(_) => {
+            eprintln!("Health probe of the {} plane timed out", plane.as_str());
+            PlaneState::Unresponsive
+        }
+    }
+}
+
+async fn probe_tcp(addr: SocketAddr) -> anyhow::Result<()> {
+    let mut stream = TcpStream::connect(addr).await.context("connect")?;
+    stream.write_all(b"CAPS\n").await?;
+    let mut buf = [0u8; 256];
+    let n = stream.read(&mut buf).await?;
+    ensure!(buf[..n].starts_with(b"CAPS"), "unexpected reply {:?}", String::from_utf8_lossy(&buf[..n]));
+    Ok(())
+}
+
+async fn probe_udp(addr: SocketAddr) -> anyhow::Result<()> {
+    let sock = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.context("bind")?;
+    sock.connect(addr).await?;
+    sock.send(b"CAPS").await?;
+    let mut buf = [0u8; 256];
+    let n = sock.recv(&mut buf).await?;
+    ensure!(buf[..n].starts_with(b"CAPS"), "unexpected reply {:?}", String::from_utf8_lossy(&buf[..n]));
+    Ok(())
+}
@@ -0,0 +1,79 @@
+// proj2-serv/src/histogram.rs
+// Latency distributions on the hdrhistogram crate: values up to an hour in µs
+// are kept to three significant digits in fixed memory, so tail percentiles
+// can be reported without storing every sample. RoundTrips pairs an RTT
+// histogram with one of jitter, the RTT change between consecutive round
+// trips, for OWD replies and echo results.
+
+use std::fmt;
+
+/// Significant decimal digits kept for each value.
+const SIGFIGS: u8 = 3;
+/// Largest value kept as is; larger ones count as this.
+const HIGHEST: u64 = 3_600_000_000;
+
+/// Percentiles reported for a distribution.
+pub const QUANTILES: [(&str, f64); 4] = [("p50", 0.50), ("p95", 0.95), ("p99", 0.99), ("p999", 0.999)];
+
+#[derive(Debug, Clone)]
+pub struct Histogram(hdrhistogram::Histogram<u64>);
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram(hdrhistogram::Histogram::new_with_bounds(1, HIGHEST, SIGFIGS).expect("valid bounds"))
+    }
+}
+
+impl Histogram {
+    pub fn record(&mut self, value: u64) {
+        self.0.saturating_record(value);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Value at or below which `q` of the samples lie, to three significant
+    /// digits; 0 when empty.
+    pub fn value_at_quantile(&self, q: f64) -> u64 {
+        self.0.value_at_quantile(q)
+    }
+
+    /// `<name>_p50_us=... <name>_p999_us=...` for result lines.
+    pub fn fields(&self, name: &str) -> String {
+        QUANTILES
+            .iter()
+            .map(|(label, q)| format!("{}_{}_us={}", name, label, self.value_at_quantile(*q)))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// RTT and jitter distributions of a sequence of round trips, in µs.
+#[derive(Debug, Clone, Default)]
+pub struct RoundTrips {
+    pub rtt: Histogram,
+    pub jitter: Histogram,
+    last: Option<u64>,
+}
+
+impl RoundTrips {
+    /// Record the next round trip in order.
+    pub fn record(&mut self, rtt_us: u64) {
+        self.rtt.record(rtt_us);
+        if let Some(last) = self.last {
+            self.jitter.record(rtt_us.abs_diff(last));
+        }
+        self.last = Some(rtt_us);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rtt.is_empty()
+    }
+}
+
+impl fmt::Display for RoundTrips {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.rtt.fields("rtt"), self.jitter.fields("jitter"))
+    }
+}
@@ -0,0 +1,130 @@
+// proj2-serv/src/hostres.rs
+// The server's own CPU and memory use over a test, from /proc (Linux only), so
+// a result can show whether the host rather than the network was the limit.
+// CPU is reported for the whole process and per runtime thread; a worker near
+// 100% means that test was CPU-bound on the server.
+
+use std::time::Instant;
+
+/// CPU counters at the start of a test.
+#[derive(Debug, Clone)]
+pub struct CpuSnapshot {
+    at: Instant,
+    process_ticks: u64,
+    /// (thread id, ticks) of the runtime's threads.
+    threads: Vec<(u32, u64)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct HostUsage {
+    /// Process CPU over the test; 100 is one full core.
+    pub cpu_pct: f64,
+    /// Busy runtime threads over the test, busiest first.
+    pub worker_cpu_pct: Vec<f64>,
+    pub rss_bytes: u64,
+}
+
+impl HostUsage {
+    pub fn fields(&self) -> String {
+        let workers: Vec<String> = self.worker_cpu_pct.iter().map(|p| format!("{:.1}", p)).collect();
+        format!(
+            "cpu_pct={:.1} worker_cpu_pct={} rss_kb={}",
+            self.cpu_pct,
+            if workers.is_empty() { "-".to_string() } else { workers.join(",") },
+            self.rss_bytes / 1024
+        )
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub fn snapshot() -> Option<CpuSnapshot> {
+    Some(CpuSnapshot { at: Instant::now(), process_ticks: stat_ticks("/proc/self/stat")?, threads: runtime_threads() })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn snapshot() -> Option<CpuSnapshot> {
+    None
+}
+
+impl CpuSnapshot {
+    /// Usage between this snapshot and now.
+    #[cfg(target_os = "linux")]
+    pub fn usage(&self) -> Option<HostUsage> {
+        let elapsed = self.at.elapsed().as_secs_f64();
+        if elapsed <= 0.0 {
+            return None;
+        }
+        // SAFETY: sysconf has no memory-safety preconditions.
+        let hz = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as f64;
+        let pct = |ticks: u64| ticks as f64 / hz / elapsed * 100.0;
+        let process_ticks = stat_ticks("/proc/self/stat")?;
+        let mut worker_cpu_pct: Vec<f64> = runtime_threads()
+            .into_iter()
+            .filter_map(|(tid, now)| {
+                let before = self.threads.iter().find(|(t, _)| *t == tid).map_or(0, |(_, ticks)| *ticks);
+                let delta = now.saturating_sub(before);
+                (delta > 0).then(|| pct(delta))
+            })
+            .collect();
+        worker_cpu_pct.sort_by(|a, b| b.total_cmp(a));
+        Some(HostUsage {
+            cpu_pct: pct(process_ticks.saturating_sub(self.process_ticks)),
+            worker_cpu_pct,
+            rss_bytes: rss_bytes().unwrap_or(0),
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn usage(&self) -> Option<HostUsage> {
+        None
+    }
+}
+
+/// utime + stime from a /proc stat file.
+#[cfg(target_os = "linux")]
+fn stat_ticks(path: &str) -> Option<u64> {
+    let stat = std::fs::read_to_string(path).ok()?;
+    // The command name may contain spaces; fields resume after its closing ')'.
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    Some(utime + stime)
+}
+
+/// Threads started by the tokio runtime (named `tokio-runtime-worker`,
+/// truncated by the kernel to 15 characters) plus the dedicated UDP plane thread.
+#[cfg(target_os = "linux")]
+fn runtime_threads() -> Vec<(u32, u64)> {
+    let Ok(tasks) = std::fs::read_dir("/proc/self/task") else { return Vec::new() };
+    tasks
+        .flatten()
+        .filter_map(|task| {
+            let tid: u32 = task.file_name().to_str()?.parse().ok()?;
+            let comm = std::fs::read_to_string(task.path().join("comm")).ok()?;
+            if !comm.starts_with("tokio-runtime") && !comm.starts_with(crate::UDP_PLANE_THREAD) {
+                return None;
+            }
+            Some((tid, stat_ticks(task.path().join("stat").to_str()?)?))
+        })
+        .collect()
+}
+
+#[cfg(target_os = "linux")]
+fn rss_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+/// This host's name reduced to the tenant-name alphabet, for default instance
+/// names (`--mesh-name`, `--mdns-name`).
+pub fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: the buffer is valid for its length; gethostname NUL-terminates
+    // on success unless truncated, which the fallback below covers.
+    let ok = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } == 0;
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(0);
+    let name: String = String::from_utf8_lossy(&buf[..len]).chars().filter(|c| c.is_ascii_alphanumeric() || *c == '-').take(32).collect();
+    if ok && crate::protocol::valid_tenant(&name) { name } else { "proj2-serv".to_string() }
+}
@@ -0,0 +1,208 @@
+// proj2-serv/src/icmp.rs
+// ICMP error reporting on the shared UDP socket (Linux IP_RECVERR). When a client
+// disappears, its ICMP port-unreachable replies land on the socket's error queue
+// tagged with the original destination, so we can stop that client's download.
+// On Windows such reports would instead fail the shared socket's next receive,
+// so they are switched off there.
+
+use std::io;
+use std::net::SocketAddr;
+
+use tokio::net::UdpSocket;
+
+/// An ICMP error reported for datagrams sent to `peer`.
+#[derive(Debug, Clone, Copy)]
+pub struct PeerError {
+    pub peer: SocketAddr,
+    pub errno: i32,
+}
+
+impl PeerError {
+    pub fn describe(&self) -> String {
+        io::Error::from_raw_os_error(self.errno).to_string()
+    }
+}
+
+/// Ask the kernel to queue ICMP errors with their destination instead of
+/// discarding them (unconnected UDP sockets drop them by default).
+#[cfg(target_os = "linux")]
+pub fn enable_error_queue(sock: &UdpSocket) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let on: libc::c_int = 1;
+    // SAFETY: passing a pointer to a live c_int together with its size.
+    let rc = unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            libc::IPPROTO_IP,
+            libc::IP_RECVERR,
+            &on as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if rc != 0 { Err(io::Error::last_os_error()) } else { Ok(()) }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn enable_error_queue(_sock: &UdpSocket) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "IP_RECVERR not supported on this platform"))
+}
+
+/// Wait for queued ICMP errors and return them. Never returns on platforms
+/// without an error queue.
+#[cfg(target_os = "linux")]
+pub async fn next_errors(sock: &UdpSocket) -> io::Result<Vec<PeerError>> {
+    use std::os::fd::AsRawFd;
+    use tokio::io::Interest;
+
+    sock.async_io(Interest::ERROR, || read_error_queue(sock.as_raw_fd())).await
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn next_errors(_sock: &UdpSocket) -> io::Result<Vec<PeerError>> {
+    std::future::pending().await
+}
+
+/// Drain the socket error queue; WouldBlock if it was empty.
+#[cfg(target_os = "linux")]
+fn read_error_queue(fd: std::os::fd::RawFd) -> io::Result<Vec<PeerError>> {
+    #[repr(C, align(8))]
+    struct CmsgBuf([u8; 256]);
+
+    let mut errors = Vec::new();
+    loop {
+        // SAFETY: all pointers in msghdr refer to locals that outlive the recvmsg
+        // call; cmsg parsing uses the libc macros within msg_controllen bounds.
+        unsafe {
+            let mut name: libc::sockaddr_storage = std::mem::zeroed();
+            let mut payload = [0u8; 64];
+            let mut iov = libc::iovec { iov_base: payload.as_mut_ptr() as *mut libc::c_void, iov_len: payload.len() };
+            let mut control = CmsgBuf([0; 256]);
+            let mut msg: libc::msghdr = std::mem::zeroed();
+            msg.msg_name = &mut name as *mut libc::sockaddr_storage as *mut libc::c_void;
+            msg.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.0.as_mut_ptr() as *mut libc::c_void;
+            msg.msg_controllen = control.0.len() as _;
+
+            if libc::recvmsg(fd, &mut msg, libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT) < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::WouldBlock && !errors.is_empty() {
+                    return Ok(errors);
+                }
+                return Err(err);
+            }
+            let Some(peer) = sockaddr_to_std(&name) else { continue };
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                let c = &*cmsg;
+                if (c.cmsg_level == libc::IPPROTO_IP && c.cmsg_type == libc::IP_RECVERR)
+                    || (c.cmsg_level == libc::IPPROTO_IPV6 && c.cmsg_type == libc::IPV6_RECVERR)
+                {
+                    let ee: libc::sock_extended_err = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const _);
+                    errors.push(PeerError { peer, errno: ee.ee_errno as i32 });
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub fn sockaddr_to_std(storage: &libc::sockaddr_storage) -> Option<SocketAddr> {
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
+
+    match storage.ss_family as libc::c_int {
+        libc::AF_INET => {
+            // SAFETY: ss_family says the storage holds a sockaddr_in.
+            let sin = unsafe { &*(storage as *const libc::sockaddr_storage as *const libc::sockaddr_in) };
+            let ip = Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr));
+            Some(SocketAddr::V4(SocketAddrV4::new(ip, u16::from_be(sin.sin_port))))
+        }
+        libc::AF_INET6 => {
+            // SAFETY: ss_family says the storage holds a sockaddr_in6.
+            let sin6 = unsafe { &*(storage as *const libc::sockaddr_storage as *const libc::sockaddr_in6) };
+            let ip = Ipv6Addr::from(sin6.sin6_addr.s6_addr);
+            Some(SocketAddr::V6(SocketAddrV6::new(ip, u16::from_be(sin6.sin6_port), sin6.sin6_flowinfo, sin6.sin6_scope_id)))
+        }
+        _ => None,
+    }
+}
+
+/// Errors that the kernel raises on the shared socket because of one peer's
+/// ICMP reply; they say nothing about the health of the socket itself.
+pub fn is_peer_error(e: &io::Error) -> bool {
+    /// Windows' report of an ICMP time-exceeded for an earlier send.
+    #[cfg(windows)]
+    const WSAENETRESET: i32 = 10052;
+    #[cfg(windows)]
+    if e.raw_os_error() == Some(WSAENETRESET) {
+        return true;
+    }
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::HostUnreachable
+            | io::ErrorKind::NetworkUnreachable
+    )
+}
+
+/// Windows reports an ICMP port-unreachable (or time-exceeded) for any earlier
+/// send as WSAECONNRESET (WSAENETRESET) from the next `recv_from` on the
+/// socket, whoever it came from. Turn both reports off so one dead client
+/// cannot disturb everyone else's tests; `is_peer_error` covers any that slip
+/// through.
+#[cfg(windows)]
+pub fn disable_connreset(sock: &socket2::Socket) -> io::Result<()> {
+    use std::ffi::c_void;
+    use std::os::windows::io::AsRawSocket;
+
+    const SIO_UDP_CONNRESET: u32 = 0x9800_000C;
+    const SIO_UDP_NETRESET: u32 = 0x9800_000F;
+
+    #[link(name = "ws2_32")]
+    unsafe extern "system" {
+        fn WSAIoctl(
+            socket: usize,
+            code: u32,
+            in_buf: *const c_void,
+            in_len: u32,
+            out_buf: *mut c_void,
+            out_len: u32,
+            returned: *mut u32,
+            overlapped: *mut c_void,
+            completion: *const c_void,
+        ) -> i32;
+    }
+
+    for code in [SIO_UDP_CONNRESET, SIO_UDP_NETRESET] {
+        let off: u32 = 0;
+        let mut returned = 0u32;
+        // SAFETY: in_buf points at a live BOOL of the given size; no output
+        // buffer, overlapped structure or completion routine is used.
+        let rc = unsafe {
+            WSAIoctl(
+                sock.as_raw_socket() as usize,
+                code,
+                &off as *const u32 as *const c_void,
+                std::mem::size_of::<u32>() as u32,
+                std::ptr::null_mut(),
+                0,
+                &mut returned,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+            )
+        };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(windows))]
+pub fn disable_connreset(_sock: &socket2::Socket) -> io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,200 @@
+// proj2-serv/src/impair.rs
+// Built-in network impairment for the UDP plane, so clients can be tested
+// against loss, duplication and jitter without tc/netem. Every datagram the
+// UDP plane sends can be dropped, duplicated or delayed; received datagrams can
+// be dropped, and received upload data duplicated (counted twice).
+
+use std::cell::Cell;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+
+use crate::rng::XorShift64;
+use crate::sockopt;
+
+/// Configured impairment; all zero (the default) disables it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Impairment {
+    /// Probability (0.0..=1.0) that a datagram is dropped.
+    pub drop: f64,
+    /// Probability (0.0..=1.0) that a datagram is delivered twice.
+    pub duplicate: f64,
+    /// Upper bound of a uniformly random extra delay on sends.
+    pub jitter: Duration,
+}
+
+impl Impairment {
+    pub fn is_active(&self) -> bool {
+        self.drop > 0.0 || self.duplicate > 0.0 || !self.jitter.is_zero()
+    }
+
+    /// How many copies of a datagram to deliver: 0 (dropped), 1 or 2.
+    pub fn copies(&self) -> usize {
+        if self.drop > 0.0 && random() < self.drop {
+            0
+        } else if self.duplicate > 0.0 && random() < self.duplicate {
+            2
+        } else {
+            1
+        }
+    }
+
+    fn delay(&self) -> Duration {
+        if self.jitter.is_zero() { Duration::ZERO } else { self.jitter.mul_f64(random()) }
+    }
+}
+
+/// A UDP socket whose sends go through the configured impairment.
+#[derive(Clone)]
+pub struct ImpairedSocket {
+    sock: Arc<UdpSocket>,
+    impair: Impairment,
+}
+
+impl ImpairedSocket {
+    pub fn new(sock: Arc<UdpSocket>, impair: Impairment) -> Self {
+        ImpairedSocket { sock, impair }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.sock.local_addr()
+    }
+
+    pub fn buffer_sizes(&self) -> io::Result<sockopt::BufferSizes> {
+        sockopt::BufferSizes::of(socket2::SockRef::from(&*self.sock))
+    }
+
+    pub fn sock_ref(&self) -> socket2::SockRef<'_> {
+        socket2::SockRef::from(&*self.sock)
+    }
+
+    /// Datagrams the kernel dropped on this socket so far, where it reports them.
+    pub fn kernel_drops(&self) -> Option<u64> {
+        sockopt::udp_drops(&self.sock)
+    }
+
+    /// A std handle on the same socket, for sending from a plain thread. It
+    /// shares the socket's non-blocking mode, and bypasses the impairment.
+    pub fn try_clone_std(&self) -> io::Result<std::net::UdpSocket> {
+        Ok(socket2::SockRef::from(&*self.sock).try_clone()?.into())
+    }
+
+    pub async fn send_to(&self, buf: &[u8], dest: &SocketAddr) -> io::Result<usize> {
+        self.send_marked(buf, *dest, None).await
+    }
+
+    /// Send with an optional IP TOS byte. A dropped or delayed datagram counts as
+    /// sent, as it would if the network rather than the server lost or held it.
+    /// Wait until the kernel send buffer has room again.
+    pub async fn writable(&self) -> io::Result<()> {
+        self.sock.writable().await
+    }
+
+    /// Non-blocking `send_marked`: WouldBlock if the datagram cannot be queued
+    /// now. Once the first copy is queued, a duplicate that does not fit is
+    /// dropped rather than reported, as the network would lose it.
+    pub fn try_send_marked(&self, buf: &[u8], dest: SocketAddr, tos: Option<u8>) -> io::Result<usize> {
+        if !self.impair.is_active() {
+            return try_send_once(&self.sock, buf, dest, tos);
+        }
+        let copies = self.impair.copies();
+        let delay = self.impair.delay();
+        if copies == 0 {
+            return Ok(buf.len());
+        }
+        if !delay.is_zero() {
+            // Delayed sends are queued on a timer and never block the caller.
+            self.send_later(buf, dest, tos, copies, delay);
+            return Ok(buf.len());
+        }
+        let n = try_send_once(&self.sock, buf, dest, tos)?;
+        for _ in 1..copies {
+            let _ = try_send_once(&self.sock, buf, dest, tos);
+        }
+        Ok(n)
+    }
+
+    /// Send as much of `batch` as fits now; the number sent, or WouldBlock if
+    /// none fit. Unimpaired batches go out in one `sendmmsg`.
+    pub fn try_send_batch(&self, batch: &[sockopt::Outgoing<'_>]) -> io::Result<usize> {
+        if !self.impair.is_active() {
+            return sockopt::try_sendmmsg(&self.sock, batch);
+        }
+        for (i, &(buf, dest, tos)) in batch.iter().enumerate() {
+            match self.try_send_marked(buf, dest, tos) {
+                Err(e) if i > 0 && e.kind() == io::ErrorKind::WouldBlock => return Ok(i),
+                Err(e) => return Err(e),
+                Ok(_) => {}
+            }
+        }
+        Ok(batch.len())
+    }
+
+    /// Whether both handles send on the same socket.
+    pub fn same_socket(&self, other: &ImpairedSocket) -> bool {
+        Arc::ptr_eq(&self.sock, &other.sock)
+    }
+
+    pub async fn send_marked(&self, buf: &[u8], dest: SocketAddr, tos: Option<u8>) -> io::Result<usize> {
+        if !self.impair.is_active() {
+            return send_once(&self.sock, buf, dest, tos).await;
+        }
+        let copies = self.impair.copies();
+        let delay = self.impair.delay();
+        if copies == 0 {
+            return Ok(buf.len());
+        }
+        if delay.is_zero() {
+            for _ in 0..copies {
+                send_once(&self.sock, buf, dest, tos).await?;
+            }
+            return Ok(buf.len());
+        }
+        self.send_later(buf, dest, tos, copies, delay);
+        Ok(buf.len())
+    }
+
+    fn send_later(&self, buf: &[u8], dest: SocketAddr, tos: Option<u8>, copies: usize, delay: Duration) {
+        let sock = self.sock.clone();
+        let buf = buf.to_vec();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            for _ in 0..copies {
+                if let Err(e) = send_once(&sock, &buf, dest, tos).await {
+                    eprintln!("UDP delayed send to {} failed: {:?}", dest, e);
+                }
+            }
+        });
+    }
+}
+
+fn try_send_once(sock: &UdpSocket, buf: &[u8], dest: SocketAddr, tos: Option<u8>) -> io::Result<usize> {
+    match tos {
+        Some(tos) => sockopt::try_send_to_with_tos(sock, buf, dest, tos),
+        None => sock.try_send_to(buf, dest),
+    }
+}
+
+async fn send_once(sock: &UdpSocket, buf: &[u8], dest: SocketAddr, tos: Option<u8>) -> io::Result<usize> {
+    match tos {
+        Some(tos) => sockopt::send_to_with_tos(sock, buf, dest, tos).await,
+        None => sock.send_to(buf, dest).await,
+    }
+}
+
+thread_local! {
+    static RNG: Cell<XorShift64> = Cell::new(XorShift64::from_entropy());
+}
+
+/// Uniform in [0, 1) from a per-thread generator.
+fn random() -> f64 {
+    RNG.with(|cell| {
+        let mut rng = cell.get();
+        let x = rng.next_f64();
+        cell.set(rng);
+        x
+    })
+}
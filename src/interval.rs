@@ -0,0 +1,104 @@
+// proj2-serv/src/interval.rs
+// Periodic interval reporting and warm-up (omit) accounting shared by the
+// transfer loops.
+
+use std::time::{Duration, Instant};
+
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Tracks cumulative bytes and yields one report per elapsed interval.
+pub struct IntervalTracker {
+    start: Instant,
+    last: Instant,
+    last_bytes: u64,
+}
+
+pub struct Interval {
+    /// Offset of the interval end from the start of the test.
+    pub end: Duration,
+    pub length: Duration,
+    pub bytes: u64,
+}
+
+impl Interval {
+    pub fn bps(&self) -> f64 {
+        let secs = self.length.as_secs_f64();
+        if secs > 0.0 { self.bytes as f64 * 8.0 / secs } else { 0.0 }
+    }
+}
+
+impl IntervalTracker {
+    pub fn new(start: Instant) -> Self {
+        IntervalTracker { start, last: start, last_bytes: 0 }
+    }
+
+    /// Returns an interval when at least `REPORT_INTERVAL` has passed since the previous one.
+    pub fn tick(&mut self, total_bytes: u64) -> Option<Interval> {
+        let now = Instant::now();
+        let length = now.duration_since(self.last);
+        if length < REPORT_INTERVAL {
+            return None;
+        }
+        let interval = Interval {
+            end: now.duration_since(self.start),
+            length,
+            bytes: total_bytes - self.last_bytes,
+        };
+        self.last = now;
+        self.last_bytes = total_bytes;
+        Some(interval)
+    }
+}
+
+/// Most `REPORT_INTERVAL`s a result keeps; later bytes count only in the total.
+pub const MAX_INTERVALS: usize = 3600;
+
+/// Byte accounting that leaves a warm-up period (`OMIT=<secs>`, like iperf3
+/// `--omit`) out of the reported throughput. The omitted time is added in front
+/// of the test window rather than taken out of it. Measured bytes are also
+/// counted per `REPORT_INTERVAL` for interval-based result formats.
+#[derive(Debug, Clone)]
+pub struct Measured {
+    pub omit: Duration,
+    from: Instant,
+    bytes: u64,
+    intervals: Vec<u64>,
+}
+
+impl Measured {
+    pub fn new(start: Instant, omit: Duration) -> Self {
+        Measured { omit, from: start + omit, bytes: 0, intervals: Vec::new() }
+    }
+
+    /// Count `n` bytes unless the warm-up is still running.
+    pub fn add(&mut self, n: u64) {
+        let Some(elapsed) = Instant::now().checked_duration_since(self.from) else { return };
+        self.bytes += n;
+        let index = (elapsed.as_nanos() / REPORT_INTERVAL.as_nanos()) as usize;
+        if index < MAX_INTERVALS {
+            if self.intervals.len() <= index {
+                self.intervals.resize(index + 1, 0);
+            }
+            self.intervals[index] += n;
+        }
+    }
+
+    /// Bytes measured in each `REPORT_INTERVAL` since the warm-up ended.
+    pub fn intervals(&self) -> &[u64] {
+        &self.intervals
+    }
+
+    /// Whether the warm-up is over.
+    pub fn is_measuring(&self) -> bool {
+        Instant::now() >= self.from
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Measured span ending at `ended`; zero if the test ended during warm-up.
+    pub fn duration(&self, ended: Instant) -> Duration {
+        ended.saturating_duration_since(self.from)
+    }
+}
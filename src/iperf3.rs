@@ -0,0 +1,207 @@
+// proj2-serv/src/iperf3.rs
+// Results in the shapes iperf tooling already reads, for `ADMIN RESULTS
+// FORMAT=iperf3|csv`. `iperf3` renders each result as the JSON document
+// `iperf3 -s -J` would have written for that test, one document per line:
+// `start`, per-second `intervals` and the `end` sums, seen from the server (so
+// a download is `reverse` and the server the `sender`). The server counts only
+// its own side, so `sum_sent` and `sum_received` carry the same bytes. `csv`
+// gives iperf's `-y C` rows, one per second of each result and then its
+// summary row:
+//   timestamp,local_address,local_port,remote_address,remote_port,id,interval,bytes,bits_per_second
+// The listening port is not kept with a result, so the local port is 0, and the
+// local address is the test's `SRC=` address when one was asked for.
+// Results without per-second counts (file transfers, scheduled and mesh tests)
+// have a single interval spanning the test.
+
+use std::fmt::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::dashboard;
+use crate::interval::REPORT_INTERVAL;
+use crate::results::{Direction, Protocol, TestResult};
+
+/// iperf3 reports a stream by its socket descriptor; each result has one stream.
+const SOCKET: u32 = 5;
+
+/// One iperf3 JSON document per result, each on its own line.
+pub fn json_lines(results: &[TestResult]) -> String {
+    let mut out = String::new();
+    for r in results {
+        let _ = writeln!(out, "{}", json(r));
+    }
+    out
+}
+
+/// iperf `-y C` rows for every result, interval rows first, then its summary.
+pub fn csv_rows(results: &[TestResult]) -> String {
+    let mut out = String::new();
+    for (id, r) in results.iter().enumerate() {
+        let stamp = csv_timestamp(r);
+        let local = r.src.unwrap_or(match r.peer.ip() {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        });
+        let mut row = |start: f64, end: f64, bytes: u64| {
+            let _ = writeln!(
+                out,
+                "{},{},0,{},{},{},{:.1}-{:.1},{},{:.0}",
+                stamp,
+                local,
+                r.peer.ip(),
+                r.peer.port(),
+                id + 1,
+                start,
+                end,
+                bytes,
+                bps(bytes, end - start)
+            );
+        };
+        for iv in intervals(r) {
+            row(iv.start, iv.end, iv.bytes);
+        }
+        row(0.0, r.duration.as_secs_f64(), r.bytes);
+    }
+    out
+}
+
+struct Span {
+    start: f64,
+    end: f64,
+    bytes: u64,
+}
+
+impl Span {
+    /// The fields iperf3 gives every interval and sum, without the braces.
+    fn fields(&self, sender: bool) -> String {
+        format!(
+            r#""start":{},"end":{},"seconds":{},"bytes":{},"bits_per_second":{},"omitted":false,"sender":{}"#,
+            self.start,
+            self.end,
+            self.end - self.start,
+            self.bytes,
+            bps(self.bytes, self.end - self.start),
+            sender
+        )
+    }
+}
+
+/// The measured span cut into `REPORT_INTERVAL`s, the last one partial. A
+/// last sliver under a tenth of an interval is folded into the one before it,
+/// as iperf3 does, rather than reported at a meaningless rate.
+fn intervals(r: &TestResult) -> Vec<Span> {
+    let total = r.duration.as_secs_f64();
+    if r.intervals.is_empty() {
+        return vec![Span { start: 0.0, end: total, bytes: r.bytes }];
+    }
+    let step = REPORT_INTERVAL.as_secs_f64();
+    let mut spans: Vec<Span> = r
+        .intervals
+        .iter()
+        .enumerate()
+        .map(|(i, &bytes)| {
+            let start = i as f64 * step;
+            Span { start, end: (start + step).min(total).max(start), bytes }
+        })
+        .collect();
+    if let [.., prev, last] = spans.as_mut_slice()
+        && last.end - last.start < step / 10.0
+    {
+        prev.end = last.end;
+        prev.bytes += last.bytes;
+        spans.pop();
+    }
+    spans
+}
+
+fn json(r: &TestResult) -> String {
+    let secs = r.finished_at.duration_since(UNIX_EPOCH).unwrap_or_default().saturating_sub(r.duration + r.omit).as_secs();
+    let sender = r.direction == Direction::Download;
+    let protocol = match r.protocol {
+        Protocol::Udp => "UDP",
+        _ => "TCP",
+    };
+    let local = r.src.map(|ip| ip.to_string()).unwrap_or_default();
+    let start = format!(
+        concat!(
+            r#"{{"connected":[{{"socket":{},"local_host":"{}","local_port":0,"remote_host":"{}","remote_port":{}}}],"#,
+            r#""version":"proj2-serv {}","timestamp":{{"time":"{}","timesecs":{}}},"#,
+            r#""accepted_connection":{{"host":"{}","port":{}}},"#,
+            r#""test_start":{{"protocol":"{}","num_streams":1,"omit":{},"duration":{},"bytes":{},"blocks":0,"reverse":{},"bidir":{},"tos":{}}}}}"#
+        ),
+        SOCKET,
+        local,
+        r.peer.ip(),
+        r.peer.port(),
+        env!("CARGO_PKG_VERSION"),
+        http_date(secs),
+        secs,
+        r.peer.ip(),
+        r.peer.port(),
+        protocol,
+        r.omit.as_secs(),
+        r.duration.as_secs(),
+        r.target_bytes.unwrap_or(0),
+        sender as u8,
+        (r.direction == Direction::Echo) as u8,
+        r.dscp.map_or(0, |d| d << 2)
+    );
+    let intervals: Vec<String> = intervals(r)
+        .iter()
+        .map(|iv| format!(r#"{{"streams":[{{"socket":{},{}}}],"sum":{{{}}}}}"#, SOCKET, iv.fields(sender), iv.fields(sender)))
+        .collect();
+    let total = Span { start: 0.0, end: r.duration.as_secs_f64(), bytes: r.bytes };
+    let mut end = format!(
+        r#""streams":[{{"sender":{{"socket":{},{}}},"receiver":{{"socket":{},{}}}}}],"sum_sent":{{{}}},"sum_received":{{{}}}"#,
+        SOCKET,
+        total.fields(true),
+        SOCKET,
+        total.fields(false),
+        total.fields(true),
+        total.fields(false)
+    );
+    if let Some(host) = &r.host {
+        let _ = write!(end, r#","cpu_utilization_percent":{{"host_total":{:.1}}}"#, host.cpu_pct);
+    }
+    if let Some(cca) = &r.cca {
+        let key = if sender { "sender_tcp_congestion" } else { "receiver_tcp_congestion" };
+        let _ = write!(end, r#","{}":"{}""#, key, dashboard::json_escape(cca));
+    }
+    format!(r#"{{"start":{},"intervals":[{}],"end":{{{}}}}}"#, start, intervals.join(","), end)
+}
+
+fn bps(bytes: u64, secs: f64) -> f64 {
+    if secs > 0.0 { bytes as f64 * 8.0 / secs } else { 0.0 }
+}
+
+/// Civil date and time (UTC) of a Unix timestamp, and its weekday (0 = Sunday).
+fn civil(secs: u64) -> (u64, u64, u64, u64, u64, u64, u64) {
+    let days = secs / 86_400;
+    let rem = secs % 86_400;
+    // Days to year/month/day, counting from 0000-03-01 so leap days fall last.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as u64;
+    (year, month, day, rem / 3600, rem % 3600 / 60, rem % 60, (days + 4) % 7)
+}
+
+/// `Thu, 16 Oct 2026 09:30:00 GMT`, as iperf3 writes `start.timestamp.time`.
+fn http_date(secs: u64) -> String {
+    const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let (year, month, day, h, m, s, weekday) = civil(secs);
+    format!("{}, {:02} {} {} {:02}:{:02}:{:02} GMT", DAYS[weekday as usize], day, MONTHS[month as usize - 1], year, h, m, s)
+}
+
+/// `YYYYMMDDHHMMSS` of the test's end, as iperf's CSV rows start.
+fn csv_timestamp(r: &TestResult) -> String {
+    let secs = r.finished_at.duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO).as_secs();
+    let (year, month, day, h, m, s, _) = civil(secs);
+    format!("{}{:02}{:02}{:02}{:02}{:02}", year, month, day, h, m, s)
+}
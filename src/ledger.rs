@@ -0,0 +1,108 @@
+// proj2-serv/src/ledger.rs
+// The UDP plane's upload ledger: which client address has an open upload
+// window, and the rules for counting datagrams into it and closing it. A
+// window is closed exactly once, by whichever comes first of END_UPLOAD, its
+// `BYTES=` target, its deadline, a newer START_UPLOAD from the same address,
+// or the idle sweep; every path takes it out of the ledger, so no other path
+// can count into it or close it again. Datagrams after the deadline are left
+// uncounted for the deadline task to close the window. The ledger knows
+// nothing about sockets or sessions, so tests/upload_accounting.rs drives it
+// directly through arbitrary interleavings.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+/// What the ledger needs from an upload window.
+pub trait Account {
+    /// Distinguishes successive windows from the same address.
+    fn id(&self) -> u64;
+    fn deadline(&self) -> Instant;
+    fn target(&self) -> Option<u64>;
+    /// Count a datagram of `len` bytes delivered `copies` times; returns the
+    /// window's total so far.
+    fn count(&mut self, len: usize, copies: usize) -> u64;
+}
+
+/// How a datagram was accounted.
+pub enum Recorded<W> {
+    /// No window is open for the sender.
+    NoWindow,
+    /// The window's deadline has passed; the datagram is not counted.
+    Late,
+    Counted,
+    /// The datagram reached the window's target and closed it.
+    Complete(W),
+}
+
+pub struct Ledger<W> {
+    windows: HashMap<SocketAddr, W>,
+}
+
+impl<W> Default for Ledger<W> {
+    fn default() -> Self {
+        Ledger { windows: HashMap::new() }
+    }
+}
+
+impl<W: Account> Ledger<W> {
+    /// Open `window` for `addr`; returns the window it displaces, which the
+    /// caller must close.
+    pub fn open(&mut self, addr: SocketAddr, window: W) -> Option<W> {
+        self.windows.insert(addr, window)
+    }
+
+    /// Count a datagram from `addr` received at `now`.
+    pub fn record(&mut self, addr: SocketAddr, len: usize, copies: usize, now: Instant) -> Recorded<W> {
+        let Some(window) = self.windows.get_mut(&addr) else { return Recorded::NoWindow };
+        if now > window.deadline() {
+            return Recorded::Late;
+        }
+        let total = window.count(len, copies);
+        match window.target() {
+            Some(target) if total >= target => self.windows.remove(&addr).map_or(Recorded::NoWindow, Recorded::Complete),
+            _ => Recorded::Counted,
+        }
+    }
+
+    pub fn get(&self, addr: &SocketAddr) -> Option<&W> {
+        self.windows.get(addr)
+    }
+
+    pub fn get_mut(&mut self, addr: &SocketAddr) -> Option<&mut W> {
+        self.windows.get_mut(addr)
+    }
+
+    /// The address and window of the first window matching `pred`.
+    pub fn find(&self, mut pred: impl FnMut(&W) -> bool) -> Option<(SocketAddr, &W)> {
+        self.windows.iter().find(|(_, w)| pred(w)).map(|(addr, w)| (*addr, w))
+    }
+
+    /// Close the window of `addr`, on END_UPLOAD.
+    pub fn end(&mut self, addr: SocketAddr) -> Option<W> {
+        self.windows.remove(&addr)
+    }
+
+    /// Close window `id` wherever it now is, at its deadline. None if another
+    /// path already closed it.
+    pub fn close(&mut self, id: u64) -> Option<(SocketAddr, W)> {
+        let (addr, _) = self.find(|w| w.id() == id)?;
+        self.windows.remove(&addr).map(|w| (addr, w))
+    }
+
+    /// Move the window of `from` to `to`; Err if `to` already has a window of
+    /// its own, in which case nothing moves.
+    pub fn relocate(&mut self, from: SocketAddr, to: SocketAddr) -> Result<Option<&mut W>, ()> {
+        if from != to && self.windows.contains_key(&to) {
+            return Err(());
+        }
+        let Some(window) = self.windows.remove(&from) else { return Ok(None) };
+        Ok(Some(self.windows.entry(to).insert_entry(window).into_mut()))
+    }
+
+    /// Close every window `reason` gives a reason for, with that reason.
+    pub fn sweep<R>(&mut self, mut reason: impl FnMut(&W) -> Option<R>) -> Vec<(SocketAddr, W, R)> {
+        let due: Vec<(SocketAddr, R)> = self.windows.iter().filter_map(|(addr, w)| reason(w).map(|r| (*addr, r))).collect();
+        due.into_iter().filter_map(|(addr, r)| self.windows.remove(&addr).map(|w| (addr, w, r))).collect()
+    }
+}
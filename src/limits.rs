@@ -0,0 +1,86 @@
+// proj2-serv/src/limits.rs
+// Limits an operator can change while the server runs (`ADMIN SET`). They start
+// from the command-line configuration; new values apply to tests started
+// afterwards. Durations are whole seconds, as on the command line, and 0
+// disables a timeout.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::protocol;
+
+pub struct Limits {
+    max_test_duration_ms: AtomicU64,
+    max_file_size: AtomicU64,
+    /// 0 when disabled.
+    handshake_timeout_ms: AtomicU64,
+    inactivity_timeout_ms: AtomicU64,
+}
+
+fn millis(d: Option<Duration>) -> u64 {
+    d.map_or(0, |d| d.as_millis() as u64)
+}
+
+fn timeout(ms: &AtomicU64) -> Option<Duration> {
+    let ms = ms.load(Ordering::Relaxed);
+    (ms > 0).then(|| Duration::from_millis(ms))
+}
+
+impl Limits {
+    pub fn new(config: &Config) -> Self {
+        Limits {
+            max_test_duration_ms: AtomicU64::new(millis(Some(config.max_test_duration))),
+            max_file_size: AtomicU64::new(config.max_file_size),
+            handshake_timeout_ms: AtomicU64::new(millis(config.handshake_timeout)),
+            inactivity_timeout_ms: AtomicU64::new(millis(config.inactivity_timeout)),
+        }
+    }
+
+    pub fn max_test_duration(&self) -> Duration {
+        Duration::from_millis(self.max_test_duration_ms.load(Ordering::Relaxed))
+    }
+
+    pub fn max_file_size(&self) -> u64 {
+        self.max_file_size.load(Ordering::Relaxed)
+    }
+
+    pub fn handshake_timeout(&self) -> Option<Duration> {
+        timeout(&self.handshake_timeout_ms)
+    }
+
+    pub fn inactivity_timeout(&self) -> Option<Duration> {
+        timeout(&self.inactivity_timeout_ms)
+    }
+
+    /// Change one limit by name; the error is the reason for an ERR reply.
+    pub fn set(&self, name: &str, value: &str) -> Result<(), String> {
+        let secs = || value.parse::<u64>().map_err(|_| format!("invalid seconds {:?} for {}", value, name));
+        match name {
+            "max_test_duration" => match secs()? {
+                0 => return Err("max_test_duration must be positive".to_string()),
+                s => self.max_test_duration_ms.store(s * 1000, Ordering::Relaxed),
+            },
+            "max_file_size" => {
+                let size = protocol::parse_byte_count(value).ok_or_else(|| format!("invalid size {:?} for {}", value, name))?;
+                self.max_file_size.store(size, Ordering::Relaxed);
+            }
+            "handshake_timeout" => self.handshake_timeout_ms.store(secs()? * 1000, Ordering::Relaxed),
+            "inactivity_timeout" => self.inactivity_timeout_ms.store(secs()? * 1000, Ordering::Relaxed),
+            _ => return Err(format!("unknown limit {:?}", name)),
+        }
+        Ok(())
+    }
+
+    /// `name=value` lines for `ADMIN LIMITS`.
+    pub fn render(&self) -> String {
+        let secs = |d: Option<Duration>| d.map_or(0, |d| d.as_secs());
+        let mut out = String::new();
+        let _ = writeln!(out, "max_test_duration={}", self.max_test_duration().as_secs());
+        let _ = writeln!(out, "max_file_size={}", self.max_file_size());
+        let _ = writeln!(out, "handshake_timeout={}", secs(self.handshake_timeout()));
+        let _ = writeln!(out, "inactivity_timeout={}", secs(self.inactivity_timeout()));
+        out
+    }
+}
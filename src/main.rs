@@ -1,283 +1,499 @@
 // proj2-serv/src/main.rs
 // Tokio-based high-throughput TCP + UDP server.
-// Listens: TCP 0.0.0.0:8080, UDP 0.0.0.0:7070
+// Listens: TCP 0.0.0.0:8080, UDP 0.0.0.0:7070 (fallback ports via --tcp-ports/--udp-ports)
 // Uses large I/O buffers, burst sends, multiple ACKs and a probe to make UDP uploads reliable.
 
-use tokio::net::{TcpListener, TcpStream, UdpSocket};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use std::time::{Duration, Instant};
-use std::io::ErrorKind;
-use std::net::{SocketAddr, SocketAddrV4, Ipv4Addr};
+mod ack;
+mod admin;
+mod affinity;
+mod auth;
+mod beacon;
+mod bloat;
+mod capacity;
+mod central;
+mod clock;
+mod collector;
+mod compress;
+mod config;
+mod conformance;
+mod dashboard;
+mod dataframe;
+mod doctor;
+mod drain;
+mod export;
+mod extend;
+mod eyeballs;
+mod echo;
+mod ecn;
+mod filexfer;
+mod flood;
+mod geoip;
+mod grpc;
+#[cfg(unix)]
+mod handover;
+mod health;
+mod histogram;
+mod hostres;
+mod icmp;
+mod impair;
+mod interval;
+mod iperf3;
+mod ledger;
+mod limits;
+mod mdns;
+mod mesh;
+mod metrics;
+mod multicast;
+mod otel;
+mod overhead;
+mod owd;
+mod pacing;
+mod payload;
+mod pkttrace;
+mod portdiag;
+mod protocol;
+mod queue;
+mod ramp;
+mod ratelimit;
+mod rendezvous;
+mod replay;
+mod resume;
+mod results;
+mod runs;
+mod rxring;
+mod rxstamp;
+mod rng;
+mod sandbox;
+mod scheduler;
+mod sockopt;
+mod session;
+mod signing;
+mod sockstats;
+mod state;
+mod supervisor;
+mod systemd;
+mod tcp;
+mod tcpinfo;
+mod transport;
+mod udp;
+#[cfg(unix)]
+mod uds;
+mod zerocopy;
+
+use tokio::net::{TcpListener, UdpSocket};
+use std::net::{SocketAddr, Ipv4Addr};
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use socket2::{Socket, Domain, Type, Protocol};
-use std::collections::HashMap;
-use tokio::sync::Mutex;
-use tokio::task;
 use anyhow::Context;
+use tokio_util::task::AbortOnDropHandle;
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    // Create and tune the UDP socket via socket2, then convert to Tokio UdpSocket.
-    let udp_sock = {
-        let s = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))
-            .context("creating socket2 UDP socket")?;
-        // Increase buffers (example: 8 MiB)
-        let buf = 8 * 1024 * 1024;
-        let _ = s.set_recv_buffer_size(buf);
-        let _ = s.set_send_buffer_size(buf);
-        s.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, 7070)).into())
-            .context("binding UDP socket")?;
-        let std_udp: std::net::UdpSocket = s.into();
-        std_udp.set_nonblocking(true).context("set_nonblocking UDP")?;
-        UdpSocket::from_std(std_udp).context("convert to tokio UdpSocket")?
-    };
-    let udp_socket = Arc::new(udp_sock);
-    println!("UDP server listening on 0.0.0.0:7070");
+use crate::config::Config;
+use crate::state::ServerState;
+use crate::supervisor::Plane;
+use crate::tcp::run_tcp_server;
+use crate::udp::run_udp_server;
 
-    // Create and tune TCP listener via socket2
-    let tcp_listener = {
-        let s = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP))
-            .context("creating socket2 TCP socket")?;
-        let buf = 4 * 1024 * 1024;
-        let _ = s.set_recv_buffer_size(buf);
-        let _ = s.set_send_buffer_size(buf);
-        let _ = s.set_reuse_address(true);
-        s.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, 8080)).into())
-            .context("binding TCP listener")?;
-        s.listen(1024).context("listen on TCP socket")?;
-        let std_listener: std::net::TcpListener = s.into();
-        std_listener.set_nonblocking(true).context("set_nonblocking TCP listener")?;
-        TcpListener::from_std(std_listener).context("convert to tokio TcpListener")?
-    };
-    println!("TCP server listening on 0.0.0.0:8080");
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(2);
+/// Kernel buffer size requested for the UDP socket and TCP listener.
+const UDP_BUFFER: usize = 8 * 1024 * 1024;
+const TCP_BUFFER: usize = 4 * 1024 * 1024;
+/// Pending-connection queue of the TCP listener (capped by net.core.somaxconn).
+const LISTEN_BACKLOG: i32 = 1024;
+/// Name of the dedicated thread that runs the UDP plane under `--udp-cpu`.
+const UDP_PLANE_THREAD: &str = "udp-plane";
 
-    // Run TCP and UDP loops concurrently
-    let udp_task = run_udp_server(udp_socket.clone());
-    let tcp_task = run_tcp_server(tcp_listener);
-    tokio::try_join!(udp_task, tcp_task)?;
-    Ok(())
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1).peekable();
+    if args.peek().map(String::as_str) == Some("conformance") {
+        args.next();
+        let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build()?;
+        return runtime.block_on(conformance::run(args));
+    }
+    if args.peek().map(String::as_str) == Some("doctor") {
+        args.next();
+        return doctor::run(args);
+    }
+    if args.peek().map(String::as_str) == Some("pkttrace") {
+        args.next();
+        return pkttrace::run(args);
+    }
+    if args.peek().map(String::as_str) == Some("collector") {
+        args.next();
+        let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build()?;
+        return runtime.block_on(collector::run(args));
+    }
+    if args.peek().map(String::as_str) == Some("discover") {
+        args.next();
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        return runtime.block_on(mdns::discover(args));
+    }
+    let mut inherited = systemd::take_listen_fds();
+    #[cfg(unix)]
+    handover::take(&mut inherited)?;
+    let config = Config::parse(args)?;
+    if config.user.is_some() {
+        // Bind while still privileged; the planes reuse these listeners.
+        if inherited.tcp.is_none() {
+            inherited.tcp = Some(bind_first("tcp", &config.tcp_ports, |p| bind_tcp(p, config.bind_device.as_deref()))?.0);
+        }
+        if inherited.udp.is_none() {
+            inherited.udp = Some(bind_first("udp", &config.udp_ports, |p| bind_udp(any_addr(p), config.bind_device.as_deref()))?.0);
+        }
+    }
+    #[cfg(unix)]
+    if let (Some(path), None) = (&config.uds, &inherited.uds) {
+        // Bound up front like a pre-bound listener: the path may need privilege.
+        inherited.uds = Some(uds::bind(path)?);
+    }
+    sandbox::drop_privileges(&config)?;
+    sandbox::apply(&config)?;
+    let uds_path = config.uds.clone().filter(|p| !p.starts_with('@'));
+    let served = build_runtime(&config)?.block_on(serve(config, inherited));
+    #[cfg(unix)]
+    let uds_path = uds_path.filter(|_| !handover::handed_over());
+    if let Some(path) = uds_path {
+        let _ = std::fs::remove_file(path);
+    }
+    served
 }
 
-async fn run_tcp_server(listener: TcpListener) -> anyhow::Result<()> {
-    loop {
-        match listener.accept().await {
-            Ok((stream, addr)) => {
-                println!("New TCP connection from {}", addr);
-                tokio::spawn(async move {
-                    if let Err(e) = handle_tcp_client(stream, addr).await {
-                        eprintln!("TCP client {} error: {:?}", addr, e);
-                    }
-                });
+/// Multi-threaded runtime honouring `--worker-threads` and `--cpu-affinity`.
+fn build_runtime(config: &Config) -> anyhow::Result<tokio::runtime::Runtime> {
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder.enable_all();
+    if let Some(n) = config.worker_threads {
+        builder.worker_threads(n);
+    }
+    if !config.cpu_affinity.is_empty() {
+        let cpus = config.cpu_affinity.clone();
+        let next = AtomicUsize::new(0);
+        builder.on_thread_start(move || {
+            let cpu = cpus[next.fetch_add(1, Ordering::Relaxed) % cpus.len()];
+            if let Err(e) = affinity::pin_current_thread(cpu) {
+                eprintln!("Cannot pin runtime thread to CPU {}: {}", cpu, e);
             }
-            Err(e) => {
-                eprintln!("TCP accept error: {:?}", e);
-                // small sleep to avoid busy loop on persistent accept errors
-                tokio::time::sleep(Duration::from_millis(10)).await;
+        });
+    }
+    builder.build().context("building tokio runtime")
+}
+
+async fn serve(config: Config, mut inherited: systemd::Inherited) -> anyhow::Result<()> {
+    let jobs = match &config.schedule {
+        Some(path) => scheduler::load(path)?,
+        None => Vec::new(),
+    };
+    let state = ServerState::new(config)?;
+    let dashboard_task = match state.config.dashboard_port {
+        Some(port) => {
+            let listener = http_listener(inherited.dashboard.take(), Ipv4Addr::LOCALHOST, port, "dashboard").await?;
+            #[cfg(unix)]
+            state.listeners.serving(handover::Listener::Dashboard, &listener);
+            println!("Dashboard on http://127.0.0.1:{}/", port);
+            Some(AbortOnDropHandle::new(tokio::spawn(dashboard::run_dashboard(listener, state.clone()))))
+        }
+        None => None,
+    };
+    let health_task = match state.config.health_port {
+        Some(port) => {
+            let listener = http_listener(inherited.health.take(), Ipv4Addr::UNSPECIFIED, port, "health").await?;
+            #[cfg(unix)]
+            state.listeners.serving(handover::Listener::Health, &listener);
+            println!("Health endpoints on http://0.0.0.0:{}/healthz and /readyz", port);
+            Some(AbortOnDropHandle::new(tokio::spawn(health::run_health(listener, state.clone()))))
+        }
+        None => None,
+    };
+    let grpc_task = match state.config.grpc_port {
+        Some(port) => {
+            let token = state.config.grpc_token.as_deref().map(grpc::Token::load).transpose()?;
+            // Remote callers only once they have a token to present.
+            let ip = if token.is_some() { Ipv4Addr::UNSPECIFIED } else { Ipv4Addr::LOCALHOST };
+            let listener = http_listener(inherited.grpc.take(), ip, port, "gRPC admin").await?;
+            #[cfg(unix)]
+            state.listeners.serving(handover::Listener::Grpc, &listener);
+            println!("gRPC admin service on {}:{}", ip, port);
+            Some(AbortOnDropHandle::new(tokio::spawn(grpc::run_grpc(listener, state.clone(), token))))
+        }
+        None => None,
+    };
+    let _exports = state.exports.enabled().then(|| AbortOnDropHandle::new(tokio::spawn(export::run_exports(state.clone()))));
+    if let Some(endpoint) = &state.config.otlp_endpoint {
+        println!("Exporting OpenTelemetry traces and metrics to {}", endpoint);
+        otel::observe_metrics(&state);
+    }
+    let schedule_task = (!jobs.is_empty()).then(|| AbortOnDropHandle::new(tokio::spawn(scheduler::run_schedule(jobs, state.clone()))));
+    let mesh_task = state.mesh.enabled().then(|| {
+        println!("Mesh member {} measuring peers every {:?}", state.config.mesh_name, state.config.mesh_interval);
+        AbortOnDropHandle::new(tokio::spawn(mesh::run_mesh(state.clone(), state.config.mesh_interval)))
+    });
+    #[cfg(unix)]
+    let uds_task = inherited.uds.take().map(|listener| {
+        let name = state.config.uds.clone().unwrap_or_else(|| "inherited socket".to_string());
+        state.listeners.serving(handover::Listener::Uds, &listener);
+        println!("UDS server listening on {}", name);
+        let state = state.clone();
+        AbortOnDropHandle::new(tokio::spawn(async move {
+            // A local baseline only; the network planes keep serving without it.
+            if let Err(e) = uds::run_uds_server(listener, state).await {
+                eprintln!("UDS server stopped: {:#}", e);
+            }
+        }))
+    });
+    let multicast_task = state.multicast.enabled().then(|| {
+        let state = state.clone();
+        AbortOnDropHandle::new(tokio::spawn(async move {
+            if let Err(e) = multicast::run_sender(state).await {
+                eprintln!("Multicast sender stopped: {:#}", e);
+            }
+        }))
+    });
+    let beacon_task = state.config.beacon.then(|| {
+        let state = state.clone();
+        AbortOnDropHandle::new(tokio::spawn(async move {
+            if let Err(e) = beacon::run_beacon(state).await {
+                eprintln!("Beacon stopped: {:#}", e);
+            }
+        }))
+    });
+    let mdns_task = state.config.mdns.then(|| {
+        let state = state.clone();
+        AbortOnDropHandle::new(tokio::spawn(async move {
+            // Discovery is a convenience; the server keeps serving without it.
+            if let Err(e) = mdns::run_responder(state).await {
+                eprintln!("mDNS responder stopped: {:#}", e);
+            }
+        }))
+    });
+
+    // Supervise TCP and UDP independently: one plane failing leaves the other serving.
+    // Socket-activated or pre-bound listeners are reused on every restart instead of binding.
+    let udp_state = state.clone();
+    let udp_plane = supervisor::supervise(Plane::Udp, state.clone(), move || {
+        let state = udp_state.clone();
+        let adopted = inherited.udp.as_ref().map(|s| s.try_clone());
+        async move {
+            let (udp_sock, port) = match adopted {
+                Some(sock) => adopt_udp(sock?)?,
+                None => adopt_udp(bind_first("udp", &state.config.udp_ports, |p| bind_udp(any_addr(p), state.config.bind_device.as_deref()))?.0)?,
+            };
+            let udp_socket = Arc::new(udp_sock);
+            state.config.steering.apply(socket2::SockRef::from(&*udp_socket)).context("applying --socket-priority/--socket-mark to UDP")?;
+            let buffers = report_buffers("UDP", socket2::SockRef::from(&*udp_socket), UDP_BUFFER);
+            println!("UDP server listening on {} ({})", udp_socket.local_addr()?, buffers.map_or("buffers unknown".to_string(), |b| b.to_string()));
+            *state.udp_buffers.lock().unwrap() = buffers;
+            #[cfg(unix)]
+            state.listeners.serving(handover::Listener::Udp, &*udp_socket);
+            state.health.mark_up(Plane::Udp, port);
+            run_udp_server(udp_socket, state).await
+        }
+    });
+    let tcp_state = state.clone();
+    let tcp_plane = supervisor::supervise(Plane::Tcp, state.clone(), move || {
+        let state = tcp_state.clone();
+        let adopted = inherited.tcp.as_ref().map(|s| s.try_clone());
+        async move {
+            let (tcp_listener, port) = match adopted {
+                Some(listener) => adopt_tcp(listener?)?,
+                None => adopt_tcp(bind_first("tcp", &state.config.tcp_ports, |p| bind_tcp(p, state.config.bind_device.as_deref()))?.0)?,
+            };
+            // Accepted connections inherit the listener's priority and mark.
+            state.config.steering.apply(socket2::SockRef::from(&tcp_listener)).context("applying --socket-priority/--socket-mark to TCP")?;
+            let buffers = report_buffers("TCP", socket2::SockRef::from(&tcp_listener), TCP_BUFFER);
+            println!("TCP server listening on {} ({})", tcp_listener.local_addr()?, buffers.map_or("buffers unknown".to_string(), |b| b.to_string()));
+            #[cfg(unix)]
+            state.listeners.serving(handover::Listener::Tcp, &tcp_listener);
+            state.health.mark_up(Plane::Tcp, port);
+            run_tcp_server(tcp_listener, state).await
+        }
+    });
+    let udp_plane: std::pin::Pin<Box<dyn Future<Output = ()> + Send>> = match state.config.udp_cpu {
+        Some(cpu) => Box::pin(run_pinned(cpu, udp_plane)?),
+        None => Box::pin(udp_plane),
+    };
+    let _notifier = AbortOnDropHandle::new(tokio::spawn(systemd::run_notifier(state.clone())));
+    #[cfg(unix)]
+    let _confirm = inherited.handover.take().map(|channel| AbortOnDropHandle::new(tokio::spawn(handover::confirm(channel, state.clone()))));
+    #[cfg(unix)]
+    let restarts = handover::serve_restarts(&state);
+    #[cfg(not(unix))]
+    let restarts = std::future::pending::<()>();
+    tokio::select! {
+        _ = async { tokio::join!(udp_plane, tcp_plane) } => anyhow::bail!("both TCP and UDP planes are down"),
+        _ = shutdown_signal() => {
+            println!("Shutting down: cancelling active sessions");
+            systemd::notify("STOPPING=1");
+            state.sessions.shutdown();
+            // Give cancelled sessions a moment to record their results.
+            let deadline = Instant::now() + SHUTDOWN_GRACE;
+            while !state.sessions.is_empty() && Instant::now() < deadline {
+                tokio::time::sleep(Duration::from_millis(50)).await;
             }
+            Ok(())
+        }
+        _ = restarts => {
+            // The new process accepts from here on; the planes stopped with this select.
+            drop((dashboard_task, health_task, grpc_task, schedule_task, mesh_task, multicast_task, beacon_task, mdns_task));
+            #[cfg(unix)]
+            drop(uds_task);
+            println!("Handed over to the new process: finishing active tests");
+            #[cfg(unix)]
+            handover::finish_sessions(&state, SHUTDOWN_GRACE).await;
+            Ok(())
         }
     }
 }
 
-async fn handle_tcp_client(mut stream: TcpStream, peer: SocketAddr) -> anyhow::Result<()> {
-    let _ = stream.set_nodelay(true);
-    const BUF_SIZE: usize = 64 * 1024;
-    let mut read_buf = vec![0u8; BUF_SIZE];
-    loop {
-        let n = match stream.read(&mut read_buf).await {
-            Ok(0) => {
-                println!("TCP client {} disconnected", peer);
-                return Ok(());
+/// A dashboard or health listener handed over by the previous process, or a new one.
+async fn http_listener(inherited: Option<std::net::TcpListener>, ip: Ipv4Addr, port: u16, what: &str) -> anyhow::Result<TcpListener> {
+    match inherited {
+        Some(listener) => Ok(adopt_tcp(listener)?.0),
+        None => TcpListener::bind((ip, port)).await.with_context(|| format!("binding {} port {}", what, port)),
+    }
+}
+
+/// Run `plane` on its own thread with a single-threaded runtime, pinned to
+/// `cpu`, so its packet path stays on one core's caches. The returned future
+/// completes when the plane does.
+fn run_pinned(cpu: usize, plane: impl Future<Output = ()> + Send + 'static) -> anyhow::Result<impl Future<Output = ()>> {
+    let (done_tx, done_rx) = tokio::sync::oneshot::channel();
+    std::thread::Builder::new()
+        .name(UDP_PLANE_THREAD.to_string())
+        .spawn(move || {
+            if let Err(e) = affinity::pin_current_thread(cpu) {
+                eprintln!("Cannot pin UDP plane to CPU {}: {}", cpu, e);
             }
-            Ok(n) => n,
-            Err(e) if e.kind() == ErrorKind::ConnectionReset => {
-                println!("TCP client {} reset connection", peer);
-                return Ok(());
+            match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+                Ok(runtime) => runtime.block_on(plane),
+                Err(e) => eprintln!("Cannot build UDP plane runtime: {}", e),
             }
-            Err(e) => {
-                eprintln!("TCP read error from {}: {:?}", peer, e);
-                return Err(e.into());
-            }
-        };
-        let command = String::from_utf8_lossy(&read_buf[..n]).trim().to_string();
-        println!("TCP server received from {}: {}", peer, command);
+            let _ = done_tx.send(());
+        })
+        .context("spawning UDP plane thread")?;
+    Ok(async move {
+        let _ = done_rx.await;
+    })
+}
 
-        if command.starts_with("START_DOWNLOAD") {
-            let payload = vec![0u8; BUF_SIZE];
-            let start = Instant::now();
-            let mut sent_bytes: usize = 0usize;
-            while start.elapsed() < Duration::from_secs(5) {
-                if let Err(e) = stream.write_all(&payload).await {
-                    if e.kind() == ErrorKind::BrokenPipe || e.kind() == ErrorKind::ConnectionReset {
-                        println!("Client {} closed connection during download", peer);
-                        break;
-                    } else {
-                        eprintln!("TCP write error to {}: {:?}", peer, e);
-                        break;
-                    }
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = term.recv() => {}
                 }
-                sent_bytes += payload.len();
             }
-            println!("TCP server finished sending download to {} (~{} bytes)", peer, sent_bytes);
-        } else if command.starts_with("START_UPLOAD") {
-            let start = Instant::now();
-            let mut total_rx: usize = 0usize;
-            while start.elapsed() < Duration::from_secs(5) {
-                match stream.read(&mut read_buf).await {
-                    Ok(0) => break,
-                    Ok(m) => total_rx += m,
-                    Err(e) if e.kind() == ErrorKind::WouldBlock => {
-                        tokio::task::yield_now().await;
-                    }
-                    Err(e) if e.kind() == ErrorKind::ConnectionReset => {
-                        println!("Client reset connection during upload: {}", peer);
-                        break;
-                    }
-                    Err(e) => {
-                        eprintln!("TCP read error during upload from {}: {:?}", peer, e);
-                        break;
-                    }
-                }
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
             }
-            println!("TCP server received {} bytes during upload from {}", total_rx, peer);
-        } else {
-            println!("TCP server: unknown command from {}: {:?}", peer, command);
         }
     }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
 }
 
-async fn run_udp_server(udp_socket: Arc<UdpSocket>) -> anyhow::Result<()> {
-    const PAYLOAD_SIZE: usize = 1400; // MTU-friendly
-    let send_payload = vec![0u8; PAYLOAD_SIZE];
-    let mut recv_buf = vec![0u8; 64 * 1024];
-
-    // Active uploads: client -> (deadline, total_bytes)
-    let active_uploads: Arc<Mutex<HashMap<std::net::SocketAddr, (Instant, usize)>>> =
-        Arc::new(Mutex::new(HashMap::new()));
-
-    loop {
-        match udp_socket.recv_from(&mut recv_buf).await {
-            Ok((len, addr)) => {
-                let msg = String::from_utf8_lossy(&recv_buf[..len]).trim().to_string();
-                println!("UDP server received from {}: {}", addr, msg);
+/// Try each configured port in order. On a conflict, report who holds the port
+/// and fall back to the next one.
+fn bind_first<T>(proto: &str, ports: &[u16], bind: impl Fn(u16) -> anyhow::Result<T>) -> anyhow::Result<(T, u16)> {
+    let mut last_err = None;
+    for &port in ports {
+        match bind(port) {
+            Ok(sock) => return Ok((sock, port)),
+            Err(e) => {
+                let holder = portdiag::describe_port_holder(proto, port)
+                    .map(|h| format!(" (held by {})", h))
+                    .unwrap_or_default();
+                eprintln!("{} port {} unavailable{}: {:#}", proto.to_uppercase(), port, holder, e);
+                last_err = Some(e.context(format!("{} port {}{}", proto.to_uppercase(), port, holder)));
+            }
+        }
+    }
+    Err(last_err.unwrap_or_else(|| anyhow::anyhow!("no {} ports configured", proto)))
+}
 
-                // Replace existing START_DOWNLOAD handling with this block
-                if msg.starts_with("START_DOWNLOAD") {
-                    // Immediately ACK so client knows we saw the request
-                    // (send a few ACKs to be robust)
-                    const ACKS: usize = 3;
-                    const ACK_INTERVAL_MS: u64 = 10;
-                    for _ in 0..ACKS {
-                        if let Err(e) = udp_socket.send_to(b"ACK_DOWNLOAD", &addr).await {
-                            eprintln!("UDP send ACK_DOWNLOAD failed to {}: {:?}", addr, e);
-                        }
-                        tokio::time::sleep(Duration::from_millis(ACK_INTERVAL_MS)).await;
-                    }
+/// Effective buffer sizes of a plane's socket, warning when the kernel granted
+/// less than `want` (bound here, or inherited with its own sizes).
+fn report_buffers(plane: &str, sock: socket2::SockRef<'_>, want: usize) -> Option<sockopt::BufferSizes> {
+    match sockopt::BufferSizes::of(sock) {
+        Ok(sizes) => {
+            if sizes.short_of(want) {
+                eprintln!(
+                    "{} socket buffers clamped to {} receive / {} send bytes (asked {}); raise net.core.rmem_max/wmem_max or grant CAP_NET_ADMIN",
+                    plane,
+                    sockopt::BufferSizes::usable(sizes.recv),
+                    sockopt::BufferSizes::usable(sizes.send),
+                    want
+                );
+            }
+            Some(sizes)
+        }
+        Err(e) => {
+            eprintln!("Cannot read {} socket buffer sizes: {}", plane, e);
+            None
+        }
+    }
+}
 
-                    // Spawn an async task that sends bursts using the shared udp_socket.
-                    // This avoids creating per-client blocking sockets and keeps the runtime efficient.
-                    let sock = udp_socket.clone();
-                    let dest = addr;
-                    let payload = send_payload.clone(); // 1400 bytes
-                    tokio::spawn(async move {
-                        const BURST: usize = 16; // tune 4..32
-                        const BACKOFF_US: u64 = 20; // microsecond backoff on WouldBlock
-                        let start = Instant::now();
-                        let mut sent_bytes: usize = 0usize;
+/// Use a UDP socket bound earlier or inherited through socket activation.
+fn adopt_udp(sock: std::net::UdpSocket) -> anyhow::Result<(UdpSocket, u16)> {
+    sock.set_nonblocking(true).context("set_nonblocking inherited UDP socket")?;
+    let port = sock.local_addr()?.port();
+    Ok((UdpSocket::from_std(sock).context("convert inherited UDP socket")?, port))
+}
 
-                        while start.elapsed() < Duration::from_secs(5) {
-                            // send a burst of datagrams
-                            let mut any_sent = false;
-                            for _ in 0..BURST {
-                                match sock.send_to(&payload, &dest).await {
-                                    Ok(n) => {
-                                        sent_bytes += n;
-                                        any_sent = true;
-                                    }
-                                    Err(e) => {
-                                        // backpressure: wait a tiny bit and break the burst
-                                        if e.kind() == std::io::ErrorKind::WouldBlock {
-                                            tokio::time::sleep(Duration::from_micros(BACKOFF_US)).await;
-                                            break;
-                                        } else {
-                                            eprintln!("UDP send_to error to {}: {:?}", dest, e);
-                                            tokio::time::sleep(Duration::from_micros(BACKOFF_US)).await;
-                                            break;
-                                        }
-                                    }
-                                }
-                            }
+/// Use a TCP listener bound earlier or inherited through socket activation.
+fn adopt_tcp(listener: std::net::TcpListener) -> anyhow::Result<(TcpListener, u16)> {
+    listener.set_nonblocking(true).context("set_nonblocking inherited TCP listener")?;
+    let port = listener.local_addr()?.port();
+    Ok((TcpListener::from_std(listener).context("convert inherited TCP listener")?, port))
+}
 
-                            // Minimal yield: only yield if we actually sent something.
-                            // This keeps the task responsive without throttling throughput.
-                            if any_sent {
-                                tokio::task::yield_now().await;
-                            } else {
-                                tokio::time::sleep(Duration::from_micros(BACKOFF_US)).await;
-                            }
-                        }
+/// The wildcard IPv4 address at `port`.
+fn any_addr(port: u16) -> SocketAddr {
+    SocketAddr::from((Ipv4Addr::UNSPECIFIED, port))
+}
 
-                        println!("UDP server finished sending download to {} (~{} bytes)", dest, sent_bytes);
-                    });
-                    continue;
-                }
-                else if msg.starts_with("START_UPLOAD") {
-                    // register an upload window for this addr and ACK (insert first)
-                    let deadline = Instant::now() + Duration::from_secs(5);
-                    {
-                        let mut map = active_uploads.lock().await;
-                        map.insert(addr, (deadline, 0));
-                    }
+/// Pin a new socket to `--bind-device`, if one is configured.
+fn apply_bind_device(s: &Socket, device: Option<&str>) -> anyhow::Result<()> {
+    match device {
+        Some(device) => sockopt::bind_device(s, device).with_context(|| format!("binding to device {}", device)),
+        None => Ok(()),
+    }
+}
 
-                    // Send multiple ACKs and a tiny probe to prime NATs/middleboxes
-                    const ACKS: usize = 3;
-                    const ACK_INTERVAL_MS: u64 = 20;
-                    for _ in 0..ACKS {
-                        if let Err(e) = udp_socket.send_to(b"ACK_UPLOAD", &addr).await {
-                            eprintln!("UDP send ACK failed to {}: {:?}", addr, e);
-                        }
-                        tokio::time::sleep(Duration::from_millis(ACK_INTERVAL_MS)).await;
-                    }
-                    // tiny probe to help NAT learn mapping
-                    if let Err(e) = udp_socket.send_to(b"P", &addr).await {
-                        eprintln!("UDP send probe failed to {}: {:?}", addr, e);
-                    } else {
-                        println!("UDP server registered upload window for {} until {:?}", addr, deadline);
-                    }
-                } else {
-                    // Non-control datagram: count toward active upload if present
-                    let now = Instant::now();
-                    let mut map = active_uploads.lock().await;
-                    if let Some((deadline, total)) = map.get_mut(&addr) {
-                        if now <= *deadline {
-                            *total += len;
-                        } else {
-                            // expired: report and remove
-                            println!("UDP server received {} bytes during upload from {} (final)", *total, addr);
-                            map.remove(&addr);
-                        }
-                    } else {
-                        // Unexpected payload; ignore or log for debug
-                        println!("UDP payload from {}: {} bytes (no active window)", addr, len);
-                    }
+/// Create and tune the UDP socket via socket2.
+fn bind_udp(addr: SocketAddr, device: Option<&str>) -> anyhow::Result<std::net::UdpSocket> {
+    let s = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))
+        .context("creating socket2 UDP socket")?;
+    apply_bind_device(&s, device)?;
+    if let Err(e) = sockopt::tune_buffers(&s, UDP_BUFFER) {
+        eprintln!("Cannot read back UDP buffer sizes: {}", e);
+    }
+    if let Err(e) = icmp::disable_connreset(&s) {
+        eprintln!("Cannot disable UDP connection-reset reports: {}", e);
+    }
+    s.bind(&addr.into()).with_context(|| format!("binding UDP socket to {}", addr))?;
+    Ok(s.into())
+}
 
-                    // Sweep expired entries and report
-                    let now = Instant::now();
-                    let expired: Vec<std::net::SocketAddr> = map
-                        .iter()
-                        .filter_map(|(client, (deadline, _))| if now > *deadline { Some(*client) } else { None })
-                        .collect();
-                    for client in expired {
-                        if let Some((_, total)) = map.remove(&client) {
-                            println!("UDP server received {} bytes during upload from {}", total, client);
-                        }
-                    }
-                }
-            }
-            Err(e) => {
-                eprintln!("UDP recv_from error: {:?}", e);
-                // small sleep to avoid busy-looping on persistent errors
-                tokio::time::sleep(Duration::from_millis(10)).await;
-            }
-        }
+/// Create and tune TCP listener via socket2
+fn bind_tcp(port: u16, device: Option<&str>) -> anyhow::Result<std::net::TcpListener> {
+    let s = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP))
+        .context("creating socket2 TCP socket")?;
+    apply_bind_device(&s, device)?;
+    if let Err(e) = sockopt::tune_buffers(&s, TCP_BUFFER) {
+        eprintln!("Cannot read back TCP buffer sizes: {}", e);
     }
+    let _ = s.set_reuse_address(true);
+    s.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)).into())
+        .context("binding TCP listener")?;
+    s.listen(LISTEN_BACKLOG).context("listen on TCP socket")?;
+    Ok(s.into())
 }
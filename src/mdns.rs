@@ -0,0 +1,402 @@
+// proj2-serv/src/mdns.rs
+// mDNS/DNS-SD service discovery on the LAN (RFC 6762/6763). With `--mdns` the
+// server answers queries for `_proj2serv._tcp.local` and `_proj2serv._udp.local`
+// (and the `_services._dns-sd._udp.local` meta-query) with PTR, SRV, TXT and A
+// records, and announces itself at startup. `proj2-serv discover` is the client
+// side: it asks the LAN and lists the servers that answer, along with any
+// servers heard announcing themselves by beacon. Only the subset of
+// DNS needed for this is implemented; names in received messages may be
+// compressed, names sent never are.
+
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+
+use crate::beacon;
+use crate::state::ServerState;
+use crate::supervisor::Plane;
+
+const MDNS_ADDR: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+const SERVICE_TCP: &str = "_proj2serv._tcp.local";
+const SERVICE_UDP: &str = "_proj2serv._udp.local";
+const META_QUERY: &str = "_services._dns-sd._udp.local";
+const TTL: u32 = 120;
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+/// Cache-flush bit on unique records (SRV, TXT, A).
+const CACHE_FLUSH: u16 = 0x8000;
+/// Unicast-response bit in a question's class.
+const UNICAST_RESPONSE: u16 = 0x8000;
+
+struct Record {
+    name: String,
+    rtype: u16,
+    class: u16,
+    rdata: Vec<u8>,
+}
+
+fn push_name(out: &mut Vec<u8>, name: &str) {
+    for label in name.split('.').filter(|l| !l.is_empty()) {
+        out.push(label.len() as u8);
+        out.extend_from_slice(label.as_bytes());
+    }
+    out.push(0);
+}
+
+fn name_bytes(name: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    push_name(&mut out, name);
+    out
+}
+
+/// Read a possibly compressed name at `pos`; returns it and the position after it.
+fn read_name(msg: &[u8], mut pos: usize) -> anyhow::Result<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    // Bounds pointer loops in malformed messages.
+    for _ in 0..128 {
+        let len = *msg.get(pos).context("truncated name")? as usize;
+        match len {
+            0 => {
+                return Ok((labels.join("."), end.unwrap_or(pos + 1)));
+            }
+            l if l & 0xc0 == 0xc0 => {
+                let low = *msg.get(pos + 1).context("truncated pointer")? as usize;
+                end.get_or_insert(pos + 2);
+                pos = ((l & 0x3f) << 8) | low;
+            }
+            l => {
+                let label = msg.get(pos + 1..pos + 1 + l).context("truncated label")?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                pos += 1 + l;
+            }
+        }
+    }
+    bail!("name too long or looping")
+}
+
+fn read_u16(msg: &[u8], pos: usize) -> anyhow::Result<u16> {
+    let b = msg.get(pos..pos + 2).context("truncated message")?;
+    Ok(u16::from_be_bytes([b[0], b[1]]))
+}
+
+fn encode(id: u16, flags: u16, questions: &[(String, u16)], answers: &[Record], additional: &[Record]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(512);
+    for v in [id, flags, questions.len() as u16, answers.len() as u16, 0, additional.len() as u16] {
+        out.extend_from_slice(&v.to_be_bytes());
+    }
+    for (name, qtype) in questions {
+        push_name(&mut out, name);
+        out.extend_from_slice(&qtype.to_be_bytes());
+        out.extend_from_slice(&CLASS_IN.to_be_bytes());
+    }
+    for r in answers.iter().chain(additional) {
+        push_name(&mut out, &r.name);
+        out.extend_from_slice(&r.rtype.to_be_bytes());
+        out.extend_from_slice(&r.class.to_be_bytes());
+        out.extend_from_slice(&TTL.to_be_bytes());
+        out.extend_from_slice(&(r.rdata.len() as u16).to_be_bytes());
+        out.extend_from_slice(&r.rdata);
+    }
+    out
+}
+
+/// What this server advertises.
+struct Advertisement {
+    instance: String,
+    host: String,
+    addr: Ipv4Addr,
+    tcp_port: Option<u16>,
+    udp_port: Option<u16>,
+}
+
+impl Advertisement {
+    fn services(&self) -> Vec<(&'static str, u16)> {
+        let mut out = Vec::new();
+        if let Some(port) = self.tcp_port {
+            out.push((SERVICE_TCP, port));
+        }
+        if let Some(port) = self.udp_port {
+            out.push((SERVICE_UDP, port));
+        }
+        out
+    }
+
+    /// Answers and additional records for a question, if it concerns us.
+    fn answer(&self, qname: &str, qtype: u16) -> Option<(Vec<Record>, Vec<Record>)> {
+        let qname = qname.to_ascii_lowercase();
+        if qname == META_QUERY && matches!(qtype, TYPE_PTR | TYPE_ANY) {
+            let answers = self
+                .services()
+                .into_iter()
+                .map(|(service, _)| Record { name: META_QUERY.to_string(), rtype: TYPE_PTR, class: CLASS_IN, rdata: name_bytes(service) })
+                .collect();
+            return Some((answers, Vec::new()));
+        }
+        let (service, port) = self.services().into_iter().find(|(s, _)| *s == qname)?;
+        if !matches!(qtype, TYPE_PTR | TYPE_ANY) {
+            return None;
+        }
+        let instance = format!("{}.{}", self.instance, service);
+        let answers = vec![Record { name: service.to_string(), rtype: TYPE_PTR, class: CLASS_IN, rdata: name_bytes(&instance) }];
+        let mut srv = Vec::new();
+        srv.extend_from_slice(&0u16.to_be_bytes());
+        srv.extend_from_slice(&0u16.to_be_bytes());
+        srv.extend_from_slice(&port.to_be_bytes());
+        push_name(&mut srv, &self.host);
+        let mut txt = Vec::new();
+        for entry in [format!("version={}", env!("CARGO_PKG_VERSION")), format!("tcp={}", self.tcp_port.unwrap_or(0)), format!("udp={}", self.udp_port.unwrap_or(0))] {
+            txt.push(entry.len() as u8);
+            txt.extend_from_slice(entry.as_bytes());
+        }
+        let unique = CLASS_IN | CACHE_FLUSH;
+        let additional = vec![
+            Record { name: instance.clone(), rtype: TYPE_SRV, class: unique, rdata: srv },
+            Record { name: instance, rtype: TYPE_TXT, class: unique, rdata: txt },
+            Record { name: self.host.clone(), rtype: TYPE_A, class: unique, rdata: self.addr.octets().to_vec() },
+        ];
+        Some((answers, additional))
+    }
+}
+
+/// Socket bound to the mDNS port and joined to the group, shared with any
+/// other responder on the host.
+fn bind_mdns() -> anyhow::Result<UdpSocket> {
+    let s = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).context("creating mDNS socket")?;
+    s.set_reuse_address(true).context("SO_REUSEADDR on mDNS socket")?;
+    #[cfg(unix)]
+    s.set_reuse_port(true).context("SO_REUSEPORT on mDNS socket")?;
+    s.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, MDNS_PORT)).into()).context("binding mDNS port 5353")?;
+    s.join_multicast_v4(&MDNS_ADDR, &Ipv4Addr::UNSPECIFIED).context("joining mDNS group")?;
+    s.set_multicast_loop_v4(true)?;
+    s.set_multicast_ttl_v4(255)?;
+    s.set_nonblocking(true)?;
+    UdpSocket::from_std(s.into()).context("convert mDNS socket")
+}
+
+/// Address of the interface multicast leaves from, advertised in the A record.
+fn local_addr() -> anyhow::Result<Ipv4Addr> {
+    let probe = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    probe.connect((MDNS_ADDR, MDNS_PORT))?;
+    match probe.local_addr()? {
+        SocketAddr::V4(a) if !a.ip().is_unspecified() => Ok(*a.ip()),
+        a => bail!("no IPv4 route for multicast (local address {})", a),
+    }
+}
+
+/// Answer mDNS queries for this server until it stops.
+pub async fn run_responder(state: Arc<ServerState>) -> anyhow::Result<()> {
+    let sock = bind_mdns()?;
+    let addr = local_addr()?;
+    // Advertise only once the planes have bound their ports.
+    while state.health.port(Plane::Tcp).is_none() && state.health.port(Plane::Udp).is_none() {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let name = state.config.mdns_name.clone();
+    let advert = || Advertisement {
+        instance: name.clone(),
+        host: format!("{}.local", name),
+        addr,
+        tcp_port: state.health.port(Plane::Tcp),
+        udp_port: state.health.port(Plane::Udp),
+    };
+    println!("mDNS advertising {} at {} ({}, {})", name, addr, SERVICE_TCP, SERVICE_UDP);
+    let group = SocketAddr::V4(SocketAddrV4::new(MDNS_ADDR, MDNS_PORT));
+    // Two unsolicited announcements, a second apart (RFC 6762 section 8.3).
+    for i in 0..2 {
+        if i > 0 {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+        let advert = advert();
+        let (mut answers, mut additional) = (Vec::new(), Vec::new());
+        for (service, _) in advert.services() {
+            if let Some((an, ar)) = advert.answer(service, TYPE_PTR) {
+                answers.extend(an);
+                additional.extend(ar);
+            }
+        }
+        if let Err(e) = sock.send_to(&encode(0, 0x8400, &[], &answers, &additional), group).await {
+            eprintln!("mDNS announcement failed: {}", e);
+        }
+    }
+
+    let mut buf = vec![0u8; 9000];
+    loop {
+        let (n, from) = sock.recv_from(&mut buf).await.context("mDNS receive")?;
+        let msg = &buf[..n];
+        let reply = match respond(&advert(), msg, from) {
+            Ok(Some(reply)) => reply,
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!("mDNS malformed query from {}: {:#}", from, e);
+                continue;
+            }
+        };
+        if let Err(e) = sock.send_to(&reply.0, reply.1).await {
+            eprintln!("mDNS reply to {} failed: {}", reply.1, e);
+        }
+    }
+}
+
+/// Reply to a query and where to send it; None when it is not for us.
+fn respond(advert: &Advertisement, msg: &[u8], from: SocketAddr) -> anyhow::Result<Option<(Vec<u8>, SocketAddr)>> {
+    let id = read_u16(msg, 0)?;
+    let flags = read_u16(msg, 2)?;
+    if flags & 0x8000 != 0 {
+        return Ok(None); // a response, not a query
+    }
+    let qdcount = read_u16(msg, 4)?;
+    let mut pos = 12;
+    let (mut answers, mut additional, mut questions) = (Vec::new(), Vec::new(), Vec::new());
+    let mut unicast = false;
+    for _ in 0..qdcount {
+        let (qname, next) = read_name(msg, pos)?;
+        let qtype = read_u16(msg, next)?;
+        let qclass = read_u16(msg, next + 2)?;
+        pos = next + 4;
+        if let Some((an, ar)) = advert.answer(&qname, qtype) {
+            unicast |= qclass & UNICAST_RESPONSE != 0;
+            answers.extend(an);
+            additional.extend(ar);
+            questions.push((qname, qtype));
+        }
+    }
+    if answers.is_empty() {
+        return Ok(None);
+    }
+    // Legacy resolvers (source port not 5353) get a unicast, DNS-style reply
+    // echoing their ID and questions (RFC 6762 section 6.7).
+    if from.port() != MDNS_PORT {
+        return Ok(Some((encode(id, 0x8400, &questions, &answers, &additional), from)));
+    }
+    let dest = if unicast { from } else { SocketAddr::V4(SocketAddrV4::new(MDNS_ADDR, MDNS_PORT)) };
+    Ok(Some((encode(0, 0x8400, &[], &answers, &additional), dest)))
+}
+
+/// A server found by `discover`.
+#[derive(Default)]
+struct Found {
+    protocol: &'static str,
+    host: String,
+    port: u16,
+    txt: Vec<String>,
+}
+
+/// `proj2-serv discover [--timeout <secs>]`: list the servers on the LAN.
+pub async fn discover(mut args: impl Iterator<Item = String>) -> anyhow::Result<()> {
+    let mut wait = Duration::from_secs(2);
+    let mut beacon_addr = beacon::DEFAULT_ADDR;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--timeout" => {
+                let secs: u64 = args.next().context("--timeout requires a value")?.parse().context("invalid --timeout")?;
+                wait = Duration::from_secs(secs.max(1));
+            }
+            "--beacon-addr" => {
+                beacon_addr = args.next().context("--beacon-addr requires a value")?.parse().context("invalid --beacon-addr")?;
+            }
+            _ => bail!("unknown discover argument {:?}", arg),
+        }
+    }
+    // Beacons arrive on their own schedule; listen for them during the whole wait.
+    let beacons = tokio::spawn(beacon::listen(beacon_addr, wait));
+    let sock = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    let query = encode(0x5032, 0, &[(SERVICE_TCP.to_string(), TYPE_PTR), (SERVICE_UDP.to_string(), TYPE_PTR)], &[], &[]);
+    sock.send_to(&query, (MDNS_ADDR, MDNS_PORT)).await.context("sending mDNS query")?;
+
+    let mut instances: BTreeMap<String, Found> = BTreeMap::new();
+    let mut addrs: BTreeMap<String, Ipv4Addr> = BTreeMap::new();
+    let mut buf = vec![0u8; 9000];
+    let deadline = tokio::time::Instant::now() + wait;
+    while let Ok(recv) = tokio::time::timeout_at(deadline, sock.recv_from(&mut buf)).await {
+        let (n, from) = recv?;
+        if let Err(e) = parse_response(&buf[..n], &mut instances, &mut addrs) {
+            eprintln!("ignoring malformed reply from {}: {:#}", from, e);
+        }
+    }
+
+    let beacons = match beacons.await? {
+        Ok(beacons) => beacons,
+        Err(e) => {
+            eprintln!("not listening for beacons: {:#}", e);
+            BTreeMap::new()
+        }
+    };
+    if instances.is_empty() && beacons.is_empty() {
+        println!("no proj2-serv servers found");
+        return Ok(());
+    }
+    if !instances.is_empty() {
+        println!("{:<40} {:<5} {:<22} txt", "instance", "proto", "address");
+        for (instance, f) in &instances {
+            let addr = addrs.get(&f.host.to_ascii_lowercase()).map_or_else(|| f.host.clone(), |a| a.to_string());
+            println!("{:<40} {:<5} {:<22} {}", instance, f.protocol, format!("{}:{}", addr, f.port), f.txt.join(" "));
+        }
+    }
+    if !beacons.is_empty() {
+        println!("{:<22} beacon", "address");
+        for (from, fields) in &beacons {
+            println!("{:<22} {}", from.ip(), fields);
+        }
+    }
+    Ok(())
+}
+
+fn parse_response(msg: &[u8], instances: &mut BTreeMap<String, Found>, addrs: &mut BTreeMap<String, Ipv4Addr>) -> anyhow::Result<()> {
+    if read_u16(msg, 2)? & 0x8000 == 0 {
+        return Ok(());
+    }
+    let counts: Vec<u16> = (0..4).map(|i| read_u16(msg, 4 + 2 * i)).collect::<anyhow::Result<_>>()?;
+    let mut pos = 12;
+    for _ in 0..counts[0] {
+        pos = read_name(msg, pos)?.1 + 4;
+    }
+    for _ in 0..counts[1] as usize + counts[2] as usize + counts[3] as usize {
+        let (name, next) = read_name(msg, pos)?;
+        let rtype = read_u16(msg, next)?;
+        let len = read_u16(msg, next + 8)? as usize;
+        let start = next + 10;
+        let rdata = msg.get(start..start + len).context("truncated record")?;
+        pos = start + len;
+        let protocol = if name.ends_with(SERVICE_UDP) { "udp" } else { "tcp" };
+        // Other responders may add records for unrelated services.
+        let ours = name.ends_with(SERVICE_TCP) || name.ends_with(SERVICE_UDP);
+        match rtype {
+            TYPE_PTR if name == SERVICE_TCP || name == SERVICE_UDP => {
+                let (instance, _) = read_name(msg, start)?;
+                instances.entry(instance).or_default().protocol = protocol;
+            }
+            TYPE_SRV if ours && len >= 6 => {
+                let found = instances.entry(name).or_default();
+                found.protocol = protocol;
+                found.port = read_u16(msg, start + 4)?;
+                found.host = read_name(msg, start + 6)?.0;
+            }
+            TYPE_TXT if ours => {
+                let found = instances.entry(name).or_default();
+                let mut i = 0;
+                found.txt.clear();
+                while i < rdata.len() {
+                    let l = rdata[i] as usize;
+                    found.txt.push(String::from_utf8_lossy(rdata.get(i + 1..i + 1 + l).unwrap_or_default()).into_owned());
+                    i += 1 + l;
+                }
+            }
+            TYPE_A if len == 4 => {
+                addrs.insert(name.to_ascii_lowercase(), Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3]));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,293 @@
+// proj2-serv/src/mesh.rs
+// Peer mesh mode (`--mesh` / `--mesh-peers`): instances register with each
+// other and periodically measure pairwise TCP and UDP download throughput and
+// UDP round-trip time. Membership starts from the static peer list and spreads
+// by gossip: each round a node sends `MESH_JOIN NAME=<name> PORT=<tcp port>` to
+// every peer it knows, and the reply lists that peer's own members
+// (`MESH_PEERS name=<peer> <name>@<addr> ...`), which are added in turn. Names
+// must be unique across the mesh. `MESH` returns this node's row of the matrix;
+// `ADMIN MESH` collects every known peer's row into the mesh-wide matrix.
+// Measurements are also stored as results under the `mesh` tenant, with the
+// peer's name as their run.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{lookup_host, TcpStream};
+use tokio::time::timeout;
+
+use crate::auth::{self, ControlAuth};
+use crate::config::Config;
+use crate::protocol::{self, error_frame, Command, ErrorCode};
+use crate::results::{Direction, Protocol, TestResult};
+use crate::scheduler;
+use crate::state::ServerState;
+use crate::supervisor::Plane;
+
+/// Tenant mesh results are stored under.
+pub const MESH_TENANT: &str = "mesh";
+const MEASURE_DURATION: Duration = Duration::from_secs(2);
+const RTT_PROBES: usize = 5;
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy)]
+struct Measurement {
+    tcp_bps: Option<f64>,
+    udp_bps: Option<f64>,
+    rtt: Option<Duration>,
+    at: Instant,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Member {
+    /// Learned from the peer's `MESH_PEERS` reply or its `MESH_JOIN`.
+    name: Option<String>,
+    last: Option<Measurement>,
+}
+
+pub struct Mesh {
+    enabled: bool,
+    name: String,
+    /// Members by TCP control address (`host:port`).
+    members: Mutex<BTreeMap<String, Member>>,
+}
+
+impl Mesh {
+    pub fn new(config: &Config) -> Self {
+        let members = config.mesh_peers.iter().map(|addr| (addr.clone(), Member::default())).collect();
+        Mesh { enabled: config.mesh, name: config.mesh_name.clone(), members: Mutex::new(members) }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn add(&self, addr: &str, name: Option<&str>) {
+        if name == Some(self.name.as_str()) {
+            return;
+        }
+        let mut members = self.members.lock().unwrap();
+        // A peer that rejoined from a new address replaces its old entry.
+        if let Some(name) = name {
+            members.retain(|a, m| a == addr || m.name.as_deref() != Some(name));
+        }
+        let member = members.entry(addr.to_string()).or_insert_with(|| {
+            println!("[{}] mesh member {} joined", MESH_TENANT, addr);
+            Member::default()
+        });
+        if let Some(name) = name {
+            member.name = Some(name.to_string());
+        }
+    }
+
+    fn snapshot(&self) -> Vec<(String, Member)> {
+        self.members.lock().unwrap().iter().map(|(a, m)| (a.clone(), m.clone())).collect()
+    }
+
+    /// `MESH_JOIN` from `peer`: register it and reply with the other members.
+    pub fn join(&self, cmd: &Command, peer: SocketAddr) -> String {
+        if !self.enabled {
+            return format!("{}\n", error_frame(ErrorCode::Disabled, "mesh mode disabled"));
+        }
+        let name = cmd.opt("NAME").filter(|n| protocol::valid_tenant(n));
+        let Some(port) = cmd.opt("PORT").and_then(|p| p.parse::<u16>().ok()) else {
+            return format!("{}\n", error_frame(ErrorCode::BadCommand, "usage: MESH_JOIN NAME=<name> PORT=<tcp port>"));
+        };
+        let addr = SocketAddr::new(peer.ip(), port).to_string();
+        self.add(&addr, name);
+        let mut reply = format!("MESH_PEERS name={}", self.name);
+        for (a, m) in self.snapshot() {
+            if let (Some(n), false) = (&m.name, a == addr) {
+                let _ = write!(reply, " {}@{}", n, a);
+            }
+        }
+        reply.push('\n');
+        reply
+    }
+
+    /// `MESH`: this node's row of the matrix, one line per member.
+    pub fn row(&self) -> String {
+        if !self.enabled {
+            return format!("{}\n", error_frame(ErrorCode::Disabled, "mesh mode disabled"));
+        }
+        let mut out = String::new();
+        let fmt = |v: Option<f64>| v.map_or("-".to_string(), |v| format!("{:.0}", v));
+        for (addr, m) in self.snapshot() {
+            let _ = write!(out, "from={} to={} addr={}", self.name, m.name.as_deref().unwrap_or("-"), addr);
+            match m.last {
+                Some(last) => {
+                    let _ = writeln!(
+                        out,
+                        " tcp_bps={} udp_bps={} rtt_us={} age_s={}",
+                        fmt(last.tcp_bps),
+                        fmt(last.udp_bps),
+                        last.rtt.map_or("-".to_string(), |r| r.as_micros().to_string()),
+                        last.at.elapsed().as_secs()
+                    );
+                }
+                None => out.push_str(" unmeasured\n"),
+            }
+        }
+        out.push_str("END\n");
+        out
+    }
+
+    /// `ADMIN MESH`: this node's row followed by every member's.
+    pub async fn matrix(&self) -> String {
+        let mut out = self.row();
+        if !self.enabled {
+            return out;
+        }
+        out.truncate(out.len() - "END\n".len());
+        for (addr, m) in self.snapshot() {
+            match fetch_row(&addr).await {
+                Ok(row) => out.push_str(&row),
+                Err(e) => {
+                    let _ = writeln!(out, "from={} addr={} error={:?}", m.name.as_deref().unwrap_or("-"), addr, format!("{:#}", e));
+                }
+            }
+        }
+        out.push_str("END\n");
+        out
+    }
+}
+
+/// Measure every member once per `interval` until the server stops.
+pub async fn run_mesh(state: Arc<ServerState>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        let Some(port) = state.health.port(Plane::Tcp) else { continue };
+        for (addr, _) in state.mesh.snapshot() {
+            if let Err(e) = join(&state.mesh, &addr, port, state.control_auth.as_ref()).await {
+                eprintln!("[{}] mesh join with {} failed: {:#}", MESH_TENANT, addr, e);
+                continue;
+            }
+            measure(&state, &addr).await;
+        }
+    }
+}
+
+async fn connect(addr: &str) -> anyhow::Result<TcpStream> {
+    let peer = lookup_host(addr).await?.next().with_context(|| format!("{} has no addresses", addr))?;
+    timeout(REPLY_TIMEOUT, TcpStream::connect(peer))
+        .await
+        .context("connect timed out")?
+        .context("connect failed")
+}
+
+/// Send `cmd` and read the reply up to `terminator`.
+async fn request(addr: &str, cmd: &str, terminator: &str) -> anyhow::Result<String> {
+    let mut stream = connect(addr).await?;
+    stream.write_all(cmd.as_bytes()).await?;
+    let mut reply = Vec::new();
+    let mut buf = [0u8; 4096];
+    // ERR replies are a single line whatever the expected terminator.
+    let complete = |r: &[u8]| r.ends_with(terminator.as_bytes()) || (r.starts_with(b"ERR") && r.ends_with(b"\n"));
+    while !complete(&reply) {
+        let n = timeout(REPLY_TIMEOUT, stream.read(&mut buf)).await.context("reply timed out")??;
+        if n == 0 {
+            bail!("connection closed");
+        }
+        reply.extend_from_slice(&buf[..n]);
+    }
+    let reply = String::from_utf8_lossy(&reply).into_owned();
+    if reply.starts_with("ERR") {
+        bail!("{}", reply.trim());
+    }
+    Ok(reply)
+}
+
+/// A member's row without its `END` line.
+async fn fetch_row(addr: &str) -> anyhow::Result<String> {
+    let row = request(addr, "MESH\n", "END\n").await?;
+    Ok(row[..row.len() - "END\n".len()].to_string())
+}
+
+async fn join(mesh: &Mesh, addr: &str, port: u16, auth: Option<&ControlAuth>) -> anyhow::Result<()> {
+    let cmd = auth::sign(auth, &format!("MESH_JOIN NAME={} PORT={}", mesh.name, port));
+    let reply = request(addr, &format!("{}\n", cmd), "\n").await?;
+    let mut parts = reply.split_whitespace();
+    if parts.next() != Some("MESH_PEERS") {
+        bail!("unexpected reply {:?}", reply.trim());
+    }
+    for part in parts {
+        if let Some(name) = part.strip_prefix("name=") {
+            mesh.add(addr, Some(name));
+        } else if let Some((name, member)) = part.split_once('@') {
+            mesh.add(member, Some(name));
+        }
+    }
+    Ok(())
+}
+
+/// Peer's UDP port, from its `CAPS` reply.
+async fn udp_port(addr: &str) -> anyhow::Result<u16> {
+    let caps = request(addr, "CAPS\n", "\n").await?;
+    caps.split_whitespace()
+        .find_map(|kv| kv.strip_prefix("udp=up:"))
+        .and_then(|p| p.parse().ok())
+        .with_context(|| format!("peer UDP plane unavailable: {:?}", caps.trim()))
+}
+
+/// Smallest round trip of a few UDP `CAPS` requests.
+async fn udp_rtt(peer: SocketAddr) -> anyhow::Result<Duration> {
+    let sock = scheduler::udp_connect(peer).await?;
+    let mut buf = [0u8; 512];
+    let mut best: Option<Duration> = None;
+    for _ in 0..RTT_PROBES {
+        let start = Instant::now();
+        sock.send(b"CAPS").await?;
+        if let Ok(Ok(_)) = timeout(REPLY_TIMEOUT, sock.recv(&mut buf)).await {
+            best = Some(best.map_or(start.elapsed(), |b| b.min(start.elapsed())));
+        }
+    }
+    best.context("no UDP reply")
+}
+
+async fn measure(state: &ServerState, addr: &str) {
+    let name = state.mesh.snapshot().into_iter().find(|(a, _)| a == addr).and_then(|(_, m)| m.name);
+    let run = name.clone().unwrap_or_else(|| addr.to_string());
+    let log = |what: &str, e: anyhow::Error| eprintln!("[{}] mesh {} to {} failed: {:#}", MESH_TENANT, what, addr, e);
+    let record = |protocol, peer, (bytes, duration): (u64, Duration)| {
+        let result = TestResult::new(MESH_TENANT, protocol, Direction::Download, peer, bytes, duration).with_run(Some(run.clone()));
+        println!("[{}] result: {}", MESH_TENANT, result.to_line());
+        let bps = result.throughput_bps();
+        state.store(result);
+        bps
+    };
+
+    let mut m = Measurement { tcp_bps: None, udp_bps: None, rtt: None, at: Instant::now() };
+    let tcp_peer = match lookup_host(addr).await.map(|mut a| a.next()) {
+        Ok(Some(peer)) => peer,
+        _ => return log("lookup", anyhow::anyhow!("cannot resolve {}", addr)),
+    };
+    match scheduler::tcp_download(tcp_peer, MEASURE_DURATION, state.control_auth.as_ref()).await {
+        Ok(sample) => m.tcp_bps = Some(record(Protocol::Tcp, tcp_peer, sample)),
+        Err(e) => log("TCP download", e),
+    }
+    match udp_port(addr).await {
+        Ok(port) => {
+            let udp_peer = SocketAddr::new(tcp_peer.ip(), port);
+            match udp_rtt(udp_peer).await {
+                Ok(rtt) => m.rtt = Some(rtt),
+                Err(e) => log("UDP RTT", e),
+            }
+            match scheduler::udp_download(udp_peer, MEASURE_DURATION, state.control_auth.as_ref()).await {
+                Ok(sample) => m.udp_bps = Some(record(Protocol::Udp, udp_peer, sample)),
+                Err(e) => log("UDP download", e),
+            }
+        }
+        Err(e) => log("UDP port lookup", e),
+    }
+    m.at = Instant::now();
+    if let Some(member) = state.mesh.members.lock().unwrap().get_mut(addr) {
+        member.last = Some(m);
+    }
+}
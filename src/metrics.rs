@@ -0,0 +1,151 @@
+// proj2-serv/src/metrics.rs
+// Per-tenant counters, exported in a Prometheus-like text format so
+// exports can be filtered by the `tenant` label. Counters are atomics; the
+// tenant registry is only write-locked to add a tenant, so sessions counting
+// and exports taking snapshots never wait on each other.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+/// A snapshot of one tenant's counters.
+#[derive(Debug, Default, Clone)]
+pub struct TenantMetrics {
+    pub sessions_started: u64,
+    pub sessions_finished: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub echo_requests: u64,
+}
+
+/// A snapshot of the counters not attributed to a tenant.
+#[derive(Debug, Default, Clone)]
+pub struct ServerMetrics {
+    pub task_panics: u64,
+    pub accept_exhausted: u64,
+    pub accept_paused: bool,
+    pub exports_failed: u64,
+    pub exports_dropped: u64,
+}
+
+#[derive(Default)]
+struct TenantCounters {
+    sessions_started: AtomicU64,
+    sessions_finished: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    echo_requests: AtomicU64,
+}
+
+impl TenantCounters {
+    fn snapshot(&self) -> TenantMetrics {
+        TenantMetrics {
+            sessions_started: self.sessions_started.load(Ordering::Relaxed),
+            sessions_finished: self.sessions_finished.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            echo_requests: self.echo_requests.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    tenants: RwLock<BTreeMap<String, Arc<TenantCounters>>>,
+    /// Session and plane tasks that panicked; not attributed to a tenant.
+    panics: AtomicU64,
+    /// TCP accepts that failed for lack of file descriptors or socket memory.
+    accept_exhausted: AtomicU64,
+    /// Whether the TCP accept loop is paused by such a failure right now.
+    accept_paused: AtomicBool,
+    /// Results a sink failed to take, after its retry.
+    exports_failed: AtomicU64,
+    /// Results dropped because a sink's queue was full.
+    exports_dropped: AtomicU64,
+}
+
+impl Metrics {
+    fn tenant(&self, tenant: &str) -> Arc<TenantCounters> {
+        if let Some(counters) = self.tenants.read().unwrap().get(tenant) {
+            return counters.clone();
+        }
+        self.tenants.write().unwrap().entry(tenant.to_string()).or_default().clone()
+    }
+
+    pub fn session_started(&self, tenant: &str) {
+        self.tenant(tenant).sessions_started.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn session_finished(&self, tenant: &str, sent: u64, received: u64) {
+        let counters = self.tenant(tenant);
+        counters.sessions_finished.fetch_add(1, Ordering::Relaxed);
+        counters.bytes_sent.fetch_add(sent, Ordering::Relaxed);
+        counters.bytes_received.fetch_add(received, Ordering::Relaxed);
+    }
+
+    pub fn echo_requests(&self, tenant: &str, n: u64) {
+        self.tenant(tenant).echo_requests.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn task_panicked(&self) {
+        self.panics.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Enter (`true`) or leave a pause of the TCP accept loop on resource exhaustion.
+    pub fn accept_exhausted(&self, paused: bool) {
+        if paused {
+            self.accept_exhausted.fetch_add(1, Ordering::Relaxed);
+        }
+        self.accept_paused.store(paused, Ordering::Relaxed);
+    }
+
+    pub fn export_failed(&self) {
+        self.exports_failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn export_dropped(&self) {
+        self.exports_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counters of every tenant, for export.
+    pub fn tenant_totals(&self) -> Vec<(String, TenantMetrics)> {
+        self.tenants.read().unwrap().iter().map(|(name, m)| (name.clone(), m.snapshot())).collect()
+    }
+
+    pub fn server(&self) -> ServerMetrics {
+        ServerMetrics {
+            task_panics: self.panics.load(Ordering::Relaxed),
+            accept_exhausted: self.accept_exhausted.load(Ordering::Relaxed),
+            accept_paused: self.accept_paused.load(Ordering::Relaxed),
+            exports_failed: self.exports_failed.load(Ordering::Relaxed),
+            exports_dropped: self.exports_dropped.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn tenants(&self) -> Vec<String> {
+        self.tenants.read().unwrap().keys().cloned().collect()
+    }
+
+    /// Render counters; `tenant = None` exports every namespace.
+    pub fn render(&self, tenant: Option<&str>) -> String {
+        let totals = self.tenant_totals();
+        let mut out = String::new();
+        for (name, m) in totals.iter().filter(|(name, _)| tenant.is_none_or(|t| t == name.as_str())) {
+            let _ = writeln!(out, "proj2serv_sessions_started_total{{tenant=\"{}\"}} {}", name, m.sessions_started);
+            let _ = writeln!(out, "proj2serv_sessions_finished_total{{tenant=\"{}\"}} {}", name, m.sessions_finished);
+            let _ = writeln!(out, "proj2serv_bytes_sent_total{{tenant=\"{}\"}} {}", name, m.bytes_sent);
+            let _ = writeln!(out, "proj2serv_bytes_received_total{{tenant=\"{}\"}} {}", name, m.bytes_received);
+            let _ = writeln!(out, "proj2serv_echo_requests_total{{tenant=\"{}\"}} {}", name, m.echo_requests);
+        }
+        if tenant.is_none() {
+            let server = self.server();
+            let _ = writeln!(out, "proj2serv_task_panics_total {}", server.task_panics);
+            let _ = writeln!(out, "proj2serv_accept_exhausted_total {}", server.accept_exhausted);
+            let _ = writeln!(out, "proj2serv_accept_paused {}", server.accept_paused as u8);
+            let _ = writeln!(out, "proj2serv_exports_failed_total {}", server.exports_failed);
+            let _ = writeln!(out, "proj2serv_exports_dropped_total {}", server.exports_dropped);
+        }
+        out
+    }
+}
@@ -0,0 +1,194 @@
+// proj2-serv/src/multicast.rs
+// Multicast test mode (`--multicast <group:port>`): the server streams
+// sequenced datagrams to the group at `--multicast-rate` for as long as it runs,
+// the way an IPTV head-end or market-data feed would. Each datagram starts with
+// `MCAST <seq> <send_us>` and is padded to 1316 bytes (seven MPEG-TS packets).
+// Subscribers learn the group with `MCAST_INFO` on the UDP plane and send
+// cumulative `MCAST_REPORT RECEIVED=<n> FIRST=<seq> LAST=<seq>` reports there;
+// each is answered with that receiver's loss, and `ADMIN MULTICAST` lists the
+// latest loss of every receiver. Receivers silent for RECEIVER_TTL are dropped.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+
+use crate::config::Config;
+use crate::owd;
+use crate::protocol::{error_frame, Command, ErrorCode};
+use crate::state::ServerState;
+
+/// Datagram size: seven 188-byte MPEG-TS packets, as IPTV streams use.
+pub const DATAGRAM_SIZE: usize = 1316;
+/// Pacing granularity of the sender.
+const TICK: Duration = Duration::from_millis(1);
+/// Pause after a failed send.
+const SEND_RETRY: Duration = Duration::from_secs(1);
+/// Receivers whose last report is older than this are forgotten.
+const RECEIVER_TTL: Duration = Duration::from_secs(300);
+/// Receivers tracked at once.
+const MAX_RECEIVERS: usize = 4096;
+
+/// A receiver's latest cumulative report.
+#[derive(Debug, Clone, Copy)]
+struct Report {
+    received: u64,
+    first: u64,
+    last: u64,
+    at: Instant,
+}
+
+impl Report {
+    fn parse(cmd: &Command) -> Option<Report> {
+        let num = |key| cmd.opt(key)?.parse::<u64>().ok();
+        let (received, first, last) = (num("RECEIVED")?, num("FIRST")?, num("LAST")?);
+        (first <= last).then_some(Report { received, first, last, at: Instant::now() })
+    }
+
+    /// Datagrams sent between the first and last sequence number seen.
+    fn expected(&self) -> u64 {
+        self.last - self.first + 1
+    }
+
+    /// Duplicates can make `received` exceed `expected`; that is no loss.
+    fn lost(&self) -> u64 {
+        self.expected().saturating_sub(self.received)
+    }
+
+    fn loss_pct(&self) -> f64 {
+        self.lost() as f64 * 100.0 / self.expected() as f64
+    }
+
+    fn fields(&self) -> String {
+        format!(
+            "expected={} received={} lost={} loss_pct={:.3}",
+            self.expected(),
+            self.received,
+            self.lost(),
+            self.loss_pct()
+        )
+    }
+}
+
+pub struct Multicast {
+    group: Option<SocketAddrV4>,
+    rate: u64,
+    /// Next sequence number to send.
+    seq: AtomicU64,
+    receivers: Mutex<BTreeMap<SocketAddr, Report>>,
+}
+
+impl Multicast {
+    pub fn new(config: &Config) -> Self {
+        Multicast { group: config.multicast, rate: config.multicast_rate, seq: AtomicU64::new(0), receivers: Mutex::new(BTreeMap::new()) }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.group.is_some()
+    }
+
+    /// `MCAST_INFO` reply: where to subscribe and what to expect.
+    pub fn info(&self) -> String {
+        match self.group {
+            Some(group) => format!(
+                "MCAST group={} rate={} size={} seq={}",
+                group,
+                self.rate,
+                DATAGRAM_SIZE,
+                self.seq.load(Ordering::Relaxed)
+            ),
+            None => error_frame(ErrorCode::Disabled, "multicast is not enabled"),
+        }
+    }
+
+    /// Store a receiver's `MCAST_REPORT` and answer with its loss.
+    pub fn report(&self, tenant: &str, addr: SocketAddr, cmd: &Command) -> String {
+        if !self.enabled() {
+            return error_frame(ErrorCode::Disabled, "multicast is not enabled");
+        }
+        let Some(report) = Report::parse(cmd) else {
+            return error_frame(ErrorCode::BadCommand, "usage: MCAST_REPORT RECEIVED=<n> FIRST=<seq> LAST=<seq>");
+        };
+        let mut receivers = self.receivers.lock().unwrap();
+        receivers.retain(|_, r| r.at.elapsed() < RECEIVER_TTL);
+        if receivers.len() >= MAX_RECEIVERS && !receivers.contains_key(&addr) {
+            return error_frame(ErrorCode::Busy, "too many multicast receivers");
+        }
+        receivers.insert(addr, report);
+        println!("[{}] multicast receiver {}: {}", tenant, addr, report.fields());
+        format!("MCAST_LOSS {}", report.fields())
+    }
+
+    /// `ADMIN MULTICAST`: every receiver's latest loss.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        if let Some(group) = self.group {
+            let _ = writeln!(out, "group={} rate={} sent={}", group, self.rate, self.seq.load(Ordering::Relaxed));
+        }
+        let mut receivers = self.receivers.lock().unwrap();
+        receivers.retain(|_, r| r.at.elapsed() < RECEIVER_TTL);
+        for (addr, report) in receivers.iter() {
+            let _ = writeln!(out, "receiver={} {} age_s={}", addr, report.fields(), report.at.elapsed().as_secs());
+        }
+        out.push_str("END\n");
+        out
+    }
+}
+
+fn bind_sender(config: &Config) -> anyhow::Result<UdpSocket> {
+    let s = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).context("creating multicast socket")?;
+    s.set_multicast_ttl_v4(config.multicast_ttl).context("setting multicast TTL")?;
+    // Keep loopback on so receivers on this host see the stream too.
+    s.set_multicast_loop_v4(true).context("enabling multicast loopback")?;
+    if let Some(iface) = config.multicast_if {
+        s.set_multicast_if_v4(&iface).with_context(|| format!("selecting multicast interface {}", iface))?;
+    }
+    s.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)).into()).context("binding multicast socket")?;
+    s.set_nonblocking(true)?;
+    UdpSocket::from_std(s.into()).context("registering multicast socket")
+}
+
+/// Stream to the group at the configured rate until the server stops.
+pub async fn run_sender(state: Arc<ServerState>) -> anyhow::Result<()> {
+    let multicast = &state.multicast;
+    let Some(group) = multicast.group else { return Ok(()) };
+    let sock = bind_sender(&state.config)?;
+    println!(
+        "Multicast streaming to {} at {} bit/s (ttl={})",
+        group, multicast.rate, state.config.multicast_ttl
+    );
+    let bytes_per_tick = multicast.rate as f64 / 8.0 * TICK.as_secs_f64();
+    let mut buf = vec![0u8; DATAGRAM_SIZE];
+    let mut credit = 0.0;
+    let mut ticker = tokio::time::interval(TICK);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        // Credit carries over so rates below one datagram per tick still pace evenly.
+        credit += bytes_per_tick;
+        while credit >= DATAGRAM_SIZE as f64 {
+            credit -= DATAGRAM_SIZE as f64;
+            let seq = multicast.seq.load(Ordering::Relaxed);
+            let header = format!("MCAST {} {}\n", seq, owd::now_us());
+            buf[..header.len()].copy_from_slice(header.as_bytes());
+            match sock.send_to(&buf, group).await {
+                Ok(_) => {
+                    multicast.seq.store(seq + 1, Ordering::Relaxed);
+                }
+                Err(e) => {
+                    // A missing route is usually transient; keep the sequence and retry shortly.
+                    eprintln!("Multicast send to {} failed: {}", group, e);
+                    credit = 0.0;
+                    tokio::time::sleep(SEND_RETRY).await;
+                    break;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,228 @@
+// proj2-serv/src/otel.rs
+// OpenTelemetry export over OTLP/HTTP with JSON encoding (`--otlp-endpoint
+// http://collector:4318`), so tests show up in Jaeger or Tempo next to the
+// services they are measuring for, built on the opentelemetry SDK and its
+// OTLP exporter. Every throughput test is a server span `proj2.test` with
+// child spans for its phases: `handshake`, `transfer` and `finalize` (building
+// and recording the result). A client that sends `TRACEPARENT=<w3c
+// traceparent>` with its start command gets the test's spans in its own trace.
+// The SDK batches spans to `/v1/traces` every second and sends the per-tenant
+// session and byte counters to `/v1/metrics` as cumulative sums every 15 s,
+// each from a thread of its own. Export is best effort: spans are dropped when
+// the queue is full and failed exports are logged by the SDK, never retried.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use opentelemetry::metrics::{Meter, MeterProvider as _};
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::{Span as _, SpanContext, SpanKind, Status, TraceContextExt, Tracer as _, TracerProvider as _};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_otlp::{MetricExporter, Protocol, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{BatchConfigBuilder, BatchSpanProcessor, SdkTracer, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+
+use crate::export;
+use crate::metrics::TenantMetrics;
+use crate::results::{Direction, TestResult};
+use crate::state::ServerState;
+
+const SERVICE_NAME: &str = "proj2-serv";
+/// Finished spans waiting for export before new ones are dropped.
+const QUEUE_DEPTH: usize = 4096;
+const MAX_BATCH: usize = 512;
+const SPAN_INTERVAL: Duration = Duration::from_secs(1);
+const METRICS_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Collector address and path prefix from `--otlp-endpoint`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OtlpEndpoint {
+    host: String,
+    base: String,
+}
+
+impl OtlpEndpoint {
+    pub fn parse(url: &str) -> Option<Self> {
+        let rest = url.strip_prefix("http://")?;
+        let (authority, base) = rest.split_once('/').unwrap_or((rest, ""));
+        Some(OtlpEndpoint { host: export::with_port(authority, 4318)?, base: base.trim_end_matches('/').to_string() })
+    }
+
+    fn url(&self, signal: &str) -> String {
+        match self.base.as_str() {
+            "" => format!("http://{}/v1/{}", self.host, signal),
+            base => format!("http://{}/{}/v1/{}", self.host, base, signal),
+        }
+    }
+}
+
+impl std::fmt::Display for OtlpEndpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "http://{}/{}", self.host, self.base)
+    }
+}
+
+/// Trace and parent span a client asked the test to join (`TRACEPARENT=`).
+#[derive(Debug, Clone)]
+pub struct TraceParent(SpanContext);
+
+impl TraceParent {
+    /// `00-<32 hex trace id>-<16 hex span id>-<2 hex flags>`, as the W3C
+    /// propagator reads it.
+    pub fn parse(value: &str) -> Option<Self> {
+        let carrier = HashMap::from([("traceparent".to_string(), value.to_string())]);
+        let cx = TraceContextPropagator::new().extract(&carrier);
+        let parent = cx.span().span_context().clone();
+        parent.is_valid().then_some(TraceParent(parent))
+    }
+}
+
+/// The SDK pipelines; inert without `--otlp-endpoint`.
+pub struct Telemetry {
+    tracer: Option<SdkTracer>,
+    traces: Option<SdkTracerProvider>,
+    metrics: Option<SdkMeterProvider>,
+}
+
+impl Telemetry {
+    pub fn new(endpoint: Option<&OtlpEndpoint>) -> Self {
+        let inert = Telemetry { tracer: None, traces: None, metrics: None };
+        let Some(endpoint) = endpoint else { return inert };
+        let resource = Resource::builder().with_service_name(SERVICE_NAME).build();
+        let spans = SpanExporter::builder().with_http().with_protocol(Protocol::HttpJson).with_endpoint(endpoint.url("traces")).build();
+        let sums = MetricExporter::builder().with_http().with_protocol(Protocol::HttpJson).with_endpoint(endpoint.url("metrics")).build();
+        let (spans, sums) = match (spans, sums) {
+            (Ok(spans), Ok(sums)) => (spans, sums),
+            (Err(e), _) | (_, Err(e)) => {
+                eprintln!("Not exporting OpenTelemetry to {}: {}", endpoint, e);
+                return inert;
+            }
+        };
+        let batches = BatchConfigBuilder::default()
+            .with_max_queue_size(QUEUE_DEPTH)
+            .with_max_export_batch_size(MAX_BATCH)
+            .with_scheduled_delay(SPAN_INTERVAL)
+            .build();
+        let traces = SdkTracerProvider::builder()
+            .with_span_processor(BatchSpanProcessor::builder(spans).with_batch_config(batches).build())
+            .with_resource(resource.clone())
+            .build();
+        let metrics = SdkMeterProvider::builder()
+            .with_reader(PeriodicReader::builder(sums).with_interval(METRICS_INTERVAL).build())
+            .with_resource(resource)
+            .build();
+        Telemetry { tracer: Some(traces.tracer(SERVICE_NAME)), traces: Some(traces), metrics: Some(metrics) }
+    }
+
+    /// Start tracing one test; a no-op trace when export is off.
+    pub fn test_trace(&self, parent: Option<&TraceParent>, tenant: &str, direction: Direction) -> TestTrace {
+        let Some(tracer) = &self.tracer else { return TestTrace { root: None, phase: None } };
+        let parent = match parent {
+            Some(TraceParent(span)) => Context::new().with_remote_span_context(span.clone()),
+            None => Context::new(),
+        };
+        let root = tracer
+            .span_builder("proj2.test")
+            .with_kind(SpanKind::Server)
+            .with_attributes([KeyValue::new("proj2.tenant", tenant.to_string()), KeyValue::new("proj2.direction", direction.as_str())])
+            .start_with_context(tracer, &parent);
+        TestTrace { root: Some((tracer.clone(), parent.with_span(root))), phase: None }
+    }
+}
+
+/// The spans of one test while it runs.
+pub struct TestTrace {
+    /// The tracer and a context holding the root span.
+    root: Option<(SdkTracer, Context)>,
+    phase: Option<opentelemetry_sdk::trace::Span>,
+}
+
+impl TestTrace {
+    /// End the current phase, if any, and start `name`.
+    pub fn phase(&mut self, name: &'static str) {
+        self.end_phase();
+        if let Some((tracer, root)) = &self.root {
+            self.phase = Some(tracer.start_with_context(name, root));
+        }
+    }
+
+    fn end_phase(&mut self) {
+        if let Some(mut span) = self.phase.take() {
+            span.end();
+        }
+    }
+
+    /// Describe the test on its root span from its result.
+    pub fn result(&mut self, result: &TestResult) {
+        let Some((_, root)) = &self.root else { return };
+        let span = root.span();
+        span.set_attributes([
+            KeyValue::new("network.transport", result.protocol.as_str()),
+            KeyValue::new("client.address", result.peer.ip().to_string()),
+            KeyValue::new("client.port", result.peer.port() as i64),
+            KeyValue::new("proj2.bytes", result.bytes as i64),
+            KeyValue::new("proj2.bps", result.throughput_bps() as i64),
+        ]);
+        if let Some(run) = &result.run {
+            span.set_attribute(KeyValue::new("proj2.run", run.clone()));
+        }
+        if let Some(reason) = result.aborted {
+            span.set_attribute(KeyValue::new("proj2.aborted", reason.to_string()));
+        }
+    }
+
+    /// Close the trace and queue its spans; `error` marks the phase it
+    /// happened in and the test as failed.
+    pub fn finish(mut self, error: Option<String>) {
+        let Some((_, root)) = self.root.take() else { return };
+        if let Some(message) = error {
+            if let Some(phase) = &mut self.phase {
+                phase.set_status(Status::error(message.clone()));
+            }
+            root.span().set_status(Status::error(message));
+        }
+        self.end_phase();
+        root.span().end();
+    }
+}
+
+/// Report the per-tenant counters as cumulative sums until the server stops.
+pub fn observe_metrics(state: &Arc<ServerState>) {
+    let Some(provider) = &state.telemetry.metrics else { return };
+    let meter = provider.meter(SERVICE_NAME);
+    let state = Arc::downgrade(state);
+    sum(&meter, &state, "proj2serv.sessions.started", "{session}", |m| m.sessions_started);
+    sum(&meter, &state, "proj2serv.sessions.finished", "{session}", |m| m.sessions_finished);
+    sum(&meter, &state, "proj2serv.bytes.sent", "By", |m| m.bytes_sent);
+    sum(&meter, &state, "proj2serv.bytes.received", "By", |m| m.bytes_received);
+}
+
+fn sum(meter: &Meter, state: &Weak<ServerState>, name: &'static str, unit: &'static str, value: fn(&TenantMetrics) -> u64) {
+    // A weak reference, so the callback does not keep the state alive.
+    let state = state.clone();
+    meter
+        .u64_observable_counter(name)
+        .with_unit(unit)
+        .with_callback(move |observer| {
+            let Some(state) = state.upgrade() else { return };
+            for (tenant, m) in state.metrics.tenant_totals() {
+                observer.observe(value(&m), &[KeyValue::new("proj2.tenant", tenant)]);
+            }
+        })
+        .build();
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        // Flush what is queued; the SDK threads stop with their providers.
+        if let Some(traces) = &self.traces {
+            let _ = traces.shutdown();
+        }
+        if let Some(metrics) = &self.metrics {
+            let _ = metrics.shutdown();
+        }
+    }
+}
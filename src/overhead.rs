@@ -0,0 +1,129 @@
+// proj2-serv/src/overhead.rs
+// Tunnel overhead probe (`OVERHEAD=<bytes>,<bytes>,...` or `OVERHEAD=1` for
+// DEFAULT_SIZES, on a UDP START_DOWNLOAD). The download floods at each
+// datagram size in turn for `STEP=<secs>` (default 1), stamping datagrams like
+// a ramp (`RAMP <step> <seq> <sent_us>`), and the client answers the FIN with
+// the same `RAMP_REPORT` of what each step delivered. On a saturated link every
+// datagram costs its payload plus a fixed per-packet overhead h, so the
+// goodput at size s is C*s/(s+h): 1/goodput is linear in 1/s, and a least
+// squares fit gives the link rate C and h. The server sends the client
+// `OVERHEAD pkt_overhead_bytes=<h> encap_bytes=<h minus IP and UDP headers>
+// capacity_bps=<C>` and the result carries the same. What is left after the
+// IP and UDP headers is link framing (38 bytes on Ethernet) plus whatever a
+// tunnel on the path adds: a VPN shows as tens of bytes more than a plain
+// path to the same server.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::protocol::MAX_UDP_PAYLOAD;
+use crate::ramp::StepReport;
+
+/// Sizes of `OVERHEAD=1`.
+pub const DEFAULT_SIZES: [usize; 5] = [200, 500, 800, 1100, 1400];
+/// Step length without `STEP=`.
+pub const DEFAULT_STEP: Duration = Duration::from_secs(1);
+/// Fewest and most sizes in one probe.
+const MIN_SIZES: usize = 2;
+const MAX_SIZES: usize = 16;
+/// Smallest size: room for the step stamp.
+const MIN_SIZE: usize = 64;
+
+/// Parse `OVERHEAD=`: `1` for the default sizes, else at least two distinct
+/// datagram sizes.
+pub fn parse_sizes(value: &str) -> Option<Vec<usize>> {
+    if value == "1" {
+        return Some(DEFAULT_SIZES.to_vec());
+    }
+    let sizes: Vec<usize> = value.split(',').map(|s| s.trim().parse().ok().filter(|s| (MIN_SIZE..=MAX_UDP_PAYLOAD).contains(s))).collect::<Option<_>>()?;
+    let distinct = sizes.iter().any(|&s| s != sizes[0]);
+    ((MIN_SIZES..=MAX_SIZES).contains(&sizes.len()) && distinct).then_some(sizes)
+}
+
+/// A probe's sizes and, as the download goes, what it sent at each.
+#[derive(Debug, Clone)]
+pub struct Overhead {
+    pub sizes: Vec<usize>,
+    pub step: Duration,
+    /// Datagrams sent at each size.
+    pub sent: Vec<u64>,
+    /// The client's report, once it arrives.
+    pub report: Option<Vec<StepReport>>,
+    /// IP and UDP header bytes toward the client.
+    headers: usize,
+}
+
+/// What the goodput curve gives.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fit {
+    /// Bytes each datagram costs beyond its payload.
+    pub per_packet: f64,
+    pub capacity_bps: f64,
+}
+
+impl Overhead {
+    pub fn new(sizes: Vec<usize>, step: Duration, dest: SocketAddr) -> Self {
+        let sent = vec![0; sizes.len()];
+        let headers = if dest.ip().to_canonical().is_ipv4() { 28 } else { 48 };
+        Overhead { sizes, step, sent, report: None, headers }
+    }
+
+    /// How long the whole probe takes.
+    pub fn duration(&self) -> Duration {
+        self.step * self.sizes.len() as u32
+    }
+
+    /// Payload bytes per second the client received at each size.
+    fn goodput(&self) -> Option<Vec<f64>> {
+        let report = self.report.as_ref()?;
+        let secs = self.step.as_secs_f64();
+        Some(self.sizes.iter().zip(report).map(|(&size, &(received, _))| (received * size as u64) as f64 / secs).collect())
+    }
+
+    /// Least squares fit of 1/goodput against 1/size; None without a report,
+    /// or when the curve does not fit the model.
+    pub fn fit(&self) -> Option<Fit> {
+        let points: Vec<(f64, f64)> = self.sizes.iter().zip(self.goodput()?).filter(|(_, g)| *g > 0.0).map(|(&s, g)| (1.0 / s as f64, 1.0 / g)).collect();
+        let n = points.len() as f64;
+        let (mx, my) = (points.iter().map(|p| p.0).sum::<f64>() / n, points.iter().map(|p| p.1).sum::<f64>() / n);
+        let sxx: f64 = points.iter().map(|(x, _)| (x - mx).powi(2)).sum();
+        let sxy: f64 = points.iter().map(|(x, y)| (x - mx) * (y - my)).sum();
+        if points.len() < MIN_SIZES || sxx == 0.0 {
+            return None;
+        }
+        let slope = sxy / sxx;
+        let intercept = my - slope * mx;
+        (intercept > 0.0 && slope >= 0.0).then(|| Fit { per_packet: slope / intercept, capacity_bps: 8.0 / intercept })
+    }
+
+    /// The line sent to the client once the fit is in.
+    pub fn reply(&self) -> Option<String> {
+        let fit = self.fit()?;
+        Some(format!("OVERHEAD {}", self.fields(fit)))
+    }
+
+    fn fields(&self, fit: Fit) -> String {
+        format!(
+            "pkt_overhead_bytes={:.0} encap_bytes={:.0} capacity_bps={:.0}",
+            fit.per_packet,
+            (fit.per_packet - self.headers as f64).max(0.0),
+            fit.capacity_bps
+        )
+    }
+}
+
+impl fmt::Display for Overhead {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |items: Vec<String>| items.join(",");
+        write!(f, "overhead_sizes={} overhead_step_s={}", join(self.sizes.iter().map(usize::to_string).collect()), self.step.as_secs())?;
+        write!(f, " overhead_sent={}", join(self.sent.iter().map(u64::to_string).collect()))?;
+        if let Some(goodput) = self.goodput() {
+            write!(f, " overhead_goodput_bps={}", join(goodput.iter().map(|g| format!("{:.0}", g * 8.0)).collect()))?;
+        }
+        if let Some(fit) = self.fit() {
+            write!(f, " {}", self.fields(fit))?;
+        }
+        Ok(())
+    }
+}
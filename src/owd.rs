@@ -0,0 +1,194 @@
+// proj2-serv/src/owd.rs
+// One-way delay estimation over UDP, NTP style. The client sends
+// `OWD_PROBE <seq> <t1>` (its send time, µs); the server answers
+// `OWD_REPLY <seq> <t1> <t2> <t3>` with its receive and send times. The client
+// then sends `OWD_REPORT <seq>:<t4>,...` with its receive times, and the server
+// estimates clock offset and skew and reports forward and reverse delay.
+//
+// Offset is fitted as a line over time (offset + skew) through the samples
+// with the lowest RTT, where queueing is least likely to make the paths look
+// asymmetric. Forward and reverse delay are then relative to that fit, so a
+// constant path asymmetry is indistinguishable from clock offset; what the
+// estimate shows is asymmetry that varies (e.g. queueing in one direction).
+// The reply also carries RTT and jitter percentiles over all probes; jitter is
+// the RTT change between consecutive probes.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::histogram::RoundTrips;
+use crate::protocol::Command;
+
+/// Probes kept per client before a report.
+const MAX_PROBES: usize = 1024;
+/// Clients with probes awaiting a report.
+const MAX_CLIENTS: usize = 1024;
+/// How long probe timestamps wait for their report.
+const PROBE_TTL: Duration = Duration::from_secs(120);
+/// Fraction of lowest-RTT samples used for the offset fit.
+const FIT_FRACTION: f64 = 0.5;
+
+/// Server wall-clock time in microseconds since the Unix epoch.
+pub fn now_us() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_micros() as i64).unwrap_or(0)
+}
+
+/// One probe's four timestamps, in microseconds on the respective clocks.
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    /// Client send.
+    pub t1: i64,
+    /// Server receive.
+    pub t2: i64,
+    /// Server send.
+    pub t3: i64,
+    /// Client receive.
+    pub t4: i64,
+}
+
+impl Sample {
+    fn rtt(&self) -> i64 {
+        (self.t4 - self.t1) - (self.t3 - self.t2)
+    }
+
+    /// Client clock minus server clock, assuming symmetric paths.
+    fn offset(&self) -> f64 {
+        ((self.t1 - self.t2) + (self.t4 - self.t3)) as f64 / 2.0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Estimate {
+    /// Client clock minus server clock at the first sample, µs.
+    pub offset_us: f64,
+    /// Drift of the client clock relative to the server, parts per million.
+    pub skew_ppm: f64,
+    pub min_rtt_us: i64,
+    /// Median client-to-server delay after offset correction, µs.
+    pub forward_us: f64,
+    /// Median server-to-client delay after offset correction, µs.
+    pub reverse_us: f64,
+    pub samples: usize,
+    pub round_trips: RoundTrips,
+}
+
+impl Estimate {
+    pub fn reply(&self) -> String {
+        format!(
+            "OWD offset_us={:.0} skew_ppm={:.2} rtt_us={} fwd_us={:.0} rev_us={:.0} asym_us={:.0} samples={} {}",
+            self.offset_us,
+            self.skew_ppm,
+            self.min_rtt_us,
+            self.forward_us,
+            self.reverse_us,
+            self.forward_us - self.reverse_us,
+            self.samples,
+            self.round_trips
+        )
+    }
+}
+
+pub fn estimate(samples: &[Sample]) -> Option<Estimate> {
+    let base = samples.iter().map(|s| s.t2).min()?;
+    let mut by_rtt: Vec<&Sample> = samples.iter().filter(|s| s.rtt() >= 0).collect();
+    if by_rtt.is_empty() {
+        return None;
+    }
+    by_rtt.sort_by_key(|s| s.rtt());
+    let min_rtt_us = by_rtt[0].rtt();
+    let fit_len = ((by_rtt.len() as f64 * FIT_FRACTION).ceil() as usize).max(1);
+    let (intercept, slope) = fit_line(by_rtt[..fit_len].iter().map(|s| ((s.t2 - base) as f64, s.offset())));
+
+    // Offset at each sample's server time, then per-direction delays.
+    // Distributions over the probes in the order they were sent.
+    let mut round_trips = RoundTrips::default();
+    let mut in_order = by_rtt.clone();
+    in_order.sort_by_key(|s| s.t1);
+    for s in &in_order {
+        round_trips.record(s.rtt() as u64);
+    }
+
+    let mut forward = Vec::with_capacity(by_rtt.len());
+    let mut reverse = Vec::with_capacity(by_rtt.len());
+    for s in &by_rtt {
+        let theta = intercept + slope * (s.t2 - base) as f64;
+        forward.push((s.t2 - s.t1) as f64 + theta);
+        reverse.push((s.t4 - s.t3) as f64 - theta);
+    }
+    Some(Estimate {
+        offset_us: intercept,
+        skew_ppm: slope * 1e6,
+        min_rtt_us,
+        forward_us: median(&mut forward),
+        reverse_us: median(&mut reverse),
+        samples: by_rtt.len(),
+        round_trips,
+    })
+}
+
+/// Least-squares line through (x, y); a single point gives a flat line.
+fn fit_line(points: impl Iterator<Item = (f64, f64)>) -> (f64, f64) {
+    let points: Vec<(f64, f64)> = points.collect();
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+    let var_x: f64 = points.iter().map(|p| (p.0 - mean_x).powi(2)).sum();
+    if var_x == 0.0 {
+        return (mean_y, 0.0);
+    }
+    let cov: f64 = points.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+    let slope = cov / var_x;
+    (mean_y - slope * mean_x, slope)
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(f64::total_cmp);
+    values[values.len() / 2]
+}
+
+/// Timestamps (t1, t2, t3) of one probe, by sequence number.
+type ProbeTimes = HashMap<u64, (i64, i64, i64)>;
+
+/// Server-side probe timestamps awaiting each client's report.
+#[derive(Default)]
+pub struct OwdProbes {
+    clients: HashMap<SocketAddr, (ProbeTimes, Instant)>,
+}
+
+impl OwdProbes {
+    /// Record a probe; false if the client or table is over its limit.
+    pub fn record(&mut self, addr: SocketAddr, seq: u64, t1: i64, t2: i64, t3: i64) -> bool {
+        let now = Instant::now();
+        self.clients.retain(|_, (_, at)| now.duration_since(*at) < PROBE_TTL);
+        if self.clients.len() >= MAX_CLIENTS && !self.clients.contains_key(&addr) {
+            return false;
+        }
+        let (probes, at) = self.clients.entry(addr).or_insert_with(|| (HashMap::new(), now));
+        if probes.len() >= MAX_PROBES && !probes.contains_key(&seq) {
+            return false;
+        }
+        *at = now;
+        probes.insert(seq, (t1, t2, t3));
+        true
+    }
+
+    /// Combine a client's report with its recorded probes and forget them.
+    pub fn complete(&mut self, addr: SocketAddr, cmd: &Command) -> Vec<Sample> {
+        let Some((probes, _)) = self.clients.remove(&addr) else { return Vec::new() };
+        let Some(report) = cmd.args.first() else { return Vec::new() };
+        report
+            .split(',')
+            .filter_map(|entry| {
+                let (seq, t4) = entry.split_once(':')?;
+                let &(t1, t2, t3) = probes.get(&seq.parse().ok()?)?;
+                Some(Sample { t1, t2, t3, t4: t4.parse().ok()? })
+            })
+            .collect()
+    }
+}
+
+/// Parse `OWD_PROBE <seq> <t1>`.
+pub fn parse_probe(cmd: &Command) -> Option<(u64, i64)> {
+    Some((cmd.args.first()?.parse().ok()?, cmd.args.get(1)?.parse().ok()?))
+}
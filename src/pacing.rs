@@ -0,0 +1,108 @@
+// proj2-serv/src/pacing.rs
+// Rate-capped downloads (`RATE=<bits/s>`, K/M/G suffixes as for BYTES=, on a
+// TCP or UDP START_DOWNLOAD). With `PACING=kernel`, the default, the server
+// sets SO_MAX_PACING_RATE on the test's socket and writes as fast as it can,
+// leaving the spacing to the kernel: TCP paces itself, UDP only where the
+// egress interface runs the fq qdisc. With `PACING=user` the send loop
+// spaces the data itself, holding each write or datagram until the bytes
+// already sent are due at the rate, in writes of at most QUANTUM of data.
+// Kernel pacing falls back to user pacing where the option cannot be set, and
+// for UDP downloads sent from the plane's shared socket, whose rate is every
+// client's (`CONNECTED=1` or `PORTS=` give the download sockets of its own,
+// which share the rate). The result carries `rate_bps=<n> pacing=kernel|user`,
+// the mechanism that actually paced the test.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use socket2::SockRef;
+
+use crate::sockopt;
+
+/// Data time of the longest write or burst a user-paced send makes.
+const QUANTUM: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mechanism {
+    Kernel,
+    User,
+}
+
+impl Mechanism {
+    /// Parse `PACING=`.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "kernel" => Some(Mechanism::Kernel),
+            "user" => Some(Mechanism::User),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Mechanism::Kernel => "kernel",
+            Mechanism::User => "user",
+        }
+    }
+}
+
+/// A download's rate cap and what enforces it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pacing {
+    pub rate_bps: u64,
+    pub mechanism: Mechanism,
+}
+
+impl Pacing {
+    /// The pacing of a `RATE=`, if given, by `PACING=` or the kernel.
+    pub fn requested(rate_bps: Option<u64>, mechanism: Option<Mechanism>) -> Option<Self> {
+        Some(Pacing { rate_bps: rate_bps?, mechanism: mechanism.unwrap_or(Mechanism::Kernel) })
+    }
+
+    /// Hand the rate to the kernel on `socks`, which share it; user pacing from
+    /// then on if any of them refuses.
+    pub fn apply(&mut self, socks: &[SockRef<'_>]) {
+        if self.mechanism == Mechanism::User {
+            return;
+        }
+        let share = (self.rate_bps / 8 / socks.len().max(1) as u64).max(1);
+        for sock in socks {
+            if let Err(e) = sockopt::set_max_pacing_rate(sock, Some(share)) {
+                eprintln!("SO_MAX_PACING_RATE unavailable ({}); pacing in userspace", e);
+                self.fall_back(socks);
+                return;
+            }
+        }
+    }
+
+    /// Pace in userspace after all, lifting any cap already set on `socks`.
+    pub fn fall_back(&mut self, socks: &[SockRef<'_>]) {
+        if self.mechanism == Mechanism::Kernel {
+            clear(socks);
+        }
+        self.mechanism = Mechanism::User;
+    }
+
+    /// When the next send of a user-paced test may go, `sent` bytes in.
+    pub fn due(&self, start: Instant, sent: u64) -> Option<Instant> {
+        (self.mechanism == Mechanism::User).then(|| start + Duration::from_secs_f64(sent as f64 * 8.0 / self.rate_bps as f64))
+    }
+
+    /// Most bytes a user-paced send may carry at once.
+    pub fn quantum(&self) -> usize {
+        ((self.rate_bps as f64 / 8.0 * QUANTUM.as_secs_f64()) as usize).max(1)
+    }
+}
+
+/// Lift a test's cap from sockets that outlive it.
+pub fn clear(socks: &[SockRef<'_>]) {
+    for sock in socks {
+        let _ = sockopt::set_max_pacing_rate(sock, None);
+    }
+}
+
+impl fmt::Display for Pacing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rate_bps={} pacing={}", self.rate_bps, self.mechanism.as_str())
+    }
+}
@@ -0,0 +1,241 @@
+// proj2-serv/src/payload.rs
+// Payload generators for downloads, selected per test with `PAYLOAD=`:
+//   zeros           all-zero bytes (the default; cheapest, but compressible)
+//   random          seeded pseudo-random stream (`SEED=<n>`), reproducible
+//   incompressible  pseudo-random stream freshly seeded for every test
+//   compressible    random bytes padded with zeros to compress about
+//                   `RATIO=<n>`:1 (default 4)
+//   file            the server's `--payload-file`, repeated
+// Compressing links (PPP, some VPNs and modems) inflate throughput on zeros;
+// the random sources keep results honest, and running the same test with
+// `compressible` and `incompressible` shows how much in-path compression adds.
+
+use std::io::IoSlice;
+use std::sync::Arc;
+
+use crate::protocol::Command;
+use crate::rng::XorShift64;
+
+pub trait PayloadSource: Send {
+    /// Fill `buf` with the next bytes of the stream.
+    fn fill(&mut self, buf: &mut [u8]);
+
+    /// Name reported in results.
+    fn name(&self) -> &'static str;
+
+    /// Payload carried by the first `wire` bytes produced, for a source that
+    /// compresses another; None for the rest.
+    fn expanded(&self, _wire: u64) -> Option<u64> {
+        None
+    }
+
+    /// Advance the stream by `n` bytes without producing them.
+    fn skip(&mut self, n: usize) {
+        let mut scratch = [0u8; 4096];
+        let mut left = n;
+        while left > 0 {
+            let k = left.min(scratch.len());
+            self.fill(&mut scratch[..k]);
+            left -= k;
+        }
+    }
+}
+
+pub struct Zeros;
+
+impl PayloadSource for Zeros {
+    fn fill(&mut self, buf: &mut [u8]) {
+        buf.fill(0);
+    }
+
+    fn name(&self) -> &'static str {
+        "zeros"
+    }
+}
+
+/// Pseudo-random bytes. With a fixed seed the stream is reproducible, so a
+/// client can verify what it received.
+pub struct Random {
+    rng: XorShift64,
+    name: &'static str,
+}
+
+impl Random {
+    pub fn seeded(seed: u64) -> Self {
+        Random { rng: XorShift64::new(seed), name: "random" }
+    }
+
+    pub fn incompressible() -> Self {
+        Random { rng: XorShift64::from_entropy(), name: "incompressible" }
+    }
+}
+
+impl PayloadSource for Random {
+    fn fill(&mut self, buf: &mut [u8]) {
+        self.rng.fill(buf);
+    }
+
+    fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+/// Largest `RATIO=` of a compressible payload.
+pub const MAX_RATIO: usize = 100;
+/// Default `RATIO=`.
+const DEFAULT_RATIO: usize = 4;
+/// Each block of a compressible payload starts with random bytes, a
+/// `1/ratio` share of it, and is zeros after.
+const BLOCK: usize = 4096;
+
+/// Random bytes padded with zeros, compressing about `ratio`:1.
+pub struct Compressible {
+    rng: XorShift64,
+    ratio: usize,
+    /// Offset in the current block.
+    pos: usize,
+}
+
+impl Compressible {
+    pub fn new(ratio: usize) -> Self {
+        Compressible { rng: XorShift64::from_entropy(), ratio, pos: 0 }
+    }
+}
+
+impl PayloadSource for Compressible {
+    fn fill(&mut self, buf: &mut [u8]) {
+        let random = BLOCK / self.ratio;
+        let mut filled = 0;
+        while filled < buf.len() {
+            let n = (buf.len() - filled).min(BLOCK - self.pos);
+            let chunk = &mut buf[filled..filled + n];
+            let head = random.saturating_sub(self.pos).min(n);
+            self.rng.fill(&mut chunk[..head]);
+            chunk[head..].fill(0);
+            filled += n;
+            self.pos = (self.pos + n) % BLOCK;
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "compressible"
+    }
+}
+
+/// The configured payload file, repeated end to end.
+pub struct FileBacked {
+    data: Arc<[u8]>,
+    pos: usize,
+}
+
+impl FileBacked {
+    pub fn new(data: Arc<[u8]>) -> Self {
+        FileBacked { data, pos: 0 }
+    }
+}
+
+impl PayloadSource for FileBacked {
+    fn fill(&mut self, buf: &mut [u8]) {
+        let mut filled = 0;
+        while filled < buf.len() {
+            let n = (buf.len() - filled).min(self.data.len() - self.pos);
+            buf[filled..filled + n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+            filled += n;
+            self.pos = (self.pos + n) % self.data.len();
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn skip(&mut self, n: usize) {
+        self.pos = (self.pos + n) % self.data.len();
+    }
+}
+
+/// Payload staged in several separate buffers so one `writev` sends them all,
+/// cutting syscalls per byte at high rates. A partial write is finished before
+/// refilling so the byte stream stays exactly the source's output.
+pub struct WriteBatch {
+    bufs: Vec<Vec<u8>>,
+    /// Bytes staged across `bufs` and the offset already written.
+    len: usize,
+    pos: usize,
+}
+
+impl WriteBatch {
+    pub fn new(slices: usize, slice_size: usize) -> Self {
+        WriteBatch { bufs: vec![vec![0u8; slice_size]; slices], len: 0, pos: 0 }
+    }
+
+    pub fn is_drained(&self) -> bool {
+        self.pos == self.len
+    }
+
+    /// Stage up to `limit` fresh bytes from `source`.
+    pub fn refill(&mut self, source: &mut dyn PayloadSource, limit: usize) {
+        let mut left = limit;
+        self.len = 0;
+        for buf in &mut self.bufs {
+            let n = buf.len().min(left);
+            source.fill(&mut buf[..n]);
+            self.len += n;
+            left -= n;
+        }
+        self.pos = 0;
+    }
+
+    /// The unwritten part as I/O slices.
+    pub fn slices(&self) -> Vec<IoSlice<'_>> {
+        let mut skip = self.pos;
+        let mut left = self.len - self.pos;
+        let mut out = Vec::with_capacity(self.bufs.len());
+        for buf in &self.bufs {
+            if left == 0 {
+                break;
+            }
+            if skip >= buf.len() {
+                skip -= buf.len();
+                continue;
+            }
+            let end = buf.len().min(skip + left);
+            out.push(IoSlice::new(&buf[skip..end]));
+            left -= end - skip;
+            skip = 0;
+        }
+        out
+    }
+
+    pub fn advance(&mut self, n: usize) {
+        self.pos += n;
+    }
+}
+
+/// Build the source requested by a command's `PAYLOAD=` and `SEED=` options.
+/// The error is the reason for an ERR reply.
+pub fn from_command(cmd: &Command, file: Option<&Arc<[u8]>>) -> Result<Box<dyn PayloadSource>, String> {
+    match cmd.opt("PAYLOAD").map(|p| p.to_ascii_lowercase()).as_deref() {
+        None | Some("zeros") => Ok(Box::new(Zeros)),
+        Some("random") => {
+            let seed = match cmd.opt("SEED") {
+                Some(s) => s.parse().map_err(|_| format!("invalid SEED={}", s))?,
+                None => 0,
+            };
+            Ok(Box::new(Random::seeded(seed)))
+        }
+        Some("incompressible") => Ok(Box::new(Random::incompressible())),
+        Some("compressible") => {
+            let ratio = match cmd.opt("RATIO") {
+                Some(r) => r.parse().ok().filter(|r| (1..=MAX_RATIO).contains(r)).ok_or_else(|| format!("invalid RATIO={} (1 to {})", r, MAX_RATIO))?,
+                None => DEFAULT_RATIO,
+            };
+            Ok(Box::new(Compressible::new(ratio)))
+        }
+        Some("file") => match file {
+            Some(data) => Ok(Box::new(FileBacked::new(data.clone()))),
+            None => Err("PAYLOAD=file requires the server to run with --payload-file".to_string()),
+        },
+        Some(other) => Err(format!("invalid PAYLOAD={} (expected zeros|random|incompressible|compressible|file)", other)),
+    }
+}
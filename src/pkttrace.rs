@@ -0,0 +1,160 @@
+// proj2-serv/src/pkttrace.rs
+// Per-packet traces of UDP tests, for digging into loss bursts and stalls
+// without tcpdump. With `--packet-trace-dir <dir>`, a START_DOWNLOAD or
+// START_UPLOAD carrying `PKT_TRACE=1` has every datagram the server sends
+// (download) or receives (upload) logged, and the log is written when the test
+// ends to `<dir>/<tenant>-<session>-<download|upload>.pkt`. The file is a
+// header followed by fixed 10-byte records, all little-endian:
+//   "P2PT", version u8, direction u8 (0 sent, 1 received), 2 reserved bytes,
+//   start u64 (Unix µs), truncated u64, peer length u16 and peer text;
+//   per datagram: offset u32 (µs since start), seq u32, size u16.
+// seq numbers datagrams in send order for downloads and arrival order for
+// uploads. A log stops growing at `--max-file-size`; later datagrams are only
+// counted in `truncated`. `proj2-serv pkttrace <file>` prints a log with the
+// gap before each datagram and a summary naming the longest gap.
+
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, ensure, Context};
+
+use crate::protocol::{self, error_frame, Command, ErrorCode};
+use crate::results::Direction;
+use crate::state::ServerState;
+
+const MAGIC: &[u8; 4] = b"P2PT";
+const VERSION: u8 = 1;
+const RECORD: usize = 10;
+
+/// Datagrams logged during one test.
+pub struct PacketLog {
+    dir: PathBuf,
+    peer: SocketAddr,
+    direction: Direction,
+    start: Instant,
+    start_us: u64,
+    records: Vec<u8>,
+    seq: u32,
+    limit: usize,
+    truncated: u64,
+}
+
+impl PacketLog {
+    fn new(dir: PathBuf, peer: SocketAddr, direction: Direction, limit: u64) -> Self {
+        PacketLog {
+            dir,
+            peer,
+            direction,
+            start: Instant::now(),
+            start_us: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64,
+            records: Vec::new(),
+            seq: 0,
+            limit: usize::try_from(limit).unwrap_or(usize::MAX),
+            truncated: 0,
+        }
+    }
+
+    /// Log one datagram of `len` bytes, sent or received now.
+    pub fn record(&mut self, len: usize) {
+        let seq = self.seq;
+        self.seq = self.seq.wrapping_add(1);
+        if self.records.len() + RECORD > self.limit {
+            self.truncated += 1;
+            return;
+        }
+        let offset = self.start.elapsed().as_micros().min(u32::MAX as u128) as u32;
+        self.records.extend_from_slice(&offset.to_le_bytes());
+        self.records.extend_from_slice(&seq.to_le_bytes());
+        self.records.extend_from_slice(&(len.min(u16::MAX as usize) as u16).to_le_bytes());
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let peer = self.peer.to_string();
+        let mut out = Vec::with_capacity(26 + peer.len() + self.records.len());
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        out.push(match self.direction {
+            Direction::Download => 0,
+            _ => 1,
+        });
+        out.extend_from_slice(&[0, 0]);
+        out.extend_from_slice(&self.start_us.to_le_bytes());
+        out.extend_from_slice(&self.truncated.to_le_bytes());
+        out.extend_from_slice(&(peer.len() as u16).to_le_bytes());
+        out.extend_from_slice(peer.as_bytes());
+        out.extend_from_slice(&self.records);
+        out
+    }
+
+    /// Write the log for session `id` in the background.
+    pub fn save(self, tenant: &str, id: u64) {
+        let path = self.dir.join(format!("{}-{}-{}.pkt", tenant, id, self.direction.as_str()));
+        let tenant = tenant.to_string();
+        tokio::task::spawn_blocking(move || match std::fs::write(&path, self.encode()) {
+            Ok(()) => println!("[{}] Packet trace of session {} written to {}", tenant, id, path.display()),
+            Err(e) => eprintln!("[{}] Cannot write packet trace {}: {}", tenant, path.display(), e),
+        });
+    }
+}
+
+/// A log for the test `cmd` starts, if it asks for one with `PKT_TRACE=1`;
+/// the error is a ready ERR frame and the test runs untraced.
+pub fn requested(state: &ServerState, cmd: &Command, peer: SocketAddr, direction: Direction) -> Result<Option<PacketLog>, String> {
+    let Some(value) = cmd.opt("PKT_TRACE") else { return Ok(None) };
+    match (protocol::parse_flag(value), &state.config.packet_trace_dir) {
+        (None, _) => Err(error_frame(ErrorCode::InvalidOption, format!("invalid PKT_TRACE={}", value))),
+        (Some(false), _) => Ok(None),
+        (Some(true), None) => Err(error_frame(ErrorCode::Disabled, "packet traces are disabled (no --packet-trace-dir)")),
+        (Some(true), Some(dir)) => Ok(Some(PacketLog::new(dir.clone(), peer, direction, state.config.max_file_size))),
+    }
+}
+
+/// `proj2-serv pkttrace <file>`: print a packet trace.
+pub fn run(mut args: impl Iterator<Item = String>) -> anyhow::Result<()> {
+    let Some(path) = args.next() else { bail!("usage: proj2-serv pkttrace <file.pkt>") };
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+    dump(Path::new(&path), &mut out)?;
+    out.flush()?;
+    Ok(())
+}
+
+fn dump(path: &Path, out: &mut impl Write) -> anyhow::Result<()> {
+    let data = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+    ensure!(data.len() >= 26 && &data[..4] == MAGIC, "{} is not a packet trace", path.display());
+    ensure!(data[4] == VERSION, "{} has unsupported packet trace version {}", path.display(), data[4]);
+    let u64_at = |at: usize| u64::from_le_bytes(data[at..at + 8].try_into().unwrap());
+    let direction = if data[5] == 0 { "sent" } else { "received" };
+    let (start_us, truncated) = (u64_at(8), u64_at(16));
+    let peer_len = u16::from_le_bytes([data[24], data[25]]) as usize;
+    let peer = data.get(26..26 + peer_len).map(String::from_utf8_lossy).context("truncated packet trace header")?;
+    let records = &data[26 + peer_len..];
+
+    writeln!(out, "# {} datagrams, peer {}, start {}.{:06}", direction, peer, start_us / 1_000_000, start_us % 1_000_000)?;
+    writeln!(out, "# offset_us seq size gap_us")?;
+    let (mut bytes, mut prev, mut longest) = (0u64, None::<u32>, (0u32, 0u32));
+    for record in records.chunks_exact(RECORD) {
+        let offset = u32::from_le_bytes(record[0..4].try_into().unwrap());
+        let seq = u32::from_le_bytes(record[4..8].try_into().unwrap());
+        let size = u16::from_le_bytes([record[8], record[9]]);
+        let gap = prev.map_or(0, |p| offset.saturating_sub(p));
+        if gap > longest.0 {
+            longest = (gap, seq);
+        }
+        writeln!(out, "{} {} {} {}", offset, seq, size, gap)?;
+        bytes += size as u64;
+        prev = Some(offset);
+    }
+    writeln!(
+        out,
+        "# packets={} bytes={} span_us={} longest_gap_us={} before_seq={} truncated={}",
+        records.len() / RECORD,
+        bytes,
+        prev.unwrap_or(0),
+        longest.0,
+        longest.1,
+        truncated
+    )?;
+    Ok(())
+}
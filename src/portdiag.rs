@@ -0,0 +1,65 @@
+// proj2-serv/src/portdiag.rs
+// Bind-failure diagnostics: find which process holds a port by parsing
+// /proc/net/{tcp,udp}[6] and matching socket inodes under /proc/<pid>/fd (Linux only).
+
+/// Human-readable description of who holds `port`, if it can be determined.
+#[cfg(target_os = "linux")]
+pub fn describe_port_holder(proto: &str, port: u16) -> Option<String> {
+    let mut owners = Vec::new();
+    for (inode, uid) in socket_inodes(proto, port) {
+        match find_inode_owner(inode) {
+            Some((pid, comm)) => owners.push(format!("pid {} ({})", pid, comm)),
+            None => owners.push(format!("socket inode {} owned by uid {} (process not visible)", inode, uid)),
+        }
+    }
+    owners.dedup();
+    if owners.is_empty() { None } else { Some(owners.join(", ")) }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn describe_port_holder(_proto: &str, _port: u16) -> Option<String> {
+    None
+}
+
+/// (inode, uid) of sockets bound to `port`. For TCP only listening sockets count.
+#[cfg(target_os = "linux")]
+fn socket_inodes(proto: &str, port: u16) -> Vec<(u64, u32)> {
+    const TCP_LISTEN: &str = "0A";
+    let mut found = Vec::new();
+    for table in [format!("/proc/net/{}", proto), format!("/proc/net/{}6", proto)] {
+        let Ok(contents) = std::fs::read_to_string(&table) else { continue };
+        for line in contents.lines().skip(1) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 10 {
+                continue;
+            }
+            let Some((_, port_hex)) = fields[1].rsplit_once(':') else { continue };
+            if u16::from_str_radix(port_hex, 16).ok() != Some(port) {
+                continue;
+            }
+            if proto == "tcp" && fields[3] != TCP_LISTEN {
+                continue;
+            }
+            if let (Ok(uid), Ok(inode)) = (fields[7].parse(), fields[9].parse()) {
+                found.push((inode, uid));
+            }
+        }
+    }
+    found
+}
+
+#[cfg(target_os = "linux")]
+fn find_inode_owner(inode: u64) -> Option<(u32, String)> {
+    let target = format!("socket:[{}]", inode);
+    for entry in std::fs::read_dir("/proc").ok()?.flatten() {
+        let Ok(pid) = entry.file_name().to_string_lossy().parse::<u32>() else { continue };
+        let Ok(fds) = std::fs::read_dir(entry.path().join("fd")) else { continue };
+        for fd in fds.flatten() {
+            if std::fs::read_link(fd.path()).is_ok_and(|link| link.as_os_str() == target.as_str()) {
+                let comm = std::fs::read_to_string(entry.path().join("comm")).unwrap_or_default();
+                return Some((pid, comm.trim().to_string()));
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,302 @@
+// proj2-serv/src/protocol.rs
+// Text control-message parsing shared by the TCP and UDP planes.
+// A command is a verb followed by optional positional args and KEY=VALUE options,
+// e.g. "START_DOWNLOAD TENANT=acme". A client may open with
+// `HELLO VERSION=<n> FEATURES=<a,b,...>` to agree on a protocol version and
+// learn which optional features the server has; clients that skip it keep
+// working as version 1. `AGENT=` and `META=` on a HELLO or start command
+// describe the client and are stored with its results; on TCP and UDS a
+// HELLO's apply to every later test on the connection that does not give its
+// own. Failures are answered with `ERR <code> <message>` (see `ErrorCode`) on
+// every transport.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Tenant used when a command does not carry a `TENANT=` option.
+pub const DEFAULT_TENANT: &str = "default";
+
+#[derive(Debug, Clone, Default)]
+pub struct Command {
+    pub verb: String,
+    pub args: Vec<String>,
+    pub opts: HashMap<String, String>,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Command {
+        let mut parts = line.split_whitespace();
+        let verb = parts.next().unwrap_or("").to_ascii_uppercase();
+        let mut args = Vec::new();
+        let mut opts = HashMap::new();
+        for part in parts {
+            match part.split_once('=') {
+                Some((k, v)) if !k.is_empty() => {
+                    opts.insert(k.to_ascii_uppercase(), v.to_string());
+                }
+                _ => args.push(part.to_string()),
+            }
+        }
+        Command { verb, args, opts }
+    }
+
+    pub fn opt(&self, key: &str) -> Option<&str> {
+        self.opts.get(key).map(|s| s.as_str())
+    }
+
+    /// Tenant namespace for this command. Invalid names fall back to the default tenant
+    /// so a typo can never leak results into another customer's namespace.
+    pub fn tenant(&self) -> String {
+        match self.opt("TENANT") {
+            Some(t) if valid_tenant(t) => t.to_string(),
+            _ => DEFAULT_TENANT.to_string(),
+        }
+    }
+
+    /// Run this test belongs to (`RUN=<name>`), grouping sessions whose results
+    /// are combined by the `RUN` verb. Names follow the tenant rules; an
+    /// invalid one leaves the test ungrouped.
+    pub fn run(&self) -> Option<String> {
+        self.opt("RUN").filter(|r| valid_tenant(r)).map(str::to_string)
+    }
+
+    /// Client metadata of a start command or HELLO: `AGENT=<name/version>` and
+    /// `META=<key>:<value>,...`. Invalid values and tags beyond MAX_META_TAGS
+    /// are left out, like an invalid `RUN=`.
+    pub fn meta(&self) -> ClientMeta {
+        let agent = self.opt("AGENT").filter(|a| valid_meta_value(a)).map(str::to_string);
+        let tags = self
+            .opt("META")
+            .into_iter()
+            .flat_map(|meta| meta.split(','))
+            .filter_map(|tag| tag.split_once(':'))
+            .filter(|(key, value)| valid_meta_key(key) && valid_meta_value(value))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .take(MAX_META_TAGS)
+            .collect();
+        ClientMeta { agent, tags }
+    }
+}
+
+/// Most `META=` tags kept for one test.
+pub const MAX_META_TAGS: usize = 8;
+/// Longest `AGENT=` or tag value.
+const MAX_META_LEN: usize = 64;
+
+/// What a client says about itself, stored with its results so operators can
+/// segment them by client population.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientMeta {
+    pub agent: Option<String>,
+    pub tags: Vec<(String, String)>,
+}
+
+impl ClientMeta {
+    /// This metadata, with an agent or tags it lacks taken from `hello`.
+    pub fn or(self, hello: &ClientMeta) -> ClientMeta {
+        ClientMeta {
+            agent: self.agent.or_else(|| hello.agent.clone()),
+            tags: if self.tags.is_empty() { hello.tags.clone() } else { self.tags },
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.agent.is_none() && self.tags.is_empty()
+    }
+}
+
+impl std::fmt::Display for ClientMeta {
+    /// `agent=<name> meta_<key>=<value> ...`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let agent = self.agent.iter().map(|a| format!("agent={}", a));
+        let tags = self.tags.iter().map(|(k, v)| format!("meta_{}={}", k, v));
+        write!(f, "{}", agent.chain(tags).collect::<Vec<_>>().join(" "))
+    }
+}
+
+fn valid_meta_key(key: &str) -> bool {
+    (1..=32).contains(&key.len()) && key.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+}
+
+fn valid_meta_value(value: &str) -> bool {
+    (1..=MAX_META_LEN).contains(&value.len()) && value.bytes().all(|b| b.is_ascii_graphic() && b != b'=' && b != b',')
+}
+
+/// Class of an `ERR <code> <message>` frame, for clients to act on without
+/// parsing the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// Unknown verb, or missing or malformed arguments.
+    BadCommand,
+    /// An option value that does not parse or is out of range.
+    InvalidOption,
+    /// A valid option this server or host cannot honour.
+    UnsupportedOption,
+    /// Not allowed for this peer.
+    Unauthorized,
+    /// A table or limit is full; retry later.
+    Busy,
+    /// The session, run, probe or file referred to does not exist.
+    NotFound,
+    /// The feature is turned off in this server's configuration.
+    Disabled,
+    /// The server failed to carry out a valid request.
+    Failed,
+}
+
+impl ErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::BadCommand => "BAD_COMMAND",
+            ErrorCode::InvalidOption => "INVALID_OPTION",
+            ErrorCode::UnsupportedOption => "UNSUPPORTED_OPTION",
+            ErrorCode::Unauthorized => "UNAUTHORIZED",
+            ErrorCode::Busy => "BUSY",
+            ErrorCode::NotFound => "NOT_FOUND",
+            ErrorCode::Disabled => "DISABLED",
+            ErrorCode::Failed => "FAILED",
+        }
+    }
+}
+
+/// An `ERR <code> <message>` frame, without the line terminator TCP adds.
+pub fn error_frame(code: ErrorCode, message: impl std::fmt::Display) -> String {
+    format!("ERR {} {}", code.as_str(), message)
+}
+
+/// Decode a control frame, a TCP read or a UDP datagram, as received: its
+/// text, trimmed and with invalid UTF-8 replaced, and the command it holds.
+/// Pure, and total over any input, since frames come straight off the network.
+pub fn decode_frame(raw: &[u8]) -> (String, Command) {
+    let text = String::from_utf8_lossy(raw).trim().to_string();
+    let cmd = Command::parse(&text);
+    (text, cmd)
+}
+
+/// Whether an unrecognised datagram looks like a mistyped command rather than
+/// test payload: it starts with a short upper-case verb.
+pub fn looks_like_command(msg: &str) -> bool {
+    let verb = msg.split_whitespace().next().unwrap_or_default();
+    (3..=32).contains(&verb.len())
+        && verb.starts_with(|c: char| c.is_ascii_uppercase())
+        && verb.bytes().all(|b| b.is_ascii_uppercase() || b.is_ascii_digit() || b == b'_')
+}
+
+/// Protocol version this server speaks. Version 1 is the protocol before
+/// `HELLO`; a client that never sends `HELLO` is served as version 1.
+pub const PROTOCOL_VERSION: u32 = 2;
+/// Oldest protocol version still served.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Optional features this server implements, as named in `HELLO FEATURES=`.
+/// Features a client asks for that are missing here (e.g. `binary`, `gso`,
+/// `tls`) are left out of the reply, and the client falls back to the
+/// text protocol without them.
+pub const FEATURES: &[&str] = &[
+    "bytes",
+    "omit",
+    "payload",
+    "run",
+    "dscp",
+    "echo",
+    "owd",
+    "capacity",
+    "tcp-cca",
+    "tcp-zerocopy",
+    "udp-handshake",
+    "udp-stripe",
+    "udp-connected",
+    "udp-bidir",
+    "tcp-framed",
+    "udp-jumbo",
+    "steering",
+    "ecn",
+    "bloat",
+    "ramp",
+    "compare",
+    "signed-results",
+    "client-meta",
+    "zstd",
+    "overhead",
+    "pacing",
+];
+
+/// Parse a `HELLO VERSION=` value; a missing one means version 1.
+pub fn parse_version(value: Option<&str>) -> Option<u32> {
+    match value {
+        Some(v) => v.parse().ok(),
+        None => Some(1),
+    }
+}
+
+/// Length of a time-based test window.
+pub const TEST_DURATION: Duration = Duration::from_secs(5);
+
+/// Longest warm-up a client may ask to omit from a test.
+pub const MAX_OMIT: Duration = Duration::from_secs(30);
+
+/// Parse an `OMIT=` value in seconds (fractions allowed, up to `MAX_OMIT`).
+pub fn parse_omit(value: &str) -> Option<Duration> {
+    let secs: f64 = value.parse().ok()?;
+    let omit = Duration::try_from_secs_f64(secs).ok()?;
+    (omit <= MAX_OMIT).then_some(omit)
+}
+
+/// Most ephemeral ports a striped UDP download (`PORTS=`) may use.
+pub const MAX_STRIPE_PORTS: usize = 16;
+
+/// Parse a `PORTS=` value: the number of server ports to stripe a UDP download across.
+pub fn parse_stripe_ports(value: &str) -> Option<usize> {
+    let n: usize = value.parse().ok()?;
+    (1..=MAX_STRIPE_PORTS).contains(&n).then_some(n)
+}
+
+/// Largest UDP payload an IPv4 datagram can carry.
+pub const MAX_UDP_PAYLOAD: usize = 65_507;
+
+/// Parse a `DATAGRAM=` value: the payload size of each datagram of a UDP
+/// download, up to a jumbo `MAX_UDP_PAYLOAD`.
+pub fn parse_datagram_size(value: &str) -> Option<usize> {
+    let n: usize = value.parse().ok()?;
+    (1..=MAX_UDP_PAYLOAD).contains(&n).then_some(n)
+}
+
+/// Parse an on/off option such as `HANDSHAKE=1`.
+pub fn parse_flag(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "on" | "yes" | "true" => Some(true),
+        "0" | "off" | "no" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// Largest TCP echo message (`SIZE=`).
+pub const MAX_ECHO_SIZE: usize = 64 * 1024;
+
+/// Parse a TCP `START_ECHO SIZE=` value: the fixed message size in bytes.
+pub fn parse_echo_size(value: &str) -> Option<usize> {
+    let n: usize = value.parse().ok()?;
+    (1..=MAX_ECHO_SIZE).contains(&n).then_some(n)
+}
+
+/// Parse a `BYTES=` value: a positive count with an optional binary K/M/G/T
+/// suffix, e.g. `500M` or `1G`.
+pub fn parse_byte_count(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let (digits, shift) = match value.as_bytes().last()?.to_ascii_uppercase() {
+        b'K' => (&value[..value.len() - 1], 10),
+        b'M' => (&value[..value.len() - 1], 20),
+        b'G' => (&value[..value.len() - 1], 30),
+        b'T' => (&value[..value.len() - 1], 40),
+        _ => (value, 0),
+    };
+    let n: u64 = digits.parse().ok()?;
+    let bytes = n.checked_mul(1u64 << shift)?;
+    (bytes > 0).then_some(bytes)
+}
+
+pub fn valid_tenant(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 32
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
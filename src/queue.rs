@@ -0,0 +1,108 @@
+// proj2-serv/src/queue.rs
+// Admission of throughput tests. With `--max-tests <n>` at most n downloads and
+// uploads run at once (echo tests and file transfers are not counted). A test
+// started beyond that is refused with ERR BUSY, unless `--queue <n>` allows a
+// queue: then the client is told `QUEUED <position>` (1 is next) and the test
+// starts when a slot frees, in arrival order. A queued test gives up after
+// `--queue-timeout` with ERR BUSY, so a lab full of clients is served in turn
+// rather than turned away.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_util::sync::CancellationToken;
+
+use crate::config::Config;
+use crate::protocol::{error_frame, ErrorCode};
+
+pub struct TestSlots {
+    /// `None` when tests are not limited.
+    permits: Option<Arc<Semaphore>>,
+    max: usize,
+    queue: usize,
+    timeout: Duration,
+    waiting: Arc<AtomicUsize>,
+}
+
+/// A running test's claim on a slot, given back when dropped.
+pub struct Slot {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+/// A place in the queue.
+pub struct Pending {
+    permits: Arc<Semaphore>,
+    timeout: Duration,
+    waiting: Arc<AtomicUsize>,
+}
+
+pub enum Admission {
+    Now(Slot),
+    /// Queued at this position.
+    Queued(usize, Pending),
+    /// Refused; the ERR frame for the client.
+    Full(String),
+}
+
+impl TestSlots {
+    pub fn new(config: &Config) -> Self {
+        TestSlots {
+            permits: (config.max_tests > 0).then(|| Arc::new(Semaphore::new(config.max_tests))),
+            max: config.max_tests,
+            queue: config.queue,
+            timeout: config.queue_timeout,
+            waiting: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn queueing(&self) -> bool {
+        self.permits.is_some() && self.queue > 0
+    }
+
+    /// Admit a test now, queue it or refuse it.
+    pub fn admit(&self) -> Admission {
+        let Some(permits) = &self.permits else { return Admission::Now(Slot { _permit: None }) };
+        if let Ok(permit) = permits.clone().try_acquire_owned() {
+            return Admission::Now(Slot { _permit: Some(permit) });
+        }
+        let queue = self.queue;
+        match self.waiting.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| (n < queue).then_some(n + 1)) {
+            Ok(ahead) => Admission::Queued(
+                ahead + 1,
+                Pending { permits: permits.clone(), timeout: self.timeout, waiting: self.waiting.clone() },
+            ),
+            Err(_) => Admission::Full(error_frame(ErrorCode::Busy, format!("server is at capacity ({} tests running)", self.max))),
+        }
+    }
+}
+
+impl Pending {
+    /// Wait for a slot until the queue timeout or `cancel`; the error is the
+    /// ERR frame for the client.
+    pub async fn wait(self, cancel: &CancellationToken) -> Result<Slot, String> {
+        tokio::select! {
+            permit = self.permits.clone().acquire_owned() => permit.map(|p| Slot { _permit: Some(p) }).map_err(|_| error_frame(ErrorCode::Failed, "test queue closed")),
+            _ = tokio::time::sleep(self.timeout) => Err(error_frame(ErrorCode::Busy, format!("no test slot freed within {}s", self.timeout.as_secs()))),
+            _ = cancel.cancelled() => Err(error_frame(ErrorCode::Busy, "queued test cancelled")),
+        }
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        self.waiting.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Admission {
+    /// The slot, once the queue (if any) has handed it over.
+    pub async fn slot(self, cancel: &CancellationToken) -> Result<Slot, String> {
+        match self {
+            Admission::Now(slot) => Ok(slot),
+            Admission::Queued(_, pending) => pending.wait(cancel).await,
+            Admission::Full(frame) => Err(frame),
+        }
+    }
+}
@@ -0,0 +1,158 @@
+// proj2-serv/src/ramp.rs
+// Staircase bitrate tests (`RAMP=<mbps>,<mbps>,...` on a UDP START_DOWNLOAD,
+// each step lasting `STEP=<secs>`, default 3). Instead of flooding, the server
+// paces the download at each rate in turn, so the rate at which loss and
+// jitter take off, the knee of the link, shows in a single test. Every
+// datagram starts with `RAMP <step> <seq> <sent_us>`: the client counts what
+// arrives in each step and works out its jitter from the send stamps (RFC 3550
+// interarrival jitter), and after the FIN it answers with
+// `RAMP_REPORT <received>:<jitter_us>,...`, one entry per step in order. The
+// server waits REPORT_WAIT for it; the result then carries the loss and jitter
+// of every step and the knee, the highest rate that lost under KNEE_LOSS.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::oneshot;
+
+use crate::owd;
+use crate::protocol::{error_frame, Command, ErrorCode};
+
+/// Step length without `STEP=`.
+pub const DEFAULT_STEP: Duration = Duration::from_secs(3);
+/// How long the download waits after its FIN for the client's report.
+pub const REPORT_WAIT: Duration = Duration::from_secs(3);
+/// Most steps in one staircase.
+const MAX_STEPS: usize = 32;
+/// Highest rate of a step, in Mbit/s.
+const MAX_MBPS: f64 = 100_000.0;
+/// Loss a step may show and still be under the knee.
+const KNEE_LOSS: f64 = 0.01;
+
+/// What the client saw of one step: datagrams received and jitter.
+pub type StepReport = (u64, u64);
+
+/// Parse `RAMP=` rates in Mbit/s.
+pub fn parse_rates(value: &str) -> Option<Vec<f64>> {
+    let rates: Vec<f64> = value.split(',').map(|r| r.trim().parse().ok().filter(|&r| r > 0.0 && r <= MAX_MBPS)).collect::<Option<_>>()?;
+    (rates.len() <= MAX_STEPS).then_some(rates)
+}
+
+/// Parse `STEP=` in whole seconds, 1 to 60.
+pub fn parse_step(value: &str) -> Option<Duration> {
+    let secs: u64 = value.parse().ok()?;
+    (1..=60).contains(&secs).then(|| Duration::from_secs(secs))
+}
+
+/// A staircase and, as the download goes, what it sent at each step.
+#[derive(Debug, Clone)]
+pub struct Ramp {
+    pub rates_mbps: Vec<f64>,
+    pub step: Duration,
+    /// Datagrams sent in each step.
+    pub sent: Vec<u64>,
+    /// The client's report, once it arrives.
+    pub report: Option<Vec<StepReport>>,
+}
+
+impl Ramp {
+    pub fn new(rates_mbps: Vec<f64>, step: Duration) -> Self {
+        let sent = vec![0; rates_mbps.len()];
+        Ramp { rates_mbps, step, sent, report: None }
+    }
+
+    /// How long the whole staircase takes.
+    pub fn duration(&self) -> Duration {
+        self.step * self.rates_mbps.len() as u32
+    }
+
+    /// Step and send offset of every datagram of `datagram` bytes, in order.
+    pub fn schedule(&self, datagram: usize) -> impl Iterator<Item = (usize, Duration)> + use<> {
+        let bits = (datagram * 8) as f64;
+        let step = self.step;
+        self.rates_mbps.clone().into_iter().enumerate().flat_map(move |(i, mbps)| {
+            let gap = Duration::from_secs_f64(bits / (mbps * 1e6)).max(Duration::from_nanos(1));
+            let begin = step * i as u32;
+            (0u32..).map(move |k| gap * k).take_while(move |&at| at < step).map(move |at| (i, begin + at))
+        })
+    }
+
+    /// Loss of step `i` as the client reported it.
+    fn loss(&self, i: usize) -> Option<f64> {
+        let (received, _) = *self.report.as_ref()?.get(i)?;
+        let sent = self.sent[i];
+        (sent > 0).then(|| sent.saturating_sub(received) as f64 / sent as f64)
+    }
+
+    /// Highest rate that lost under KNEE_LOSS, once the client has reported.
+    pub fn knee_mbps(&self) -> Option<f64> {
+        self.report.as_ref()?;
+        (0..self.rates_mbps.len()).filter(|&i| self.loss(i).is_some_and(|l| l < KNEE_LOSS)).map(|i| self.rates_mbps[i]).reduce(f64::max)
+    }
+}
+
+impl fmt::Display for Ramp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |items: Vec<String>| items.join(",");
+        write!(f, "ramp_mbps={} ramp_step_s={}", join(self.rates_mbps.iter().map(f64::to_string).collect()), self.step.as_secs())?;
+        write!(f, " ramp_sent={}", join(self.sent.iter().map(u64::to_string).collect()))?;
+        if let Some(report) = &self.report {
+            let loss = (0..self.sent.len()).map(|i| self.loss(i).map_or("-".to_string(), |l| format!("{:.2}", l * 100.0))).collect();
+            write!(f, " ramp_loss_pct={}", join(loss))?;
+            write!(f, " ramp_jitter_us={}", join(report.iter().map(|(_, jitter)| jitter.to_string()).collect()))?;
+            if let Some(knee) = self.knee_mbps() {
+                write!(f, " ramp_knee_mbps={}", knee)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Write the `RAMP <step> <seq> <sent_us>` header over the start of `buf`; it
+/// is cut short in a datagram too small for it.
+pub fn stamp(buf: &mut [u8], step: usize, seq: usize) {
+    let header = format!("RAMP {} {} {} ", step, seq, owd::now_us());
+    let n = header.len().min(buf.len());
+    buf[..n].copy_from_slice(&header.as_bytes()[..n]);
+}
+
+/// Parse `RAMP_REPORT <received>:<jitter_us>,...`.
+fn parse_report(cmd: &Command) -> Option<Vec<StepReport>> {
+    cmd.args
+        .first()?
+        .split(',')
+        .map(|entry| {
+            let (received, jitter) = entry.split_once(':')?;
+            Some((received.parse().ok()?, jitter.parse().ok()?))
+        })
+        .collect()
+}
+
+type Waiting = HashMap<SocketAddr, oneshot::Sender<Vec<StepReport>>>;
+
+/// Ramp downloads waiting for their client's `RAMP_REPORT`.
+#[derive(Clone, Default)]
+pub struct PendingReports(Arc<Mutex<Waiting>>);
+
+impl PendingReports {
+    /// Await the report of the ramp download to `addr`, replacing any earlier wait.
+    pub fn expect(&self, addr: SocketAddr) -> oneshot::Receiver<Vec<StepReport>> {
+        let (tx, rx) = oneshot::channel();
+        let mut waiting = self.0.lock().unwrap();
+        waiting.retain(|_, tx| !tx.is_closed());
+        waiting.insert(addr, tx);
+        rx
+    }
+
+    /// Hand a `RAMP_REPORT` from `addr` to its download; the error is a
+    /// ready ERR frame.
+    pub fn report(&self, addr: SocketAddr, cmd: &Command) -> Result<(), String> {
+        let report = parse_report(cmd).ok_or_else(|| error_frame(ErrorCode::BadCommand, "usage: RAMP_REPORT <received>:<jitter_us>,..."))?;
+        let not_found = || error_frame(ErrorCode::NotFound, "no ramp download awaits a report");
+        let tx = self.0.lock().unwrap().remove(&addr).ok_or_else(not_found)?;
+        tx.send(report).map_err(|_| not_found())
+    }
+}
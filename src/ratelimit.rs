@@ -0,0 +1,97 @@
+// proj2-serv/src/ratelimit.rs
+// Per-peer rate limiting of commands that start work (tests, file transfers,
+// probes, connect-backs), so one client cannot spawn floods faster than
+// `--control-rate` per second with bursts of `--control-burst`. Peers are keyed
+// by IP, since a client can pick any source port. Over-limit commands are
+// rejected with `ERR BUSY`; on UDP that reply goes out at most once per
+// NOTICE_INTERVAL per peer, so spoofed commands cannot turn it into a reflector.
+// Cheap commands (CAPS, END_*, OWD probes, ...) are not limited.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+
+/// Verbs that start work; they count against the limit.
+const LIMITED_VERBS: &[&str] = &[
+    "START_DOWNLOAD",
+    "START_UPLOAD",
+    "START_ECHO",
+    "SEND_FILE",
+    "RECV_FILE",
+    "CONNECT_BACK",
+    "CAPACITY_PROBE",
+    "MESH_JOIN",
+    "RENDEZVOUS",
+];
+/// Peers tracked at once; idle buckets are dropped first when it fills up.
+const MAX_PEERS: usize = 16384;
+/// Shortest gap between BUSY replies to one peer on UDP.
+const NOTICE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    notified: Option<Instant>,
+}
+
+/// Outcome of `ControlLimiter::check`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    /// Over the limit; answer with `ERR BUSY`.
+    Reject,
+    /// Over the limit and the peer was told recently; drop silently.
+    Drop,
+}
+
+pub struct ControlLimiter {
+    /// Commands per second; 0 disables limiting.
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+/// Whether `verb` starts a test, transfer or probe.
+pub fn starts_work(verb: &str) -> bool {
+    LIMITED_VERBS.contains(&verb)
+}
+
+impl ControlLimiter {
+    pub fn new(config: &Config) -> Self {
+        ControlLimiter { rate: config.control_rate as f64, burst: config.control_burst as f64, buckets: Mutex::new(HashMap::new()) }
+    }
+
+    /// Charge `verb` from `ip` against its bucket. `quiet` limits BUSY replies
+    /// to one per NOTICE_INTERVAL, for transports without a connection.
+    pub fn check(&self, ip: IpAddr, verb: &str, quiet: bool) -> Verdict {
+        if self.rate <= 0.0 || !starts_work(verb) {
+            return Verdict::Allow;
+        }
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_PEERS && !buckets.contains_key(&ip) {
+            // A full bucket is the same as none; forget those first.
+            let (rate, burst) = (self.rate, self.burst);
+            buckets.retain(|_, b| b.tokens + now.duration_since(b.updated).as_secs_f64() * rate < burst);
+            if buckets.len() >= MAX_PEERS {
+                return if quiet { Verdict::Drop } else { Verdict::Reject };
+            }
+        }
+        let bucket = buckets.entry(ip).or_insert(Bucket { tokens: self.burst, updated: now, notified: None });
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * self.rate).min(self.burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Verdict::Allow;
+        }
+        if quiet && bucket.notified.is_some_and(|t| now.duration_since(t) < NOTICE_INTERVAL) {
+            return Verdict::Drop;
+        }
+        bucket.notified = Some(now);
+        Verdict::Reject
+    }
+}
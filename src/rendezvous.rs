@@ -0,0 +1,59 @@
+// proj2-serv/src/rendezvous.rs
+// UDP hole-punching rendezvous. Two clients send `RENDEZVOUS <token>` from the
+// sockets they will test with; once both have registered, each is told the
+// other's observed public address and when to start sending, so their NATs open
+// mappings toward each other at the same moment.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// How long a registration waits for its partner.
+const PENDING_TTL: Duration = Duration::from_secs(30);
+/// Cap on waiting registrations so unpaired tokens cannot grow the table unbounded.
+const MAX_PENDING: usize = 1024;
+/// Delay before both peers start punching, long enough for both replies to arrive.
+pub const START_DELAY: Duration = Duration::from_millis(250);
+
+pub enum Outcome {
+    /// First registration for the token; waiting for the partner.
+    Waiting,
+    /// The partner was already waiting at this address.
+    Paired(SocketAddr),
+    /// Too many registrations are already waiting.
+    Full,
+}
+
+#[derive(Default)]
+pub struct Rendezvous {
+    pending: HashMap<String, (SocketAddr, Instant)>,
+}
+
+impl Rendezvous {
+    pub fn register(&mut self, token: &str, addr: SocketAddr) -> Outcome {
+        let now = Instant::now();
+        self.pending.retain(|_, (_, at)| now.duration_since(*at) < PENDING_TTL);
+        match self.pending.get(token) {
+            // A retransmitted registration from the same socket just refreshes it.
+            Some((waiting, _)) if *waiting != addr => {
+                let partner = *waiting;
+                self.pending.remove(token);
+                Outcome::Paired(partner)
+            }
+            Some(_) => {
+                self.pending.insert(token.to_string(), (addr, now));
+                Outcome::Waiting
+            }
+            None if self.pending.len() >= MAX_PENDING => Outcome::Full,
+            None => {
+                self.pending.insert(token.to_string(), (addr, now));
+                Outcome::Waiting
+            }
+        }
+    }
+}
+
+/// Tokens are short opaque names chosen by the clients.
+pub fn valid_token(token: &str) -> bool {
+    !token.is_empty() && token.len() <= 64 && token.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
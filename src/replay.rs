@@ -0,0 +1,272 @@
+// proj2-serv/src/replay.rs
+// Traffic replay for UDP downloads (`REPLAY=<name>`): instead of a constant
+// flood, the server sends datagrams with the payload sizes and spacing of the
+// UDP packets in a capture from `--replay-dir`, so a real application's traffic
+// pattern (a video call, a game) can be tried on the path. Captures are pcap
+// (micro- or nanosecond) or pcapng, over Ethernet, raw IP, BSD loopback or
+// Linux cooked links. Every UDP packet in the capture is replayed, whichever
+// its direction or ports, so filter it down to the one flow first (e.g.
+// `tshark -r call.pcapng -w call.pcap udp.srcport==3478`). A capture shorter
+// than the test window is replayed again from the start, one mean packet gap
+// after its last packet; `BYTES=` and the window end the replay as they end a
+// flood.
+
+use std::time::Duration;
+
+use crate::filexfer;
+use crate::protocol::{error_frame, ErrorCode};
+use crate::state::ServerState;
+
+/// Largest UDP payload that fits an IPv4 datagram.
+pub const MAX_DATAGRAM: usize = 65_507;
+
+const PCAPNG_SECTION: u32 = 0x0A0D_0D0A;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Packet {
+    /// Offset from the capture's first UDP packet.
+    pub at: Duration,
+    /// UDP payload size, capped at `MAX_DATAGRAM`.
+    pub len: usize,
+}
+
+/// The UDP packets of one capture, in capture order.
+#[derive(Debug)]
+pub struct Trace {
+    pub name: String,
+    pub packets: Vec<Packet>,
+    /// Time from the start of one pass over the capture to the next.
+    pub period: Duration,
+}
+
+impl Trace {
+    fn new(name: String, stamps: Vec<(Duration, usize)>) -> Result<Self, String> {
+        let Some(&(first, _)) = stamps.first() else { return Err("capture has no UDP packets".to_string()) };
+        if stamps.len() < 2 {
+            return Err("capture has a single UDP packet".to_string());
+        }
+        let packets: Vec<Packet> =
+            stamps.into_iter().map(|(at, len)| Packet { at: at.saturating_sub(first), len: len.min(MAX_DATAGRAM) }).collect();
+        let span = packets.iter().map(|p| p.at).max().unwrap_or_default();
+        if span.is_zero() {
+            return Err("capture packets all have the same timestamp".to_string());
+        }
+        let period = span + span / (packets.len() as u32 - 1);
+        Ok(Trace { name, packets, period })
+    }
+
+    /// Send times and sizes, over as many passes as the test lasts.
+    pub fn schedule(&self) -> impl Iterator<Item = Packet> + '_ {
+        (0u32..).flat_map(move |pass| {
+            let base = self.period * pass;
+            self.packets.iter().map(move |p| Packet { at: base + p.at, len: p.len })
+        })
+    }
+}
+
+/// Load capture `name` from `--replay-dir`; the error is a ready ERR frame.
+pub async fn load(state: &ServerState, name: &str) -> Result<Trace, String> {
+    let dir = state.config.replay_dir.as_ref().ok_or_else(|| error_frame(ErrorCode::Disabled, "traffic replay is disabled (no --replay-dir)"))?;
+    if !filexfer::valid_file_name(name) {
+        return Err(error_frame(ErrorCode::InvalidOption, format!("invalid capture name {:?}", name)));
+    }
+    let path = dir.join(name);
+    let meta = tokio::fs::metadata(&path).await.map_err(|e| error_frame(ErrorCode::NotFound, format!("capture {}: {}", name, e)))?;
+    if meta.len() > state.config.max_file_size {
+        return Err(error_frame(ErrorCode::InvalidOption, format!("capture {} is larger than {} bytes", name, state.config.max_file_size)));
+    }
+    let data = tokio::fs::read(&path).await.map_err(|e| error_frame(ErrorCode::Failed, format!("capture {}: {}", name, e)))?;
+    let owned = name.to_string();
+    let parsed = tokio::task::spawn_blocking(move || parse(&data).and_then(|stamps| Trace::new(owned, stamps))).await;
+    match parsed {
+        Ok(trace) => trace.map_err(|e| error_frame(ErrorCode::InvalidOption, format!("capture {}: {}", name, e))),
+        Err(e) => Err(error_frame(ErrorCode::Failed, format!("capture {}: {}", name, e))),
+    }
+}
+
+/// Timestamps and UDP payload sizes of the UDP packets in a pcap or pcapng
+/// capture. Packets of other protocols and truncated records are skipped.
+fn parse(data: &[u8]) -> Result<Vec<(Duration, usize)>, String> {
+    let magic = data.get(..4).ok_or("file too short for a capture")?;
+    match u32::from_le_bytes(magic.try_into().unwrap()) {
+        PCAPNG_SECTION => parse_pcapng(data),
+        _ => parse_pcap(data),
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Reader {
+    big_endian: bool,
+}
+
+impl Reader {
+    fn u16(self, data: &[u8], at: usize) -> Option<u16> {
+        let b: [u8; 2] = data.get(at..at + 2)?.try_into().ok()?;
+        Some(if self.big_endian { u16::from_be_bytes(b) } else { u16::from_le_bytes(b) })
+    }
+
+    fn u32(self, data: &[u8], at: usize) -> Option<u32> {
+        let b: [u8; 4] = data.get(at..at + 4)?.try_into().ok()?;
+        Some(if self.big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) })
+    }
+}
+
+fn parse_pcap(data: &[u8]) -> Result<Vec<(Duration, usize)>, String> {
+    if data.len() < 24 {
+        return Err("file too short for a pcap header".to_string());
+    }
+    let (big_endian, nanos) = match data[..4] {
+        [0xd4, 0xc3, 0xb2, 0xa1] => (false, false),
+        [0xa1, 0xb2, 0xc3, 0xd4] => (true, false),
+        [0x4d, 0x3c, 0xb2, 0xa1] => (false, true),
+        [0xa1, 0xb2, 0x3c, 0x4d] => (true, true),
+        _ => return Err("not a pcap or pcapng capture".to_string()),
+    };
+    let r = Reader { big_endian };
+    let link = r.u32(data, 20).unwrap_or_default() & 0xffff;
+    let mut stamps = Vec::new();
+    let mut at = 24;
+    while let (Some(secs), Some(frac), Some(caplen)) = (r.u32(data, at), r.u32(data, at + 4), r.u32(data, at + 8)) {
+        let start = at + 16;
+        let Some(frame) = data.get(start..start + caplen as usize) else { break };
+        let ts = Duration::from_secs(secs as u64) + if nanos { Duration::from_nanos(frac as u64) } else { Duration::from_micros(frac as u64) };
+        if let Some(len) = udp_payload_len(link, frame) {
+            stamps.push((ts, len));
+        }
+        at = start + caplen as usize;
+    }
+    Ok(stamps)
+}
+
+fn parse_pcapng(data: &[u8]) -> Result<Vec<(Duration, usize)>, String> {
+    let mut r = Reader { big_endian: false };
+    // Link type and timestamp units per second of each interface in the section.
+    let mut interfaces: Vec<(u32, u64)> = Vec::new();
+    let mut stamps = Vec::new();
+    let mut at = 0;
+    while at + 12 <= data.len() {
+        if r.u32(data, at) == Some(PCAPNG_SECTION) {
+            r.big_endian = match data.get(at + 8..at + 12) {
+                Some([0x1a, 0x2b, 0x3c, 0x4d]) => true,
+                Some([0x4d, 0x3c, 0x2b, 0x1a]) => false,
+                _ => return Err("bad pcapng byte-order magic".to_string()),
+            };
+            interfaces.clear();
+        }
+        let kind = r.u32(data, at).unwrap_or_default();
+        let total = r.u32(data, at + 4).unwrap_or_default() as usize;
+        if total < 12 || !total.is_multiple_of(4) {
+            return Err(format!("malformed pcapng block at offset {}", at));
+        }
+        let Some(block) = data.get(at..at + total) else { break };
+        match kind {
+            // Interface description: link type, then options.
+            1 => interfaces.push((r.u16(block, 8).unwrap_or_default() as u32, ts_units(r, block))),
+            // Enhanced packet: interface, timestamp, captured length, data.
+            6 => {
+                let (Some(iface), Some(high), Some(low), Some(caplen)) = (r.u32(block, 8), r.u32(block, 12), r.u32(block, 16), r.u32(block, 20)) else {
+                    break;
+                };
+                let Some(&(link, units)) = interfaces.get(iface as usize) else {
+                    return Err(format!("pcapng packet on undescribed interface {}", iface));
+                };
+                if let Some(frame) = block.get(28..28 + caplen as usize)
+                    && let Some(len) = udp_payload_len(link, frame)
+                {
+                    let ticks = ((high as u64) << 32) | low as u64;
+                    let ts = Duration::from_secs(ticks / units) + Duration::from_nanos((ticks % units) * 1_000_000_000 / units);
+                    stamps.push((ts, len));
+                }
+            }
+            _ => {}
+        }
+        at += total;
+    }
+    Ok(stamps)
+}
+
+/// Timestamp units per second from an interface description's `if_tsresol`
+/// option (code 9); microseconds when absent.
+fn ts_units(r: Reader, block: &[u8]) -> u64 {
+    let mut at = 16;
+    while let (Some(code), Some(len)) = (r.u16(block, at), r.u16(block, at + 2)) {
+        if code == 0 {
+            break;
+        }
+        if code == 9
+            && let Some(&res) = block.get(at + 4)
+        {
+            let exp = (res & 0x7f) as u32;
+            let units = if res & 0x80 != 0 { 2u64.checked_pow(exp) } else { 10u64.checked_pow(exp) };
+            return units.filter(|&u| u > 0).unwrap_or(1_000_000);
+        }
+        at += 4 + (len as usize).div_ceil(4) * 4;
+    }
+    1_000_000
+}
+
+/// UDP payload size of a captured frame on link type `link`, if it carries
+/// the first fragment of a UDP datagram. The UDP header's length is used, so
+/// frames cut short by the snap length still count in full.
+fn udp_payload_len(link: u32, frame: &[u8]) -> Option<usize> {
+    let ip = match link {
+        // BSD loopback: a 4-byte address family in the capturing host's order.
+        0 | 108 => frame.get(4..)?,
+        // Ethernet, past any VLAN tags.
+        1 => {
+            let mut at = 12;
+            while matches!(frame.get(at..at + 2)?, [0x81, 0x00] | [0x88, 0xa8]) {
+                at += 4;
+            }
+            match frame.get(at..at + 2)? {
+                [0x08, 0x00] | [0x86, 0xdd] => frame.get(at + 2..)?,
+                _ => return None,
+            }
+        }
+        // Raw IP.
+        101 | 228 | 229 => frame,
+        // Linux cooked capture, v1 and v2.
+        113 => frame.get(16..)?,
+        276 => frame.get(20..)?,
+        _ => return None,
+    };
+    let udp = match ip.first()? >> 4 {
+        4 => {
+            let header = ((ip[0] & 0x0f) as usize) * 4;
+            let fragment_offset = u16::from_be_bytes([*ip.get(6)?, *ip.get(7)?]) & 0x1fff;
+            if *ip.get(9)? != 17 || fragment_offset != 0 {
+                return None;
+            }
+            ip.get(header..)?
+        }
+        6 => ipv6_udp(ip)?,
+        _ => return None,
+    };
+    let len = u16::from_be_bytes([*udp.get(4)?, *udp.get(5)?]) as usize;
+    len.checked_sub(8)
+}
+
+/// The UDP header of an IPv6 packet, past hop-by-hop, routing, fragment and
+/// destination options headers.
+fn ipv6_udp(ip: &[u8]) -> Option<&[u8]> {
+    let mut next = *ip.get(6)?;
+    let mut at = 40;
+    loop {
+        match next {
+            17 => return ip.get(at..),
+            0 | 43 | 60 => {
+                next = *ip.get(at)?;
+                at += (*ip.get(at + 1)? as usize + 1) * 8;
+            }
+            44 => {
+                let offset = u16::from_be_bytes([*ip.get(at + 2)?, *ip.get(at + 3)?]) >> 3;
+                if offset != 0 {
+                    return None;
+                }
+                next = *ip.get(at)?;
+                at += 8;
+            }
+            _ => return None,
+        }
+    }
+}
@@ -0,0 +1,580 @@
+// proj2-serv/src/results.rs
+// Completed test results, kept in a bounded in-memory store namespaced by tenant.
+
+use std::collections::VecDeque;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::bloat::Latency;
+use crate::compress::Compression;
+use crate::dashboard;
+use crate::ecn::EcnCounts;
+use crate::eyeballs::Race;
+use crate::geoip::Geo;
+use crate::histogram::RoundTrips;
+use crate::hostres::HostUsage;
+use crate::overhead::Overhead;
+use crate::pacing::Pacing;
+use crate::protocol::ClientMeta;
+use crate::ramp::Ramp;
+use crate::resume::Resumes;
+use crate::scheduler::Setup;
+use crate::sockopt::{self, BufferSizes, Steering};
+use crate::tcpinfo::TcpInfoSample;
+
+const MAX_STORED_RESULTS: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+    /// Unix domain socket: a local baseline without the network stack.
+    Uds,
+}
+
+impl Protocol {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Protocol::Tcp => "tcp",
+            Protocol::Udp => "udp",
+            Protocol::Uds => "uds",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Download,
+    Upload,
+    /// Request/response: every message is sent back (`START_ECHO`).
+    Echo,
+}
+
+impl Direction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Direction::Download => "download",
+            Direction::Upload => "upload",
+            Direction::Echo => "echo",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TestResult {
+    pub tenant: String,
+    pub protocol: Protocol,
+    pub direction: Direction,
+    pub peer: SocketAddr,
+    /// Bytes and duration of the measured part of the test, after any warm-up.
+    pub bytes: u64,
+    pub duration: Duration,
+    /// Byte target of a `BYTES=` test; `None` for time-based tests.
+    pub target_bytes: Option<u64>,
+    /// File moved by SEND_FILE/RECV_FILE.
+    pub file: Option<String>,
+    /// Payload generator used for downloads (`PAYLOAD=`).
+    pub payload: Option<&'static str>,
+    /// Capture a UDP download replayed (`REPLAY=`).
+    pub replay: Option<String>,
+    /// Staircase of a UDP `RAMP=` download, with what each step delivered.
+    pub ramp: Option<Ramp>,
+    /// Sizes of a UDP `OVERHEAD=` download and the overhead they point to.
+    pub overhead: Option<Overhead>,
+    /// UDP download sender (`SENDER=`).
+    pub sender: Option<&'static str>,
+    /// Warm-up excluded from `bytes` and `duration` (`OMIT=`).
+    pub omit: Duration,
+    pub finished_at: SystemTime,
+    /// Last kernel TCP_INFO sample taken at the end of a TCP test.
+    pub tcp_info: Option<TcpInfoSample>,
+    /// Congestion control algorithm in effect for TCP downloads.
+    pub cca: Option<String>,
+    /// DSCP the server marked its test traffic with.
+    pub dscp: Option<u8>,
+    /// A UDP download went out marked ECT(0) (`ECN=1`).
+    pub ect: bool,
+    /// ECN codepoints a UDP upload's datagrams arrived with, when any came
+    /// ECN-capable; CE counts are congestion marks from queues on the path.
+    pub ecn: Option<EcnCounts>,
+    /// SO_PRIORITY and SO_MARK of the test's socket.
+    pub steering: Steering,
+    /// Local address a UDP download was sent from (`SRC=`).
+    pub src: Option<IpAddr>,
+    /// Interface the test ran over, where known.
+    pub iface: Option<String>,
+    /// Number of server ports a UDP download was striped across (`PORTS=`).
+    pub stripe_ports: Option<usize>,
+    /// A UDP download was sent from its own connected socket (`CONNECTED=1`).
+    pub connected: bool,
+    /// Payload bytes of each datagram of a UDP download (`DATAGRAM=`).
+    pub datagram: Option<usize>,
+    /// MTU of the route to the client, where known.
+    pub path_mtu: Option<usize>,
+    /// Effective kernel buffer sizes of the server's socket for the test.
+    pub buffers: Option<BufferSizes>,
+    /// Server CPU and memory use over the test.
+    pub host: Option<HostUsage>,
+    /// The client stopped responding (ICMP unreachable) before the window ended.
+    pub client_unreachable: bool,
+    /// Round trips with the link idle and under the test's load (`BLOAT=1`).
+    pub latency: Option<Latency>,
+    /// Datagrams the kernel dropped on the UDP plane's socket during an upload,
+    /// i.e. receive-buffer overflow on the server rather than network loss. The
+    /// socket is shared, so other clients' traffic in the same window counts too.
+    pub kernel_drops: Option<u64>,
+    /// Messages echoed in an echo test.
+    pub requests: Option<u64>,
+    /// RTT and jitter percentiles of an echo test's requests, as the server
+    /// sees them (see echo.rs).
+    pub round_trips: Option<RoundTrips>,
+    /// Why the test was cut short by the server (`no_data`, `inactive`).
+    pub aborted: Option<&'static str>,
+    /// Codec and ratio of a compressed TCP download (`COMPRESS=`).
+    pub compression: Option<Compression>,
+    /// Rate cap of a download (`RATE=`) and the mechanism that paced it.
+    pub pacing: Option<Pacing>,
+    /// Run the test was grouped under (`RUN=`).
+    pub run: Option<String>,
+    /// How long a scheduled test took to resolve and connect.
+    pub setup: Option<Setup>,
+    /// How a scheduled test's IPv6/IPv4 connection race went.
+    pub race: Option<Race>,
+    /// Bytes moved in each second of the measured span; empty where the
+    /// transport does not count them.
+    pub intervals: Vec<u64>,
+    /// Times the client resumed the test after losing its path, and the gaps.
+    pub resumes: Resumes,
+    /// What the client said about itself (`AGENT=`, `META=`).
+    pub meta: ClientMeta,
+    /// Country and network of the client address (`--geoip`).
+    pub geo: Option<Geo>,
+    /// Ed25519 signature of the rest of the result line (`--result-key`).
+    pub signature: Option<String>,
+}
+
+impl TestResult {
+    pub fn new(tenant: &str, protocol: Protocol, direction: Direction, peer: SocketAddr, bytes: u64, duration: Duration) -> Self {
+        TestResult {
+            tenant: tenant.to_string(),
+            protocol,
+            direction,
+            peer,
+            bytes,
+            duration,
+            target_bytes: None,
+            file: None,
+            payload: None,
+            replay: None,
+            ramp: None,
+            overhead: None,
+            sender: None,
+            omit: Duration::ZERO,
+            finished_at: SystemTime::now(),
+            tcp_info: None,
+            cca: None,
+            dscp: None,
+            ect: false,
+            ecn: None,
+            steering: Steering::default(),
+            src: None,
+            iface: None,
+            stripe_ports: None,
+            connected: false,
+            datagram: None,
+            path_mtu: None,
+            buffers: None,
+            host: None,
+            client_unreachable: false,
+            latency: None,
+            kernel_drops: None,
+            requests: None,
+            round_trips: None,
+            aborted: None,
+            compression: None,
+            pacing: None,
+            run: None,
+            setup: None,
+            race: None,
+            intervals: Vec::new(),
+            resumes: Resumes::default(),
+            meta: ClientMeta::default(),
+            geo: None,
+            signature: None,
+        }
+    }
+
+    pub fn with_run(mut self, run: Option<String>) -> Self {
+        self.run = run;
+        self
+    }
+
+    pub fn with_setup(mut self, setup: Option<Setup>) -> Self {
+        self.setup = setup;
+        self
+    }
+
+    pub fn with_race(mut self, race: Option<Race>) -> Self {
+        self.race = race;
+        self
+    }
+
+    pub fn with_compression(mut self, compression: Option<Compression>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn with_pacing(mut self, pacing: Option<Pacing>) -> Self {
+        self.pacing = pacing;
+        self
+    }
+
+    pub fn with_meta(mut self, meta: ClientMeta) -> Self {
+        self.meta = meta;
+        self
+    }
+
+    pub fn with_resumes(mut self, resumes: Resumes) -> Self {
+        self.resumes = resumes;
+        self
+    }
+
+    pub fn with_intervals(mut self, intervals: &[u64]) -> Self {
+        self.intervals = intervals.to_vec();
+        self
+    }
+
+    pub fn with_target_bytes(mut self, target: Option<u64>) -> Self {
+        self.target_bytes = target;
+        self
+    }
+
+    pub fn with_file(mut self, name: &str) -> Self {
+        self.file = Some(name.to_string());
+        self
+    }
+
+    pub fn with_payload(mut self, payload: &'static str) -> Self {
+        self.payload = Some(payload);
+        self
+    }
+
+    pub fn with_replay(mut self, replay: Option<String>) -> Self {
+        self.replay = replay;
+        self
+    }
+
+    pub fn with_ramp(mut self, ramp: Option<Ramp>) -> Self {
+        self.ramp = ramp;
+        self
+    }
+
+    pub fn with_overhead(mut self, overhead: Option<Overhead>) -> Self {
+        self.overhead = overhead;
+        self
+    }
+
+    pub fn with_sender(mut self, sender: &'static str) -> Self {
+        self.sender = Some(sender);
+        self
+    }
+
+    pub fn with_omit(mut self, omit: Duration) -> Self {
+        self.omit = omit;
+        self
+    }
+
+    pub fn with_tcp_info(mut self, info: Option<TcpInfoSample>) -> Self {
+        self.tcp_info = info;
+        self
+    }
+
+    pub fn with_cca(mut self, cca: Option<String>) -> Self {
+        self.cca = cca;
+        self
+    }
+
+    pub fn with_dscp(mut self, dscp: Option<u8>) -> Self {
+        self.dscp = dscp;
+        self
+    }
+
+    pub fn with_ect(mut self, ect: bool) -> Self {
+        self.ect = ect;
+        self
+    }
+
+    pub fn with_ecn_counts(mut self, ecn: Option<EcnCounts>) -> Self {
+        self.ecn = ecn;
+        self
+    }
+
+    pub fn with_steering(mut self, steering: Steering) -> Self {
+        self.steering = steering;
+        self
+    }
+
+    pub fn with_src(mut self, src: Option<IpAddr>) -> Self {
+        self.src = src;
+        self
+    }
+
+    pub fn with_interface(mut self, iface: Option<String>) -> Self {
+        self.iface = iface;
+        self
+    }
+
+    pub fn with_stripe_ports(mut self, ports: Option<usize>) -> Self {
+        self.stripe_ports = ports;
+        self
+    }
+
+    pub fn with_connected(mut self, connected: bool) -> Self {
+        self.connected = connected;
+        self
+    }
+
+    pub fn with_datagram(mut self, datagram: Option<usize>, path_mtu: Option<usize>) -> Self {
+        self.datagram = datagram;
+        self.path_mtu = path_mtu;
+        self
+    }
+
+    pub fn with_buffers(mut self, buffers: Option<BufferSizes>) -> Self {
+        self.buffers = buffers;
+        self
+    }
+
+    pub fn with_host_usage(mut self, host: Option<HostUsage>) -> Self {
+        self.host = host;
+        self
+    }
+
+    pub fn with_client_unreachable(mut self, unreachable: bool) -> Self {
+        self.client_unreachable = unreachable;
+        self
+    }
+
+    pub fn with_latency(mut self, latency: Option<Latency>) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    pub fn with_kernel_drops(mut self, drops: Option<u64>) -> Self {
+        self.kernel_drops = drops;
+        self
+    }
+
+    pub fn with_requests(mut self, requests: u64) -> Self {
+        self.requests = Some(requests);
+        self
+    }
+
+    pub fn with_round_trips(mut self, round_trips: RoundTrips) -> Self {
+        self.round_trips = Some(round_trips).filter(|r| !r.is_empty());
+        self
+    }
+
+    pub fn with_aborted(mut self, reason: Option<&'static str>) -> Self {
+        self.aborted = reason;
+        self
+    }
+
+    pub fn throughput_bps(&self) -> f64 {
+        let secs = self.duration.as_secs_f64();
+        if secs > 0.0 { self.bytes as f64 * 8.0 / secs } else { 0.0 }
+    }
+
+    /// Single-line `key=value` rendering used by admin queries and logs,
+    /// ending in the signature once the result is signed.
+    pub fn to_line(&self) -> String {
+        let line = self.unsigned_line();
+        match &self.signature {
+            Some(sig) => format!("{} sig={}", line, sig),
+            None => line,
+        }
+    }
+
+    /// The result line a signature covers.
+    pub fn unsigned_line(&self) -> String {
+        let ts = self.finished_at.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let mut line = format!(
+            "tenant={} proto={} dir={} peer={} bytes={} duration_ms={} bps={:.0} finished_at={}",
+            self.tenant,
+            self.protocol.as_str(),
+            self.direction.as_str(),
+            self.peer,
+            self.bytes,
+            self.duration.as_millis(),
+            self.throughput_bps(),
+            ts
+        );
+        if let Some(run) = &self.run {
+            line.push_str(&format!(" run={}", run));
+        }
+        if let Some(setup) = &self.setup {
+            line.push_str(&format!(" {}", setup));
+        }
+        if let Some(race) = &self.race {
+            line.push_str(&format!(" {}", race));
+        }
+        if let Some(c) = &self.compression {
+            line.push_str(&format!(" {} payload_bps={:.0}", c, self.throughput_bps() * c.ratio));
+        }
+        if let Some(pacing) = &self.pacing {
+            line.push_str(&format!(" {}", pacing));
+        }
+        if !self.meta.is_empty() {
+            line.push_str(&format!(" {}", self.meta));
+        }
+        if let Some(geo) = &self.geo {
+            line.push_str(&format!(" {}", geo));
+        }
+        if let Some(requests) = self.requests {
+            let secs = self.duration.as_secs_f64();
+            let rps = if secs > 0.0 { requests as f64 / secs } else { 0.0 };
+            line.push_str(&format!(" requests={} rps={:.0}", requests, rps));
+        }
+        if let Some(round_trips) = &self.round_trips {
+            line.push_str(&format!(" {}", round_trips));
+        }
+        if let Some(target) = self.target_bytes {
+            line.push_str(&format!(" target_bytes={}", target));
+        }
+        if let Some(file) = &self.file {
+            line.push_str(&format!(" file={}", file));
+        }
+        if let Some(payload) = self.payload {
+            line.push_str(&format!(" payload={}", payload));
+        }
+        if let Some(replay) = &self.replay {
+            line.push_str(&format!(" replay={}", replay));
+        }
+        if let Some(ramp) = &self.ramp {
+            line.push_str(&format!(" {}", ramp));
+        }
+        if let Some(overhead) = &self.overhead {
+            line.push_str(&format!(" {}", overhead));
+        }
+        if let Some(sender) = self.sender {
+            line.push_str(&format!(" sender={}", sender));
+        }
+        if !self.omit.is_zero() {
+            line.push_str(&format!(" omit_ms={}", self.omit.as_millis()));
+        }
+        if let Some(cca) = &self.cca {
+            line.push_str(&format!(" cca={}", cca));
+        }
+        if let Some(dscp) = self.dscp {
+            line.push_str(&format!(" dscp={}", dscp));
+        }
+        if self.ect {
+            line.push_str(" ecn=ect0");
+        }
+        if let Some(ecn) = self.ecn {
+            line.push_str(&format!(" {}", ecn));
+        }
+        if self.steering.is_set() {
+            line.push_str(&format!(" {}", self.steering));
+        }
+        if let Some(src) = self.src {
+            line.push_str(&format!(" src={}", src));
+        }
+        if let Some(iface) = &self.iface {
+            line.push_str(&format!(" iface={}", iface));
+        }
+        if let Some(ports) = self.stripe_ports {
+            line.push_str(&format!(" ports={}", ports));
+        }
+        if self.connected {
+            line.push_str(" connected=1");
+        }
+        if let Some(datagram) = self.datagram {
+            line.push_str(&format!(" datagram={}", datagram));
+            if let Some(mtu) = self.path_mtu {
+                line.push_str(&format!(" mtu={}", mtu));
+                // Fragmentation risk: one lost fragment loses the whole datagram.
+                let fragments = sockopt::fragments(datagram, self.peer, mtu);
+                if fragments > 1 {
+                    line.push_str(&format!(" fragments={}", fragments));
+                }
+            }
+        }
+        if let Some(buffers) = self.buffers {
+            line.push_str(&format!(" {}", buffers));
+        }
+        if self.client_unreachable {
+            line.push_str(" client_unreachable=1");
+        }
+        if let Some(latency) = &self.latency {
+            line.push_str(&format!(" {}", latency));
+        }
+        if let Some(drops) = self.kernel_drops {
+            line.push_str(&format!(" kernel_drops={}", drops));
+        }
+        if let Some(reason) = self.aborted {
+            line.push_str(&format!(" aborted={}", reason));
+        }
+        if self.resumes.count > 0 {
+            line.push_str(&format!(" resumed={} gap_ms={}", self.resumes.count, self.resumes.gap.as_millis()));
+        }
+        if let Some(host) = &self.host {
+            line.push(' ');
+            line.push_str(&host.fields());
+        }
+        if let Some(info) = &self.tcp_info {
+            line.push(' ');
+            line.push_str(&info.fields());
+        }
+        line
+    }
+
+    /// The result line's fields as a flat JSON object; numeric values are
+    /// numbers, the rest strings.
+    pub fn to_json(&self) -> String {
+        let fields: Vec<String> = self
+            .to_line()
+            .split(' ')
+            .filter_map(|field| field.split_once('='))
+            .map(|(key, value)| match value.parse::<f64>() {
+                Ok(n) if n.is_finite() => format!("\"{}\":{}", key, value),
+                _ => format!("\"{}\":\"{}\"", key, dashboard::json_escape(value)),
+            })
+            .collect();
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+#[derive(Default)]
+pub struct ResultStore {
+    results: Mutex<VecDeque<TestResult>>,
+}
+
+impl ResultStore {
+    pub fn push(&self, result: TestResult) {
+        let mut q = self.results.lock().unwrap();
+        if q.len() == MAX_STORED_RESULTS {
+            q.pop_front();
+        }
+        q.push_back(result);
+    }
+
+    /// A tenant's results grouped under `run`, oldest first.
+    pub fn query_run(&self, tenant: &str, run: &str) -> Vec<TestResult> {
+        let q = self.results.lock().unwrap();
+        q.iter()
+            .filter(|r| r.tenant == tenant && r.run.as_deref() == Some(run))
+            .cloned()
+            .collect()
+    }
+
+    /// Results for one tenant (or all when `tenant` is None), oldest first.
+    pub fn query(&self, tenant: Option<&str>) -> Vec<TestResult> {
+        let q = self.results.lock().unwrap();
+        q.iter()
+            .filter(|r| tenant.is_none_or(|t| t == r.tenant))
+            .cloned()
+            .collect()
+    }
+}
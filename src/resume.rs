@@ -0,0 +1,117 @@
+// proj2-serv/src/resume.rs
+// Session resumption. A START_DOWNLOAD or START_UPLOAD with `RESUMABLE=1` is
+// given a ticket, `SESSION=<id> TOKEN=<16 hex>` with a token fresh from the
+// OS random source: on TCP in a `RESUMABLE SESSION=<id> TOKEN=<t>` line ahead
+// of the test's data, on UDP appended to the ACK (or, for a `HANDSHAKE=1`
+// upload, in a `RESUMABLE` datagram once the window opens). A client that loses connectivity mid-test
+// may pick the test up again within `--resume-grace` by sending `RESUME
+// SESSION=<id> TOKEN=<t>` on a new TCP connection, or from a new UDP source
+// address. The server answers `RESUMED SESSION=<id>` (with `BYTES=`, the bytes
+// counted so far, except for UDP downloads) and carries on with the same test
+// over the new path, so its
+// byte counts are stitched together; the result notes `resumed=<n>
+// gap_ms=<total>`. The test window is not extended; gaps count against it.
+// A gap runs from the last data the old path carried to the resume. A UDP
+// download cannot see when its datagrams stopped arriving, so there the client
+// reports the gap with `GAP_MS=` on RESUME. With return-path validation
+// (ack.rs) a UDP download only moves once the new address echoes a fresh
+// cookie, so a leaked ticket cannot aim the flood at someone else.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+use crate::protocol::{error_frame, Command, ErrorCode};
+use crate::rng;
+use crate::state::ServerState;
+
+/// A new connection taking over a TCP test, and where it comes from.
+pub type TcpResume = (TcpStream, SocketAddr);
+
+/// A UDP download's new destination, and the gap the client reports.
+pub type UdpMove = (SocketAddr, Duration);
+
+/// Path changes a resumable test went through.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Resumes {
+    pub count: u32,
+    /// Total time without data across them.
+    pub gap: Duration,
+}
+
+impl Resumes {
+    pub fn add(&mut self, gap: Duration) {
+        self.count += 1;
+        self.gap += gap;
+    }
+}
+
+/// The ticket of a resumable TCP test; new connections arrive on `streams`.
+pub struct TcpTicket {
+    pub token: String,
+    pub streams: mpsc::Receiver<TcpResume>,
+}
+
+#[derive(Default)]
+pub struct Resumptions {
+    tcp: Mutex<HashMap<u64, (String, mpsc::Sender<TcpResume>)>>,
+}
+
+impl Resumptions {
+    /// A fresh ticket token, from the OS random source.
+    pub fn token(&self) -> String {
+        rng::token_hex()
+    }
+
+    /// Register TCP session `id` as resumable until the ticket is dropped.
+    pub fn offer_tcp(&self, id: u64) -> TcpTicket {
+        let token = self.token();
+        let (tx, streams) = mpsc::channel(1);
+        let mut tcp = self.tcp.lock().unwrap();
+        tcp.retain(|_, (_, tx)| !tx.is_closed());
+        tcp.insert(id, (token.clone(), tx));
+        TcpTicket { token, streams }
+    }
+
+    /// Hand `stream` to the TCP test named by `cmd`'s ticket. On failure the
+    /// stream comes back with an ERR frame for it.
+    pub fn resume_tcp(&self, cmd: &Command, stream: TcpStream, peer: SocketAddr) -> Result<(), (TcpStream, String)> {
+        let Some((id, token)) = ticket(cmd) else { return Err((stream, usage())) };
+        let tx = match self.tcp.lock().unwrap().get(&id) {
+            Some((expected, tx)) if *expected == token && !tx.is_closed() => tx.clone(),
+            _ => return Err((stream, not_found(id))),
+        };
+        tx.try_send((stream, peer)).map_err(|e| {
+            let (stream, _) = e.into_inner();
+            (stream, error_frame(ErrorCode::Busy, format!("session {} is already being resumed", id)))
+        })
+    }
+}
+
+/// Whether `cmd` asks for a resumable test; the error is a ready ERR frame and
+/// the test runs without a ticket.
+pub fn requested(state: &ServerState, cmd: &Command) -> Result<bool, String> {
+    let Some(value) = cmd.opt("RESUMABLE") else { return Ok(false) };
+    match crate::protocol::parse_flag(value) {
+        None => Err(error_frame(ErrorCode::InvalidOption, format!("invalid RESUMABLE={}", value))),
+        Some(true) if state.config.resume_grace.is_none() => Err(error_frame(ErrorCode::Disabled, "session resumption is disabled")),
+        Some(wanted) => Ok(wanted),
+    }
+}
+
+/// `SESSION=` and `TOKEN=` of a RESUME command.
+pub fn ticket(cmd: &Command) -> Option<(u64, &str)> {
+    Some((cmd.opt("SESSION")?.parse().ok()?, cmd.opt("TOKEN")?))
+}
+
+pub fn usage() -> String {
+    error_frame(ErrorCode::BadCommand, "usage: RESUME SESSION=<id> TOKEN=<token>")
+}
+
+pub fn not_found(id: u64) -> String {
+    error_frame(ErrorCode::NotFound, format!("no resumable session {}", id))
+}
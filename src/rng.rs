@@ -0,0 +1,59 @@
+// proj2-serv/src/rng.rs
+// Small xorshift64* generator for payloads and impairment decisions. Not
+// cryptographic; its job is to be fast, seedable and incompressible. Values a
+// client must not be able to guess, return-path cookies and resume tokens, come
+// from the OS random source through `token_hex` instead.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy)]
+pub struct XorShift64(u64);
+
+impl XorShift64 {
+    pub fn new(seed: u64) -> Self {
+        // A zero state would stay zero forever; scramble the seed so nearby
+        // seeds do not produce similar opening output.
+        XorShift64(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    /// Seed from the clock and a stack address, so concurrent generators differ.
+    pub fn from_entropy() -> Self {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0);
+        let local = 0u8;
+        XorShift64::new(nanos ^ (&local as *const u8 as u64))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0 = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Uniform in [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        let mut chunks = buf.chunks_exact_mut(8);
+        for chunk in &mut chunks {
+            chunk.copy_from_slice(&self.next_u64().to_le_bytes());
+        }
+        let rest = chunks.into_remainder();
+        if !rest.is_empty() {
+            let bytes = self.next_u64().to_le_bytes();
+            rest.copy_from_slice(&bytes[..rest.len()]);
+        }
+    }
+}
+
+/// A 64-bit token as 16 hex digits, each drawn afresh from the OS random
+/// source, so no token says anything about another.
+pub fn token_hex() -> String {
+    let mut bytes = [0u8; 8];
+    getrandom::fill(&mut bytes).expect("the OS random source is available");
+    format!("{:016x}", u64::from_le_bytes(bytes))
+}
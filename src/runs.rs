@@ -0,0 +1,105 @@
+// proj2-serv/src/runs.rs
+// Runs: a client groups several TCP/UDP sessions under one name by passing
+// `RUN=<name>` on each start command, then asks for the combined statistics
+// with `RUN <name> [TENANT=<name>]` on either plane. The reply sums the
+// streams' bytes and throughput and reports Jain's fairness index over the
+// per-stream throughputs (1 when every stream got the same share, 1/n when one
+// stream got everything). Runs are scoped to the tenant, like results.
+//
+// A run also serves as the session of a TCP/UDP comparison: the client starts
+// a TCP and a UDP test under the same `RUN=`, back to back or at once, and
+// `COMPARE <name>` sets the two protocols side by side for every direction the
+// run has both in, with UDP throughput as a share of TCP's.
+
+use crate::protocol::{self, error_frame, Command, ErrorCode};
+use crate::results::{Direction, Protocol, TestResult};
+use crate::state::ServerState;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunSummary {
+    pub streams: usize,
+    pub bytes: u64,
+    /// Sum of the per-stream throughputs.
+    pub bps: f64,
+    pub fairness: f64,
+}
+
+impl RunSummary {
+    /// None when the run has no results.
+    pub fn summarize(results: &[TestResult]) -> Option<RunSummary> {
+        if results.is_empty() {
+            return None;
+        }
+        let rates: Vec<f64> = results.iter().map(TestResult::throughput_bps).collect();
+        let bps: f64 = rates.iter().sum();
+        let squares: f64 = rates.iter().map(|x| x * x).sum();
+        let fairness = if squares > 0.0 { bps * bps / (rates.len() as f64 * squares) } else { 1.0 };
+        Some(RunSummary { streams: results.len(), bytes: results.iter().map(|r| r.bytes).sum(), bps, fairness })
+    }
+}
+
+/// Reply to `RUN <name>`, without a line terminator.
+pub fn reply(state: &ServerState, cmd: &Command) -> String {
+    let Some(name) = cmd.args.first().filter(|n| protocol::valid_tenant(n)) else {
+        return error_frame(ErrorCode::BadCommand, "usage: RUN <name>");
+    };
+    match RunSummary::summarize(&state.results.query_run(&cmd.tenant(), name)) {
+        Some(s) => format!(
+            "RUN name={} streams={} bytes={} bps={:.0} fairness={:.3}",
+            name, s.streams, s.bytes, s.bps, s.fairness
+        ),
+        None => error_frame(ErrorCode::NotFound, format!("no results for run {}", name)),
+    }
+}
+
+/// TCP and UDP streams of one direction of a run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub direction: Direction,
+    pub tcp: RunSummary,
+    pub udp: RunSummary,
+}
+
+impl Comparison {
+    /// One comparison per direction the run has both protocols in; empty when
+    /// it has none.
+    pub fn compare(results: &[TestResult]) -> Vec<Comparison> {
+        let side = |direction, protocol| {
+            let streams: Vec<TestResult> = results.iter().filter(|r| r.direction == direction && r.protocol == protocol).cloned().collect();
+            RunSummary::summarize(&streams)
+        };
+        [Direction::Download, Direction::Upload]
+            .into_iter()
+            .filter_map(|direction| Some(Comparison { direction, tcp: side(direction, Protocol::Tcp)?, udp: side(direction, Protocol::Udp)? }))
+            .collect()
+    }
+
+    /// UDP throughput as a share of TCP's.
+    pub fn udp_over_tcp(&self) -> f64 {
+        if self.tcp.bps > 0.0 { self.udp.bps / self.tcp.bps } else { 0.0 }
+    }
+}
+
+/// Reply to `COMPARE <name>`, without a line terminator.
+pub fn compare_reply(state: &ServerState, cmd: &Command) -> String {
+    let Some(name) = cmd.args.first().filter(|n| protocol::valid_tenant(n)) else {
+        return error_frame(ErrorCode::BadCommand, "usage: COMPARE <name>");
+    };
+    let comparisons = Comparison::compare(&state.results.query_run(&cmd.tenant(), name));
+    if comparisons.is_empty() {
+        return error_frame(ErrorCode::NotFound, format!("run {} has no TCP and UDP results in the same direction", name));
+    }
+    let mut reply = format!("COMPARE name={}", name);
+    for c in comparisons {
+        let dir = c.direction.as_str();
+        reply += &format!(
+            " {dir}_tcp_streams={} {dir}_tcp_bps={:.0} {dir}_udp_streams={} {dir}_udp_bps={:.0} {dir}_udp_over_tcp={:.3}",
+            c.tcp.streams,
+            c.tcp.bps,
+            c.udp.streams,
+            c.udp.bps,
+            c.udp_over_tcp()
+        );
+    }
+    reply
+}
@@ -0,0 +1,111 @@
+// proj2-serv/src/rxring.rs
+// The UDP plane's receive stage. One task does nothing but receive datagrams,
+// stamp them and copy them into a ring of `--udp-rx-ring` reusable slots
+// (default 8192), each of up to `--udp-max-datagram` bytes (default 65507, the
+// largest UDP payload); the plane's loop dispatches them from the ring on its own
+// task. Control handling and the upload and session maps can then take their
+// time without the socket going unread, so a burst of control work during an
+// upload flood lands in the ring rather than overflowing the kernel's receive
+// buffer. When every slot is taken the stage waits for one to come back and the
+// kernel buffer takes up the slack, as before.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio_util::task::AbortOnDropHandle;
+
+use crate::icmp;
+use crate::owd;
+use crate::rxstamp;
+
+/// Consecutive recv errors after which the socket is considered dead and the
+/// supervisor rebinds it.
+const MAX_CONSECUTIVE_ERRORS: u32 = 100;
+
+/// A datagram waiting in the ring. Its slot goes back to the ring when dropped.
+pub struct Received {
+    pub buf: Vec<u8>,
+    pub addr: SocketAddr,
+    /// Kernel receive stamp, or the clock on receipt.
+    pub received_us: i64,
+    /// TOS byte the datagram arrived with, where the kernel reports it.
+    pub tos: Option<u8>,
+    free: mpsc::Sender<Vec<u8>>,
+}
+
+impl Drop for Received {
+    fn drop(&mut self) {
+        let _ = self.free.try_send(std::mem::take(&mut self.buf));
+    }
+}
+
+/// The dispatch end of the ring.
+pub struct RxRing {
+    filled: mpsc::Receiver<Received>,
+    stage: AbortOnDropHandle<anyhow::Error>,
+}
+
+impl RxRing {
+    /// Start receiving on `sock` into a ring of `depth` slots, taking datagrams
+    /// of up to `max_datagram` bytes whole; longer ones are cut short.
+    pub fn spawn(sock: Arc<UdpSocket>, depth: usize, max_datagram: usize) -> Self {
+        let (free_tx, free_rx) = mpsc::channel(depth);
+        for _ in 0..depth {
+            let _ = free_tx.try_send(Vec::new());
+        }
+        let (filled_tx, filled) = mpsc::channel(depth);
+        let stage = tokio::spawn(receive(sock, max_datagram, free_tx, free_rx, filled_tx));
+        RxRing { filled, stage: AbortOnDropHandle::new(stage) }
+    }
+
+    /// The next datagram, or why the receive stage gave up on the socket.
+    pub async fn next(&mut self) -> Result<Received, anyhow::Error> {
+        match self.filled.recv().await {
+            Some(received) => Ok(received),
+            None => Err((&mut self.stage).await.unwrap_or_else(|e| anyhow::Error::new(e).context("UDP receive stage failed"))),
+        }
+    }
+}
+
+async fn receive(
+    sock: Arc<UdpSocket>,
+    max_datagram: usize,
+    free_tx: mpsc::Sender<Vec<u8>>,
+    mut free_rx: mpsc::Receiver<Vec<u8>>,
+    filled: mpsc::Sender<Received>,
+) -> anyhow::Error {
+    let mut consecutive_errors = 0u32;
+    let mut recv_buf = vec![0u8; max_datagram];
+    loop {
+        match rxstamp::recv_from(&sock, &mut recv_buf).await {
+            Ok((len, addr, stamped_us, tos)) => {
+                let received_us = stamped_us.unwrap_or_else(owd::now_us);
+                consecutive_errors = 0;
+                // Every slot is out only while dispatch lags; wait for one back.
+                let Some(mut buf) = free_rx.recv().await else { return anyhow::anyhow!("UDP receive ring closed") };
+                buf.clear();
+                buf.extend_from_slice(&recv_buf[..len]);
+                let received = Received { buf, addr, received_us, tos, free: free_tx.clone() };
+                if filled.send(received).await.is_err() {
+                    return anyhow::anyhow!("UDP dispatch stopped");
+                }
+            }
+            Err(e) if icmp::is_peer_error(&e) => {
+                // An ICMP error for one peer surfaced here; the watcher handles it.
+                continue;
+            }
+            Err(e) => {
+                eprintln!("UDP recv_from error: {:?}", e);
+                consecutive_errors += 1;
+                if consecutive_errors >= MAX_CONSECUTIVE_ERRORS {
+                    return anyhow::Error::new(e).context("UDP socket failing persistently");
+                }
+                // small sleep to avoid busy-looping on persistent errors
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,169 @@
+// proj2-serv/src/rxstamp.rs
+// Kernel receive timestamps on the UDP plane (`--rx-timestamps`). By default
+// (`kernel`) the socket asks for SO_TIMESTAMPING software receive stamps, taken
+// as the datagram enters the stack, and OWD probes carry those instead of the
+// clock read once the receive loop gets to the datagram, which at high packet
+// rates adds scheduling and queueing delay to every sample. `hardware:<iface>`
+// also turns on NIC stamping for the interface (SIOCSHWTSTAMP, needs
+// CAP_NET_ADMIN) and prefers the NIC's stamp; it is in the NIC's clock, so keep
+// that in step with the system clock (phc2sys). With `off`, off Linux, or for
+// datagrams the kernel did not stamp, the clock is read on receipt as before.
+
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+
+use tokio::net::UdpSocket;
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum RxTimestamps {
+    Off,
+    #[default]
+    Kernel,
+    /// NIC stamps from this interface, kernel ones where it has none.
+    Hardware(String),
+}
+
+impl RxTimestamps {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.split_once(':') {
+            Some(("hardware", iface)) if !iface.is_empty() => Some(RxTimestamps::Hardware(iface.to_string())),
+            Some(_) => None,
+            None => match value {
+                "off" => Some(RxTimestamps::Off),
+                "kernel" => Some(RxTimestamps::Kernel),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl fmt::Display for RxTimestamps {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RxTimestamps::Off => f.write_str("off"),
+            RxTimestamps::Kernel => f.write_str("kernel"),
+            RxTimestamps::Hardware(iface) => write!(f, "hardware:{}", iface),
+        }
+    }
+}
+
+/// Ask the kernel to stamp datagrams received on `sock` as `mode` says. False
+/// when the mode is `off`.
+#[cfg(target_os = "linux")]
+pub fn enable(sock: &UdpSocket, mode: &RxTimestamps) -> io::Result<bool> {
+    use std::os::fd::AsRawFd;
+
+    let mut flags = libc::SOF_TIMESTAMPING_RX_SOFTWARE | libc::SOF_TIMESTAMPING_SOFTWARE;
+    match mode {
+        RxTimestamps::Off => return Ok(false),
+        RxTimestamps::Kernel => {}
+        RxTimestamps::Hardware(iface) => {
+            enable_nic(sock.as_raw_fd(), iface)?;
+            flags |= libc::SOF_TIMESTAMPING_RX_HARDWARE | libc::SOF_TIMESTAMPING_RAW_HARDWARE;
+        }
+    }
+    // SAFETY: passing a pointer to a live c_uint together with its size.
+    let rc = unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_TIMESTAMPING,
+            &flags as *const libc::c_uint as *const libc::c_void,
+            std::mem::size_of::<libc::c_uint>() as libc::socklen_t,
+        )
+    };
+    if rc != 0 { Err(io::Error::last_os_error()) } else { Ok(true) }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn enable(_sock: &UdpSocket, mode: &RxTimestamps) -> io::Result<bool> {
+    match mode {
+        RxTimestamps::Off => Ok(false),
+        _ => Err(io::Error::new(io::ErrorKind::Unsupported, "SO_TIMESTAMPING not supported on this platform")),
+    }
+}
+
+/// Have `iface` stamp every received packet.
+#[cfg(target_os = "linux")]
+fn enable_nic(fd: std::os::fd::RawFd, iface: &str) -> io::Result<()> {
+    if iface.len() >= libc::IFNAMSIZ || iface.contains('\0') {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid interface name {:?}", iface)));
+    }
+    let mut config = libc::hwtstamp_config { flags: 0, tx_type: libc::HWTSTAMP_TX_OFF as libc::c_int, rx_filter: libc::HWTSTAMP_FILTER_ALL as libc::c_int };
+    // SAFETY: ifreq is plain data; the name fits with its NUL and ifru_data
+    // points at `config`, which outlives the ioctl.
+    let rc = unsafe {
+        let mut req: libc::ifreq = std::mem::zeroed();
+        for (dst, src) in req.ifr_name.iter_mut().zip(iface.bytes()) {
+            *dst = src as libc::c_char;
+        }
+        req.ifr_ifru.ifru_data = &mut config as *mut libc::hwtstamp_config as *mut libc::c_char;
+        libc::ioctl(fd, libc::SIOCSHWTSTAMP as _, &mut req)
+    };
+    if rc != 0 {
+        let e = io::Error::last_os_error();
+        return Err(io::Error::new(e.kind(), format!("enabling hardware timestamps on {}: {}", iface, e)));
+    }
+    Ok(())
+}
+
+/// Receive one datagram with its kernel receive time in µs since the Unix
+/// epoch, when the kernel stamped it, and its TOS byte, when IP_RECVTOS is on
+/// (see ecn.rs).
+#[cfg(target_os = "linux")]
+pub async fn recv_from(sock: &UdpSocket, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, Option<i64>, Option<u8>)> {
+    use std::os::fd::AsRawFd;
+    use tokio::io::Interest;
+
+    sock.async_io(Interest::READABLE, || recvmsg_stamped(sock.as_raw_fd(), buf)).await
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn recv_from(sock: &UdpSocket, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, Option<i64>, Option<u8>)> {
+    let (len, addr) = sock.recv_from(buf).await?;
+    Ok((len, addr, None, None))
+}
+
+#[cfg(target_os = "linux")]
+fn recvmsg_stamped(fd: std::os::fd::RawFd, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, Option<i64>, Option<u8>)> {
+    #[repr(C, align(8))]
+    struct CmsgBuf([u8; 128]);
+
+    let mut control = CmsgBuf([0; 128]);
+    let mut iov = libc::iovec { iov_base: buf.as_mut_ptr() as *mut libc::c_void, iov_len: buf.len() };
+    // SAFETY: msghdr and sockaddr_storage are plain data; every pointer set
+    // below refers to a live local that outlives the recvmsg call, and control
+    // messages are read within the bounds the kernel reported.
+    unsafe {
+        let mut storage: libc::sockaddr_storage = std::mem::zeroed();
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_name = &mut storage as *mut libc::sockaddr_storage as *mut libc::c_void;
+        msg.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.0.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = control.0.len() as _;
+        let n = libc::recvmsg(fd, &mut msg, libc::MSG_DONTWAIT);
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let addr = crate::icmp::sockaddr_to_std(&storage).ok_or_else(|| io::Error::other("datagram from an unknown address family"))?;
+        let (mut stamp, mut tos) = (None, None);
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_TIMESTAMPING {
+                // [software, deprecated, raw hardware]; unset ones are zero.
+                let ts = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const [libc::timespec; 3]);
+                stamp = [ts[2], ts[0]]
+                    .into_iter()
+                    .find(|t| t.tv_sec != 0 || t.tv_nsec != 0)
+                    .map(|t| t.tv_sec * 1_000_000 + t.tv_nsec / 1_000);
+            } else if (*cmsg).cmsg_level == libc::IPPROTO_IP && (*cmsg).cmsg_type == libc::IP_TOS {
+                tos = Some(*libc::CMSG_DATA(cmsg));
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+        Ok((n as usize, addr, stamp, tos))
+    }
+}
@@ -0,0 +1,298 @@
+// proj2-serv/src/sandbox.rs
+// Hardening for servers exposed to untrusted networks. `--user`/`--group` drop
+// root privileges once the listeners are bound (they are then reused across
+// plane restarts rather than rebound). `--sandbox` (Linux) additionally:
+//   - applies a Landlock ruleset: the filesystem becomes read-only /proc and
+//     /etc (for diagnostics and name resolution), the payload, schedule and
+//     control key files, `--replay-dir`, and read-write `--file-dir`; everything else is
+//     denied;
+//   - installs a seccomp filter failing with EPERM the syscalls a compromised
+//     server could use to escalate: exec, ptrace, mounts, module loading, bpf,
+//     keyrings, reboot and the like.
+// Both run before the runtime starts, so every thread inherits them.
+
+use std::path::Path;
+
+use anyhow::{bail, Context};
+
+use crate::config::Config;
+
+/// Switch to `--user`/`--group`; the group defaults to the user's primary one.
+#[cfg(unix)]
+pub fn drop_privileges(config: &Config) -> anyhow::Result<()> {
+    let Some(user) = &config.user else { return Ok(()) };
+    let (uid, primary_gid) = lookup_user(user)?;
+    let gid = match &config.group {
+        Some(group) => lookup_group(group)?,
+        None => primary_gid,
+    };
+    // A hot-restarted successor starts as the user its predecessor dropped to.
+    // SAFETY: getuid and getgid cannot fail.
+    if uid != 0 && unsafe { (libc::getuid(), libc::getgid()) } == (uid, gid) {
+        return Ok(());
+    }
+    // SAFETY: plain syscalls on integers; called before other threads exist.
+    unsafe {
+        if libc::setgroups(1, &gid) != 0 {
+            return Err(std::io::Error::last_os_error()).context("setgroups");
+        }
+        if libc::setgid(gid) != 0 {
+            return Err(std::io::Error::last_os_error()).with_context(|| format!("setgid {}", gid));
+        }
+        if libc::setuid(uid) != 0 {
+            return Err(std::io::Error::last_os_error()).with_context(|| format!("setuid {}", uid));
+        }
+        // Regaining root must fail, or the drop did not take.
+        if uid != 0 && libc::setuid(0) == 0 {
+            bail!("privileges could be regained after dropping to uid {}", uid);
+        }
+    }
+    println!("Dropped privileges to uid {} gid {}", uid, gid);
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn drop_privileges(config: &Config) -> anyhow::Result<()> {
+    if config.user.is_some() {
+        bail!("--user is only supported on Unix");
+    }
+    Ok(())
+}
+
+/// (uid, primary gid) of a user name or numeric id.
+#[cfg(unix)]
+fn lookup_user(user: &str) -> anyhow::Result<(libc::uid_t, libc::gid_t)> {
+    let name = std::ffi::CString::new(user).context("user name contains NUL")?;
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    // SAFETY: passwd is plain data filled in by getpwnam_r, whose string
+    // pointers refer into `buf`, which outlives their (unused) lifetime here.
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let rc = unsafe { libc::getpwnam_r(name.as_ptr(), &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if rc == 0 && !result.is_null() {
+        return Ok((pwd.pw_uid, pwd.pw_gid));
+    }
+    match user.parse::<libc::uid_t>() {
+        Ok(uid) => Ok((uid, uid)),
+        Err(_) => bail!("unknown user {:?}", user),
+    }
+}
+
+#[cfg(unix)]
+fn lookup_group(group: &str) -> anyhow::Result<libc::gid_t> {
+    let name = std::ffi::CString::new(group).context("group name contains NUL")?;
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    // SAFETY: as in lookup_user.
+    let mut grp: libc::group = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let rc = unsafe { libc::getgrnam_r(name.as_ptr(), &mut grp, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if rc == 0 && !result.is_null() {
+        return Ok(grp.gr_gid);
+    }
+    group.parse().map_err(|_| anyhow::anyhow!("unknown group {:?}", group))
+}
+
+/// Apply `--sandbox`.
+#[cfg(target_os = "linux")]
+pub fn apply(config: &Config) -> anyhow::Result<()> {
+    if !config.sandbox {
+        return Ok(());
+    }
+    // Required for unprivileged Landlock and seccomp alike.
+    // SAFETY: prctl with integer arguments.
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("PR_SET_NO_NEW_PRIVS");
+    }
+    match landlock::restrict(config)? {
+        Some(abi) => println!("Landlock filesystem sandbox applied (ABI {})", abi),
+        None => eprintln!("Landlock unsupported by this kernel; filesystem not sandboxed"),
+    }
+    seccomp::install()?;
+    println!("seccomp filter installed");
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn apply(config: &Config) -> anyhow::Result<()> {
+    if config.sandbox {
+        bail!("--sandbox is only supported on Linux");
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+mod landlock {
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::ffi::OsStrExt;
+
+    use super::*;
+
+    const CREATE_RULESET_VERSION: u32 = 1;
+    const RULE_PATH_BENEATH: libc::c_int = 1;
+    const WRITE_FILE: u64 = 1 << 1;
+    const READ_FILE: u64 = 1 << 2;
+    const READ_DIR: u64 = 1 << 3;
+    const REMOVE_FILE: u64 = 1 << 5;
+    const MAKE_REG: u64 = 1 << 8;
+    /// Every right of ABI 1; later ABIs add REFER (2) and TRUNCATE (3).
+    const ABI1_ALL: u64 = (1 << 13) - 1;
+    const REFER: u64 = 1 << 13;
+    const TRUNCATE: u64 = 1 << 14;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: i32,
+    }
+
+    /// Restrict the filesystem; returns the ABI used, or None if unsupported.
+    pub fn restrict(config: &Config) -> anyhow::Result<Option<i64>> {
+        // SAFETY: the version query takes no attribute.
+        let abi = unsafe { libc::syscall(libc::SYS_landlock_create_ruleset, std::ptr::null::<RulesetAttr>(), 0, CREATE_RULESET_VERSION) };
+        if abi < 1 {
+            return Ok(None);
+        }
+        let mut handled = ABI1_ALL;
+        if abi >= 2 {
+            handled |= REFER;
+        }
+        let truncate = if abi >= 3 { TRUNCATE } else { 0 };
+        handled |= truncate;
+        let attr = RulesetAttr { handled_access_fs: handled };
+        // SAFETY: attr is a live ruleset attribute of the given size.
+        let fd = unsafe { libc::syscall(libc::SYS_landlock_create_ruleset, &attr, size_of::<RulesetAttr>(), 0) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error()).context("landlock_create_ruleset");
+        }
+        // SAFETY: the syscall returned a new descriptor we now own.
+        let ruleset = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+
+        let read_dir = READ_FILE | READ_DIR;
+        allow(&ruleset, Path::new("/proc"), read_dir)?;
+        allow(&ruleset, Path::new("/etc"), read_dir)?;
+        for file in [&config.payload_file, &config.schedule, &config.control_key, &config.result_key].into_iter().flatten() {
+            allow(&ruleset, file, READ_FILE)?;
+        }
+        for file in &config.geoip {
+            allow(&ruleset, file, READ_FILE)?;
+        }
+        if let Some(dir) = &config.replay_dir {
+            allow(&ruleset, dir, read_dir)?;
+        }
+        if let Some(dir) = &config.file_dir {
+            allow(&ruleset, dir, read_dir | WRITE_FILE | MAKE_REG | REMOVE_FILE | truncate)?;
+        }
+        if let Some(dir) = &config.packet_trace_dir {
+            allow(&ruleset, dir, read_dir | WRITE_FILE | MAKE_REG | truncate)?;
+        }
+
+        // SAFETY: ruleset is a valid Landlock ruleset descriptor.
+        if unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0) } != 0 {
+            return Err(std::io::Error::last_os_error()).context("landlock_restrict_self");
+        }
+        Ok(Some(abi))
+    }
+
+    fn allow(ruleset: &OwnedFd, path: &Path, access: u64) -> anyhow::Result<()> {
+        let c_path = std::ffi::CString::new(path.as_os_str().as_bytes()).context("path contains NUL")?;
+        // SAFETY: c_path is a valid NUL-terminated string.
+        let fd = unsafe { libc::open(c_path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error()).with_context(|| format!("opening {} for the sandbox", path.display()));
+        }
+        // SAFETY: open returned a new descriptor we now own.
+        let parent = unsafe { OwnedFd::from_raw_fd(fd) };
+        // Directory rights are invalid on a file.
+        let access = if path.is_dir() { access } else { access & (READ_FILE | WRITE_FILE | TRUNCATE) };
+        let attr = PathBeneathAttr { allowed_access: access, parent_fd: parent.as_raw_fd() };
+        // SAFETY: attr is a live path-beneath rule and ruleset a valid descriptor.
+        let rc = unsafe { libc::syscall(libc::SYS_landlock_add_rule, ruleset.as_raw_fd(), RULE_PATH_BENEATH, &attr, 0) };
+        if rc != 0 {
+            return Err(std::io::Error::last_os_error()).with_context(|| format!("landlock rule for {}", path.display()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod seccomp {
+    use super::*;
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xc000_003e;
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+    /// Syscalls refused with EPERM.
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    const DENIED: &[libc::c_long] = &[
+        libc::SYS_execve,
+        libc::SYS_execveat,
+        libc::SYS_ptrace,
+        libc::SYS_process_vm_readv,
+        libc::SYS_process_vm_writev,
+        libc::SYS_mount,
+        libc::SYS_umount2,
+        libc::SYS_pivot_root,
+        libc::SYS_chroot,
+        libc::SYS_unshare,
+        libc::SYS_setns,
+        libc::SYS_init_module,
+        libc::SYS_finit_module,
+        libc::SYS_delete_module,
+        libc::SYS_kexec_load,
+        libc::SYS_reboot,
+        libc::SYS_swapon,
+        libc::SYS_swapoff,
+        libc::SYS_bpf,
+        libc::SYS_perf_event_open,
+        libc::SYS_keyctl,
+        libc::SYS_add_key,
+        libc::SYS_request_key,
+    ];
+
+    fn stmt(code: u32, k: u32) -> libc::sock_filter {
+        libc::sock_filter { code: code as u16, jt: 0, jf: 0, k }
+    }
+
+    fn jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+        libc::sock_filter { code: code as u16, jt, jf, k }
+    }
+
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    pub fn install() -> anyhow::Result<()> {
+        use libc::{BPF_ABS, BPF_JEQ, BPF_JMP, BPF_K, BPF_LD, BPF_RET, BPF_W};
+        // Offsets of `nr` and `arch` in struct seccomp_data.
+        const NR: u32 = 0;
+        const ARCH: u32 = 4;
+        let deny = libc::SECCOMP_RET_ERRNO | libc::EPERM as u32;
+        let mut program = vec![
+            stmt(BPF_LD | BPF_W | BPF_ABS, ARCH),
+            // Another architecture's syscall numbers mean something else.
+            jump(BPF_JMP | BPF_JEQ | BPF_K, AUDIT_ARCH, 1, 0),
+            stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+            stmt(BPF_LD | BPF_W | BPF_ABS, NR),
+        ];
+        for &nr in DENIED {
+            program.push(jump(BPF_JMP | BPF_JEQ | BPF_K, nr as u32, 0, 1));
+            program.push(stmt(BPF_RET | BPF_K, deny));
+        }
+        program.push(stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_ALLOW));
+        let prog = libc::sock_fprog { len: program.len() as u16, filter: program.as_mut_ptr() };
+        // SAFETY: prog points at a live, well-formed filter for the call's duration.
+        if unsafe { libc::prctl(libc::PR_SET_SECCOMP, libc::SECCOMP_MODE_FILTER, &prog as *const libc::sock_fprog) } != 0 {
+            return Err(std::io::Error::last_os_error()).context("installing seccomp filter");
+        }
+        Ok(())
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    pub fn install() -> anyhow::Result<()> {
+        bail!("seccomp filter not available for this architecture")
+    }
+}
@@ -0,0 +1,330 @@
+// proj2-serv/src/scheduler.rs
+// Scheduled test campaigns (`--schedule <file>`): the server periodically runs
+// client-mode tests against peer servers and records their results, so it can
+// double as a continuous monitoring agent. One job per line, `#` comments:
+//   <name> every=<secs> target=<host:port> proto=tcp|udp dir=download|upload
+//          [duration=<secs>] [rate=<bits/s>]
+// `target` is the peer's TCP or UDP port according to `proto`; `rate` paces UDP
+// uploads (default 10M). Results are stored under the `scheduler` tenant with
+// the job name as their run, so `ADMIN RESULTS TENANT=scheduler` lists the
+// history and `RUN <job> TENANT=scheduler` summarises it. A failed test is
+// recorded with `aborted=error` so outages show up in the history. With
+// `--control-key`, the tests' commands are signed with it. TCP jobs against a
+// target with both IPv6 and IPv4 addresses race the two (see eyeballs.rs).
+// Every result also breaks down how the test got going, apart from its
+// throughput: `dns_ms=` for resolving the target and, for TCP, `connect_ms=`
+// for the handshake of the connection the test ran on (absent when none was
+// made). A TLS handshake time is deliberately out of scope and not provided:
+// the test protocol has no TLS and runs in the clear, so there is no handshake
+// to time, a result has no `tls_ms=`, and `connect_ms=` ends at the TCP one.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, ensure, Context};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{lookup_host, TcpStream, UdpSocket};
+use tokio::time::{timeout, MissedTickBehavior};
+
+use crate::auth::{self, ControlAuth};
+use crate::eyeballs::{self, Race};
+use crate::protocol;
+use crate::results::{Direction, Protocol, TestResult};
+use crate::state::ServerState;
+
+/// Tenant scheduled results are stored under.
+pub const SCHEDULE_TENANT: &str = "scheduler";
+const DEFAULT_DURATION: Duration = Duration::from_secs(5);
+const DEFAULT_UDP_RATE: u64 = 10_000_000;
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+const DATAGRAM_SIZE: usize = 1400;
+/// Pacing tick for UDP uploads.
+const TICK: Duration = Duration::from_millis(10);
+const KEYS: [&str; 6] = ["every", "target", "proto", "dir", "duration", "rate"];
+
+/// Time spent setting a scheduled test up, before any data moved. There is
+/// no TLS handshake field; see the header.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Setup {
+    pub dns: Duration,
+    /// TCP handshake; None for UDP or when no connection was made.
+    pub connect: Option<Duration>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub name: String,
+    pub every: Duration,
+    /// `host:port`, resolved before every run.
+    pub target: String,
+    pub protocol: Protocol,
+    pub direction: Direction,
+    pub duration: Duration,
+    /// UDP upload rate in bits per second.
+    pub rate: u64,
+}
+
+/// Read and validate a schedule file.
+pub fn load(path: &Path) -> anyhow::Result<Vec<Job>> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("reading schedule {}", path.display()))?;
+    let mut jobs = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let job = parse_job(line).with_context(|| format!("{} line {}", path.display(), i + 1))?;
+        ensure!(jobs.iter().all(|j: &Job| j.name != job.name), "{}: duplicate job {:?}", path.display(), job.name);
+        jobs.push(job);
+    }
+    Ok(jobs)
+}
+
+fn parse_job(line: &str) -> anyhow::Result<Job> {
+    let mut parts = line.split_whitespace();
+    let name = parts.next().unwrap_or_default().to_string();
+    ensure!(protocol::valid_tenant(&name), "invalid job name {:?}", name);
+    let mut opts = HashMap::new();
+    for part in parts {
+        let (key, value) = part.split_once('=').with_context(|| format!("expected key=value, got {:?}", part))?;
+        ensure!(KEYS.contains(&key), "unknown option {}=", key);
+        opts.insert(key, value);
+    }
+    let required = |key: &str| opts.get(key).copied().with_context(|| format!("missing {}=", key));
+    let secs = |key: &str, value: &str| match value.parse::<u64>() {
+        Ok(s) if s > 0 => Ok(Duration::from_secs(s)),
+        _ => bail!("invalid seconds {:?} for {}=", value, key),
+    };
+    let every = secs("every", required("every")?)?;
+    let target = required("target")?.to_string();
+    ensure!(target.rsplit_once(':').is_some_and(|(_, p)| p.parse::<u16>().is_ok()), "target {:?} lacks a port", target);
+    let protocol = match required("proto")? {
+        "tcp" => Protocol::Tcp,
+        "udp" => Protocol::Udp,
+        other => bail!("invalid proto {:?} (expected tcp|udp)", other),
+    };
+    let direction = match required("dir")? {
+        "download" => Direction::Download,
+        "upload" => Direction::Upload,
+        other => bail!("invalid dir {:?} (expected download|upload)", other),
+    };
+    let duration = opts.get("duration").map(|v| secs("duration", v)).transpose()?.unwrap_or(DEFAULT_DURATION);
+    ensure!(duration < every, "duration must be shorter than every");
+    let rate = match opts.get("rate") {
+        Some(v) => protocol::parse_byte_count(v).filter(|&r| r > 0).with_context(|| format!("invalid rate {:?}", v))?,
+        None => DEFAULT_UDP_RATE,
+    };
+    Ok(Job { name, every, target, protocol, direction, duration, rate })
+}
+
+/// Run every job on its own interval until the server stops.
+pub async fn run_schedule(jobs: Vec<Job>, state: Arc<ServerState>) {
+    let tasks: Vec<_> = jobs
+        .into_iter()
+        .map(|job| {
+            println!("Scheduled {} {} {} to {} every {:?}", job.name, job.protocol.as_str(), job.direction.as_str(), job.target, job.every);
+            tokio::spawn(run_job(job, state.clone()))
+        })
+        .collect();
+    for task in tasks {
+        let _ = task.await;
+    }
+}
+
+async fn run_job(job: Job, state: Arc<ServerState>) {
+    let mut ticker = tokio::time::interval(job.every);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        let start = Instant::now();
+        let resolved = resolve(&job.target).await;
+        let mut setup = Setup { dns: start.elapsed(), connect: None };
+        let (peer, race, outcome) = match resolved {
+            Ok(addrs) => run_test(&job, &addrs, state.control_auth.as_ref(), &mut setup).await,
+            Err(e) => (SocketAddr::from(([0, 0, 0, 0], 0)), None, Err(e)),
+        };
+        let result = match outcome {
+            Ok((bytes, duration)) => TestResult::new(SCHEDULE_TENANT, job.protocol, job.direction, peer, bytes, duration),
+            Err(e) => {
+                eprintln!("[{}] scheduled test {} against {} failed: {:#}", SCHEDULE_TENANT, job.name, job.target, e);
+                TestResult::new(SCHEDULE_TENANT, job.protocol, job.direction, peer, 0, start.elapsed()).with_aborted(Some("error"))
+            }
+        };
+        let result = result.with_run(Some(job.name.clone())).with_setup(Some(setup)).with_race(race);
+        println!("[{}] result: {}", SCHEDULE_TENANT, result.to_line());
+        // Not a session of this server, so it stays out of the serving metrics.
+        state.store(result);
+    }
+}
+
+/// Every address of `target`, in the resolver's order.
+async fn resolve(target: &str) -> anyhow::Result<Vec<SocketAddr>> {
+    let addrs: Vec<SocketAddr> = lookup_host(target).await.with_context(|| format!("resolving {}", target))?.collect();
+    ensure!(!addrs.is_empty(), "{} has no addresses", target);
+    Ok(addrs)
+}
+
+/// The peer tested, how a family race went, and the bytes moved and time
+/// taken; the connect time goes into `setup`.
+async fn run_test(
+    job: &Job,
+    addrs: &[SocketAddr],
+    auth: Option<&ControlAuth>,
+    setup: &mut Setup,
+) -> (SocketAddr, Option<Race>, anyhow::Result<(u64, Duration)>) {
+    let peer = addrs[0];
+    let (protocol, direction) = (job.protocol, job.direction);
+    if protocol == Protocol::Tcp {
+        let (stream, connect, racing) = match eyeballs::connect(addrs).await {
+            Ok(connected) => connected,
+            Err(e) => return (peer, None, Err(e)),
+        };
+        setup.connect = Some(connect);
+        let peer = stream.peer_addr().unwrap_or(peer);
+        let outcome = match direction {
+            Direction::Download => download_on(stream, job.duration, auth).await,
+            _ => upload_on(stream, job.duration, auth).await,
+        };
+        let race = match racing {
+            Some(racing) => Some(racing.settle().await),
+            None => None,
+        };
+        return (peer, race, outcome);
+    }
+    let outcome = match (protocol, direction) {
+        (Protocol::Udp, Direction::Download) => udp_download(peer, job.duration, auth).await,
+        (Protocol::Udp, _) => udp_upload(peer, job.duration, job.rate, auth).await,
+        _ => Err(anyhow::anyhow!("scheduled tests cannot use UDS")),
+    };
+    (peer, None, outcome)
+}
+
+async fn tcp_connect(peer: SocketAddr) -> anyhow::Result<TcpStream> {
+    timeout(REPLY_TIMEOUT, TcpStream::connect(peer))
+        .await
+        .context("connect timed out")?
+        .context("connect failed")
+}
+
+pub async fn tcp_download(peer: SocketAddr, duration: Duration, auth: Option<&ControlAuth>) -> anyhow::Result<(u64, Duration)> {
+    download_on(tcp_connect(peer).await?, duration, auth).await
+}
+
+async fn download_on(mut stream: TcpStream, duration: Duration, auth: Option<&ControlAuth>) -> anyhow::Result<(u64, Duration)> {
+    stream.write_all(auth::sign(auth, "START_DOWNLOAD").as_bytes()).await?;
+    let start = Instant::now();
+    let deadline = tokio::time::Instant::from_std(start + duration);
+    let mut buf = vec![0u8; 256 * 1024];
+    let mut bytes = 0u64;
+    while let Ok(read) = tokio::time::timeout_at(deadline, stream.read(&mut buf)).await {
+        match read? {
+            0 => break,
+            n => bytes += n as u64,
+        }
+    }
+    let elapsed = start.elapsed();
+    let _ = stream.write_all(b"END_DOWNLOAD").await;
+    ensure!(bytes > 0, "no download data received");
+    Ok((bytes, elapsed))
+}
+
+async fn upload_on(mut stream: TcpStream, duration: Duration, auth: Option<&ControlAuth>) -> anyhow::Result<(u64, Duration)> {
+    stream.write_all(auth::sign(auth, "START_UPLOAD").as_bytes()).await?;
+    // Keep the command out of the first data chunk.
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let chunk = vec![0u8; 64 * 1024];
+    let start = Instant::now();
+    let mut bytes = 0u64;
+    while start.elapsed() < duration {
+        stream.write_all(&chunk).await.context("upload write failed")?;
+        bytes += chunk.len() as u64;
+    }
+    let elapsed = start.elapsed();
+    stream.write_all(b"END_UPLOAD").await?;
+    Ok((bytes, elapsed))
+}
+
+pub async fn udp_connect(peer: SocketAddr) -> anyhow::Result<UdpSocket> {
+    let bind: SocketAddr = if peer.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }.parse()?;
+    let sock = UdpSocket::bind(bind).await?;
+    sock.connect(peer).await?;
+    Ok(sock)
+}
+
+/// Send `cmd` and wait for a datagram starting with `ack`, skipping others.
+async fn udp_request(sock: &UdpSocket, cmd: &str, ack: &str, buf: &mut [u8]) -> anyhow::Result<String> {
+    sock.send(cmd.as_bytes()).await?;
+    let deadline = tokio::time::Instant::now() + REPLY_TIMEOUT;
+    loop {
+        let n = tokio::time::timeout_at(deadline, sock.recv(buf)).await.with_context(|| format!("no {} reply", ack))??;
+        let reply = String::from_utf8_lossy(&buf[..n]);
+        if reply.starts_with("ERR") {
+            bail!("server replied {:?}", reply);
+        }
+        if reply.starts_with(ack) {
+            return Ok(reply.into_owned());
+        }
+    }
+}
+
+pub async fn udp_download(peer: SocketAddr, duration: Duration, auth: Option<&ControlAuth>) -> anyhow::Result<(u64, Duration)> {
+    let sock = udp_connect(peer).await?;
+    let mut buf = vec![0u8; 64 * 1024];
+    let ack = udp_request(&sock, &auth::sign(auth, "START_DOWNLOAD"), "ACK_DOWNLOAD", &mut buf).await?;
+    // Echo the return-path cookie; the server floods nothing until it does.
+    if let Some(cookie) = ack.split_whitespace().find_map(|kv| kv.strip_prefix("COOKIE=")) {
+        sock.send(format!("CONFIRM COOKIE={}", cookie).as_bytes()).await?;
+    }
+    let start = Instant::now();
+    let deadline = tokio::time::Instant::from_std(start + duration);
+    let mut bytes = 0u64;
+    while let Ok(recv) = tokio::time::timeout_at(deadline, sock.recv(&mut buf)).await {
+        let n = recv?;
+        if !buf[..n].starts_with(b"ACK_DOWNLOAD") {
+            bytes += n as u64;
+        }
+    }
+    let elapsed = start.elapsed();
+    let _ = sock.send(b"END_DOWNLOAD").await;
+    ensure!(bytes > 0, "no download datagrams received");
+    Ok((bytes, elapsed))
+}
+
+/// Upload paced at `rate`; the byte count is what the server acknowledges.
+async fn udp_upload(peer: SocketAddr, duration: Duration, rate: u64, auth: Option<&ControlAuth>) -> anyhow::Result<(u64, Duration)> {
+    let sock = udp_connect(peer).await?;
+    let mut buf = vec![0u8; 2048];
+    udp_request(&sock, &auth::sign(auth, "START_UPLOAD"), "ACK_UPLOAD", &mut buf).await?;
+    let per_tick = ((rate as f64 / 8.0 * TICK.as_secs_f64()) / DATAGRAM_SIZE as f64).ceil() as usize;
+    let payload = vec![0u8; DATAGRAM_SIZE];
+    let start = Instant::now();
+    let mut ticker = tokio::time::interval(TICK);
+    while start.elapsed() < duration {
+        ticker.tick().await;
+        for _ in 0..per_tick {
+            sock.send(&payload).await?;
+        }
+    }
+    let elapsed = start.elapsed();
+    let reply = udp_request(&sock, "END_UPLOAD", "ACK_END_UPLOAD", &mut buf).await?;
+    let bytes = reply
+        .split_whitespace()
+        .find_map(|kv| kv.strip_prefix("bytes="))
+        .and_then(|b| b.parse().ok())
+        .with_context(|| format!("no byte count in {:?}", reply))?;
+    Ok((bytes, elapsed))
+}
+
+impl fmt::Display for Setup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "dns_ms={:.2}", self.dns.as_secs_f64() * 1000.0)?;
+        if let Some(connect) = self.connect {
+            write!(f, " connect_ms={:.2}", connect.as_secs_f64() * 1000.0)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,383 @@
+// proj2-serv/src/session.rs
+// Registry of running session tasks. Each session gets a child of the server's
+// root cancellation token, so an admin kill, client disconnect or shutdown can
+// stop it deterministically. A session's live counts are atomics the data path
+// bumps without locking; STATUS, the dashboard and idle sweeps read snapshots,
+// and `ADMIN SOCKETS` the session's full-buffer and failed sends. A client
+// host runs one test per direction at a time across the TCP and UDP planes,
+// keyed by IP like the control rate limit, so a new source port or another
+// connection does not get it a second flood.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio_util::sync::CancellationToken;
+
+use crate::clock;
+use crate::results::{Direction, Protocol};
+
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    pub id: u64,
+    pub protocol: Protocol,
+    pub peer: SocketAddr,
+    pub tenant: String,
+    /// Transfer in progress; `None` while a TCP connection is idle between tests.
+    pub direction: Option<Direction>,
+    pub started: Instant,
+    /// Bytes moved by the current transfer so far.
+    pub bytes: u64,
+    /// Writes, reads or datagrams that moved them.
+    pub packets: u64,
+    /// Last counted transfer, or the start of the current one.
+    pub last_activity: Instant,
+    /// Sends that found the socket buffer full, and sends that failed, over
+    /// the whole session.
+    pub would_block: u64,
+    pub send_errors: u64,
+}
+
+struct Entry {
+    info: SessionInfo,
+    token: CancellationToken,
+    counters: Arc<Counters>,
+    window: Arc<Window>,
+    /// Started with `BIDIR=1`: may run alongside the opposite direction.
+    bidir: bool,
+    /// The command that started the transfer, and the ACK it was answered
+    /// with once that went out.
+    start: Option<(String, Option<String>)>,
+}
+
+/// Live counts of a session's current transfer.
+pub struct Counters {
+    origin: Instant,
+    bytes: AtomicU64,
+    packets: AtomicU64,
+    /// Microseconds from `origin` to the last counted transfer or reset.
+    last_us: AtomicU64,
+    /// Kept across transfers.
+    would_block: AtomicU64,
+    send_errors: AtomicU64,
+}
+
+/// The counts at one moment.
+#[derive(Debug, Clone, Copy)]
+pub struct CounterSnapshot {
+    pub bytes: u64,
+    pub packets: u64,
+    pub last_activity: Instant,
+    pub would_block: u64,
+    pub send_errors: u64,
+}
+
+impl Counters {
+    fn new() -> Self {
+        Counters {
+            origin: clock::now(),
+            bytes: AtomicU64::new(0),
+            packets: AtomicU64::new(0),
+            last_us: AtomicU64::new(0),
+            would_block: AtomicU64::new(0),
+            send_errors: AtomicU64::new(0),
+        }
+    }
+
+    fn touch(&self) {
+        self.last_us.store(clock::elapsed(self.origin).as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn reset(&self) {
+        self.bytes.store(0, Ordering::Relaxed);
+        self.packets.store(0, Ordering::Relaxed);
+        self.touch();
+    }
+
+    fn add(&self, n: u64) {
+        self.bytes.fetch_add(n, Ordering::Relaxed);
+        self.packets.fetch_add(1, Ordering::Relaxed);
+        self.touch();
+    }
+
+    /// Count a send that found the socket buffer full.
+    pub fn would_block(&self) {
+        self.would_block.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a send the kernel refused.
+    pub fn send_error(&self) {
+        self.send_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> CounterSnapshot {
+        CounterSnapshot {
+            bytes: self.bytes.load(Ordering::Relaxed),
+            packets: self.packets.load(Ordering::Relaxed),
+            last_activity: self.origin + Duration::from_micros(self.last_us.load(Ordering::Relaxed)),
+            would_block: self.would_block.load(Ordering::Relaxed),
+            send_errors: self.send_errors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The window of a session's running test, which `EXTEND` can lengthen.
+#[derive(Default)]
+pub struct Window {
+    base_ms: AtomicU64,
+    extra_ms: AtomicU64,
+}
+
+impl Window {
+    /// Open a test's window of `base`, dropping any earlier extension.
+    pub fn open(&self, base: Duration) {
+        self.base_ms.store(base.as_millis() as u64, Ordering::Relaxed);
+        self.extra_ms.store(0, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> Duration {
+        Duration::from_millis(self.base_ms.load(Ordering::Relaxed) + self.extra_ms.load(Ordering::Relaxed))
+    }
+
+    /// Lengthen the window by `by`, to at most `max_total`; the new length, or
+    /// None if no test is open or it is already that long.
+    pub fn extend(&self, by: Duration, max_total: Duration) -> Option<Duration> {
+        let base = self.base_ms.load(Ordering::Relaxed);
+        if base == 0 {
+            return None;
+        }
+        let room = (max_total.as_millis() as u64).saturating_sub(base);
+        let by = by.as_millis() as u64;
+        self.extra_ms
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |extra| Some(extra.saturating_add(by).min(room)).filter(|&e| e > extra))
+            .ok()?;
+        Some(self.get())
+    }
+}
+
+#[derive(Default)]
+pub struct SessionRegistry {
+    next_id: AtomicU64,
+    sessions: Mutex<BTreeMap<u64, Entry>>,
+    root: CancellationToken,
+}
+
+/// Removes the session from the registry when the task owning it ends.
+pub struct SessionGuard {
+    id: u64,
+    registry: Arc<SessionRegistry>,
+    token: CancellationToken,
+    counters: Arc<Counters>,
+    window: Arc<Window>,
+}
+
+impl SessionGuard {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    pub fn window(&self) -> &Arc<Window> {
+        &self.window
+    }
+
+    /// Mark the start of a transfer; resets the live counters.
+    pub fn begin(&self, tenant: &str, direction: Direction) {
+        self.counters.reset();
+        if let Some(entry) = self.registry.sessions.lock().unwrap().get_mut(&self.id) {
+            entry.info.tenant = tenant.to_string();
+            entry.info.direction = Some(direction);
+            entry.info.started = clock::now();
+        }
+    }
+
+    /// Let the transfer run alongside one in the opposite direction that also
+    /// allows it, for `BIDIR=1`.
+    pub fn allow_bidir(&self) {
+        if let Some(entry) = self.registry.sessions.lock().unwrap().get_mut(&self.id) {
+            entry.bidir = true;
+        }
+    }
+
+    /// Record the command that started the transfer, so a retransmission of it
+    /// can be told from a new start.
+    pub fn started_by(&self, command: &str) {
+        if let Some(entry) = self.registry.sessions.lock().unwrap().get_mut(&self.id) {
+            entry.start = Some((command.to_string(), None));
+        }
+    }
+
+    /// Record the ACK the starting command was answered with.
+    pub fn acked(&self, ack: &str) {
+        if let Some(entry) = self.registry.sessions.lock().unwrap().get_mut(&self.id)
+            && let Some((_, acked)) = &mut entry.start
+        {
+            *acked = Some(ack.to_string());
+        }
+    }
+
+    /// Mark the end of a transfer; the session stays registered (idle).
+    pub fn end(&self) {
+        if let Some(entry) = self.registry.sessions.lock().unwrap().get_mut(&self.id) {
+            entry.info.direction = None;
+        }
+    }
+
+    /// Count `n` bytes moved by one write, read or datagram.
+    pub fn add_bytes(&self, n: u64) {
+        self.counters.add(n);
+    }
+
+    /// Count activity that moved no bytes, such as a resumption.
+    pub fn touch(&self) {
+        self.counters.touch();
+    }
+
+    pub fn counters(&self) -> CounterSnapshot {
+        self.counters.snapshot()
+    }
+
+    /// The live counts, for a sender working on the session's behalf.
+    pub fn shared_counters(&self) -> Arc<Counters> {
+        self.counters.clone()
+    }
+
+    pub fn would_block(&self) {
+        self.counters.would_block();
+    }
+
+    pub fn send_error(&self) {
+        self.counters.send_error();
+    }
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.registry.sessions.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl SessionRegistry {
+    pub fn register(
+        self: &Arc<Self>,
+        protocol: Protocol,
+        peer: SocketAddr,
+        tenant: &str,
+        direction: Option<Direction>,
+    ) -> SessionGuard {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let token = self.root.child_token();
+        let counters = Arc::new(Counters::new());
+        let window = Arc::new(Window::default());
+        let info = SessionInfo {
+            id,
+            protocol,
+            peer,
+            tenant: tenant.to_string(),
+            direction,
+            started: clock::now(),
+            bytes: 0,
+            packets: 0,
+            last_activity: clock::now(),
+            would_block: 0,
+            send_errors: 0,
+        };
+        self.sessions.lock().unwrap().insert(id, Entry { info, token: token.clone(), counters: counters.clone(), window: window.clone(), bidir: false, start: None });
+        SessionGuard { id, registry: self.clone(), token, counters, window }
+    }
+
+    /// Cancel one session; returns false if it no longer exists.
+    pub fn kill(&self, id: u64) -> bool {
+        match self.sessions.lock().unwrap().get(&id) {
+            Some(entry) => {
+                entry.token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Cancel every session, present and future.
+    pub fn shutdown(&self) {
+        self.root.cancel();
+    }
+
+    pub fn list(&self) -> Vec<SessionInfo> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .values()
+            .map(|e| {
+                let counts = e.counters.snapshot();
+                SessionInfo {
+                    bytes: counts.bytes,
+                    packets: counts.packets,
+                    last_activity: counts.last_activity,
+                    would_block: counts.would_block,
+                    send_errors: counts.send_errors,
+                    ..e.info.clone()
+                }
+            })
+            .collect()
+    }
+
+    /// The direction of a running transfer that a new one from `peer` in
+    /// `direction` would clash with: one in the same direction from the same
+    /// host, whatever port or connection it uses, and one in another direction
+    /// from the same address unless both are an upload and a download that
+    /// allow `BIDIR=1`.
+    pub fn conflict(&self, peer: SocketAddr, direction: Direction, bidir: bool) -> Option<Direction> {
+        let sessions = self.sessions.lock().unwrap();
+        let mut running = sessions
+            .values()
+            .filter(|e| e.info.peer.ip() == peer.ip())
+            .filter_map(|e| Some((e.info.peer, e.info.direction?, e.bidir)));
+        running
+            .find(|&(other_peer, other, other_bidir)| {
+                let opposite = matches!((direction, other), (Direction::Upload, Direction::Download) | (Direction::Download, Direction::Upload));
+                other == direction || (other_peer == peer && !(opposite && bidir && other_bidir))
+            })
+            .map(|(_, other, _)| other)
+    }
+
+    /// Whether `command` repeats the one that started `peer`'s running transfer
+    /// in `direction`: Some with the ACK to resend, or with None while the ACK
+    /// has yet to go out.
+    pub fn repeated(&self, peer: SocketAddr, direction: Direction, command: &str) -> Option<Option<String>> {
+        let sessions = self.sessions.lock().unwrap();
+        sessions
+            .values()
+            .filter(|e| e.info.peer == peer && e.info.direction == Some(direction))
+            .find_map(|e| e.start.as_ref().filter(|(start, _)| start == command).map(|(_, ack)| ack.clone()))
+    }
+
+    /// Windows of `peer`'s running `protocol` transfers, for `EXTEND`.
+    pub fn windows(&self, protocol: Protocol, peer: SocketAddr) -> Vec<Arc<Window>> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|e| e.info.protocol == protocol && e.info.peer == peer && e.info.direction.is_some())
+            .map(|e| e.window.clone())
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.lock().unwrap().is_empty()
+    }
+}
+
+/// Direction of the test a verb starts, if it starts one.
+pub fn starts_test(verb: &str) -> Option<Direction> {
+    match verb {
+        "START_DOWNLOAD" => Some(Direction::Download),
+        "START_UPLOAD" => Some(Direction::Upload),
+        "START_ECHO" => Some(Direction::Echo),
+        _ => None,
+    }
+}
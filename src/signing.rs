@@ -0,0 +1,63 @@
+// proj2-serv/src/signing.rs
+// Signed results (`--result-key <file>`). With an Ed25519 key, every recorded
+// result line ends in ` sig=<hex signature>` over the line before it, so a
+// leaderboard or reporting service the result is submitted to can tell it came
+// from this server unaltered. The key file holds the 32-byte secret seed in
+// hex (trailing newline stripped); the server logs the public key at startup
+// and hands it out with `RESULT_KEY` on every plane, replying
+// `RESULT_KEY ed25519 <hex public key>`. Exports carry the signature as their
+// `sig` field; the signed text is the result line without it, which is how
+// `proj2-serv collector` checks them.
+
+use std::path::Path;
+
+use anyhow::{bail, Context};
+use ed25519_dalek::{Signer, SigningKey};
+
+use crate::protocol::{error_frame, ErrorCode};
+
+pub struct ResultSigner {
+    key: SigningKey,
+}
+
+impl ResultSigner {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("reading result key {}", path.display()))?;
+        let Some(seed) = from_hex(text.trim()) else {
+            bail!("result key {} must be a 32-byte Ed25519 seed in hex", path.display());
+        };
+        Ok(ResultSigner { key: SigningKey::from_bytes(&seed) })
+    }
+
+    /// Hex signature of `line`.
+    pub fn sign(&self, line: &str) -> String {
+        to_hex(&self.key.sign(line.as_bytes()).to_bytes())
+    }
+
+    pub fn public_key(&self) -> String {
+        to_hex(self.key.verifying_key().as_bytes())
+    }
+}
+
+/// Reply to `RESULT_KEY`, without a line terminator.
+pub fn reply(signer: Option<&ResultSigner>) -> String {
+    match signer {
+        Some(signer) => format!("RESULT_KEY ed25519 {}", signer.public_key()),
+        None => error_frame(ErrorCode::NotFound, "results are not signed (no --result-key)"),
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<[u8; 32]> {
+    if s.len() != 64 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut out = [0u8; 32];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(out)
+}
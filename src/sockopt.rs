@@ -0,0 +1,603 @@
+// proj2-serv/src/sockopt.rs
+// Per-session socket options requested by clients via command options,
+// kernel buffer sizing with read-back of what was actually granted, and
+// interface pinning (`--bind-device`) for multi-homed servers.
+
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use socket2::{SockRef, TcpKeepalive};
+use tokio::net::{TcpStream, UdpSocket};
+
+/// Enable keepalive probes after `idle`, so half-open connections are detected.
+pub fn set_tcp_keepalive(stream: &TcpStream, idle: Duration) -> io::Result<()> {
+    let params = TcpKeepalive::new().with_time(idle);
+    #[cfg(any(target_os = "linux", target_os = "macos", target_os = "freebsd", windows))]
+    let params = params.with_interval(Duration::from_secs(10));
+    #[cfg(any(target_os = "linux", target_os = "macos", target_os = "freebsd"))]
+    let params = params.with_retries(3);
+    SockRef::from(stream).set_tcp_keepalive(&params)
+}
+
+/// Switch the connection to the named congestion control algorithm (`CCA=bbr`).
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+pub fn set_tcp_congestion(stream: &TcpStream, name: &str) -> io::Result<()> {
+    SockRef::from(stream).set_tcp_congestion(name.as_bytes())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "freebsd")))]
+pub fn set_tcp_congestion(_stream: &TcpStream, _name: &str) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "TCP_CONGESTION not supported on this platform"))
+}
+
+/// Congestion control algorithm currently in effect on the connection.
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+pub fn tcp_congestion(stream: &TcpStream) -> Option<String> {
+    let name = SockRef::from(stream).tcp_congestion().ok()?;
+    let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    Some(String::from_utf8_lossy(&name[..end]).into_owned())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "freebsd")))]
+pub fn tcp_congestion(_stream: &TcpStream) -> Option<String> {
+    None
+}
+
+/// Cap the rate the kernel sends on `sock` at, in bytes per second
+/// (SO_MAX_PACING_RATE); None lifts the cap. TCP paces itself to it, UDP only
+/// under the fq qdisc.
+#[cfg(target_os = "linux")]
+pub fn set_max_pacing_rate(sock: &SockRef<'_>, bytes_per_sec: Option<u64>) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    // The kernel takes a u64 rate, or a u32 on older kernels where ~0 is no cap.
+    let (wide, narrow) = match bytes_per_sec {
+        Some(rate) => (rate, u32::try_from(rate).ok()),
+        None => (u64::MAX, Some(u32::MAX)),
+    };
+    // SAFETY: the option value points at a live integer of the size passed.
+    let rc = unsafe {
+        match narrow {
+            Some(rate) => libc::setsockopt(
+                sock.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_MAX_PACING_RATE,
+                &rate as *const u32 as *const libc::c_void,
+                std::mem::size_of::<u32>() as libc::socklen_t,
+            ),
+            None => libc::setsockopt(
+                sock.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_MAX_PACING_RATE,
+                &wide as *const u64 as *const libc::c_void,
+                std::mem::size_of::<u64>() as libc::socklen_t,
+            ),
+        }
+    };
+    if rc == 0 { Ok(()) } else { Err(io::Error::last_os_error()) }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_max_pacing_rate(_sock: &SockRef<'_>, _bytes_per_sec: Option<u64>) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "SO_MAX_PACING_RATE not supported on this platform"))
+}
+
+/// Parse a DSCP given by name (`EF`, `AF41`, `CS5`, `BE`) or number (0-63).
+pub fn parse_dscp(value: &str) -> Option<u8> {
+    let v = value.to_ascii_uppercase();
+    let dscp = match v.as_str() {
+        "BE" | "DF" | "CS0" => 0,
+        "EF" => 46,
+        "VA" => 44,
+        "LE" => 1,
+        _ => {
+            if let Some(class) = v.strip_prefix("CS") {
+                match class.parse::<u8>() {
+                    Ok(c @ 1..=7) => c << 3,
+                    _ => return None,
+                }
+            } else if let Some(af) = v.strip_prefix("AF") {
+                let bytes = af.as_bytes();
+                if bytes.len() != 2 {
+                    return None;
+                }
+                let (class, drop) = (bytes[0].wrapping_sub(b'0'), bytes[1].wrapping_sub(b'0'));
+                if !(1..=4).contains(&class) || !(1..=3).contains(&drop) {
+                    return None;
+                }
+                (class << 3) | (drop << 1)
+            } else {
+                match v.parse::<u8>() {
+                    Ok(n) if n < 64 => n,
+                    _ => return None,
+                }
+            }
+        }
+    };
+    Some(dscp)
+}
+
+/// Mark all traffic sent on the connection with `dscp` (IP_TOS / IPV6_TCLASS).
+pub fn set_tcp_dscp(stream: &TcpStream, peer: SocketAddr, dscp: u8) -> io::Result<()> {
+    set_dscp(SockRef::from(stream), peer, dscp)
+}
+
+/// Mark everything sent on a per-session UDP socket with the TOS byte `tos`,
+/// DSCP and ECN bits both (see ecn.rs); TCP keeps ECN to itself.
+pub fn set_udp_tos(sock: &UdpSocket, peer: SocketAddr, tos: u8) -> io::Result<()> {
+    set_tos_byte(SockRef::from(sock), peer, u32::from(tos))
+}
+
+fn set_dscp(sock: SockRef<'_>, peer: SocketAddr, dscp: u8) -> io::Result<()> {
+    set_tos_byte(sock, peer, u32::from(dscp) << 2)
+}
+
+fn set_tos_byte(sock: SockRef<'_>, peer: SocketAddr, tos: u32) -> io::Result<()> {
+    if peer.is_ipv4() {
+        sock.set_tos_v4(tos)
+    } else {
+        set_tclass(&sock, tos)
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "freebsd"))]
+fn set_tclass(sock: &SockRef<'_>, tclass: u32) -> io::Result<()> {
+    sock.set_tclass_v6(tclass)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "freebsd")))]
+fn set_tclass(_sock: &SockRef<'_>, _tclass: u32) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "IPV6_TCLASS not supported on this platform"))
+}
+
+/// SO_PRIORITY and SO_MARK for a session's sockets (`PRIORITY=`, `MARK=`, or
+/// `--socket-priority` and `--socket-mark` for all of them), so tc filters and
+/// fwmark rules on the host can pick test traffic out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Steering {
+    pub priority: Option<u32>,
+    pub mark: Option<u32>,
+}
+
+/// Highest SO_PRIORITY a process may set without CAP_NET_ADMIN, and so the
+/// highest a client may ask for.
+pub const MAX_CLIENT_PRIORITY: u32 = 6;
+
+impl Steering {
+    pub fn is_set(&self) -> bool {
+        self.priority.is_some() || self.mark.is_some()
+    }
+
+    /// These settings, falling back to `default` for any not given.
+    pub fn or(self, default: Steering) -> Steering {
+        Steering { priority: self.priority.or(default.priority), mark: self.mark.or(default.mark) }
+    }
+
+    pub fn apply(&self, sock: SockRef<'_>) -> io::Result<()> {
+        if let Some(priority) = self.priority {
+            set_priority(&sock, priority)?;
+        }
+        if let Some(mark) = self.mark {
+            set_mark(&sock, mark)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Steering {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut sep = "";
+        if let Some(priority) = self.priority {
+            write!(f, "priority={}", priority)?;
+            sep = " ";
+        }
+        if let Some(mark) = self.mark {
+            write!(f, "{}mark={}", sep, mark)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse a `PRIORITY=` value a client may set: 0 to `MAX_CLIENT_PRIORITY`.
+pub fn parse_priority(value: &str) -> Option<u32> {
+    value.parse().ok().filter(|&p| p <= MAX_CLIENT_PRIORITY)
+}
+
+/// Parse a firewall mark, in decimal or as `0x` hex.
+pub fn parse_mark(value: &str) -> Option<u32> {
+    match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn set_priority(sock: &SockRef<'_>, priority: u32) -> io::Result<()> {
+    sock.set_priority(priority)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn set_priority(_sock: &SockRef<'_>, _priority: u32) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "SO_PRIORITY not supported on this platform"))
+}
+
+/// Needs CAP_NET_ADMIN.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn set_mark(sock: &SockRef<'_>, mark: u32) -> io::Result<()> {
+    sock.set_mark(mark)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn set_mark(_sock: &SockRef<'_>, _mark: u32) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "SO_MARK not supported on this platform"))
+}
+
+/// Whether per-datagram TOS marking on the shared UDP socket is available.
+pub const UDP_TOS_SUPPORTED: bool = cfg!(target_os = "linux");
+
+/// Send one datagram carrying `tos` in an IP_TOS / IPV6_TCLASS control message, so
+/// sessions on the shared UDP socket can be marked independently.
+#[cfg(target_os = "linux")]
+pub async fn send_to_with_tos(sock: &UdpSocket, buf: &[u8], dest: SocketAddr, tos: u8) -> io::Result<usize> {
+    use std::os::fd::AsRawFd;
+    use tokio::io::Interest;
+
+    sock.async_io(Interest::WRITABLE, || sendmsg_tos(sock.as_raw_fd(), buf, dest, tos)).await
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn send_to_with_tos(sock: &UdpSocket, buf: &[u8], dest: SocketAddr, _tos: u8) -> io::Result<usize> {
+    sock.send_to(buf, dest).await
+}
+
+/// Non-blocking counterpart of `send_to_with_tos`: WouldBlock when the send
+/// buffer is full, clearing the socket's write readiness.
+#[cfg(target_os = "linux")]
+pub fn try_send_to_with_tos(sock: &UdpSocket, buf: &[u8], dest: SocketAddr, tos: u8) -> io::Result<usize> {
+    use std::os::fd::AsRawFd;
+    use tokio::io::Interest;
+
+    sock.try_io(Interest::WRITABLE, || sendmsg_tos(sock.as_raw_fd(), buf, dest, tos))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn try_send_to_with_tos(sock: &UdpSocket, buf: &[u8], dest: SocketAddr, _tos: u8) -> io::Result<usize> {
+    sock.try_send_to(buf, dest)
+}
+
+/// Blocking counterpart of `send_to_with_tos`, for the dedicated sender thread.
+#[cfg(target_os = "linux")]
+pub fn send_to_with_tos_std(sock: &std::net::UdpSocket, buf: &[u8], dest: SocketAddr, tos: u8) -> io::Result<usize> {
+    use std::os::fd::AsRawFd;
+
+    sendmsg_tos(sock.as_raw_fd(), buf, dest, tos)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn send_to_with_tos_std(sock: &std::net::UdpSocket, buf: &[u8], dest: SocketAddr, _tos: u8) -> io::Result<usize> {
+    sock.send_to(buf, dest)
+}
+
+/// One datagram of a batch: payload, destination and optional TOS byte.
+pub type Outgoing<'a> = (&'a [u8], SocketAddr, Option<u8>);
+
+/// Send as much of `batch` as fits in the send buffer with one `sendmmsg`;
+/// the number sent, or WouldBlock (clearing write readiness) if none fit.
+#[cfg(target_os = "linux")]
+pub fn try_sendmmsg(sock: &UdpSocket, batch: &[Outgoing<'_>]) -> io::Result<usize> {
+    use std::os::fd::AsRawFd;
+    use tokio::io::Interest;
+
+    sock.try_io(Interest::WRITABLE, || sendmmsg(sock.as_raw_fd(), batch))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn try_sendmmsg(sock: &UdpSocket, batch: &[Outgoing<'_>]) -> io::Result<usize> {
+    for (i, &(buf, dest, _)) in batch.iter().enumerate() {
+        match sock.try_send_to(buf, dest) {
+            Err(e) if i > 0 && e.kind() == io::ErrorKind::WouldBlock => return Ok(i),
+            Err(e) => return Err(e),
+            Ok(_) => {}
+        }
+    }
+    Ok(batch.len())
+}
+
+#[cfg(target_os = "linux")]
+#[repr(C, align(8))]
+struct CmsgBuf([u8; 32]);
+
+/// Attach an IP_TOS (or IPV6_TCLASS) control message to `msg`.
+///
+/// # Safety
+/// `control` must outlive every use of `msg`.
+#[cfg(target_os = "linux")]
+unsafe fn set_tos(msg: &mut libc::msghdr, control: &mut CmsgBuf, dest: SocketAddr, tos: u8) {
+    let value_len = std::mem::size_of::<libc::c_int>() as u32;
+    msg.msg_control = control.0.as_mut_ptr() as *mut libc::c_void;
+    // SAFETY: the control buffer is large enough and aligned for a single
+    // int-sized cmsg, so CMSG_FIRSTHDR is non-null and in bounds.
+    unsafe {
+        msg.msg_controllen = libc::CMSG_SPACE(value_len) as _;
+        let cmsg = libc::CMSG_FIRSTHDR(msg);
+        if dest.is_ipv4() {
+            (*cmsg).cmsg_level = libc::IPPROTO_IP;
+            (*cmsg).cmsg_type = libc::IP_TOS;
+        } else {
+            (*cmsg).cmsg_level = libc::IPPROTO_IPV6;
+            (*cmsg).cmsg_type = libc::IPV6_TCLASS;
+        }
+        (*cmsg).cmsg_len = libc::CMSG_LEN(value_len) as _;
+        std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut libc::c_int, libc::c_int::from(tos));
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn sendmsg_tos(fd: std::os::fd::RawFd, buf: &[u8], dest: SocketAddr, tos: u8) -> io::Result<usize> {
+    let addr = socket2::SockAddr::from(dest);
+    let mut iov = libc::iovec { iov_base: buf.as_ptr() as *mut libc::c_void, iov_len: buf.len() };
+    let mut control = CmsgBuf([0; 32]);
+    // SAFETY: msghdr is plain data; every pointer stored in it refers to locals that
+    // outlive the sendmsg call.
+    let n = unsafe {
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_name = addr.as_ptr() as *mut libc::c_void;
+        msg.msg_namelen = addr.len();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        set_tos(&mut msg, &mut control, dest, tos);
+        libc::sendmsg(fd, &msg, 0)
+    };
+    if n < 0 { Err(io::Error::last_os_error()) } else { Ok(n as usize) }
+}
+
+#[cfg(target_os = "linux")]
+fn sendmmsg(fd: std::os::fd::RawFd, batch: &[Outgoing<'_>]) -> io::Result<usize> {
+    let addrs: Vec<socket2::SockAddr> = batch.iter().map(|&(_, dest, _)| socket2::SockAddr::from(dest)).collect();
+    let mut iovs: Vec<libc::iovec> =
+        batch.iter().map(|&(buf, _, _)| libc::iovec { iov_base: buf.as_ptr() as *mut libc::c_void, iov_len: buf.len() }).collect();
+    let mut controls: Vec<CmsgBuf> = batch.iter().map(|_| CmsgBuf([0; 32])).collect();
+    // SAFETY: mmsghdr is plain data; every pointer stored in it refers to the
+    // vectors above, which are neither moved nor resized until sendmmsg returns.
+    let n = unsafe {
+        let mut msgs: Vec<libc::mmsghdr> = (0..batch.len()).map(|_| std::mem::zeroed()).collect();
+        for (i, msg) in msgs.iter_mut().enumerate() {
+            let hdr = &mut msg.msg_hdr;
+            hdr.msg_name = addrs[i].as_ptr() as *mut libc::c_void;
+            hdr.msg_namelen = addrs[i].len();
+            hdr.msg_iov = &mut iovs[i];
+            hdr.msg_iovlen = 1;
+            if let (_, dest, Some(tos)) = batch[i] {
+                set_tos(hdr, &mut controls[i], dest, tos);
+            }
+        }
+        libc::sendmmsg(fd, msgs.as_mut_ptr(), msgs.len() as libc::c_uint, 0)
+    };
+    if n < 0 { Err(io::Error::last_os_error()) } else { Ok(n as usize) }
+}
+
+/// Datagrams the kernel has dropped on `sock` since it was created, mostly
+/// receive-buffer overflows: the `drops` column of its /proc/net/udp{,6} row.
+#[cfg(target_os = "linux")]
+pub fn udp_drops(sock: &UdpSocket) -> Option<u64> {
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: fstat only fills the zeroed struct we pass.
+    let inode = unsafe {
+        let mut st: libc::stat = std::mem::zeroed();
+        if libc::fstat(sock.as_raw_fd(), &mut st) != 0 {
+            return None;
+        }
+        st.st_ino.to_string()
+    };
+    ["/proc/net/udp", "/proc/net/udp6"].iter().find_map(|path| {
+        let table = std::fs::read_to_string(path).ok()?;
+        table.lines().skip(1).find_map(|row| {
+            let cols: Vec<&str> = row.split_whitespace().collect();
+            (cols.get(9) == Some(&inode.as_str())).then(|| cols.last()?.parse().ok())?
+        })
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn udp_drops(_sock: &UdpSocket) -> Option<u64> {
+    None
+}
+
+/// MTU of the route to `dest` (IP_MTU of a socket connected there), pinned to
+/// `device` if given; the most an unfragmented datagram to it can carry, with
+/// its headers.
+#[cfg(target_os = "linux")]
+pub fn path_mtu(dest: SocketAddr, device: Option<&str>) -> Option<usize> {
+    use std::os::fd::AsRawFd;
+
+    let sock = socket2::Socket::new(socket2::Domain::for_address(dest), socket2::Type::DGRAM, None).ok()?;
+    if let Some(device) = device {
+        bind_device(&sock, device).ok()?;
+    }
+    sock.connect(&dest.into()).ok()?;
+    let (level, name) = match dest {
+        SocketAddr::V4(_) => (libc::IPPROTO_IP, libc::IP_MTU),
+        SocketAddr::V6(_) => (libc::IPPROTO_IPV6, libc::IPV6_MTU),
+    };
+    let mut mtu: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: getsockopt writes at most `len` bytes into the int we pass.
+    let rc = unsafe { libc::getsockopt(sock.as_raw_fd(), level, name, &mut mtu as *mut libc::c_int as *mut libc::c_void, &mut len) };
+    (rc == 0 && mtu > 0).then_some(mtu as usize)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn path_mtu(_dest: SocketAddr, _device: Option<&str>) -> Option<usize> {
+    None
+}
+
+/// IP packets a UDP datagram of `payload` bytes to `dest` takes on a path of
+/// `mtu`: 1 when it fits, else the fragments it is cut into.
+pub fn fragments(payload: usize, dest: SocketAddr, mtu: usize) -> usize {
+    const UDP_HEADER: usize = 8;
+    // IPv6 fragments carry an extension header of 8 bytes on top of the fixed 40.
+    let (header, frag_header) = match dest {
+        SocketAddr::V4(_) => (20, 0),
+        SocketAddr::V6(_) => (40, 8),
+    };
+    let datagram = payload + UDP_HEADER;
+    if header + datagram <= mtu {
+        return 1;
+    }
+    // Every fragment but the last carries a multiple of 8 bytes.
+    let per_fragment = (mtu.saturating_sub(header + frag_header) & !7).max(8);
+    datagram.div_ceil(per_fragment)
+}
+
+/// Pin `sock` to `iface` (SO_BINDTODEVICE), so it only sends and receives there.
+#[cfg(target_os = "linux")]
+pub fn bind_device(sock: &socket2::Socket, iface: &str) -> io::Result<()> {
+    sock.bind_device(Some(iface.as_bytes()))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn bind_device(_sock: &socket2::Socket, _iface: &str) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "SO_BINDTODEVICE not supported on this platform"))
+}
+
+/// Name of the interface that holds `ip`.
+#[cfg(unix)]
+pub fn interface_of(ip: IpAddr) -> Option<String> {
+    let mut head: *mut libc::ifaddrs = std::ptr::null_mut();
+    // SAFETY: getifaddrs fills `head` with a list we walk read-only and free once.
+    unsafe {
+        if libc::getifaddrs(&mut head) != 0 {
+            return None;
+        }
+        let mut found = None;
+        let mut cur = head;
+        while !cur.is_null() && found.is_none() {
+            let ifa = &*cur;
+            let addr = ifa.ifa_addr;
+            let matches = !addr.is_null()
+                && match (i32::from((*addr).sa_family), ip) {
+                    (libc::AF_INET, IpAddr::V4(v4)) => {
+                        let sin = &*(addr as *const libc::sockaddr_in);
+                        u32::from_be(sin.sin_addr.s_addr) == u32::from(v4)
+                    }
+                    (libc::AF_INET6, IpAddr::V6(v6)) => {
+                        let sin6 = &*(addr as *const libc::sockaddr_in6);
+                        sin6.sin6_addr.s6_addr == v6.octets()
+                    }
+                    _ => false,
+                };
+            if matches {
+                found = Some(std::ffi::CStr::from_ptr(ifa.ifa_name).to_string_lossy().into_owned());
+            }
+            cur = ifa.ifa_next;
+        }
+        libc::freeifaddrs(head);
+        found
+    }
+}
+
+#[cfg(not(unix))]
+pub fn interface_of(_ip: IpAddr) -> Option<String> {
+    None
+}
+
+/// Interface a test's traffic used: the `--bind-device` one, or the one holding
+/// the socket's local address. Unknown for the shared wildcard-bound socket.
+pub fn test_interface(local: io::Result<SocketAddr>, device: Option<&str>) -> Option<String> {
+    match device {
+        Some(device) => Some(device.to_string()),
+        None => local.ok().map(|a| a.ip()).filter(|ip| !ip.is_unspecified()).and_then(interface_of),
+    }
+}
+
+/// Kernel socket buffer sizes as reported by SO_RCVBUF/SO_SNDBUF. Linux reports
+/// twice the usable size (the rest is bookkeeping) and caps requests at
+/// net.core.rmem_max/wmem_max.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferSizes {
+    pub recv: usize,
+    pub send: usize,
+}
+
+impl BufferSizes {
+    pub fn of(sock: SockRef<'_>) -> io::Result<Self> {
+        Ok(BufferSizes { recv: sock.recv_buffer_size()?, send: sock.send_buffer_size()? })
+    }
+
+    /// Bytes actually available for data out of a reported size.
+    pub fn usable(reported: usize) -> usize {
+        const REPORTED_PER_USABLE: usize = if cfg!(any(target_os = "linux", target_os = "android")) { 2 } else { 1 };
+        reported / REPORTED_PER_USABLE
+    }
+
+    /// Whether the kernel granted less than `want` usable bytes in either direction.
+    pub fn short_of(&self, want: usize) -> bool {
+        Self::usable(self.recv) < want || Self::usable(self.send) < want
+    }
+}
+
+impl fmt::Display for BufferSizes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rcvbuf={} sndbuf={}", self.recv, self.send)
+    }
+}
+
+/// Ask for `want` bytes of receive and send buffer. Where the sysctl caps clamp
+/// the request, retry with SO_RCVBUFFORCE/SO_SNDBUFFORCE, which bypass them when
+/// the process has CAP_NET_ADMIN. Returns the sizes the kernel settled on.
+pub fn tune_buffers(sock: &socket2::Socket, want: usize) -> io::Result<BufferSizes> {
+    if let Err(e) = sock.set_recv_buffer_size(want) {
+        eprintln!("Cannot set receive buffer to {} bytes: {}", want, e);
+    }
+    if let Err(e) = sock.set_send_buffer_size(want) {
+        eprintln!("Cannot set send buffer to {} bytes: {}", want, e);
+    }
+    let sizes = BufferSizes::of(SockRef::from(sock))?;
+    if !sizes.short_of(want) {
+        return Ok(sizes);
+    }
+    if BufferSizes::usable(sizes.recv) < want {
+        let _ = force_buffer(sock, ForceBuffer::Recv, want);
+    }
+    if BufferSizes::usable(sizes.send) < want {
+        let _ = force_buffer(sock, ForceBuffer::Send, want);
+    }
+    BufferSizes::of(SockRef::from(sock))
+}
+
+#[derive(Clone, Copy)]
+enum ForceBuffer {
+    Recv,
+    Send,
+}
+
+/// Set a buffer size past the sysctl cap; EPERM without CAP_NET_ADMIN.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn force_buffer(sock: &socket2::Socket, which: ForceBuffer, size: usize) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let opt = match which {
+        ForceBuffer::Recv => libc::SO_RCVBUFFORCE,
+        ForceBuffer::Send => libc::SO_SNDBUFFORCE,
+    };
+    let value = libc::c_int::try_from(size).unwrap_or(libc::c_int::MAX);
+    // SAFETY: the option value is a live c_int of the length passed.
+    let rc = unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            libc::SOL_SOCKET,
+            opt,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if rc < 0 { Err(io::Error::last_os_error()) } else { Ok(()) }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn force_buffer(_sock: &socket2::Socket, _which: ForceBuffer, _size: usize) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "SO_RCVBUFFORCE not supported on this platform"))
+}
@@ -0,0 +1,85 @@
+// proj2-serv/src/sockstats.rs
+// Socket statistics snapshot (`ADMIN SOCKETS`), for scripts that tune buffer
+// sizes and pacing. One line per listening socket with its kernel memory
+// accounting from SO_MEMINFO: `rx_queued=` bytes waiting in the receive buffer
+// of `rcvbuf=`, `tx_queued=` of `sndbuf=`, and `drops=` since it was opened
+// (Linux only; other platforms list the socket alone). Then one line per
+// session with the sends that found its socket buffer full (`would_block=`, at
+// most one per datagram or write however long it waited) and the sends that
+// failed (`send_errors=`), both counted over the whole session. Ends with END.
+
+use std::fmt::{self, Write};
+
+use crate::state::ServerState;
+
+/// A socket's kernel memory accounting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemInfo {
+    pub rx_queued: u32,
+    pub rcvbuf: u32,
+    pub tx_queued: u32,
+    pub sndbuf: u32,
+    pub drops: u32,
+}
+
+#[cfg(target_os = "linux")]
+pub fn meminfo(fd: std::os::fd::RawFd) -> Option<MemInfo> {
+    let mut info = [0u32; libc::SK_MEMINFO_DROPS as usize + 1];
+    let mut len = std::mem::size_of_val(&info) as libc::socklen_t;
+    // SAFETY: `info` is a plain u32 array and `len` holds its size; the kernel
+    // writes at most `len` bytes.
+    let rc = unsafe { libc::getsockopt(fd, libc::SOL_SOCKET, libc::SO_MEMINFO, info.as_mut_ptr() as *mut libc::c_void, &mut len) };
+    if rc != 0 || (len as usize) < std::mem::size_of_val(&info) {
+        return None;
+    }
+    let at = |i: libc::c_int| info[i as usize];
+    Some(MemInfo {
+        rx_queued: at(libc::SK_MEMINFO_RMEM_ALLOC),
+        rcvbuf: at(libc::SK_MEMINFO_RCVBUF),
+        tx_queued: at(libc::SK_MEMINFO_WMEM_ALLOC),
+        sndbuf: at(libc::SK_MEMINFO_SNDBUF),
+        drops: at(libc::SK_MEMINFO_DROPS),
+    })
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+pub fn meminfo(_fd: std::os::fd::RawFd) -> Option<MemInfo> {
+    None
+}
+
+/// Reply to `ADMIN SOCKETS`.
+pub fn render(state: &ServerState) -> String {
+    let mut out = String::new();
+    #[cfg(unix)]
+    for (listener, fd) in state.listeners.live(state) {
+        let _ = write!(out, "socket={}", listener.as_str());
+        if let Some(info) = meminfo(fd) {
+            let _ = write!(out, " {}", info);
+        }
+        out.push('\n');
+    }
+    for s in state.sessions.list() {
+        let _ = writeln!(
+            out,
+            "session={} proto={} peer={} tenant={} would_block={} send_errors={}",
+            s.id,
+            s.protocol.as_str(),
+            s.peer,
+            s.tenant,
+            s.would_block,
+            s.send_errors
+        );
+    }
+    out.push_str("END\n");
+    out
+}
+
+impl fmt::Display for MemInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rx_queued={} rcvbuf={} tx_queued={} sndbuf={} drops={}",
+            self.rx_queued, self.rcvbuf, self.tx_queued, self.sndbuf, self.drops
+        )
+    }
+}
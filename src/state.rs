@@ -0,0 +1,237 @@
+// proj2-serv/src/state.rs
+// Shared server state handed to both the TCP and UDP planes.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context};
+
+use crate::auth::ControlAuth;
+use crate::config::Config;
+use crate::drain::Drain;
+use crate::export::Exports;
+use crate::geoip::GeoIp;
+#[cfg(unix)]
+use crate::handover::Listeners;
+use crate::limits::Limits;
+use crate::mesh::Mesh;
+use crate::metrics::Metrics;
+use crate::multicast::Multicast;
+use crate::otel::Telemetry;
+use crate::protocol::{self, error_frame, Command, ErrorCode};
+use crate::queue::TestSlots;
+use crate::ratelimit::ControlLimiter;
+use crate::resume::Resumptions;
+use crate::results::{ResultStore, TestResult};
+use crate::session::SessionRegistry;
+use crate::signing::ResultSigner;
+use crate::sockopt::BufferSizes;
+use crate::supervisor::{Plane, PlaneHealth};
+
+pub struct ServerState {
+    pub config: Config,
+    /// Limits adjustable at runtime, initialised from `config`.
+    pub limits: Limits,
+    pub metrics: Metrics,
+    pub results: ResultStore,
+    /// Queues to the `--export-results` sinks.
+    pub exports: Exports,
+    /// Test spans on their way to `--otlp-endpoint`.
+    pub telemetry: Telemetry,
+    pub health: PlaneHealth,
+    pub sessions: Arc<SessionRegistry>,
+    /// Contents of `--payload-file`, loaded once at startup.
+    pub payload_file: Option<Arc<[u8]>>,
+    /// Key from `--control-key`, checking signed commands.
+    pub control_auth: Option<ControlAuth>,
+    /// Key from `--result-key`, signing recorded results.
+    pub result_signer: Option<ResultSigner>,
+    /// Databases from `--geoip`, enriching recorded results.
+    pub geoip: Option<GeoIp>,
+    pub mesh: Mesh,
+    pub multicast: Multicast,
+    pub control: ControlLimiter,
+    /// Maintenance mode refusing new work (`ADMIN DRAIN`).
+    pub drain: Drain,
+    /// Tickets of resumable TCP tests.
+    pub resume: Resumptions,
+    /// Slots for concurrent tests, and the queue for them.
+    pub slots: TestSlots,
+    /// Listeners to pass on at a hot restart.
+    #[cfg(unix)]
+    pub listeners: Listeners,
+    /// Effective buffer sizes of the UDP plane's socket, once bound.
+    pub udp_buffers: Mutex<Option<BufferSizes>>,
+}
+
+impl ServerState {
+    pub fn new(config: Config) -> anyhow::Result<Arc<Self>> {
+        let payload_file = match &config.payload_file {
+            Some(path) => {
+                let data = std::fs::read(path).with_context(|| format!("reading payload file {}", path.display()))?;
+                if data.is_empty() {
+                    bail!("payload file {} is empty", path.display());
+                }
+                Some(Arc::from(data))
+            }
+            None => None,
+        };
+        let control_auth = config.control_key.as_deref().map(ControlAuth::load).transpose()?;
+        let result_signer = config.result_key.as_deref().map(ResultSigner::load).transpose()?;
+        let geoip = GeoIp::load(&config.geoip)?;
+        if let Some(signer) = &result_signer {
+            println!("Signing results with Ed25519 public key {}", signer.public_key());
+        }
+        Ok(Arc::new(ServerState {
+            limits: Limits::new(&config),
+            mesh: Mesh::new(&config),
+            multicast: Multicast::new(&config),
+            control: ControlLimiter::new(&config),
+            exports: Exports::new(&config.exports),
+            telemetry: Telemetry::new(config.otlp_endpoint.as_ref()),
+            drain: Drain::default(),
+            resume: Resumptions::default(),
+            slots: TestSlots::new(&config),
+            #[cfg(unix)]
+            listeners: Listeners::default(),
+            config,
+            metrics: Metrics::default(),
+            results: ResultStore::default(),
+            health: PlaneHealth::default(),
+            sessions: Arc::new(SessionRegistry::default()),
+            payload_file,
+            control_auth,
+            result_signer,
+            geoip,
+            udp_buffers: Mutex::new(None),
+        }))
+    }
+
+    /// Time limit for a test: the fixed window, or the safety limit for `BYTES=`
+    /// tests, plus any warm-up.
+    pub fn test_window(&self, target: Option<u64>, omit: Duration) -> Duration {
+        let base = if target.is_some() { self.limits.max_test_duration() } else { protocol::TEST_DURATION };
+        base + omit
+    }
+
+    /// Why a UDP window should be dropped before its deadline: nothing received
+    /// within the handshake timeout, or nothing for the inactivity timeout since
+    /// the last datagram.
+    pub fn stale_reason(&self, received: bool, last_activity: Instant, now: Instant) -> Option<&'static str> {
+        let (limit, reason) = if received {
+            (self.limits.inactivity_timeout(), "inactive")
+        } else {
+            (self.limits.handshake_timeout(), "no_data")
+        };
+        limit.filter(|&limit| now.saturating_duration_since(last_activity) >= limit).map(|_| reason)
+    }
+
+    /// Capability line sent in reply to `CAPS`.
+    pub fn caps(&self) -> String {
+        let status = |p| match self.health.port(p) {
+            Some(port) => format!("up:{}", port),
+            None => "down".to_string(),
+        };
+        format!(
+            "CAPS tcp={} udp={} degraded={} draining={}",
+            status(Plane::Tcp),
+            status(Plane::Udp),
+            self.health.degraded() as u8,
+            self.drain.active() as u8
+        )
+    }
+
+    /// `HELLO` reply: the agreed protocol version, the features both sides
+    /// support (all of ours if the client lists none), the longest test the
+    /// server allows and the largest UDP datagram it takes.
+    pub fn hello(&self, cmd: &Command) -> String {
+        let version = match protocol::parse_version(cmd.opt("VERSION")) {
+            Some(v) if v >= protocol::MIN_PROTOCOL_VERSION => v.min(protocol::PROTOCOL_VERSION),
+            _ => {
+                return error_frame(
+                    ErrorCode::UnsupportedOption,
+                    format!(
+                        "unsupported protocol version {} (server speaks {}-{})",
+                        cmd.opt("VERSION").unwrap_or_default(),
+                        protocol::MIN_PROTOCOL_VERSION,
+                        protocol::PROTOCOL_VERSION
+                    ),
+                )
+            }
+        };
+        let mut ours: Vec<&str> = protocol::FEATURES.to_vec();
+        if self.multicast.enabled() {
+            ours.push("multicast");
+        }
+        if self.config.uds.is_some() {
+            ours.push("uds");
+        }
+        if self.control_auth.is_some() {
+            ours.push("signed");
+        }
+        if self.config.udp_acks.validate {
+            ours.push("udp-cookie");
+        }
+        if self.config.resume_grace.is_some() {
+            ours.push("resume");
+        }
+        if self.config.max_total_duration.is_some() {
+            ours.push("extend");
+        }
+        if self.slots.queueing() {
+            ours.push("queue");
+        }
+        let features: Vec<&str> = match cmd.opt("FEATURES") {
+            Some(wanted) => {
+                let wanted: Vec<String> = wanted.split(',').map(|f| f.trim().to_ascii_lowercase()).collect();
+                ours.into_iter().filter(|f| wanted.iter().any(|w| w == f)).collect()
+            }
+            None => ours,
+        };
+        format!(
+            "HELLO version={} features={} max_duration={} max_datagram={}",
+            version,
+            features.join(","),
+            self.limits.max_test_duration().as_secs(),
+            self.config.udp_max_datagram
+        )
+    }
+
+    /// Record a finished test in both the metrics and the result store.
+    pub fn record(&self, mut result: TestResult) {
+        let (sent, received) = match result.direction {
+            crate::results::Direction::Download => (result.bytes, 0),
+            crate::results::Direction::Upload => (0, result.bytes),
+            crate::results::Direction::Echo => (result.bytes, result.bytes),
+        };
+        self.metrics.session_finished(&result.tenant, sent, received);
+        if let Some(requests) = result.requests {
+            self.metrics.echo_requests(&result.tenant, requests);
+        }
+        self.finish(&mut result);
+        println!("[{}] result: {}", result.tenant, result.to_line());
+        self.store(result);
+    }
+
+    /// Keep a result and hand it to the exporters, enriched and signed.
+    pub fn store(&self, mut result: TestResult) {
+        self.finish(&mut result);
+        self.exports.export(&result, &self.metrics);
+        self.results.push(result);
+    }
+
+    /// Add the client's GeoIP fields and sign, once.
+    fn finish(&self, result: &mut TestResult) {
+        if result.signature.is_some() {
+            return;
+        }
+        if let Some(geoip) = &self.geoip
+            && result.geo.is_none()
+        {
+            result.geo = geoip.lookup(result.peer.ip());
+        }
+        if let Some(signer) = &self.result_signer {
+            result.signature = Some(signer.sign(&result.unsigned_line()));
+        }
+    }
+}
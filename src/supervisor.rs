@@ -0,0 +1,143 @@
+// proj2-serv/src/supervisor.rs
+// Independent supervision of the TCP and UDP planes so a failure in one
+// never tears down the other, and panic isolation for per-client tasks. A
+// panicking plane counts as failed and is restarted by policy; a panicking
+// session is logged with its context, counted, and its leftovers cleaned up,
+// while the accept and receive loops carry on.
+
+use std::any::Any;
+use std::future::Future;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio_util::task::AbortOnDropHandle;
+
+use crate::config::PlanePolicy;
+use crate::state::ServerState;
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// A plane that ran at least this long before failing restarts with a fresh backoff.
+const HEALTHY_RUN: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Plane {
+    Tcp,
+    Udp,
+}
+
+impl Plane {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Plane::Tcp => "tcp",
+            Plane::Udp => "udp",
+        }
+    }
+}
+
+/// Bound port per plane; 0 means the plane is down.
+#[derive(Default)]
+pub struct PlaneHealth {
+    tcp: AtomicU16,
+    udp: AtomicU16,
+}
+
+impl PlaneHealth {
+    fn slot(&self, plane: Plane) -> &AtomicU16 {
+        match plane {
+            Plane::Tcp => &self.tcp,
+            Plane::Udp => &self.udp,
+        }
+    }
+
+    pub fn mark_up(&self, plane: Plane, port: u16) {
+        self.slot(plane).store(port, Ordering::Relaxed);
+    }
+
+    pub fn mark_down(&self, plane: Plane) {
+        self.slot(plane).store(0, Ordering::Relaxed);
+    }
+
+    pub fn port(&self, plane: Plane) -> Option<u16> {
+        match self.slot(plane).load(Ordering::Relaxed) {
+            0 => None,
+            port => Some(port),
+        }
+    }
+
+    pub fn is_up(&self, plane: Plane) -> bool {
+        self.port(plane).is_some()
+    }
+
+    /// True when at least one plane is down.
+    pub fn degraded(&self) -> bool {
+        !self.is_up(Plane::Tcp) || !self.is_up(Plane::Udp)
+    }
+}
+
+/// Run `start` (which binds and serves a plane, marking it up once bound) until it
+/// fails, then restart or give up according to the configured policy. Returns only
+/// when the plane is permanently down.
+pub async fn supervise<F, Fut>(plane: Plane, state: Arc<ServerState>, mut start: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+{
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        let started = Instant::now();
+        // Its own task, so a panic in the receive or accept loop surfaces here as a failure.
+        let err = match AbortOnDropHandle::new(tokio::spawn(start())).await {
+            Ok(Ok(())) => anyhow::anyhow!("{} plane exited", plane.as_str()),
+            Ok(Err(e)) => e,
+            Err(e) if e.is_panic() => {
+                state.metrics.task_panicked();
+                anyhow::anyhow!("{} plane panicked: {}", plane.as_str(), panic_message(e.into_panic()))
+            }
+            Err(e) => anyhow::anyhow!("{} plane task failed: {}", plane.as_str(), e),
+        };
+        state.health.mark_down(plane);
+        eprintln!("{} plane failed: {:#}", plane.as_str().to_uppercase(), err);
+
+        if state.config.plane_policy == PlanePolicy::StayDown {
+            eprintln!("{} plane staying down by policy; serving degraded", plane.as_str().to_uppercase());
+            return;
+        }
+        if started.elapsed() >= HEALTHY_RUN {
+            backoff = INITIAL_BACKOFF;
+        }
+        eprintln!("restarting {} plane in {:?}", plane.as_str().to_uppercase(), backoff);
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// Spawn a per-client task, described by `context` (e.g. "UDP download to
+/// <addr>") in logs. If it panics, the panic is logged and counted and
+/// `cleanup` runs to remove what the task would have removed on a normal exit;
+/// session guards are dropped by the unwind itself.
+pub fn spawn_session<Fut, Clean>(state: &Arc<ServerState>, context: String, task: Fut, cleanup: Clean)
+where
+    Fut: Future<Output = ()> + Send + 'static,
+    Clean: Future<Output = ()> + Send + 'static,
+{
+    let state = state.clone();
+    tokio::spawn(async move {
+        match AbortOnDropHandle::new(tokio::spawn(task)).await {
+            Err(e) if e.is_panic() => {
+                eprintln!("{} panicked: {}", context, panic_message(e.into_panic()));
+                state.metrics.task_panicked();
+                cleanup.await;
+            }
+            _ => {}
+        }
+    });
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(msg) => *msg,
+        Err(payload) => payload.downcast_ref::<&str>().map_or("(non-string panic payload)", |m| m).to_string(),
+    }
+}
@@ -0,0 +1,130 @@
+// proj2-serv/src/systemd.rs
+// systemd integration. Socket activation: listeners passed in through
+// LISTEN_PID/LISTEN_FDS (one stream and/or one datagram socket) are used instead
+// of binding `--tcp-ports`/`--udp-ports`, so the unit can run without the
+// privilege to bind and with the sockets owned by systemd. A Unix stream socket
+// (`ListenStream=/run/proj2-serv.sock`) stands in for `--uds`. Notify: with
+// NOTIFY_SOCKET set, READY=1 is sent once the planes are up, STOPPING=1 on
+// shutdown, and WATCHDOG=1 every half WATCHDOG_USEC while at least one plane
+// serves. A matching unit pairs `Type=notify` and `WatchdogSec=` with a
+// `.socket` unit holding `ListenStream=8080` and `ListenDatagram=7070`.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::state::ServerState;
+use crate::supervisor::Plane;
+
+/// Listeners inherited from the service manager, or from the previous process
+/// on a hot restart (see `handover`).
+#[derive(Default)]
+pub struct Inherited {
+    pub tcp: Option<std::net::TcpListener>,
+    pub udp: Option<std::net::UdpSocket>,
+    #[cfg(unix)]
+    pub uds: Option<std::os::unix::net::UnixListener>,
+    /// Only passed on a hot restart, as are `health`, `grpc` and `handover`.
+    pub dashboard: Option<std::net::TcpListener>,
+    pub health: Option<std::net::TcpListener>,
+    pub grpc: Option<std::net::TcpListener>,
+    /// Channel to tell the previous process this one is ready.
+    #[cfg(unix)]
+    pub handover: Option<std::os::unix::net::UnixStream>,
+}
+
+/// Take the sockets systemd passed to this process. Must run before any other
+/// thread starts, since it clears the LISTEN_* variables.
+#[cfg(unix)]
+pub fn take_listen_fds() -> Inherited {
+    use std::os::fd::FromRawFd;
+
+    const SD_LISTEN_FDS_START: i32 = 3;
+    let mut inherited = Inherited::default();
+    let for_us = std::env::var("LISTEN_PID").ok().and_then(|p| p.parse::<u32>().ok()) == Some(std::process::id());
+    let count = std::env::var("LISTEN_FDS").ok().and_then(|n| n.parse::<i32>().ok()).unwrap_or(0);
+    // SAFETY: called from main before the runtime or any other thread exists.
+    unsafe {
+        std::env::remove_var("LISTEN_PID");
+        std::env::remove_var("LISTEN_FDS");
+        std::env::remove_var("LISTEN_FDNAMES");
+    }
+    if !for_us {
+        return inherited;
+    }
+    for fd in SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count {
+        // SAFETY: LISTEN_PID names this process, so systemd handed us ownership
+        // of these descriptors and nothing else refers to them.
+        let sock = unsafe { socket2::Socket::from_raw_fd(fd) };
+        let unix = sock.domain().is_ok_and(|d| d == socket2::Domain::UNIX);
+        match sock.r#type() {
+            Ok(socket2::Type::STREAM) if unix && inherited.uds.is_none() => inherited.uds = Some(sock.into()),
+            Ok(socket2::Type::STREAM) if !unix && inherited.tcp.is_none() => inherited.tcp = Some(sock.into()),
+            Ok(socket2::Type::DGRAM) if inherited.udp.is_none() => inherited.udp = Some(sock.into()),
+            Ok(t) => eprintln!("Ignoring extra inherited socket fd {} ({:?})", fd, t),
+            Err(e) => eprintln!("Ignoring inherited fd {}: {}", fd, e),
+        }
+    }
+    inherited
+}
+
+#[cfg(not(unix))]
+pub fn take_listen_fds() -> Inherited {
+    Inherited::default()
+}
+
+/// Send a state update to the service manager; a no-op outside systemd.
+#[cfg(target_os = "linux")]
+pub fn notify(state: &str) {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+    let Ok(path) = std::env::var("NOTIFY_SOCKET") else { return };
+    let addr = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name.as_bytes()),
+        None => SocketAddr::from_pathname(&path),
+    };
+    let sent = addr.and_then(|addr| UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr));
+    if let Err(e) = sent {
+        eprintln!("sd_notify {:?} failed: {}", state, e);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn notify(_state: &str) {}
+
+/// Report readiness once both planes are up (or after a grace period with
+/// whichever came up), then keep the watchdog fed while anything serves.
+pub async fn run_notifier(state: Arc<ServerState>) {
+    const READY_GRACE: Duration = Duration::from_secs(5);
+    let serving = |p| state.health.port(p).is_some();
+    let waited = tokio::time::timeout(READY_GRACE, async {
+        while !(serving(Plane::Tcp) && serving(Plane::Udp)) {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await;
+    let status = match (state.health.port(Plane::Tcp), state.health.port(Plane::Udp)) {
+        (Some(t), Some(u)) => format!("STATUS=serving TCP {} and UDP {}", t, u),
+        (t, u) => format!("STATUS=degraded: TCP {:?}, UDP {:?}", t, u),
+    };
+    if waited.is_err() {
+        eprintln!("Planes not all up after {:?}; reporting ready anyway", READY_GRACE);
+    }
+    notify(&format!("READY=1\n{}", status));
+
+    let Some(interval) = watchdog_interval() else { return };
+    loop {
+        tokio::time::sleep(interval).await;
+        // With both planes down, let the watchdog fire so systemd restarts us.
+        if serving(Plane::Tcp) || serving(Plane::Udp) {
+            notify("WATCHDOG=1");
+        }
+    }
+}
+
+/// Half the watchdog timeout systemd asked for, if it is meant for us.
+fn watchdog_interval() -> Option<Duration> {
+    let pid_ok = std::env::var("WATCHDOG_PID").map_or(true, |p| p.parse::<u32>().ok() == Some(std::process::id()));
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    (pid_ok && usec > 0).then(|| Duration::from_micros(usec / 2))
+}
@@ -0,0 +1,914 @@
+// proj2-serv/src/tcp.rs
+// TCP plane: accept loop and per-connection command handling. Connections the
+// server dials out with CONNECT_BACK (for servers behind NAT) run the same handler.
+// Each read is a command unless a test is running; an upload that closes on its
+// deadline or target leaves the connection draining what the client still had in
+// flight, by frame with `FRAMED=1` (see dataframe.rs) or until it goes quiet, so
+// payload is never taken for a command.
+
+use tokio::net::{TcpListener, TcpStream};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_util::sync::CancellationToken;
+use std::time::{Duration, Instant};
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::admin;
+use crate::clock;
+use crate::compress::{self, Compression};
+use crate::dataframe::{Event, FrameDecoder};
+use crate::echo;
+use crate::extend;
+use crate::filexfer;
+use crate::interval::{Interval, IntervalTracker, Measured};
+use crate::pacing::{self, Mechanism, Pacing};
+use crate::payload::{self, PayloadSource, WriteBatch};
+use crate::protocol::{self, error_frame, ClientMeta, Command, ErrorCode, DEFAULT_TENANT};
+use crate::queue::{Admission, Slot};
+use crate::ratelimit::Verdict;
+use crate::resume::{self, Resumes, TcpResume, TcpTicket};
+use crate::runs;
+use crate::results::{Direction, Protocol, TestResult};
+use crate::session::{starts_test, SessionGuard, Window};
+use crate::signing;
+use crate::sockopt::{self, Steering};
+use crate::state::ServerState;
+use crate::supervisor;
+use crate::tcpinfo;
+use crate::transport::{self, Streamed, TestSpec, TestTransport};
+use crate::zerocopy;
+
+/// Silence after which unframed upload data still in flight is taken to be over.
+pub const DRAIN_QUIET: Duration = Duration::from_millis(250);
+
+/// First pause after a failed accept; doubles on each consecutive failure.
+const INITIAL_ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(2);
+
+/// How an accept error should be handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AcceptFailure {
+    /// The connection died before it was accepted; the next one is unaffected.
+    Connection,
+    /// Out of file descriptors or socket memory.
+    Exhausted,
+    Other,
+}
+
+fn classify_accept_error(e: &std::io::Error) -> AcceptFailure {
+    match e.kind() {
+        ErrorKind::ConnectionAborted | ErrorKind::ConnectionReset | ErrorKind::Interrupted => return AcceptFailure::Connection,
+        _ => {}
+    }
+    match e.raw_os_error() {
+        Some(libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM) => AcceptFailure::Exhausted,
+        // A firewall rejected the connection, or a protocol error on it (Linux).
+        Some(libc::EPERM | libc::EPROTO) => AcceptFailure::Connection,
+        _ => AcceptFailure::Other,
+    }
+}
+
+pub async fn run_tcp_server(listener: TcpListener, state: Arc<ServerState>) -> anyhow::Result<()> {
+    // Held so that, out of descriptors, one can be freed to accept and
+    // immediately close a pending connection instead of leaving it to hang.
+    let mut reserve = std::fs::File::open("/dev/null").ok();
+    let mut backoff = INITIAL_ACCEPT_BACKOFF;
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                backoff = INITIAL_ACCEPT_BACKOFF;
+                if reserve.is_none() {
+                    reserve = std::fs::File::open("/dev/null").ok();
+                }
+                println!("New TCP connection from {}", addr);
+                spawn_session(stream, addr, state.clone());
+            }
+            Err(e) => {
+                match classify_accept_error(&e) {
+                    AcceptFailure::Connection => {
+                        eprintln!("TCP accept: connection lost before accept: {}", e);
+                        continue;
+                    }
+                    AcceptFailure::Exhausted => {
+                        state.metrics.accept_exhausted(true);
+                        eprintln!("TCP accept: out of resources ({}); shedding a pending connection and pausing accepts for {:?}", e, backoff);
+                        if reserve.take().is_some() {
+                            // Dropped at once: the client sees a reset, not a timeout.
+                            let _ = socket2::SockRef::from(&listener).accept();
+                            reserve = std::fs::File::open("/dev/null").ok();
+                        }
+                    }
+                    AcceptFailure::Other => eprintln!("TCP accept error: {:?}", e),
+                }
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_ACCEPT_BACKOFF);
+                state.metrics.accept_exhausted(false);
+            }
+        }
+    }
+}
+
+/// Register a session for `stream` and run the command handler on it.
+fn spawn_session(stream: TcpStream, addr: SocketAddr, state: Arc<ServerState>) {
+    let session = state.sessions.register(Protocol::Tcp, addr, DEFAULT_TENANT, None);
+    let context = format!("TCP client {} (session {})", addr, session.id());
+    let task_state = state.clone();
+    supervisor::spawn_session(&state, context, async move {
+        if let Err(e) = handle_tcp_client(stream, addr, task_state, &session).await {
+            eprintln!("TCP client {} error: {:?}", addr, e);
+        }
+    }, std::future::ready(()));
+}
+
+/// Dial a listening client for `CONNECT_BACK <addr>` and serve it like an
+/// accepted connection. Only the requesting peer's own IP may be dialled, so the
+/// server cannot be used to open connections to third parties.
+async fn connect_back(cmd: &Command, peer: SocketAddr, state: &Arc<ServerState>) -> String {
+    const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+    let Some(target) = cmd.args.first().and_then(|a| a.parse::<SocketAddr>().ok()) else {
+        return format!("{}\n", error_frame(ErrorCode::BadCommand, "usage: CONNECT_BACK <ip:port>"));
+    };
+    if target.ip() != peer.ip() {
+        let message = format!("CONNECT_BACK target {} must match the requesting address {}", target, peer.ip());
+        return format!("{}\n", error_frame(ErrorCode::Unauthorized, message));
+    }
+    match tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(target)).await {
+        Ok(Ok(stream)) => {
+            println!("TCP connect-back to {} established (requested by {})", target, peer);
+            spawn_session(stream, target, state.clone());
+            format!("OK CONNECT_BACK {}\n", target)
+        }
+        Ok(Err(e)) => format!("{}\n", error_frame(ErrorCode::Failed, format!("CONNECT_BACK {} failed: {}", target, e))),
+        Err(_) => format!("{}\n", error_frame(ErrorCode::Failed, format!("CONNECT_BACK {} timed out", target))),
+    }
+}
+
+async fn handle_tcp_client(mut stream: TcpStream, peer: SocketAddr, state: Arc<ServerState>, session: &SessionGuard) -> anyhow::Result<()> {
+    let cancel = session.token();
+    let _ = stream.set_nodelay(true);
+    if let Some(idle) = state.config.tcp_keepalive
+        && let Err(e) = sockopt::set_tcp_keepalive(&stream, idle)
+    {
+        eprintln!("TCP {} keepalive setup failed: {}", peer, e);
+    }
+    let idle_timeout = state.config.idle_timeout;
+    const BUF_SIZE: usize = 64 * 1024;
+    let mut read_buf = vec![0u8; BUF_SIZE];
+    let mut mode = Mode::Command;
+    // Client metadata from HELLO, for the tests that follow on this connection.
+    let mut hello = ClientMeta::default();
+    loop {
+        let quiet = matches!(mode, Mode::Unframed).then_some(DRAIN_QUIET);
+        let read = tokio::select! {
+            res = stream.read(&mut read_buf) => res,
+            _ = cancel.cancelled() => {
+                println!("TCP session with {} cancelled", peer);
+                return Ok(());
+            }
+            _ = sleep_or_forever(idle_timeout) => {
+                println!("TCP client {} idle for {:?}, closing", peer, idle_timeout.unwrap_or_default());
+                return Ok(());
+            }
+            _ = sleep_or_forever(quiet) => {
+                mode = Mode::Command;
+                continue;
+            }
+        };
+        let n = match read {
+            Ok(0) => {
+                println!("TCP client {} disconnected", peer);
+                return Ok(());
+            }
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::ConnectionReset => {
+                println!("TCP client {} reset connection", peer);
+                return Ok(());
+            }
+            Err(e) => {
+                eprintln!("TCP read error from {}: {:?}", peer, e);
+                return Err(e.into());
+            }
+        };
+        let drained;
+        let frame = match mode.drain(&read_buf[..n], peer) {
+            Drained::Command => &read_buf[..n],
+            Drained::Line(line) => {
+                drained = line;
+                drained.as_bytes()
+            }
+            Drained::Nothing => continue,
+        };
+        let (command, cmd) = protocol::decode_frame(frame);
+        let tenant = cmd.tenant();
+        println!("[{}] TCP server received from {}: {}", tenant, peer, command);
+        if state.control.check(peer.ip(), &cmd.verb, false) != Verdict::Allow {
+            println!("[{}] TCP {} over the control rate limit; rejecting {}", tenant, peer, cmd.verb);
+            let frame = error_frame(ErrorCode::Busy, "control rate limit exceeded");
+            stream.write_all(format!("{}\n", frame).as_bytes()).await?;
+            continue;
+        }
+        if let Some(frame) = state.drain.refuse(&cmd.verb) {
+            println!("[{}] TCP {} refused {} while draining", tenant, peer, cmd.verb);
+            stream.write_all(format!("{}\n", frame).as_bytes()).await?;
+            continue;
+        }
+        if let Some(Err(frame)) = state.control_auth.as_ref().map(|auth| auth.verify(&command, &cmd)) {
+            println!("[{}] TCP {} refused unsigned or invalid {}: {}", tenant, peer, cmd.verb, frame);
+            stream.write_all(format!("{}\n", frame).as_bytes()).await?;
+            continue;
+        }
+        // One test per direction at a time per client host, as on UDP.
+        if let Some(direction) = starts_test(&cmd.verb)
+            && let Some(running) = state.sessions.conflict(peer, direction, false)
+        {
+            println!("[{}] TCP {} refused {}: a {} test is already running for its host", tenant, peer, cmd.verb, running.as_str());
+            let frame = error_frame(ErrorCode::Busy, format!("a {} test is already running for {}", running.as_str(), peer.ip()));
+            stream.write_all(format!("{}\n", frame).as_bytes()).await?;
+            continue;
+        }
+
+        if cmd.verb == "START_DOWNLOAD" {
+            let prior_cca = cmd.opt("CCA").and_then(|_| sockopt::tcp_congestion(&stream));
+            if let Some(cca) = cmd.opt("CCA") {
+                // Fall back to the system default algorithm, but tell the client.
+                if let Err(e) = sockopt::set_tcp_congestion(&stream, cca) {
+                    eprintln!("[{}] TCP {} cannot use CCA {:?}: {}", tenant, peer, cca, e);
+                    let frame = error_frame(ErrorCode::UnsupportedOption, format!("unsupported CCA={}: {}", cca, e));
+                    stream.write_all(format!("{}\n", frame).as_bytes()).await?;
+                }
+            }
+            let dscp = apply_dscp(&mut stream, &cmd, &tenant, peer).await?;
+            let steering = apply_steering(&mut stream, &cmd, &state, &tenant, peer).await?;
+            let omit = read_option(&mut stream, &cmd, "OMIT", protocol::parse_omit).await?.unwrap_or_default();
+            let target = read_option(&mut stream, &cmd, "BYTES", protocol::parse_byte_count).await?;
+            let source = match payload::from_command(&cmd, state.payload_file.as_ref()) {
+                Ok(source) => source,
+                Err(e) => {
+                    stream.write_all(format!("{}\n", error_frame(ErrorCode::UnsupportedOption, e)).as_bytes()).await?;
+                    Box::new(payload::Zeros)
+                }
+            };
+            let codec = read_option(&mut stream, &cmd, "COMPRESS", compress::parse_codec).await?;
+            let source: Box<dyn PayloadSource> = match codec {
+                Some(_) => Box::new(compress::Zstd::new(source)?),
+                None => source,
+            };
+            let rate = read_option(&mut stream, &cmd, "RATE", protocol::parse_byte_count).await?;
+            let mut pacing = Pacing::requested(rate, read_option(&mut stream, &cmd, "PACING", Mechanism::parse).await?);
+            let ticket = offer_ticket(&mut stream, &cmd, &state, session).await?;
+            let spec = TestSpec::new(&state, &cmd, Direction::Download, target, omit).with_hello(&hello);
+            let Some(_slot) = take_slot(&mut stream, &state, session, &tenant, peer).await? else {
+                restore_steering(&stream, steering, &state);
+                restore_cca(&stream, prior_cca.as_deref());
+                continue;
+            };
+            if let Some(pacing) = &mut pacing {
+                pacing.apply(&[socket2::SockRef::from(&stream)]);
+            }
+            let mut transport =
+                TcpTransport { stream: &mut stream, peer, state: &state, session, source, dscp, steering, ticket, framed: false, after: Mode::Command, codec, compression: None, pacing };
+            transport::run_test(&mut transport, &state, &spec).await?;
+            restore_steering(&stream, steering, &state);
+            restore_cca(&stream, prior_cca.as_deref());
+            if pacing.is_some_and(|p| p.mechanism == Mechanism::Kernel) {
+                pacing::clear(&[socket2::SockRef::from(&stream)]);
+            }
+        } else if cmd.verb == "START_UPLOAD" {
+            let dscp = apply_dscp(&mut stream, &cmd, &tenant, peer).await?;
+            let steering = apply_steering(&mut stream, &cmd, &state, &tenant, peer).await?;
+            let omit = read_option(&mut stream, &cmd, "OMIT", protocol::parse_omit).await?.unwrap_or_default();
+            let target = read_option(&mut stream, &cmd, "BYTES", protocol::parse_byte_count).await?;
+            let framed = read_option(&mut stream, &cmd, "FRAMED", protocol::parse_flag).await?.unwrap_or(false);
+            let spec = TestSpec::new(&state, &cmd, Direction::Upload, target, omit).with_hello(&hello);
+            let source = Box::new(payload::Zeros);
+            let ticket = offer_ticket(&mut stream, &cmd, &state, session).await?;
+            let Some(_slot) = take_slot(&mut stream, &state, session, &tenant, peer).await? else {
+                restore_steering(&stream, steering, &state);
+                continue;
+            };
+            let mut transport =
+                TcpTransport { stream: &mut stream, peer, state: &state, session, source, dscp, steering, ticket, framed, after: Mode::Command, codec: None, compression: None, pacing: None };
+            transport::run_test(&mut transport, &state, &spec).await?;
+            mode = transport.after;
+            restore_steering(&stream, steering, &state);
+        } else if cmd.verb == "RESUME" {
+            // The connection is handed to the test it resumes.
+            match state.resume.resume_tcp(&cmd, stream, peer) {
+                Ok(()) => return Ok(()),
+                Err((returned, frame)) => {
+                    stream = returned;
+                    println!("[{}] TCP {} cannot resume: {}", tenant, peer, frame);
+                    stream.write_all(format!("{}\n", frame).as_bytes()).await?;
+                }
+            }
+        } else if cmd.verb == "EXTEND" {
+            // Between tests there is nothing to extend; no window is open.
+            stream.write_all(format!("{}\n", extend::extend(&state, &cmd, [])).as_bytes()).await?;
+        } else if cmd.verb == "START_ECHO" {
+            let size = read_option(&mut stream, &cmd, "SIZE", protocol::parse_echo_size).await?.unwrap_or(echo::DEFAULT_TCP_MESSAGE_SIZE);
+            let omit = read_option(&mut stream, &cmd, "OMIT", protocol::parse_omit).await?.unwrap_or_default();
+            echo::tcp_echo(&mut stream, &cmd, peer, &state, session, size, omit).await?;
+        } else if cmd.verb == "RUN" {
+            stream.write_all(format!("{}\n", runs::reply(&state, &cmd)).as_bytes()).await?;
+        } else if cmd.verb == "COMPARE" {
+            stream.write_all(format!("{}\n", runs::compare_reply(&state, &cmd)).as_bytes()).await?;
+        } else if cmd.verb == "RESULT_KEY" {
+            stream.write_all(format!("{}\n", signing::reply(state.result_signer.as_ref())).as_bytes()).await?;
+        } else if cmd.verb == "CAPS" {
+            stream.write_all(format!("{}\n", state.caps()).as_bytes()).await?;
+        } else if cmd.verb == "HELLO" {
+            hello = cmd.meta();
+            stream.write_all(format!("{}\n", state.hello(&cmd)).as_bytes()).await?;
+        } else if cmd.verb == "SEND_FILE" {
+            filexfer::send_file(&mut stream, &cmd, peer, &state, session).await?;
+        } else if cmd.verb == "RECV_FILE" {
+            filexfer::recv_file(&mut stream, &cmd, peer, &state, session).await?;
+        } else if cmd.verb == "WHOAMI" {
+            stream.write_all(format!("YOUARE {}\n", peer).as_bytes()).await?;
+        } else if cmd.verb == "CONNECT_BACK" {
+            let reply = connect_back(&cmd, peer, &state).await;
+            stream.write_all(reply.as_bytes()).await?;
+        } else if cmd.verb == "STATUS" {
+            stream.write_all(admin::status(&state, peer).as_bytes()).await?;
+        } else if cmd.verb == "MESH_JOIN" {
+            stream.write_all(state.mesh.join(&cmd, peer).as_bytes()).await?;
+        } else if cmd.verb == "MESH" {
+            stream.write_all(state.mesh.row().as_bytes()).await?;
+        } else if cmd.verb == "ADMIN" {
+            let reply = admin::handle_admin(&state, &cmd, peer).await;
+            stream.write_all(reply.as_bytes()).await?;
+        } else {
+            println!("[{}] TCP server: unknown command from {}: {:?}", tenant, peer, command);
+            let frame = error_frame(ErrorCode::BadCommand, format!("unknown command {:?}", cmd.verb));
+            stream.write_all(format!("{}\n", frame).as_bytes()).await?;
+        }
+    }
+}
+
+/// A client connection as the transport of a throughput test.
+struct TcpTransport<'a> {
+    stream: &'a mut TcpStream,
+    peer: SocketAddr,
+    state: &'a ServerState,
+    session: &'a SessionGuard,
+    /// Download payload (`PAYLOAD=`).
+    source: Box<dyn PayloadSource>,
+    dscp: Option<u8>,
+    /// `PRIORITY=` and `MARK=` of the test.
+    steering: Steering,
+    /// Set for a `RESUMABLE=1` test.
+    ticket: Option<TcpTicket>,
+    /// Upload data comes in `DATA <n>` frames (`FRAMED=1`).
+    framed: bool,
+    /// How the connection reads once the test is over.
+    after: Mode,
+    /// Codec the download is compressed with (`COMPRESS=`), and how well it did.
+    codec: Option<&'static str>,
+    compression: Option<Compression>,
+    /// Rate cap of the download (`RATE=`) and what enforces it.
+    pacing: Option<Pacing>,
+}
+
+/// What the bytes arriving on a connection are.
+enum Mode {
+    /// Each read is a command.
+    Command,
+    /// An unframed upload closed without END_UPLOAD; reads are discarded until
+    /// the client goes quiet or sends END_UPLOAD.
+    Unframed,
+    /// A framed upload closed without END_UPLOAD; frames still in flight are
+    /// skipped, and the first other line is a command.
+    Framed(FrameDecoder),
+}
+
+/// What a read turned out to be.
+enum Drained {
+    /// The whole read is a command.
+    Command,
+    /// Upload data ended with this command line.
+    Line(String),
+    /// Upload data, discarded.
+    Nothing,
+}
+
+impl Mode {
+    /// Take a read while draining; back in command mode once the upload's
+    /// leftovers are over.
+    fn drain(&mut self, chunk: &[u8], peer: SocketAddr) -> Drained {
+        let drained = match self {
+            Mode::Command => return Drained::Command,
+            Mode::Unframed if chunk.trim_ascii_end().ends_with(b"END_UPLOAD") => Drained::Nothing,
+            Mode::Unframed => return Drained::Nothing,
+            Mode::Framed(decoder) => match framed_chunk(decoder, chunk).1 {
+                None => return Drained::Nothing,
+                Some(Ok(line)) if line == "END_UPLOAD" => Drained::Nothing,
+                Some(Ok(line)) => Drained::Line(line),
+                Some(Err(e)) => {
+                    println!("TCP {} upload leftovers lost their framing ({}); reading commands again", peer, e);
+                    Drained::Nothing
+                }
+            },
+        };
+        *self = Mode::Command;
+        drained
+    }
+}
+
+/// Why a stream loop stopped on its connection.
+enum Stop {
+    Done,
+    Lost,
+    Moved(TcpResume),
+}
+
+impl TestTransport for TcpTransport<'_> {
+    const PROTOCOL: Protocol = Protocol::Tcp;
+
+    fn peer(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// Announce the ticket of a resumable test before its data.
+    async fn handshake(&mut self, _spec: &TestSpec) -> anyhow::Result<bool> {
+        if let Some(ticket) = &self.ticket {
+            let line = format!("RESUMABLE SESSION={} TOKEN={}\n", self.session.id(), ticket.token);
+            self.stream.write_all(line.as_bytes()).await?;
+        }
+        Ok(true)
+    }
+
+    async fn send_stream(&mut self, spec: &TestSpec) -> anyhow::Result<Streamed> {
+        let (tenant, state, session) = (&spec.tenant, self.state, self.session);
+        let cancel = session.token();
+        session.begin(tenant, Direction::Download);
+        let window = session.window();
+        window.open(spec.window);
+        let source = &mut self.source;
+        let mut batch = WriteBatch::new(state.config.tcp_write_slices, state.config.tcp_write_size);
+        let batch_size = state.config.tcp_write_slices * state.config.tcp_write_size;
+        // PAYLOAD=file goes straight from the file with sendfile where possible,
+        // wrapping at the end of the file like the in-memory source, unless it
+        // is compressed on the way.
+        let mut zero_copy = match (&state.config.payload_file, &state.payload_file) {
+            (Some(path), Some(data)) if source.name() == "file" && self.codec.is_none() => {
+                std::fs::File::open(path).ok().map(|file| (file, data.len() as u64))
+            }
+            _ => None,
+        };
+        let mut file_offset = 0u64;
+        let start = clock::now();
+        let mut sent_bytes: usize = 0usize;
+        let mut measured = Measured::new(start, spec.omit);
+        let mut intervals = IntervalTracker::new(start);
+        let mut ctl_buf = [0u8; 256];
+        let mut last_progress = start;
+        let mut resumes = Resumes::default();
+        let mut ended = None;
+        loop {
+            let peer = self.peer;
+            let (mut rd, mut wr) = self.stream.split();
+            let stop = loop {
+                if clock::elapsed(start) >= window.get() || !spec.below_target(sent_bytes as u64) {
+                    break Stop::Done;
+                }
+                // A user-paced download writes a quantum at a time.
+                let most = self.pacing.filter(|p| p.mechanism == Mechanism::User).map_or(batch_size, |p| batch_size.min(p.quantum()));
+                if zero_copy.is_none() && batch.is_drained() {
+                    let limit = spec.target.map_or(most, |t| most.min((t - sent_bytes as u64) as usize));
+                    batch.refill(source.as_mut(), limit);
+                }
+                let due = self.pacing.and_then(|p| p.due(start, sent_bytes as u64));
+                let send = async {
+                    if let Some(due) = due {
+                        tokio::time::sleep_until(due.into()).await;
+                    }
+                    match &zero_copy {
+                        Some((file, len)) => {
+                            let count = ((len - file_offset) as usize).min(most);
+                            let count = spec.target.map_or(count, |t| count.min((t - sent_bytes as u64) as usize));
+                            zerocopy::sendfile(wr.as_ref(), file, file_offset, count).await
+                        }
+                        None => wr.write_vectored(&batch.slices()).await,
+                    }
+                };
+                // Watch the read side for END_DOWNLOAD or a disconnect while sending.
+                tokio::select! {
+                    res = send => match res {
+                        Ok(n) => {
+                            match &zero_copy {
+                                // The file shrank since startup; serve the loaded copy.
+                                Some(_) if n == 0 => {
+                                    zero_copy = None;
+                                    source.skip(file_offset as usize);
+                                }
+                                Some((_, len)) => file_offset = (file_offset + n as u64) % len,
+                                None => batch.advance(n),
+                            }
+                            sent_bytes += n;
+                            measured.add(n as u64);
+                            session.add_bytes(n as u64);
+                            if n > 0 {
+                                last_progress = clock::now();
+                            }
+                        }
+                        Err(e) if zero_copy.is_some() && zerocopy::is_unsupported(&e) => {
+                            // Continue from the same file position with the in-memory copy.
+                            zero_copy = None;
+                            source.skip(file_offset as usize);
+                        }
+                        Err(e) if e.kind() == ErrorKind::BrokenPipe || e.kind() == ErrorKind::ConnectionReset => {
+                            println!("[{}] Client {} closed connection during download", tenant, peer);
+                            session.send_error();
+                            break Stop::Lost;
+                        }
+                        Err(e) => {
+                            eprintln!("[{}] TCP write error to {}: {:?}", tenant, peer, e);
+                            session.send_error();
+                            break Stop::Lost;
+                        }
+                    },
+                    _ = cancel.cancelled() => {
+                        println!("[{}] TCP download to {} cancelled", tenant, peer);
+                        break Stop::Done;
+                    }
+                    // A client that stops reading stalls the send; the window ends regardless.
+                    _ = clock::window_end(start, || window.get(), cancel) => break Stop::Done,
+                    res = rd.read(&mut ctl_buf) => match res {
+                        Ok(0) | Err(_) => {
+                            println!("[{}] Client {} closed connection during download", tenant, peer);
+                            break Stop::Lost;
+                        }
+                        Ok(m) => {
+                            let cmd = Command::parse(&String::from_utf8_lossy(&ctl_buf[..m]));
+                            if cmd.verb == "END_DOWNLOAD" {
+                                println!("[{}] TCP client {} ended download early", tenant, peer);
+                                break Stop::Done;
+                            }
+                            if cmd.verb == "EXTEND" {
+                                let reply = extend::extend(state, &cmd, [&**window]);
+                                println!("[{}] TCP download to {}: {}", tenant, peer, reply);
+                            }
+                        }
+                    },
+                    moved = next_resume(&mut self.ticket) => break Stop::Moved(moved),
+                }
+                if let Some(iv) = intervals.tick(sent_bytes as u64) {
+                    report_interval(tenant, peer, wr.as_ref(), &iv);
+                }
+            };
+            let moved = match stop {
+                Stop::Done => break,
+                Stop::Moved(moved) => moved,
+                Stop::Lost => match await_resume(&mut self.ticket, state, start, window, cancel).await {
+                    Some(moved) => moved,
+                    None => {
+                        ended = self.ticket.is_some().then_some(last_progress);
+                        break;
+                    }
+                },
+            };
+            resumes.add(clock::elapsed(last_progress));
+            println!("[{}] TCP download {} resumed from {} after {:?}", tenant, session.id(), moved.1, clock::elapsed(last_progress));
+            take_over(self.stream, &mut self.peer, moved, session.id(), sent_bytes as u64, self.dscp, self.steering).await;
+        }
+        session.end();
+        println!("[{}] TCP server finished sending download to {} (~{} bytes)", tenant, self.peer, sent_bytes);
+        self.compression = self.codec.zip(self.source.expanded(sent_bytes as u64)).map(|(codec, payload)| Compression {
+            codec,
+            ratio: if sent_bytes > 0 { payload as f64 / sent_bytes as f64 } else { 1.0 },
+        });
+        Ok(Streamed { ended: ended.unwrap_or_else(clock::now), resumes, ..Streamed::finished(measured) })
+    }
+
+    async fn recv_stream(&mut self, spec: &TestSpec) -> anyhow::Result<Streamed> {
+        let (tenant, state, session) = (&spec.tenant, self.state, self.session);
+        let cancel = session.token();
+        session.begin(tenant, Direction::Upload);
+        let window = session.window();
+        window.open(spec.window);
+        let mut read_buf = vec![0u8; 64 * 1024];
+        let start = clock::now();
+        let mut total_rx: usize = 0usize;
+        let mut measured = Measured::new(start, spec.omit);
+        let mut intervals = IntervalTracker::new(start);
+        let mut last_progress = start;
+        let mut resumes = Resumes::default();
+        let mut ended = None;
+        let mut frames = self.framed.then(FrameDecoder::default);
+        // Whether the client closed the upload, leaving nothing in flight.
+        let mut end_upload = false;
+        loop {
+            let peer = self.peer;
+            let stop = loop {
+                if clock::elapsed(start) >= window.get() || !spec.below_target(total_rx as u64) {
+                    break Stop::Done;
+                }
+                let read = tokio::select! {
+                    res = self.stream.read(&mut read_buf) => res,
+                    _ = cancel.cancelled() => {
+                        println!("[{}] TCP upload from {} cancelled", tenant, peer);
+                        break Stop::Done;
+                    }
+                    // A silent client sends nothing to wake the read; the window ends regardless.
+                    _ = clock::window_end(start, || window.get(), cancel) => break Stop::Done,
+                    moved = next_resume(&mut self.ticket) => break Stop::Moved(moved),
+                };
+                match read {
+                    Ok(0) => break Stop::Lost,
+                    Ok(m) if let Some(decoder) = &mut frames => {
+                        last_progress = clock::now();
+                        let (data, line) = framed_chunk(decoder, &read_buf[..m]);
+                        total_rx += data;
+                        measured.add(data as u64);
+                        session.add_bytes(data as u64);
+                        if let Some(iv) = intervals.tick(total_rx as u64) {
+                            report_interval(tenant, peer, self.stream, &iv);
+                        }
+                        match line.map(|line| line.map(|l| Command::parse(&l))) {
+                            None => {}
+                            Some(Ok(cmd)) if cmd.verb == "END_UPLOAD" => {
+                                println!("[{}] TCP client {} ended upload early", tenant, peer);
+                                end_upload = true;
+                                break Stop::Done;
+                            }
+                            Some(Ok(cmd)) if cmd.verb == "EXTEND" => {
+                                let reply = extend::extend(state, &cmd, [&**window]);
+                                println!("[{}] TCP upload from {}: {}", tenant, peer, reply);
+                            }
+                            Some(Ok(cmd)) => {
+                                let frame = error_frame(ErrorCode::BadCommand, format!("{} is not accepted during an upload (only END_UPLOAD and EXTEND)", cmd.verb));
+                                self.stream.write_all(format!("{}\n", frame).as_bytes()).await?;
+                            }
+                            Some(Err(e)) => {
+                                println!("[{}] TCP upload from {} lost its framing: {}", tenant, peer, e);
+                                self.stream.write_all(format!("{}\n", error_frame(ErrorCode::BadCommand, e)).as_bytes()).await?;
+                                frames = None;
+                                end_upload = true;
+                                break Stop::Done;
+                            }
+                        }
+                    }
+                    Ok(m) => {
+                        last_progress = clock::now();
+                        // Without framing, END_UPLOAD is recognised only as the tail of a chunk.
+                        let chunk = read_buf[..m].trim_ascii_end();
+                        if chunk.ends_with(b"END_UPLOAD") {
+                            let data = chunk.len() - b"END_UPLOAD".len();
+                            total_rx += data;
+                            measured.add(data as u64);
+                            session.add_bytes(data as u64);
+                            println!("[{}] TCP client {} ended upload early", tenant, peer);
+                            end_upload = true;
+                            break Stop::Done;
+                        }
+                        let data = match extend::trailing(chunk) {
+                            Some((at, cmd)) => {
+                                let reply = extend::extend(state, &cmd, [&**window]);
+                                println!("[{}] TCP upload from {}: {}", tenant, peer, reply);
+                                at
+                            }
+                            None => m,
+                        };
+                        total_rx += data;
+                        measured.add(data as u64);
+                        session.add_bytes(data as u64);
+                        if let Some(iv) = intervals.tick(total_rx as u64) {
+                            report_interval(tenant, peer, self.stream, &iv);
+                        }
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                        tokio::task::yield_now().await;
+                    }
+                    Err(e) if e.kind() == ErrorKind::ConnectionReset => {
+                        println!("[{}] Client reset connection during upload: {}", tenant, peer);
+                        break Stop::Lost;
+                    }
+                    Err(e) => {
+                        eprintln!("[{}] TCP read error during upload from {}: {:?}", tenant, peer, e);
+                        break Stop::Lost;
+                    }
+                }
+            };
+            let moved = match stop {
+                Stop::Done => break,
+                Stop::Moved(moved) => moved,
+                Stop::Lost => match await_resume(&mut self.ticket, state, start, window, cancel).await {
+                    Some(moved) => moved,
+                    None => {
+                        ended = self.ticket.is_some().then_some(last_progress);
+                        break;
+                    }
+                },
+            };
+            resumes.add(clock::elapsed(last_progress));
+            println!("[{}] TCP upload {} resumed from {} after {:?}", tenant, session.id(), moved.1, clock::elapsed(last_progress));
+            take_over(self.stream, &mut self.peer, moved, session.id(), total_rx as u64, self.dscp, self.steering).await;
+            // The client starts the new connection on a frame boundary.
+            if let Some(decoder) = &mut frames {
+                *decoder = FrameDecoder::default();
+            }
+        }
+        session.end();
+        println!("[{}] TCP server received {} bytes during upload from {}", tenant, total_rx, self.peer);
+        if !end_upload {
+            self.after = frames.map_or(Mode::Unframed, Mode::Framed);
+        }
+        Ok(Streamed { ended: ended.unwrap_or_else(clock::now), resumes, ..Streamed::finished(measured) })
+    }
+
+    fn report(&self, result: TestResult) -> TestResult {
+        let stream = &*self.stream;
+        let result = result
+            .with_tcp_info(tcpinfo::sample(stream))
+            .with_buffers(sockopt::BufferSizes::of(socket2::SockRef::from(stream)).ok())
+            .with_dscp(self.dscp)
+            .with_steering(self.steering.or(self.state.config.steering))
+            .with_interface(sockopt::test_interface(stream.local_addr(), self.state.config.bind_device.as_deref()));
+        match result.direction {
+            Direction::Download => {
+                result
+                    .with_payload(self.source.name())
+                    .with_cca(sockopt::tcp_congestion(stream))
+                    .with_compression(self.compression)
+                    .with_pacing(self.pacing)
+            }
+            _ => result,
+        }
+    }
+}
+
+/// A slot to run the test in, after queueing for one if the server is at
+/// capacity; None if the test was refused, which the client has been told.
+async fn take_slot(stream: &mut TcpStream, state: &ServerState, session: &SessionGuard, tenant: &str, peer: SocketAddr) -> anyhow::Result<Option<Slot>> {
+    let admission = state.slots.admit();
+    if let Admission::Queued(position, _) = &admission {
+        println!("[{}] TCP {} queued for a test slot at position {}", tenant, peer, position);
+        stream.write_all(format!("QUEUED {}\n", position).as_bytes()).await?;
+    }
+    match admission.slot(session.token()).await {
+        Ok(slot) => Ok(Some(slot)),
+        Err(frame) => {
+            println!("[{}] TCP {} test refused: {}", tenant, peer, frame);
+            stream.write_all(format!("{}\n", frame).as_bytes()).await?;
+            Ok(None)
+        }
+    }
+}
+
+/// A ticket for the test `cmd` starts, if it asks to be resumable; a refusal
+/// gets an ERR frame and the test runs without one.
+async fn offer_ticket(stream: &mut TcpStream, cmd: &Command, state: &ServerState, session: &SessionGuard) -> anyhow::Result<Option<TcpTicket>> {
+    match resume::requested(state, cmd) {
+        Ok(wanted) => Ok(wanted.then(|| state.resume.offer_tcp(session.id()))),
+        Err(frame) => {
+            stream.write_all(format!("{}\n", frame).as_bytes()).await?;
+            Ok(None)
+        }
+    }
+}
+
+/// The next connection resuming the test, if it has a ticket.
+async fn next_resume(ticket: &mut Option<TcpTicket>) -> TcpResume {
+    match ticket {
+        Some(ticket) => match ticket.streams.recv().await {
+            Some(moved) => moved,
+            None => std::future::pending().await,
+        },
+        None => std::future::pending().await,
+    }
+}
+
+/// After the connection is lost, wait for the client to resume the test within
+/// the grace period and what is left of the window opened at `start`.
+async fn await_resume(ticket: &mut Option<TcpTicket>, state: &ServerState, start: Instant, window: &Window, cancel: &CancellationToken) -> Option<TcpResume> {
+    let ticket = ticket.as_mut()?;
+    let grace = state.config.resume_grace?;
+    tokio::select! {
+        moved = ticket.streams.recv() => moved,
+        _ = tokio::time::sleep(grace) => None,
+        // Also ends on a kill.
+        _ = clock::window_end(start, || window.get(), cancel) => None,
+    }
+}
+
+/// Carry the test on over the resuming connection and tell the client how far
+/// it got. A failed write shows up as a lost connection on the next round.
+async fn take_over(stream: &mut TcpStream, peer: &mut SocketAddr, (new, from): TcpResume, id: u64, bytes: u64, dscp: Option<u8>, steering: Steering) {
+    *stream = new;
+    *peer = from;
+    let _ = stream.set_nodelay(true);
+    if let Some(dscp) = dscp {
+        let _ = sockopt::set_tcp_dscp(stream, from, dscp);
+    }
+    let _ = steering.apply(socket2::SockRef::from(&*stream));
+    let _ = stream.write_all(format!("RESUMED SESSION={} BYTES={}\n", id, bytes).as_bytes()).await;
+}
+
+/// Count the payload of a read of framed upload data, up to the first control
+/// line, which is returned along with the payload byte count. Bytes after the
+/// line are left unread, as a command read is.
+fn framed_chunk(decoder: &mut FrameDecoder, chunk: &[u8]) -> (usize, Option<Result<String, String>>) {
+    let mut input = chunk;
+    let mut data = 0;
+    while let Some(event) = decoder.next(&mut input) {
+        match event {
+            Ok(Event::Data(n)) => data += n,
+            Ok(Event::Line(line)) => return (data, Some(Ok(line))),
+            Err(e) => return (data, Some(Err(e))),
+        }
+    }
+    (data, None)
+}
+
+async fn sleep_or_forever(duration: Option<Duration>) {
+    match duration {
+        Some(d) => tokio::time::sleep(d).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Apply a `DSCP=` option to the connection. Invalid or unsupported values are
+/// reported with an ERR frame and the test runs unmarked.
+async fn apply_dscp(stream: &mut TcpStream, cmd: &Command, tenant: &str, peer: SocketAddr) -> anyhow::Result<Option<u8>> {
+    let Some(value) = cmd.opt("DSCP") else { return Ok(None) };
+    let Some(dscp) = sockopt::parse_dscp(value) else {
+        stream.write_all(format!("{}\n", error_frame(ErrorCode::InvalidOption, format!("invalid DSCP={}", value))).as_bytes()).await?;
+        return Ok(None);
+    };
+    if let Err(e) = sockopt::set_tcp_dscp(stream, peer, dscp) {
+        eprintln!("[{}] TCP {} cannot set DSCP {}: {}", tenant, peer, dscp, e);
+        let frame = error_frame(ErrorCode::UnsupportedOption, format!("unsupported DSCP={}: {}", value, e));
+        stream.write_all(format!("{}\n", frame).as_bytes()).await?;
+        return Ok(None);
+    }
+    Ok(Some(dscp))
+}
+
+/// Apply `PRIORITY=` and `MARK=` options to the connection; what was applied.
+/// A mark not among `--client-marks`, or one the kernel refuses, is reported
+/// with an ERR frame and the connection keeps the server's setting.
+async fn apply_steering(stream: &mut TcpStream, cmd: &Command, state: &ServerState, tenant: &str, peer: SocketAddr) -> anyhow::Result<Steering> {
+    let mut steering = Steering {
+        priority: read_option(stream, cmd, "PRIORITY", sockopt::parse_priority).await?,
+        mark: read_option(stream, cmd, "MARK", sockopt::parse_mark).await?,
+    };
+    if let Some(mark) = steering.mark.filter(|m| !state.config.client_marks.contains(m)) {
+        let frame = error_frame(ErrorCode::Unauthorized, format!("MARK={} is not among this server's client marks", mark));
+        stream.write_all(format!("{}\n", frame).as_bytes()).await?;
+        steering.mark = None;
+    }
+    if let Err(e) = steering.apply(socket2::SockRef::from(&*stream)) {
+        eprintln!("[{}] TCP {} cannot set {}: {}", tenant, peer, steering, e);
+        let frame = error_frame(ErrorCode::UnsupportedOption, format!("unsupported {}: {}", steering, e));
+        stream.write_all(format!("{}\n", frame).as_bytes()).await?;
+        restore_steering(stream, steering, state);
+        return Ok(Steering::default());
+    }
+    Ok(steering)
+}
+
+/// Put what a test's `PRIORITY=` and `MARK=` changed back to the server's
+/// setting (or the kernel default of 0), so the next test on the connection
+/// starts from it.
+fn restore_steering(stream: &TcpStream, steering: Steering, state: &ServerState) {
+    let default = state.config.steering;
+    let restore = Steering {
+        priority: steering.priority.map(|_| default.priority.unwrap_or(0)),
+        mark: steering.mark.map(|_| default.mark.unwrap_or(0)),
+    };
+    let _ = restore.apply(socket2::SockRef::from(stream));
+}
+
+/// Put back the congestion control a test's `CCA=` replaced, so the next test
+/// on the connection starts from the one the connection had before.
+fn restore_cca(stream: &TcpStream, original: Option<&str>) {
+    if let Some(cca) = original {
+        let _ = sockopt::set_tcp_congestion(stream, cca);
+    }
+}
+
+/// Read a test option such as `OMIT=` or `BYTES=`; an invalid value gets an
+/// ERR frame and the test runs as if the option were absent.
+async fn read_option<T>(
+    stream: &mut TcpStream,
+    cmd: &Command,
+    key: &str,
+    parse: fn(&str) -> Option<T>,
+) -> anyhow::Result<Option<T>> {
+    let Some(value) = cmd.opt(key) else { return Ok(None) };
+    let parsed = parse(value);
+    if parsed.is_none() {
+        stream.write_all(format!("{}\n", error_frame(ErrorCode::InvalidOption, format!("invalid {}={}", key, value))).as_bytes()).await?;
+    }
+    Ok(parsed)
+}
+
+
+fn report_interval(tenant: &str, peer: SocketAddr, stream: &TcpStream, iv: &Interval) {
+    let info = tcpinfo::sample(stream).map(|i| i.fields()).unwrap_or_default();
+    println!(
+        "[{}] TCP interval {} t={:.1}s bytes={} bps={:.0} {}",
+        tenant, peer, iv.end.as_secs_f64(), iv.bytes, iv.bps(), info
+    );
+}
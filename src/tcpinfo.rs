@@ -0,0 +1,104 @@
+// proj2-serv/src/tcpinfo.rs
+// Kernel TCP_INFO sampling (Linux only). Other platforms report nothing.
+
+use tokio::net::TcpStream;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TcpInfoSample {
+    pub rtt_us: u32,
+    pub rttvar_us: u32,
+    pub snd_cwnd: u32,
+    pub total_retrans: u32,
+    /// Only reported by kernels new enough to fill `tcpi_pacing_rate` (bytes/sec).
+    pub pacing_rate: Option<u64>,
+}
+
+impl TcpInfoSample {
+    pub fn fields(&self) -> String {
+        let mut out = format!(
+            "rtt_us={} rttvar_us={} cwnd={} retrans={}",
+            self.rtt_us, self.rttvar_us, self.snd_cwnd, self.total_retrans
+        );
+        if let Some(rate) = self.pacing_rate {
+            out.push_str(&format!(" pacing_rate={}", rate));
+        }
+        out
+    }
+}
+
+/// Prefix of the kernel's `struct tcp_info` up to `tcpi_pacing_rate`. glibc's
+/// definition in libc stops before the pacing fields, so we mirror it here.
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Default)]
+#[allow(dead_code)]
+struct KernelTcpInfo {
+    state: u8,
+    ca_state: u8,
+    retransmits: u8,
+    probes: u8,
+    backoff: u8,
+    options: u8,
+    wscale: u8,
+    flags: u8,
+    rto: u32,
+    ato: u32,
+    snd_mss: u32,
+    rcv_mss: u32,
+    unacked: u32,
+    sacked: u32,
+    lost: u32,
+    retrans: u32,
+    fackets: u32,
+    last_data_sent: u32,
+    last_ack_sent: u32,
+    last_data_recv: u32,
+    last_ack_recv: u32,
+    pmtu: u32,
+    rcv_ssthresh: u32,
+    rtt: u32,
+    rttvar: u32,
+    snd_ssthresh: u32,
+    snd_cwnd: u32,
+    advmss: u32,
+    reordering: u32,
+    rcv_rtt: u32,
+    rcv_space: u32,
+    total_retrans: u32,
+    pacing_rate: u64,
+}
+
+#[cfg(target_os = "linux")]
+pub fn sample(stream: &TcpStream) -> Option<TcpInfoSample> {
+    use std::os::fd::AsRawFd;
+
+    let mut info = KernelTcpInfo::default();
+    let mut len = std::mem::size_of::<KernelTcpInfo>() as libc::socklen_t;
+    // SAFETY: `info` is a plain repr(C) struct and `len` holds its size; the kernel
+    // writes at most `len` bytes and updates `len` with the amount written.
+    let rc = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            &mut info as *mut KernelTcpInfo as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if rc != 0 {
+        return None;
+    }
+    let pacing_end = std::mem::offset_of!(KernelTcpInfo, pacing_rate) + std::mem::size_of::<u64>();
+    Some(TcpInfoSample {
+        rtt_us: info.rtt,
+        rttvar_us: info.rttvar,
+        snd_cwnd: info.snd_cwnd,
+        total_retrans: info.total_retrans,
+        pacing_rate: (len as usize >= pacing_end).then_some(info.pacing_rate),
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn sample(_stream: &TcpStream) -> Option<TcpInfoSample> {
+    None
+}
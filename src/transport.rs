@@ -0,0 +1,158 @@
+// proj2-serv/src/transport.rs
+// The transport-independent half of a throughput test. A transport implements
+// `TestTransport`: its handshake, moving the data stream in either direction,
+// and the result fields only it knows about. `run_test` wraps that with what
+// every test shares: metrics, host CPU sampling, the common result fields and
+// recording the result. The TCP and UDP planes parse their commands into a
+// `TestSpec` and hand it to `run_test` with their transport; a new transport
+// (QUIC, SCTP, WebSocket) plugs in the same way.
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use anyhow::bail;
+
+use crate::hostres;
+use crate::interval::Measured;
+use crate::otel::{TestTrace, TraceParent};
+use crate::protocol::{ClientMeta, Command};
+use crate::results::{Direction, Protocol, TestResult};
+use crate::resume::Resumes;
+use crate::state::ServerState;
+
+/// What the client asked for, independent of the transport.
+#[derive(Debug, Clone)]
+pub struct TestSpec {
+    pub tenant: String,
+    pub direction: Direction,
+    /// Time limit, including the warm-up.
+    pub window: Duration,
+    /// `BYTES=` target; the test ends as soon as it is reached.
+    pub target: Option<u64>,
+    /// Warm-up excluded from the result (`OMIT=`).
+    pub omit: Duration,
+    /// Run the test belongs to (`RUN=`).
+    pub run: Option<String>,
+    /// Client trace to record the test's spans in (`TRACEPARENT=`); an
+    /// invalid value is ignored, as W3C trace context asks.
+    pub trace_parent: Option<TraceParent>,
+    /// What the client says about itself (`AGENT=`, `META=`).
+    pub meta: ClientMeta,
+}
+
+impl TestSpec {
+    pub fn new(state: &ServerState, cmd: &Command, direction: Direction, target: Option<u64>, omit: Duration) -> Self {
+        TestSpec {
+            tenant: cmd.tenant(),
+            direction,
+            window: state.test_window(target, omit),
+            target,
+            omit,
+            run: cmd.run(),
+            trace_parent: cmd.opt("TRACEPARENT").and_then(TraceParent::parse),
+            meta: cmd.meta(),
+        }
+    }
+
+    /// Fill in client metadata the start command left out from the
+    /// connection's HELLO.
+    pub fn with_hello(mut self, hello: &ClientMeta) -> Self {
+        self.meta = self.meta.or(hello);
+        self
+    }
+
+    /// Whether `moved` bytes still fall short of the `BYTES=` target.
+    pub fn below_target(&self, moved: u64) -> bool {
+        self.target.is_none_or(|t| moved < t)
+    }
+}
+
+/// How a data stream went.
+pub struct Streamed {
+    pub measured: Measured,
+    /// When the stream stopped; the end of the measured interval.
+    pub ended: Instant,
+    /// Why the server cut the test short, if it did.
+    pub aborted: Option<&'static str>,
+    /// Paths the client resumed the test over (`RESUMABLE=1`).
+    pub resumes: Resumes,
+}
+
+impl Streamed {
+    pub fn finished(measured: Measured) -> Self {
+        Streamed { measured, ended: Instant::now(), aborted: None, resumes: Resumes::default() }
+    }
+}
+
+pub trait TestTransport: Send {
+    /// Protocol results are recorded under.
+    const PROTOCOL: Protocol;
+
+    fn peer(&self) -> SocketAddr;
+
+    /// Agree on the test with the client before any data moves. False if the
+    /// client never completed the handshake and the test did not start.
+    fn handshake(&mut self, _spec: &TestSpec) -> impl Future<Output = anyhow::Result<bool>> + Send {
+        async { Ok(true) }
+    }
+
+    /// Send the download stream until the window, the target or the client ends it.
+    fn send_stream(&mut self, _spec: &TestSpec) -> impl Future<Output = anyhow::Result<Streamed>> + Send {
+        async { bail!("{} transport cannot send a download stream", Self::PROTOCOL.as_str()) }
+    }
+
+    /// Receive the upload stream until the window, the target or the client ends it.
+    fn recv_stream(&mut self, _spec: &TestSpec) -> impl Future<Output = anyhow::Result<Streamed>> + Send {
+        async { bail!("{} transport cannot receive an upload stream", Self::PROTOCOL.as_str()) }
+    }
+
+    /// Add the fields only this transport knows about to the finished result.
+    fn report(&self, result: TestResult) -> TestResult {
+        result
+    }
+}
+
+/// Run one throughput test over `transport` and record its result. Returns
+/// false if the handshake failed and nothing was recorded.
+pub async fn run_test<T: TestTransport>(transport: &mut T, state: &ServerState, spec: &TestSpec) -> anyhow::Result<bool> {
+    let mut trace = state.telemetry.test_trace(spec.trace_parent.as_ref(), &spec.tenant, spec.direction);
+    let outcome = run_phases(transport, state, spec, &mut trace).await;
+    let error = match &outcome {
+        Ok(true) => None,
+        Ok(false) => Some("client never completed the handshake".to_string()),
+        Err(e) => Some(format!("{:#}", e)),
+    };
+    trace.finish(error);
+    outcome
+}
+
+async fn run_phases<T: TestTransport>(transport: &mut T, state: &ServerState, spec: &TestSpec, trace: &mut TestTrace) -> anyhow::Result<bool> {
+    trace.phase("handshake");
+    if !transport.handshake(spec).await? {
+        return Ok(false);
+    }
+    state.metrics.session_started(&spec.tenant);
+    let cpu = hostres::snapshot();
+    trace.phase("transfer");
+    let streamed = match spec.direction {
+        Direction::Download => transport.send_stream(spec).await?,
+        Direction::Upload => transport.recv_stream(spec).await?,
+        Direction::Echo => bail!("echo tests are not streamed"),
+    };
+    trace.phase("finalize");
+    let measured = &streamed.measured;
+    let result = TestResult::new(&spec.tenant, T::PROTOCOL, spec.direction, transport.peer(), measured.bytes(), measured.duration(streamed.ended))
+        .with_omit(spec.omit)
+        .with_target_bytes(spec.target)
+        .with_host_usage(cpu.and_then(|c| c.usage()))
+        .with_aborted(streamed.aborted)
+        .with_resumes(streamed.resumes)
+        .with_intervals(measured.intervals())
+        .with_run(spec.run.clone())
+        .with_meta(spec.meta.clone());
+    let result = transport.report(result);
+    trace.result(&result);
+    state.record(result);
+    Ok(true)
+}
@@ -0,0 +1,1414 @@
+// proj2-serv/src/udp.rs
+// UDP plane: single shared socket handling control datagrams, download floods
+// and upload accounting windows. A download may instead be striped across extra
+// ephemeral ports (`PORTS=N`) to expose and sidestep per-flow policers, or sent
+// from its own ephemeral socket connected to the client (`CONNECTED=1`, announced
+// as `ACK_DOWNLOAD PORT=<p>`), which keeps send-buffer backpressure and socket
+// options such as DSCP to that session; `SRC=<ip>` does the same from a chosen
+// local address on a multi-homed server, and `PRIORITY=`/`MARK=` to set the
+// socket's SO_PRIORITY and SO_MARK for tc and fwmark rules. `REPLAY=<capture>`
+// paces the download by the packet sizes and timings of a capture (see
+// replay.rs), `PKT_TRACE=1` logs every datagram of a test (see pkttrace.rs), and
+// `ECN=1` marks it ECT(0); uploads count the ECN codepoints their datagrams
+// arrive with (see ecn.rs). `BLOAT=1` pings the client before and during the
+// download to grade latency under load (see bloat.rs), and `RAMP=` paces it
+// through a staircase of rates to find the knee of the link (see ramp.rs);
+// `OVERHEAD=` floods at several datagram sizes to estimate the per-packet
+// overhead of a tunnel on the path (see overhead.rs), and `RATE=` caps it,
+// paced by the kernel or the flood (see pacing.rs). A client address runs one
+// test at a time and further starts get `ERR BUSY`, except that an upload and a
+// download both started with `BIDIR=1` run together, and a repeat of the
+// running test's own START is answered with its ACK again. A download ends with
+// bursts of `FIN` until the client answers `FIN_ACK` (see ack.rs). Datagrams are
+// received on a task of their own and dispatched from a ring (see rxring.rs).
+
+use anyhow::{bail, Context};
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::AbortOnDropHandle;
+
+use crate::ack::{self, AckPolicy, PendingConfirms, AMPLIFICATION_LIMIT};
+use crate::auth;
+use crate::bloat::{self, Latency, Pinger, Pongs};
+use crate::capacity::{self, PendingProbes, ProbeSpec};
+use crate::clock;
+use crate::echo::UdpEchoes;
+use crate::ecn::{self, EcnCounts};
+use crate::extend;
+use crate::central::{self, CentralSender};
+use crate::flood::{self, Flood, SenderMode};
+use crate::icmp;
+use crate::impair::{ImpairedSocket, Impairment};
+use crate::interval::Measured;
+use crate::ledger::{Account, Ledger, Recorded};
+use crate::owd::{self, OwdProbes};
+use crate::pacing::{Mechanism, Pacing};
+use crate::payload::{self, PayloadSource};
+use crate::protocol::{self, error_frame, Command, ErrorCode};
+use crate::queue::Admission;
+use crate::overhead::{self, Overhead};
+use crate::ramp::{self, PendingReports, Ramp, StepReport};
+use crate::ratelimit::Verdict;
+use crate::rendezvous::{self, Outcome, Rendezvous};
+use crate::pkttrace::{self, PacketLog};
+use crate::replay::{self, Trace};
+use crate::resume::{self, Resumes, UdpMove};
+use crate::runs;
+use crate::rxring::RxRing;
+use crate::rxstamp;
+use crate::results::{Direction, Protocol, TestResult};
+use crate::session::{starts_test, SessionGuard, Window};
+use crate::signing;
+use crate::sockopt::{self, BufferSizes, Steering};
+use crate::state::ServerState;
+use crate::supervisor;
+use crate::transport::{self, Streamed, TestSpec, TestTransport};
+
+/// An in-progress UDP upload accounting window for one client address.
+struct UploadWindow {
+    /// Registry entry for the window; its id distinguishes successive windows
+    /// from the same address so a stale deadline timer never finalizes a newer one.
+    session: SessionGuard,
+    tenant: String,
+    started: Instant,
+    /// `BYTES=` target; the window closes as soon as it is reached.
+    target: Option<u64>,
+    measured: Measured,
+    /// ECN codepoints of the counted datagrams.
+    ecn: EcnCounts,
+    /// Per-packet log asked for with `PKT_TRACE=1`.
+    packets: Option<PacketLog>,
+    /// Ticket token of a `RESUMABLE=1` upload.
+    resume_token: Option<String>,
+    resumes: Resumes,
+    /// Hands the outcome to the upload's transport when the window closes.
+    done: oneshot::Sender<(Streamed, EcnCounts)>,
+}
+
+impl Account for UploadWindow {
+    fn id(&self) -> u64 {
+        self.session.id()
+    }
+
+    /// End of the window, as `EXTEND` has left it.
+    fn deadline(&self) -> Instant {
+        self.started + self.session.window().get()
+    }
+
+    fn target(&self) -> Option<u64> {
+        self.target
+    }
+
+    fn count(&mut self, len: usize, copies: usize) -> u64 {
+        let counted = (len * copies) as u64;
+        self.measured.add(counted);
+        self.session.add_bytes(counted);
+        if let Some(log) = &mut self.packets {
+            log.record(len);
+        }
+        self.session.counters().bytes
+    }
+}
+
+/// A running UDP download flood for one client address.
+struct DownloadHandle {
+    id: u64,
+    cancel: CancellationToken,
+    /// Set when ICMP errors show the client is gone.
+    unreachable: Arc<OnceLock<String>>,
+    /// Ticket token of a `RESUMABLE=1` download, and where its moves go.
+    resume: Option<(String, mpsc::Sender<UdpMove>)>,
+    /// ICMP errors came back for a resumable download; it stops unless it moves.
+    lost: bool,
+}
+
+type Downloads = Arc<Mutex<HashMap<SocketAddr, DownloadHandle>>>;
+type Uploads = Arc<Mutex<Ledger<UploadWindow>>>;
+
+pub async fn run_udp_server(udp_socket: Arc<UdpSocket>, state: Arc<ServerState>) -> anyhow::Result<()> {
+    // Active uploads: client -> window
+    let active_uploads: Uploads = Arc::default();
+    // Active downloads: client -> flood handle
+    let active_downloads: Downloads = Arc::new(Mutex::new(HashMap::new()));
+    let mut rendezvous = Rendezvous::default();
+    let mut capacity_probes = PendingProbes::default();
+    let mut owd_probes = OwdProbes::default();
+    let echoes = UdpEchoes::default();
+    let confirms = PendingConfirms::default();
+    let fin_acks = PendingConfirms::default();
+    let pongs = Pongs::default();
+    let ramp_reports = PendingReports::default();
+    let acks = state.config.udp_acks;
+    let impairment = state.config.impairment;
+    let device = state.config.bind_device.as_deref();
+    let tx = ImpairedSocket::new(udp_socket.clone(), impairment);
+    let (central, _central_sender) = CentralSender::spawn();
+    if impairment.is_active() {
+        println!(
+            "UDP impairment active: drop={:.1}% dup={:.1}% jitter={:?}",
+            impairment.drop * 100.0,
+            impairment.duplicate * 100.0,
+            impairment.jitter
+        );
+    }
+
+    // Stop floods toward clients that answer with ICMP unreachable.
+    let _gc = AbortOnDropHandle::new(tokio::spawn(collect_garbage(active_uploads.clone(), echoes.clone(), state.clone())));
+    let _icmp_watcher = match icmp::enable_error_queue(&udp_socket) {
+        Ok(()) => Some(AbortOnDropHandle::new(tokio::spawn(watch_icmp_errors(udp_socket.clone(), active_downloads.clone(), state.config.resume_grace)))),
+        Err(e) => {
+            eprintln!("UDP ICMP error reporting unavailable: {}", e);
+            None
+        }
+    };
+    match rxstamp::enable(&udp_socket, &state.config.rx_timestamps) {
+        Ok(true) => println!("UDP receive timestamps: {}", state.config.rx_timestamps),
+        Ok(false) => {}
+        Err(e) => eprintln!("UDP kernel receive timestamps unavailable, using the clock on receipt: {}", e),
+    }
+    if let Err(e) = ecn::enable_recv_tos(&udp_socket) {
+        eprintln!("UDP ECN codepoints of received datagrams unavailable; uploads will not count CE marks: {}", e);
+    }
+    // Receive on a task of its own, dispatch here.
+    let mut ring = RxRing::spawn(udp_socket.clone(), state.config.udp_rx_ring, state.config.udp_max_datagram);
+
+    loop {
+        let received = ring.next().await?;
+        let (addr, received_us, tos) = (received.addr, received.received_us, received.tos);
+        let len = received.buf.len();
+        // Simulated loss on receive; duplicates only matter for upload accounting.
+        let copies = impairment.copies();
+        if copies == 0 {
+            continue;
+        }
+        // Echo traffic goes straight back, whatever it contains.
+        let datagram = &received.buf[..];
+        if datagram.trim_ascii() != b"END_ECHO" && echoes.count(addr, len).await {
+            for copy in 0..copies {
+                if copy > 0 {
+                    echoes.count(addr, len).await;
+                }
+                if let Err(e) = tx.send_to(datagram, &addr).await {
+                    eprintln!("UDP echo to {} failed: {:?}", addr, e);
+                }
+            }
+            continue;
+        }
+        let (msg, cmd) = protocol::decode_frame(&received.buf);
+        let tenant = cmd.tenant();
+        println!("[{}] UDP server received from {}: {}", tenant, addr, msg);
+        // A START retransmitted because its ACK was lost gets the ACK again and
+        // leaves the running test as it is. Its NONCE, TS and MAC may differ, or
+        // repeat a nonce already taken; once its MAC checks out it is answered
+        // without a rate-limit token.
+        if let Some(direction) = starts_test(&cmd.verb)
+            && let Some(ack) = state.sessions.repeated(addr, direction, &auth::unsigned(&msg))
+            && state.control_auth.as_ref().is_none_or(|auth| auth.authenticate(&msg, &cmd).is_ok())
+        {
+            println!("[{}] UDP {} repeated {}; {}", tenant, addr, cmd.verb, if ack.is_some() { "re-sending its ACK" } else { "ACK still to come" });
+            if let Some(ack) = ack {
+                send_reply(&tx, addr, &ack).await;
+            }
+            continue;
+        }
+        match state.control.check(addr.ip(), &cmd.verb, true) {
+            Verdict::Allow => {}
+            Verdict::Reject => {
+                println!("[{}] UDP {} over the control rate limit; rejecting {}", tenant, addr, cmd.verb);
+                send_reply(&tx, addr, &error_frame(ErrorCode::Busy, "control rate limit exceeded")).await;
+                continue;
+            }
+            Verdict::Drop => continue,
+        }
+        if let Some(frame) = state.drain.refuse(&cmd.verb) {
+            println!("[{}] UDP {} refused {} while draining", tenant, addr, cmd.verb);
+            send_reply(&tx, addr, &frame).await;
+            continue;
+        }
+        if let Some(Err(frame)) = state.control_auth.as_ref().map(|auth| auth.verify(&msg, &cmd)) {
+            println!("[{}] UDP {} refused unsigned or invalid {}: {}", tenant, addr, cmd.verb, frame);
+            send_reply(&tx, addr, &frame).await;
+            continue;
+        }
+        // One test at a time per client address, so its datagrams are never
+        // mistaken for another test's, and one per direction per client host;
+        // retried starts must not stack floods. An upload and a download may
+        // share the address if both ask with BIDIR=1.
+        let bidir = match starts_test(&cmd.verb) {
+            Some(_) => read_option(&tx, addr, &cmd, "BIDIR", protocol::parse_flag).await.unwrap_or(false),
+            None => false,
+        };
+        if let Some(direction) = starts_test(&cmd.verb)
+            && let Some(running) = state.sessions.conflict(addr, direction, bidir)
+        {
+            let frame = match running == direction {
+                true => error_frame(ErrorCode::Busy, format!("a {} test is already running for {}", running.as_str(), addr.ip())),
+                false => error_frame(
+                    ErrorCode::Busy,
+                    format!("a {} test is already running for {}; start both with BIDIR=1 to run an upload and a download together", running.as_str(), addr),
+                ),
+            };
+            send_reply(&tx, addr, &frame).await;
+            continue;
+        }
+
+        if cmd.verb == "START_DOWNLOAD" {
+            let mut dscp = None;
+            if let Some(value) = cmd.opt("DSCP") {
+                let err = match sockopt::parse_dscp(value) {
+                    None => Some(error_frame(ErrorCode::InvalidOption, format!("invalid DSCP={}", value))),
+                    Some(_) if !sockopt::UDP_TOS_SUPPORTED => Some(error_frame(ErrorCode::UnsupportedOption, format!("unsupported DSCP={}", value))),
+                    parsed => {
+                        dscp = parsed;
+                        None
+                    }
+                };
+                if let Some(err) = err
+                    && let Err(e) = tx.send_to(err.as_bytes(), &addr).await
+                {
+                    eprintln!("UDP send ERR failed to {}: {:?}", addr, e);
+                }
+            }
+            let mut ect = read_option(&tx, addr, &cmd, "ECN", protocol::parse_flag).await.unwrap_or(false);
+            if ect && !sockopt::UDP_TOS_SUPPORTED {
+                send_reply(&tx, addr, &error_frame(ErrorCode::UnsupportedOption, "unsupported ECN=1")).await;
+                ect = false;
+            }
+            let omit = read_option(&tx, addr, &cmd, "OMIT", protocol::parse_omit).await.unwrap_or_default();
+            let target = read_option(&tx, addr, &cmd, "BYTES", protocol::parse_byte_count).await;
+            let source = match payload::from_command(&cmd, state.payload_file.as_ref()) {
+                Ok(source) => source,
+                Err(e) => {
+                    send_reply(&tx, addr, &error_frame(ErrorCode::UnsupportedOption, e)).await;
+                    Box::new(payload::Zeros)
+                }
+            };
+            let replay = match cmd.opt("REPLAY") {
+                Some(name) => match replay::load(&state, name).await {
+                    Ok(trace) => Some(trace),
+                    Err(e) => {
+                        send_reply(&tx, addr, &e).await;
+                        None
+                    }
+                },
+                None => None,
+            };
+            let ramp = match read_option(&tx, addr, &cmd, "RAMP", ramp::parse_rates).await {
+                Some(_) if replay.is_some() => {
+                    send_reply(&tx, addr, &error_frame(ErrorCode::UnsupportedOption, "RAMP= cannot be combined with REPLAY=")).await;
+                    None
+                }
+                Some(rates) => Some(Ramp::new(rates, read_option(&tx, addr, &cmd, "STEP", ramp::parse_step).await.unwrap_or(ramp::DEFAULT_STEP))),
+                None => None,
+            };
+            let overhead = match read_option(&tx, addr, &cmd, "OVERHEAD", overhead::parse_sizes).await {
+                Some(_) if replay.is_some() || ramp.is_some() => {
+                    send_reply(&tx, addr, &error_frame(ErrorCode::UnsupportedOption, "OVERHEAD= cannot be combined with REPLAY= or RAMP=")).await;
+                    None
+                }
+                Some(sizes) => {
+                    let step = read_option(&tx, addr, &cmd, "STEP", ramp::parse_step).await.unwrap_or(overhead::DEFAULT_STEP);
+                    Some(Overhead::new(sizes, step, addr))
+                }
+                None => None,
+            };
+            let packets = match pkttrace::requested(&state, &cmd, addr, Direction::Download) {
+                Ok(packets) => packets,
+                Err(e) => {
+                    send_reply(&tx, addr, &e).await;
+                    None
+                }
+            };
+            let mut resumable = requested_resume(&tx, addr, &state, &cmd).await;
+            let sender = read_option(&tx, addr, &cmd, "SENDER", SenderMode::parse).await.unwrap_or(state.config.udp_sender);
+            let weight = read_option(&tx, addr, &cmd, "WEIGHT", central::parse_weight).await.unwrap_or(1);
+            let datagram = match read_option(&tx, addr, &cmd, "DATAGRAM", protocol::parse_datagram_size).await {
+                Some(n) if n > state.config.udp_max_datagram => {
+                    let frame = error_frame(ErrorCode::UnsupportedOption, format!("DATAGRAM={} exceeds this server's limit of {}", n, state.config.udp_max_datagram));
+                    send_reply(&tx, addr, &frame).await;
+                    None
+                }
+                n => n,
+            };
+            // Checked against the route to the client: a datagram cut into
+            // fragments is lost whole when any one of them is.
+            let path_mtu = sockopt::path_mtu(addr, device);
+            if let (Some(n), Some(mtu)) = (datagram, path_mtu)
+                && let fragments @ 2.. = sockopt::fragments(n, addr, mtu)
+            {
+                println!("[{}] UDP download to {}: DATAGRAM={} exceeds the path MTU of {} and goes out in {} fragments", tenant, addr, n, mtu, fragments);
+            }
+            let (socks, stripe_ports) = match read_option(&tx, addr, &cmd, "PORTS", protocol::parse_stripe_ports).await {
+                Some(n) => match bind_stripe_ports(n, state.config.steering, impairment, device) {
+                    Ok(socks) => (socks, Some(n)),
+                    Err(e) => {
+                        eprintln!("[{}] UDP cannot open {} stripe ports for {}: {:#}", tenant, n, addr, e);
+                        send_reply(&tx, addr, &error_frame(ErrorCode::Failed, format!("cannot open PORTS={}: {:#}", n, e))).await;
+                        (vec![tx.clone()], None)
+                    }
+                },
+                None => (vec![tx.clone()], None),
+            };
+            // SRC=, PRIORITY= and MARK= need a socket of its own, as CONNECTED=1 gives.
+            let src = read_option(&tx, addr, &cmd, "SRC", |v| v.parse::<IpAddr>().ok()).await;
+            let mut steering = Steering {
+                priority: read_option(&tx, addr, &cmd, "PRIORITY", sockopt::parse_priority).await,
+                mark: read_option(&tx, addr, &cmd, "MARK", sockopt::parse_mark).await,
+            };
+            if let Some(mark) = steering.mark.filter(|m| !state.config.client_marks.contains(m)) {
+                send_reply(&tx, addr, &error_frame(ErrorCode::Unauthorized, format!("MARK={} is not among this server's client marks", mark))).await;
+                steering.mark = None;
+            }
+            let want_connected = read_option(&tx, addr, &cmd, "CONNECTED", protocol::parse_flag).await;
+            let (socks, connected) = match want_connected.unwrap_or(false) || src.is_some() || steering.is_set() {
+                true if stripe_ports.is_some() => {
+                    let frame = error_frame(ErrorCode::UnsupportedOption, "CONNECTED=1, SRC=, PRIORITY= and MARK= cannot be combined with PORTS=");
+                    send_reply(&tx, addr, &frame).await;
+                    (socks, false)
+                }
+                true => match connect_session_socket(addr, src, ecn::tos(dscp, ect), steering.or(state.config.steering), impairment, device).await {
+                    Ok(sock) => (vec![sock], true),
+                    Err(e) => {
+                        eprintln!("[{}] UDP cannot open a connected socket for {}: {:#}", tenant, addr, e);
+                        send_reply(&tx, addr, &error_frame(ErrorCode::Failed, format!("cannot open CONNECTED socket: {:#}", e))).await;
+                        (socks, false)
+                    }
+                },
+                false => (socks, false),
+            };
+            if !connected {
+                // Sent from the shared socket after all, with the server's setting.
+                steering = Steering::default();
+            }
+            let mut pacing = Pacing::requested(
+                read_option(&tx, addr, &cmd, "RATE", protocol::parse_byte_count).await,
+                read_option(&tx, addr, &cmd, "PACING", Mechanism::parse).await,
+            );
+            if pacing.is_some() && (replay.is_some() || ramp.is_some() || overhead.is_some()) {
+                send_reply(&tx, addr, &error_frame(ErrorCode::UnsupportedOption, "RATE= cannot be combined with REPLAY=, RAMP= or OVERHEAD=")).await;
+                pacing = None;
+            }
+            if let Some(pacing) = &mut pacing {
+                // The shared socket's rate would be every client's.
+                match connected || stripe_ports.is_some() {
+                    true => pacing.apply(&socks.iter().map(ImpairedSocket::sock_ref).collect::<Vec<_>>()),
+                    false => pacing.fall_back(&[]),
+                }
+            }
+            if resumable && connected {
+                // A connected socket cannot follow the client to a new address.
+                send_reply(&tx, addr, &error_frame(ErrorCode::UnsupportedOption, "RESUMABLE=1 cannot be combined with CONNECTED=1, SRC=, PRIORITY= or MARK=")).await;
+                resumable = false;
+            }
+            // Announce the stripe ports so the client can expect data from each.
+            let mut ack = match stripe_ports {
+                _ if connected => {
+                    let port = socks[0].local_addr().map(|a| a.port().to_string()).unwrap_or_default();
+                    format!("ACK_DOWNLOAD PORT={}", port)
+                }
+                Some(_) => {
+                    let ports: Vec<String> = socks
+                        .iter()
+                        .map(|s| s.local_addr().map(|a| a.port().to_string()).unwrap_or_default())
+                        .collect();
+                    format!("ACK_DOWNLOAD PORTS={}", ports.join(","))
+                }
+                None => "ACK_DOWNLOAD".to_string(),
+            };
+            if let (Some(_), Some(mtu)) = (datagram, path_mtu) {
+                ack.push_str(&format!(" MTU={}", mtu));
+            }
+            let bloat = read_option(&tx, addr, &cmd, "BLOAT", protocol::parse_flag).await.unwrap_or(false);
+            if bloat {
+                ack.push_str(&format!(" BLOAT_IDLE_MS={}", bloat::IDLE.as_millis()));
+            }
+            let handshake = read_option(&tx, addr, &cmd, "HANDSHAKE", protocol::parse_flag).await.unwrap_or(acks.handshake);
+            // Nothing is flooded toward an address until it echoes the cookie.
+            let cookie = acks.validate.then(|| confirms.cookie());
+            if let Some(cookie) = &cookie {
+                ack.push_str(&format!(" COOKIE={}", cookie));
+            }
+            let budget = cookie.is_some().then_some(len * AMPLIFICATION_LIMIT);
+            let Some(admission) = admit(&tx, addr, &state, &tenant).await else { continue };
+            let confirmed = (handshake || cookie.is_some()).then(|| confirms.expect(addr, cookie));
+
+            // The flood sends on the shared udp_socket (or the stripe ports, or its
+            // own connected socket), from this task or from a dedicated thread with
+            // SENDER=thread.
+            let session = state.sessions.register(Protocol::Udp, addr, &tenant, Some(Direction::Download));
+            session.started_by(&auth::unsigned(&msg));
+            if bidir {
+                session.allow_bidir();
+            }
+            let id = session.id();
+            let cancel = session.token().clone();
+            let unreachable = Arc::new(OnceLock::new());
+            let (resume, moves) = match resumable {
+                true => {
+                    let token = state.resume.token();
+                    ack.push_str(&format!(" SESSION={} TOKEN={}", id, token));
+                    let (tx, moves) = mpsc::channel(1);
+                    (Some((token, tx)), Some(moves))
+                }
+                false => (None, None),
+            };
+            active_downloads.lock().await.insert(
+                addr,
+                DownloadHandle { id, cancel: session.token().clone(), unreachable: unreachable.clone(), resume, lost: false },
+            );
+            let mut spec = TestSpec::new(&state, &cmd, Direction::Download, target, omit);
+            if let Some(ramp) = &ramp {
+                // The staircase sets the test's length.
+                spec.window = ramp.duration().min(state.limits.max_test_duration()) + omit;
+            }
+            if let Some(overhead) = &overhead {
+                spec.window = overhead.duration().min(state.limits.max_test_duration()) + omit;
+            }
+            let mut download = UdpDownload {
+                dest: addr,
+                tx: tx.clone(),
+                acks,
+                ack,
+                confirmed,
+                budget,
+                downloads: active_downloads.clone(),
+                id,
+                session: Some(session),
+                source: Some(source),
+                payload: "zeros",
+                replay_name: replay.as_ref().map(|t| t.name.clone()),
+                replay,
+                ramp_report: (ramp.is_some() || overhead.is_some()).then(|| ramp_reports.expect(addr)),
+                ramp,
+                overhead,
+                pacing,
+                packets,
+                moves,
+                resumes: Resumes::default(),
+                sender,
+                mode: sender,
+                central: central.clone(),
+                weight,
+                datagram: datagram.unwrap_or(flood::PAYLOAD_SIZE),
+                steering: steering.or(state.config.steering),
+                path_mtu,
+                buffers: socks[0].buffer_sizes().ok(),
+                src: src.filter(|_| connected),
+                iface: sockopt::test_interface(socks[0].local_addr(), device),
+                socks,
+                impairment,
+                stripe_ports,
+                connected,
+                dscp,
+                ect,
+                unreachable,
+                fin_acks: fin_acks.clone(),
+                pinger: bloat.then(|| Pinger::new(tx.clone(), addr, &pongs)),
+                latency: None,
+            };
+            let context = format!("[{}] UDP download to {} (session {})", tenant, addr, id);
+            let cleanup = {
+                let downloads = active_downloads.clone();
+                async move { forget_download(&downloads, id).await }
+            };
+            let task_state = state.clone();
+            supervisor::spawn_session(&state, context, async move {
+                let _slot = match admission.slot(&cancel).await {
+                    Ok(slot) => slot,
+                    Err(frame) => return send_reply(&download.tx, addr, &frame).await,
+                };
+                if let Err(e) = transport::run_test(&mut download, &task_state, &spec).await {
+                    eprintln!("[{}] UDP download to {} failed: {:#}", spec.tenant, addr, e);
+                }
+            }, cleanup);
+            continue;
+        }
+        else if cmd.verb == "CAPS" {
+            if let Err(e) = tx.send_to(state.caps().as_bytes(), &addr).await {
+                eprintln!("UDP send CAPS failed to {}: {:?}", addr, e);
+            }
+        }
+        else if cmd.verb == "HELLO" {
+            send_reply(&tx, addr, &state.hello(&cmd)).await;
+        }
+        else if cmd.verb == "WHOAMI" {
+            // Observed source address, as a NAT would present it to peers.
+            send_reply(&tx, addr, &format!("YOUARE {}", addr)).await;
+        }
+        else if cmd.verb == "OWD_PROBE" {
+            match owd::parse_probe(&cmd) {
+                Some((seq, t1)) => {
+                    let t3 = owd::now_us();
+                    if owd_probes.record(addr, seq, t1, received_us, t3) {
+                        send_reply(&tx, addr, &format!("OWD_REPLY {} {} {} {}", seq, t1, received_us, t3)).await;
+                    } else {
+                        send_reply(&tx, addr, &error_frame(ErrorCode::Busy, "too many pending OWD probes")).await;
+                    }
+                }
+                None => send_reply(&tx, addr, &error_frame(ErrorCode::BadCommand, "usage: OWD_PROBE <seq> <t1_us>")).await,
+            }
+        }
+        else if cmd.verb == "OWD_REPORT" {
+            let samples = owd_probes.complete(addr, &cmd);
+            let reply = match owd::estimate(&samples) {
+                Some(est) => {
+                    let line = est.reply();
+                    println!("[{}] UDP one-way delay for {}: {}", tenant, addr, line);
+                    line
+                }
+                None => error_frame(ErrorCode::NotFound, "no matching OWD probes in report"),
+            };
+            send_reply(&tx, addr, &reply).await;
+        }
+        else if cmd.verb == "CAPACITY_PROBE" {
+            match ProbeSpec::from_command(&cmd) {
+                Ok(spec) if capacity_probes.insert(addr, spec) => {
+                    let mut ack = format!("ACK_CAPACITY_PROBE TRAINS={} LEN={} SIZE={}", spec.trains, spec.train_len, spec.size);
+                    if acks.validate {
+                        // The trains wait for the cookie, as a download does.
+                        let cookie = confirms.cookie();
+                        ack.push_str(&format!(" COOKIE={}", cookie));
+                        let confirmed = confirms.expect(addr, Some(cookie));
+                        let tx = tx.clone();
+                        tokio::spawn(async move {
+                            if acks.handshake(&tx, addr, &ack, confirmed, Some(len * AMPLIFICATION_LIMIT)).await {
+                                capacity::send_trains(tx, addr, spec).await;
+                            }
+                        });
+                    } else {
+                        send_reply(&tx, addr, &ack).await;
+                        tokio::spawn(capacity::send_trains(tx.clone(), addr, spec));
+                    }
+                }
+                Ok(_) => send_reply(&tx, addr, &error_frame(ErrorCode::Busy, "too many pending capacity probes")).await,
+                Err(e) => send_reply(&tx, addr, &error_frame(ErrorCode::InvalidOption, e)).await,
+            }
+        }
+        else if cmd.verb == "DISPERSION_REPORT" {
+            let reply = match capacity_probes.take(addr) {
+                None => error_frame(ErrorCode::NotFound, "no capacity probe pending"),
+                Some(spec) => {
+                    let dispersions = capacity::parse_report(&cmd);
+                    match spec.estimate_bps(&dispersions) {
+                        Some(bps) => {
+                            println!("[{}] UDP capacity estimate for {}: {:.0} bps from {} trains", tenant, addr, bps, dispersions.len());
+                            format!("CAPACITY bps={:.0} trains={}", bps, dispersions.len())
+                        }
+                        None => error_frame(ErrorCode::BadCommand, "no usable dispersion in report"),
+                    }
+                }
+            };
+            send_reply(&tx, addr, &reply).await;
+        }
+        else if cmd.verb == "MCAST_INFO" {
+            send_reply(&tx, addr, &state.multicast.info()).await;
+        }
+        else if cmd.verb == "MCAST_REPORT" {
+            send_reply(&tx, addr, &state.multicast.report(&tenant, addr, &cmd)).await;
+        }
+        else if cmd.verb == "RENDEZVOUS" {
+            handle_rendezvous(&tx, &mut rendezvous, &cmd, addr).await;
+        }
+        else if cmd.verb == "END_DOWNLOAD" {
+            if let Some(handle) = active_downloads.lock().await.remove(&addr) {
+                handle.cancel.cancel();
+            }
+            if let Err(e) = tx.send_to(b"ACK_END_DOWNLOAD", &addr).await {
+                eprintln!("UDP send ACK_END_DOWNLOAD failed to {}: {:?}", addr, e);
+            }
+        }
+        else if cmd.verb == "START_ECHO" {
+            let omit = read_option(&tx, addr, &cmd, "OMIT", protocol::parse_omit).await.unwrap_or_default();
+            echoes.start(&state, addr, &tenant, cmd.run(), omit).await;
+            send_reply(&tx, addr, "ACK_ECHO").await;
+        }
+        else if cmd.verb == "END_ECHO" {
+            let requests = echoes.end(&state, addr).await;
+            send_reply(&tx, addr, &format!("ACK_END_ECHO requests={}", requests)).await;
+        }
+        else if cmd.verb == "END_UPLOAD" {
+            let window = active_uploads.lock().await.end(addr);
+            let reply = match window {
+                Some(window) => {
+                    let total = window.session.counters().bytes;
+                    println!("[{}] UDP server received {} bytes during upload from {} (ended early)", window.tenant, total, addr);
+                    finish_upload(window, clock::now(), None);
+                    format!("ACK_END_UPLOAD bytes={}", total)
+                }
+                None => "ACK_END_UPLOAD bytes=0".to_string(),
+            };
+            if let Err(e) = tx.send_to(reply.as_bytes(), &addr).await {
+                eprintln!("UDP send ACK_END_UPLOAD failed to {}: {:?}", addr, e);
+            }
+        }
+        else if cmd.verb == "START_UPLOAD" {
+            let omit = read_option(&tx, addr, &cmd, "OMIT", protocol::parse_omit).await.unwrap_or_default();
+            let target = read_option(&tx, addr, &cmd, "BYTES", protocol::parse_byte_count).await;
+            let handshake = read_option(&tx, addr, &cmd, "HANDSHAKE", protocol::parse_flag).await.unwrap_or(acks.handshake);
+            let packets = match pkttrace::requested(&state, &cmd, addr, Direction::Upload) {
+                Ok(packets) => packets,
+                Err(e) => {
+                    send_reply(&tx, addr, &e).await;
+                    None
+                }
+            };
+            let resume_token = requested_resume(&tx, addr, &state, &cmd).await.then(|| state.resume.token());
+            let Some(admission) = admit(&tx, addr, &state, &tenant).await else { continue };
+            // Registered now, though the window opens later, so the address is
+            // taken while the upload waits for CONFIRM or a slot.
+            let session = state.sessions.register(Protocol::Udp, addr, &tenant, Some(Direction::Upload));
+            session.started_by(&auth::unsigned(&msg));
+            if bidir {
+                session.allow_bidir();
+            }
+            let spec = TestSpec::new(&state, &cmd, Direction::Upload, target, omit);
+            let mut upload = UdpUpload {
+                session: Some(session),
+                addr,
+                tx: tx.clone(),
+                acks,
+                confirmed: None,
+                closed: None,
+                ecn: None,
+                packets,
+                resume_token,
+                uploads: active_uploads.clone(),
+                buffers: *state.udp_buffers.lock().unwrap(),
+                drops_before: tx.kernel_drops(),
+                state: state.clone(),
+            };
+            let queued = matches!(admission, Admission::Queued(..));
+            if handshake {
+                // The window opens only once the client confirms.
+                upload.confirmed = Some(confirms.expect(addr, None));
+            } else if !queued {
+                upload.open_announced(&spec).await;
+            }
+            // The window, if open, is still closed by its deadline task.
+            let context = format!("[{}] UDP upload from {}", tenant, addr);
+            let task_state = state.clone();
+            supervisor::spawn_session(&state, context, async move {
+                // A queued upload has no session to cancel it yet; it waits out the queue.
+                let _slot = match admission.slot(&CancellationToken::new()).await {
+                    Ok(slot) => slot,
+                    Err(frame) => return send_reply(&upload.tx, addr, &frame).await,
+                };
+                if queued && !handshake {
+                    upload.open_announced(&spec).await;
+                }
+                if let Err(e) = transport::run_test(&mut upload, &task_state, &spec).await {
+                    eprintln!("[{}] UDP upload from {} failed: {:#}", spec.tenant, addr, e);
+                }
+            }, std::future::ready(()));
+        }
+        else if cmd.verb == "RUN" {
+            send_reply(&tx, addr, &runs::reply(&state, &cmd)).await;
+        }
+        else if cmd.verb == "COMPARE" {
+            send_reply(&tx, addr, &runs::compare_reply(&state, &cmd)).await;
+        }
+        else if cmd.verb == "RESULT_KEY" {
+            send_reply(&tx, addr, &signing::reply(state.result_signer.as_ref())).await;
+        }
+        else if cmd.verb == "EXTEND" {
+            let windows = state.sessions.windows(Protocol::Udp, addr);
+            let reply = extend::extend(&state, &cmd, windows.iter().map(|w| &**w));
+            println!("[{}] UDP {}: {}", tenant, addr, reply);
+            send_reply(&tx, addr, &reply).await;
+        }
+        else if cmd.verb == "RESUME" {
+            let Some((id, token)) = resume::ticket(&cmd) else {
+                send_reply(&tx, addr, &resume::usage()).await;
+                continue;
+            };
+            if let Some(reply) = resume_upload(&active_uploads, addr, id, token).await {
+                send_reply(&tx, addr, &reply).await;
+                continue;
+            }
+            let (from, moves) = match resumable_download(&active_downloads, addr, id, token).await {
+                Some(Ok(found)) => found,
+                Some(Err(frame)) => {
+                    send_reply(&tx, addr, &frame).await;
+                    continue;
+                }
+                None => {
+                    send_reply(&tx, addr, &resume::not_found(id)).await;
+                    continue;
+                }
+            };
+            // Only the client knows when the datagrams stopped reaching it.
+            let gap = cmd
+                .opt("GAP_MS")
+                .and_then(|v| v.parse().ok())
+                .map_or(Duration::ZERO, Duration::from_millis)
+                .min(state.limits.max_test_duration());
+            let reply = format!("RESUMED SESSION={}", id);
+            if acks.validate {
+                // The flood moves only once the new address echoes a fresh cookie.
+                let cookie = confirms.cookie();
+                let reply = format!("{} COOKIE={}", reply, cookie);
+                let confirmed = confirms.expect(addr, Some(cookie));
+                let (tx, downloads) = (tx.clone(), active_downloads.clone());
+                tokio::spawn(async move {
+                    if acks.handshake(&tx, addr, &reply, confirmed, Some(len * AMPLIFICATION_LIMIT)).await
+                        && move_download(&downloads, id, addr, &moves, gap).await
+                    {
+                        println!("[{}] UDP download {} resumed at {} (was {})", tenant, id, addr, from);
+                    }
+                });
+            } else if move_download(&active_downloads, id, addr, &moves, gap).await {
+                println!("[{}] UDP download {} resumed at {} (was {})", tenant, id, addr, from);
+                send_reply(&tx, addr, &reply).await;
+            } else {
+                send_reply(&tx, addr, &resume::not_found(id)).await;
+            }
+        }
+        else if cmd.verb == "CONFIRM" {
+            if !confirms.confirm(addr, cmd.opt("COOKIE")) {
+                send_reply(&tx, addr, &error_frame(ErrorCode::NotFound, "no handshake pending for this COOKIE")).await;
+            }
+        } else if cmd.verb == "RAMP_REPORT" {
+            let reply = match ramp_reports.report(addr, &cmd) {
+                Ok(()) => "ACK_RAMP_REPORT".to_string(),
+                Err(frame) => frame,
+            };
+            send_reply(&tx, addr, &reply).await;
+        } else if cmd.verb == "PONG" {
+            // Answers a BLOAT=1 download's PING; stray ones are ignored.
+            pongs.pong(addr, &cmd);
+        } else if cmd.verb == "FIN_ACK" {
+            // Every FIN of a burst may be answered; only the first one counts.
+            fin_acks.confirm(addr, None);
+        } else {
+            // Non-control datagram: count toward active upload if present.
+            // Late datagrams past the deadline are ignored; the window's
+            // timer task finalizes it.
+            let now = clock::now();
+            let recorded = {
+                let mut uploads = active_uploads.lock().await;
+                let recorded = uploads.record(addr, len, copies, now);
+                if let Recorded::Counted = recorded
+                    && let Some(window) = uploads.get_mut(&addr)
+                {
+                    window.ecn.add(tos, copies);
+                }
+                recorded
+            };
+            match recorded {
+                Recorded::Complete(mut window) => {
+                    window.ecn.add(tos, copies);
+                    println!("[{}] UDP server received {} bytes during upload from {} (target reached)", window.tenant, window.session.counters().bytes, addr);
+                    finish_upload(window, now, None);
+                }
+                Recorded::Counted | Recorded::Late => {}
+                Recorded::NoWindow if protocol::looks_like_command(&msg) => {
+                    println!("[{}] UDP server: unknown command from {}: {:?}", tenant, addr, msg);
+                    let frame = error_frame(ErrorCode::BadCommand, format!("unknown command {:?}", cmd.verb));
+                    send_reply(&tx, addr, &frame).await;
+                }
+                Recorded::NoWindow => {
+                    // Unexpected payload; ignore or log for debug
+                    println!("UDP payload from {}: {} bytes (no active window)", addr, len);
+                }
+            }
+        }
+    }
+}
+
+/// A download flood toward one client as the transport of a throughput test.
+struct UdpDownload {
+    dest: SocketAddr,
+    /// Shared socket the ACKs go out on.
+    tx: ImpairedSocket,
+    acks: AckPolicy,
+    /// `ACK_DOWNLOAD`, announcing any stripe or connected port.
+    ack: String,
+    /// With `HANDSHAKE=1` or return-path validation, resolves once the client
+    /// sends CONFIRM.
+    confirmed: Option<oneshot::Receiver<()>>,
+    /// Bytes of ACKs allowed before the cookie comes back.
+    budget: Option<usize>,
+    downloads: Downloads,
+    id: u64,
+    /// Handed to the flood once it starts.
+    session: Option<SessionGuard>,
+    source: Option<Box<dyn PayloadSource>>,
+    /// Name of the payload the flood sent.
+    payload: &'static str,
+    /// Capture to replay, handed to the flood with the session.
+    replay: Option<Trace>,
+    replay_name: Option<String>,
+    /// Staircase of a `RAMP=` download, handed to the flood and back with what
+    /// it sent, and where the client's report of it arrives.
+    ramp: Option<Ramp>,
+    ramp_report: Option<oneshot::Receiver<Vec<StepReport>>>,
+    /// Sizes of an `OVERHEAD=` download, handed to the flood and back like a
+    /// staircase; the client reports it the same way.
+    overhead: Option<Overhead>,
+    /// Rate cap of the download (`RATE=`) and what enforces it.
+    pacing: Option<Pacing>,
+    /// Per-packet log, handed to the flood with the session.
+    packets: Option<PacketLog>,
+    /// New destinations of a `RESUMABLE=1` download, handed to the flood.
+    moves: Option<mpsc::Receiver<UdpMove>>,
+    resumes: Resumes,
+    /// Sender asked for, and the one that ran.
+    sender: SenderMode,
+    mode: SenderMode,
+    /// The plane's central sender, and this download's `WEIGHT=`.
+    central: CentralSender,
+    weight: usize,
+    /// Payload bytes of each datagram, and the MTU of the route to the client.
+    datagram: usize,
+    path_mtu: Option<usize>,
+    /// SO_PRIORITY and SO_MARK the datagrams went out with.
+    steering: Steering,
+    socks: Vec<ImpairedSocket>,
+    impairment: Impairment,
+    stripe_ports: Option<usize>,
+    connected: bool,
+    dscp: Option<u8>,
+    /// The datagrams went out ECT(0) (`ECN=1`).
+    ect: bool,
+    buffers: Option<BufferSizes>,
+    /// Local address asked for with `SRC=`.
+    src: Option<IpAddr>,
+    /// Interface the flood left from, where known.
+    iface: Option<String>,
+    /// Set when ICMP errors show the client is gone.
+    unreachable: Arc<OnceLock<String>>,
+    /// Where the client's `FIN_ACK` arrives.
+    fin_acks: PendingConfirms,
+    /// Pings of a `BLOAT=1` download, and what they showed.
+    pinger: Option<Pinger>,
+    latency: Option<Latency>,
+}
+
+/// Drop a finished download's handle, wherever a resume may have moved it.
+async fn forget_download(downloads: &Downloads, id: u64) {
+    downloads.lock().await.retain(|_, h| h.id != id);
+}
+
+impl TestTransport for UdpDownload {
+    const PROTOCOL: Protocol = Protocol::Udp;
+
+    fn peer(&self) -> SocketAddr {
+        self.dest
+    }
+
+    /// ACK before the first datagram so the client knows the request was seen.
+    async fn handshake(&mut self, spec: &TestSpec) -> anyhow::Result<bool> {
+        if let Some(session) = &self.session {
+            session.acked(&self.ack);
+        }
+        let ready = match self.confirmed.take() {
+            Some(confirmed) => self.acks.handshake(&self.tx, self.dest, &self.ack, confirmed, self.budget).await,
+            None => {
+                self.acks.send_burst(&self.tx, self.dest, &self.ack).await;
+                true
+            }
+        };
+        if !ready {
+            println!("[{}] UDP download to {} not started: client never sent CONFIRM", spec.tenant, self.dest);
+            forget_download(&self.downloads, self.id).await;
+        }
+        Ok(ready)
+    }
+
+    async fn send_stream(&mut self, spec: &TestSpec) -> anyhow::Result<Streamed> {
+        let (Some(session), Some(source)) = (self.session.take(), self.source.take()) else {
+            bail!("UDP download to {} already sent", self.dest);
+        };
+        let cancel = session.token().clone();
+        if let Some(pinger) = &mut self.pinger {
+            pinger.idle().await;
+        }
+        session.window().open(spec.window);
+        let flood = Flood {
+            dest: self.dest,
+            target: spec.target,
+            omit: spec.omit,
+            tos: ecn::tos(self.dscp, self.ect).filter(|_| !self.connected),
+            connected: self.connected,
+            replay: self.replay.take(),
+            ramp: self.ramp.take(),
+            overhead: self.overhead.take(),
+            pacing: self.pacing,
+            packets: self.packets.take(),
+            moves: self.moves.take(),
+            resumes: Resumes::default(),
+            central: self.central.clone(),
+            weight: self.weight,
+            datagram: self.datagram,
+            source,
+            session,
+        };
+        // The FIN follows the data out of the socket the client hears it from.
+        let fin_sock = self.socks.first().cloned().unwrap_or_else(|| self.tx.clone());
+        let run = flood.run(self.sender, std::mem::take(&mut self.socks), self.impairment);
+        let (flood, sent) = match &mut self.pinger {
+            Some(pinger) => pinger.loaded(run).await,
+            None => run.await,
+        };
+        self.latency = self.pinger.take().map(Pinger::finish);
+        self.payload = flood.source.name();
+        self.resumes = flood.resumes;
+        self.ramp = flood.ramp;
+        self.overhead = flood.overhead;
+        if let Some(log) = flood.packets {
+            log.save(&spec.tenant, self.id);
+        }
+        self.mode = sent.mode;
+        if let Some(reason) = sent.unreachable {
+            let _ = self.unreachable.set(reason);
+        }
+        forget_download(&self.downloads, self.id).await;
+        let (tenant, dest) = (&spec.tenant, self.dest);
+        if let Some(reason) = self.unreachable.get() {
+            println!("[{}] UDP download to {} stopped: client unreachable ({})", tenant, dest, reason);
+        } else if cancel.is_cancelled() {
+            println!("[{}] UDP download to {} stopped early", tenant, dest);
+        }
+        println!("[{}] UDP server finished sending download to {} (~{} bytes)", tenant, dest, sent.bytes);
+        if let Some(latency) = &self.latency {
+            println!("[{}] UDP download to {} latency under load: {}", tenant, dest, latency);
+        }
+        if self.unreachable.get().is_none() {
+            // A resumed flood ends at the address it moved to.
+            let (dest, acks, tenant) = (flood.dest, self.acks, tenant.clone());
+            let fin = format!("FIN {} PACKETS={}", sent.bytes, flood.session.counters().packets);
+            let acked = self.fin_acks.expect(dest, None);
+            tokio::spawn(async move {
+                if !acks.finish(&fin_sock, dest, &fin, acked).await {
+                    println!("[{}] UDP download to {}: no FIN_ACK after {} bursts", tenant, dest, ack::FIN_BURSTS);
+                }
+            });
+            // The client reports what each step delivered once it has the FIN.
+            if let Some(report) = self.ramp_report.take() {
+                let report = tokio::time::timeout(ramp::REPORT_WAIT, report).await.ok().and_then(Result::ok);
+                if report.is_none() {
+                    println!("[{}] UDP download to {}: no RAMP_REPORT within {:?}", spec.tenant, dest, ramp::REPORT_WAIT);
+                }
+                if let Some(ramp) = &mut self.ramp {
+                    ramp.report = report;
+                } else if let Some(probe) = &mut self.overhead {
+                    probe.report = report;
+                    let reply = probe.reply().unwrap_or_else(|| error_frame(ErrorCode::NotFound, "no overhead estimate from the client's report"));
+                    send_reply(&self.tx, dest, &reply).await;
+                }
+            }
+        }
+        Ok(Streamed { resumes: self.resumes, ..Streamed::finished(sent.measured) })
+    }
+
+    fn report(&self, result: TestResult) -> TestResult {
+        result
+            .with_payload(self.payload)
+            .with_replay(self.replay_name.clone())
+            .with_ramp(self.ramp.clone())
+            .with_overhead(self.overhead.clone())
+            .with_pacing(self.pacing)
+            .with_sender(self.mode.as_str())
+            .with_stripe_ports(self.stripe_ports)
+            .with_connected(self.connected)
+            .with_datagram(self.replay_name.is_none().then_some(self.datagram), self.path_mtu)
+            .with_buffers(self.buffers)
+            .with_dscp(self.dscp)
+            .with_ect(self.ect)
+            .with_steering(self.steering)
+            .with_src(self.src)
+            .with_interface(self.iface.clone())
+            .with_client_unreachable(self.unreachable.get().is_some())
+            .with_latency(self.latency)
+    }
+}
+
+/// Register an upload window for `addr` and finalize it at its deadline;
+/// returns the window's session id and where its outcome will arrive.
+async fn open_upload_window(
+    uploads: &Uploads,
+    session: SessionGuard,
+    addr: SocketAddr,
+    spec: &TestSpec,
+    packets: Option<PacketLog>,
+    resume_token: Option<String>,
+) -> (u64, oneshot::Receiver<(Streamed, EcnCounts)>) {
+    let started = clock::now();
+    session.begin(&spec.tenant, Direction::Upload);
+    session.window().open(spec.window);
+    let deadline = started + spec.window;
+    let id = session.id();
+    let extended = session.window().clone();
+    let cancel = session.token().clone();
+    let (done, closed) = oneshot::channel();
+    let window = UploadWindow {
+        session,
+        tenant: spec.tenant.clone(),
+        started,
+        target: spec.target,
+        measured: Measured::new(started, spec.omit),
+        ecn: EcnCounts::default(),
+        packets,
+        resume_token,
+        resumes: Resumes::default(),
+        done,
+    };
+    if let Some(displaced) = uploads.lock().await.open(addr, window) {
+        // A repeated START_UPLOAD; the earlier window ends with what it counted.
+        println!("[{}] UDP upload from {} replaced by a new START_UPLOAD after {} bytes", displaced.tenant, addr, displaced.session.counters().bytes);
+        finish_upload(displaced, started, Some("replaced"));
+    }
+    // Finalize exactly at the deadline, even if no further datagram arrives.
+    tokio::spawn(finalize_at_deadline(uploads.clone(), id, started, extended, cancel));
+    println!("[{}] UDP server registered upload window for {} until {:?}", spec.tenant, addr, deadline);
+    (id, closed)
+}
+
+/// An upload window as the transport of a throughput test. The receive loop
+/// counts the window's datagrams; whichever path closes it hands the outcome here.
+struct UdpUpload {
+    /// Handed to the window when it opens.
+    session: Option<SessionGuard>,
+    addr: SocketAddr,
+    tx: ImpairedSocket,
+    acks: AckPolicy,
+    /// With `HANDSHAKE=1`, resolves once the client sends CONFIRM.
+    confirmed: Option<oneshot::Receiver<()>>,
+    /// Outcome of a window opened before the test started.
+    closed: Option<oneshot::Receiver<(Streamed, EcnCounts)>>,
+    /// ECN codepoints the window counted, once it has closed.
+    ecn: Option<EcnCounts>,
+    /// Per-packet log, handed to the window when it opens.
+    packets: Option<PacketLog>,
+    /// Ticket token of a `RESUMABLE=1` upload, handed to the window.
+    resume_token: Option<String>,
+    uploads: Uploads,
+    buffers: Option<BufferSizes>,
+    /// Kernel drop count of the socket when the test was set up.
+    drops_before: Option<u64>,
+    state: Arc<ServerState>,
+}
+
+impl UdpUpload {
+    /// Open the window before ACKing, so no early datagram is missed, and
+    /// resend the ACK while no data arrives; the client may have missed it.
+    async fn open_announced(&mut self, spec: &TestSpec) {
+        let Some(session) = self.session.take() else { return };
+        let token = self.resume_token.take();
+        let ack = match &token {
+            Some(token) => format!("ACK_UPLOAD SESSION={} TOKEN={}", session.id(), token),
+            None => "ACK_UPLOAD".to_string(),
+        };
+        session.acked(&ack);
+        let (id, closed) = open_upload_window(&self.uploads, session, self.addr, spec, self.packets.take(), token).await;
+        self.closed = Some(closed);
+        let (uploads, tx, acks, addr) = (self.uploads.clone(), self.tx.clone(), self.acks, self.addr);
+        tokio::spawn(async move {
+            acks.send_burst(&tx, addr, &ack).await;
+            send_probe(&tx, addr).await;
+            for _ in 0..acks.retries {
+                tokio::time::sleep(ack::SILENCE_TIMEOUT).await;
+                let silent = uploads.lock().await.get(&addr).is_some_and(|w| w.session.id() == id && w.session.counters().packets == 0);
+                if !silent {
+                    break;
+                }
+                acks.send_burst(&tx, addr, &ack).await;
+            }
+        });
+    }
+}
+
+impl TestTransport for UdpUpload {
+    const PROTOCOL: Protocol = Protocol::Udp;
+
+    fn peer(&self) -> SocketAddr {
+        self.addr
+    }
+
+    async fn handshake(&mut self, spec: &TestSpec) -> anyhow::Result<bool> {
+        let Some(confirmed) = self.confirmed.take() else { return Ok(true) };
+        if let Some(session) = &self.session {
+            session.acked("ACK_UPLOAD");
+        }
+        let ready = self.acks.handshake(&self.tx, self.addr, "ACK_UPLOAD", confirmed, None).await;
+        if !ready {
+            println!("[{}] UDP upload from {} not started: client never sent CONFIRM", spec.tenant, self.addr);
+        }
+        Ok(ready)
+    }
+
+    async fn recv_stream(&mut self, spec: &TestSpec) -> anyhow::Result<Streamed> {
+        let closed = match self.closed.take() {
+            Some(closed) => closed,
+            None => {
+                let session = self.session.take().context("upload window already opened")?;
+                let token = self.resume_token.take();
+                let (id, closed) = open_upload_window(&self.uploads, session, self.addr, spec, self.packets.take(), token.clone()).await;
+                match token {
+                    // The ACK went out before the window had a session.
+                    Some(token) => send_reply(&self.tx, self.addr, &format!("RESUMABLE SESSION={} TOKEN={}", id, token)).await,
+                    None => send_probe(&self.tx, self.addr).await,
+                }
+                closed
+            }
+        };
+        let (streamed, ecn) = closed.await.context("upload window dropped without an outcome")?;
+        self.ecn = Some(ecn);
+        Ok(streamed)
+    }
+
+    fn report(&self, result: TestResult) -> TestResult {
+        let drops = self.drops_before.zip(self.tx.kernel_drops()).map(|(before, after)| after.saturating_sub(before));
+        if let Some(drops @ 1..) = drops {
+            println!(
+                "[{}] UDP upload from {}: the kernel dropped {} datagrams on the server socket (receive buffer overflow)",
+                result.tenant, self.addr, drops
+            );
+        }
+        result
+            .with_buffers(self.buffers)
+            .with_kernel_drops(drops)
+            .with_ecn_counts(self.ecn.filter(EcnCounts::capable))
+            .with_interface(self.state.config.bind_device.clone())
+    }
+}
+
+/// A tiny datagram after the ACKs to help NATs learn the mapping.
+async fn send_probe(sock: &ImpairedSocket, addr: SocketAddr) {
+    if let Err(e) = sock.send_to(b"P", &addr).await {
+        eprintln!("UDP send probe failed to {}: {:?}", addr, e);
+    }
+}
+
+/// Open `n` ephemeral UDP sockets for a striped download.
+fn bind_stripe_ports(n: usize, steering: Steering, impairment: Impairment, device: Option<&str>) -> anyhow::Result<Vec<ImpairedSocket>> {
+    (0..n)
+        .map(|_| {
+            let (sock, _) = crate::bind_udp(crate::any_addr(0), device).and_then(crate::adopt_udp)?;
+            steering.apply(socket2::SockRef::from(&sock)).context("setting SO_PRIORITY/SO_MARK")?;
+            Ok(ImpairedSocket::new(Arc::new(sock), impairment))
+        })
+        .collect()
+}
+
+/// Open an ephemeral UDP socket connected to `addr` for one download, so its
+/// send buffer and socket options belong to that session alone. `src` picks
+/// the local address (`SRC=`) on a multi-homed server; `tos` carries the
+/// session's DSCP and ECN bits, and `steering` is the
+/// session's `PRIORITY=` and `MARK=` over the server's own.
+async fn connect_session_socket(
+    addr: SocketAddr,
+    src: Option<IpAddr>,
+    tos: Option<u8>,
+    steering: Steering,
+    impairment: Impairment,
+    device: Option<&str>,
+) -> anyhow::Result<ImpairedSocket> {
+    let local = src.map_or_else(|| crate::any_addr(0), |ip| SocketAddr::new(ip, 0));
+    let (sock, _) = crate::bind_udp(local, device).and_then(crate::adopt_udp)?;
+    sock.connect(addr).await.context("connecting to client")?;
+    if let Some(tos) = tos {
+        sockopt::set_udp_tos(&sock, addr, tos).context("setting DSCP and ECN")?;
+    }
+    steering.apply(socket2::SockRef::from(&sock)).with_context(|| format!("setting {}", steering))?;
+    Ok(ImpairedSocket::new(Arc::new(sock), impairment))
+}
+
+/// `RENDEZVOUS <token>`: pair two clients and tell each where the other is.
+async fn handle_rendezvous(sock: &ImpairedSocket, rendezvous: &mut Rendezvous, cmd: &Command, addr: SocketAddr) {
+    let Some(token) = cmd.args.first().filter(|t| rendezvous::valid_token(t)) else {
+        send_reply(sock, addr, &error_frame(ErrorCode::BadCommand, "usage: RENDEZVOUS <token>")).await;
+        return;
+    };
+    match rendezvous.register(token, addr) {
+        Outcome::Waiting => send_reply(sock, addr, &format!("RENDEZVOUS_WAIT {}", addr)).await,
+        Outcome::Full => send_reply(sock, addr, &error_frame(ErrorCode::Busy, "rendezvous table full")).await,
+        Outcome::Paired(partner) => {
+            println!("UDP rendezvous {:?}: pairing {} with {}", token, partner, addr);
+            let start = rendezvous::START_DELAY.as_millis();
+            send_reply(sock, partner, &format!("PEER {} START_IN_MS={}", addr, start)).await;
+            send_reply(sock, addr, &format!("PEER {} START_IN_MS={}", partner, start)).await;
+        }
+    }
+}
+
+/// Admit a test to a slot, telling the client if it is queued; None if it
+/// was refused, which the client has been told.
+async fn admit(sock: &ImpairedSocket, addr: SocketAddr, state: &ServerState, tenant: &str) -> Option<Admission> {
+    match state.slots.admit() {
+        Admission::Full(frame) => {
+            println!("[{}] UDP {} test refused: {}", tenant, addr, frame);
+            send_reply(sock, addr, &frame).await;
+            None
+        }
+        admission => {
+            if let Admission::Queued(position, _) = &admission {
+                println!("[{}] UDP {} queued for a test slot at position {}", tenant, addr, position);
+                send_reply(sock, addr, &format!("QUEUED {}", position)).await;
+            }
+            Some(admission)
+        }
+    }
+}
+
+/// Whether the test `cmd` starts asks to be resumable; a refusal gets an ERR
+/// datagram and the test runs without a ticket.
+async fn requested_resume(sock: &ImpairedSocket, addr: SocketAddr, state: &ServerState, cmd: &Command) -> bool {
+    match resume::requested(state, cmd) {
+        Ok(wanted) => wanted,
+        Err(frame) => {
+            send_reply(sock, addr, &frame).await;
+            false
+        }
+    }
+}
+
+/// Move resumable upload `id` to `addr`, if its ticket matches; the reply to
+/// send, or None if no such upload is running.
+async fn resume_upload(uploads: &Uploads, addr: SocketAddr, id: u64, token: &str) -> Option<String> {
+    let mut ledger = uploads.lock().await;
+    let (from, _) = ledger.find(|w| w.session.id() == id && w.resume_token.as_deref() == Some(token))?;
+    let Ok(window) = ledger.relocate(from, addr) else {
+        return Some(error_frame(ErrorCode::Busy, format!("an upload is already running for {}", addr)));
+    };
+    let window = window?;
+    let gap = clock::elapsed(window.session.counters().last_activity);
+    window.resumes.add(gap);
+    window.session.touch();
+    println!("[{}] UDP upload {} resumed from {} (was {}) after {:?}", window.tenant, id, addr, from, gap);
+    Some(format!("RESUMED SESSION={} BYTES={}", id, window.session.counters().bytes))
+}
+
+/// Where resumable download `id` would move to `addr`, if its ticket matches:
+/// its current address and the flood's move channel.
+async fn resumable_download(downloads: &Downloads, addr: SocketAddr, id: u64, token: &str) -> Option<Result<(SocketAddr, mpsc::Sender<UdpMove>), String>> {
+    let map = downloads.lock().await;
+    let (from, handle) = map.iter().find(|(_, h)| h.id == id && h.resume.as_ref().is_some_and(|(t, _)| t == token))?;
+    if *from != addr && map.contains_key(&addr) {
+        return Some(Err(error_frame(ErrorCode::Busy, format!("a download is already running for {}", addr))));
+    }
+    Some(Ok((*from, handle.resume.as_ref()?.1.clone())))
+}
+
+/// Point download `id` at `addr`; false if it has ended meanwhile.
+async fn move_download(downloads: &Downloads, id: u64, addr: SocketAddr, moves: &mpsc::Sender<UdpMove>, gap: Duration) -> bool {
+    let mut map = downloads.lock().await;
+    let Some(from) = map.iter().find(|(_, h)| h.id == id).map(|(from, _)| *from) else { return false };
+    if moves.try_send((addr, gap)).is_err() {
+        return false;
+    }
+    if let Some(mut handle) = map.remove(&from) {
+        handle.lost = false;
+        map.insert(addr, handle);
+    }
+    true
+}
+
+async fn send_reply(sock: &ImpairedSocket, addr: SocketAddr, reply: &str) {
+    if let Err(e) = sock.send_to(reply.as_bytes(), &addr).await {
+        eprintln!("UDP send reply failed to {}: {:?}", addr, e);
+    }
+}
+
+async fn watch_icmp_errors(sock: Arc<UdpSocket>, downloads: Downloads, grace: Option<Duration>) {
+    loop {
+        match icmp::next_errors(&sock).await {
+            Ok(errors) => {
+                let mut map = downloads.lock().await;
+                for err in errors {
+                    let Some(handle) = map.get_mut(&err.peer) else { continue };
+                    if let (Some(_), Some(grace)) = (&handle.resume, grace) {
+                        // A resumable download gets the grace period to move first.
+                        if !handle.lost {
+                            handle.lost = true;
+                            tokio::spawn(stop_unless_resumed(downloads.clone(), err.peer, handle.id, err.describe(), grace));
+                        }
+                    } else if handle.unreachable.set(err.describe()).is_ok() {
+                        handle.cancel.cancel();
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("UDP error queue read failed: {:?}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+/// Stop download `id` as unreachable if it is still sent to `peer` after `grace`.
+async fn stop_unless_resumed(downloads: Downloads, peer: SocketAddr, id: u64, reason: String, grace: Duration) {
+    tokio::time::sleep(grace).await;
+    if let Some(handle) = downloads.lock().await.get(&peer).filter(|h| h.id == id && h.lost)
+        && handle.unreachable.set(reason).is_ok()
+    {
+        handle.cancel.cancel();
+    }
+}
+
+async fn finalize_at_deadline(uploads: Uploads, id: u64, started: Instant, window: Arc<Window>, cancel: CancellationToken) {
+    // An admin kill or shutdown closes the window early; EXTEND moves the deadline on.
+    clock::window_end(started, || window.get(), &cancel).await;
+    // A resumed window sits under its new address.
+    let closed = uploads.lock().await.close(id);
+    if let Some((_, window)) = closed {
+        println!("[{}] UDP server received {} bytes during upload (session {})", window.tenant, window.session.counters().bytes, id);
+        finish_upload(window, clock::now(), None);
+    }
+}
+
+fn finish_upload(mut window: UploadWindow, ended: Instant, aborted: Option<&'static str>) {
+    let ended = ended.min(window.deadline());
+    if let Some(log) = window.packets.take() {
+        log.save(&window.tenant, window.session.id());
+    }
+    let streamed = Streamed { measured: window.measured, ended, aborted, resumes: window.resumes };
+    let _ = window.done.send((streamed, window.ecn));
+}
+
+/// Sweep upload and echo windows whose client has gone quiet, recording them as
+/// aborted, so abandoned tests do not hold sessions until their deadline.
+async fn collect_garbage(uploads: Uploads, echoes: UdpEchoes, state: Arc<ServerState>) {
+    const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+    loop {
+        tokio::time::sleep(SWEEP_INTERVAL).await;
+        let now = clock::now();
+        let stale = uploads.lock().await.sweep(|w| {
+            // A resumable upload may go quiet for the grace period first.
+            let grace = w.resume_token.as_ref().and(state.config.resume_grace).unwrap_or_default();
+            let counts = w.session.counters();
+            state.stale_reason(counts.packets > 0, counts.last_activity + grace, now)
+        });
+        for (addr, window, reason) in stale {
+            let counts = window.session.counters();
+            println!("[{}] UDP upload from {} aborted ({}) after {} bytes", window.tenant, addr, reason, counts.bytes);
+            let ended = counts.last_activity;
+            finish_upload(window, ended, Some(reason));
+        }
+        echoes.collect_garbage(&state, now).await;
+    }
+}
+
+/// Read a test option such as `OMIT=` or `BYTES=`; an invalid value gets an
+/// ERR datagram and the test runs as if the option were absent.
+async fn read_option<T>(
+    sock: &ImpairedSocket,
+    addr: SocketAddr,
+    cmd: &Command,
+    key: &str,
+    parse: fn(&str) -> Option<T>,
+) -> Option<T> {
+    let value = cmd.opt(key)?;
+    let parsed = parse(value);
+    if parsed.is_none()
+        && let Err(e) = sock.send_to(error_frame(ErrorCode::InvalidOption, format!("invalid {}={}", key, value)).as_bytes(), &addr).await
+    {
+        eprintln!("UDP send ERR failed to {}: {:?}", addr, e);
+    }
+    parsed
+}
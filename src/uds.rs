@@ -0,0 +1,276 @@
+// proj2-serv/src/uds.rs
+// Unix domain socket plane (`--uds <path>`, or `--uds @name` for a Linux
+// abstract socket). It speaks the TCP plane's stream protocol for START_DOWNLOAD,
+// START_UPLOAD, CAPS, HELLO and RUN with no NIC or IP stack in the path, so its
+// results (`proto=uds`) are the server's own processing ceiling to compare
+// network results against. UDS peers have no address; they are recorded as 0.0.0.0:0.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+
+use crate::clock;
+use crate::interval::Measured;
+use crate::payload::{self, PayloadSource, WriteBatch};
+use crate::protocol::{self, error_frame, ClientMeta, Command, ErrorCode, DEFAULT_TENANT};
+use crate::results::{Direction, Protocol, TestResult};
+use crate::runs;
+use crate::session::SessionGuard;
+use crate::signing;
+use crate::state::ServerState;
+use crate::supervisor;
+use crate::tcp::DRAIN_QUIET;
+use crate::transport::{self, Streamed, TestSpec, TestTransport};
+
+/// Stand-in peer address for UDS clients in sessions and results.
+const UDS_PEER: SocketAddr = SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED), 0);
+
+/// Bind the listener for `--uds`. A stale socket file left by an earlier run
+/// is replaced; any other file at the path is an error.
+pub fn bind(spec: &str) -> anyhow::Result<std::os::unix::net::UnixListener> {
+    if let Some(name) = spec.strip_prefix('@') {
+        return bind_abstract(name);
+    }
+    use std::os::unix::fs::FileTypeExt;
+    if let Ok(meta) = std::fs::symlink_metadata(spec) {
+        if !meta.file_type().is_socket() {
+            bail!("{} exists and is not a socket", spec);
+        }
+        std::fs::remove_file(spec).with_context(|| format!("removing stale socket {}", spec))?;
+    }
+    std::os::unix::net::UnixListener::bind(spec).with_context(|| format!("binding UDS {}", spec))
+}
+
+#[cfg(target_os = "linux")]
+fn bind_abstract(name: &str) -> anyhow::Result<std::os::unix::net::UnixListener> {
+    use std::os::linux::net::SocketAddrExt;
+
+    let addr = std::os::unix::net::SocketAddr::from_abstract_name(name.as_bytes())?;
+    std::os::unix::net::UnixListener::bind_addr(&addr).with_context(|| format!("binding abstract UDS @{}", name))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_abstract(name: &str) -> anyhow::Result<std::os::unix::net::UnixListener> {
+    bail!("abstract socket @{} needs Linux", name)
+}
+
+pub async fn run_uds_server(listener: std::os::unix::net::UnixListener, state: Arc<ServerState>) -> anyhow::Result<()> {
+    listener.set_nonblocking(true).context("set_nonblocking UDS listener")?;
+    let listener = UnixListener::from_std(listener).context("convert UDS listener")?;
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let session = state.sessions.register(Protocol::Uds, UDS_PEER, DEFAULT_TENANT, None);
+                let context = format!("UDS client (session {})", session.id());
+                let task_state = state.clone();
+                supervisor::spawn_session(&state, context, async move {
+                    if let Err(e) = handle_uds_client(stream, task_state, &session).await {
+                        eprintln!("UDS client error: {:?}", e);
+                    }
+                }, std::future::ready(()));
+            }
+            Err(e) => {
+                eprintln!("UDS accept error: {:?}", e);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+    }
+}
+
+async fn handle_uds_client(mut stream: UnixStream, state: Arc<ServerState>, session: &SessionGuard) -> anyhow::Result<()> {
+    let mut read_buf = vec![0u8; 4096];
+    // Client metadata from HELLO, for the tests that follow on this connection.
+    let mut hello = ClientMeta::default();
+    // After an upload that closed without END_UPLOAD, reads are its leftovers
+    // until the client goes quiet or sends END_UPLOAD, as on TCP.
+    let mut draining = false;
+    loop {
+        let n = tokio::select! {
+            res = stream.read(&mut read_buf) => res?,
+            _ = session.token().cancelled() => return Ok(()),
+            _ = tokio::time::sleep(DRAIN_QUIET), if draining => {
+                draining = false;
+                continue;
+            }
+        };
+        if n == 0 {
+            return Ok(());
+        }
+        if draining {
+            draining = !read_buf[..n].trim_ascii_end().ends_with(b"END_UPLOAD");
+            continue;
+        }
+        let (command, cmd) = protocol::decode_frame(&read_buf[..n]);
+        let tenant = cmd.tenant();
+        println!("[{}] UDS server received: {}", tenant, command);
+        if let Some(frame) = state.drain.refuse(&cmd.verb) {
+            stream.write_all(format!("{}\n", frame).as_bytes()).await?;
+            continue;
+        }
+
+        if cmd.verb == "START_DOWNLOAD" || cmd.verb == "START_UPLOAD" {
+            let Some((target, omit)) = test_options(&mut stream, &cmd).await? else { continue };
+            let (direction, source) = if cmd.verb == "START_DOWNLOAD" {
+                let source = match payload::from_command(&cmd, state.payload_file.as_ref()) {
+                    Ok(source) => source,
+                    Err(e) => {
+                        stream.write_all(format!("{}\n", error_frame(ErrorCode::UnsupportedOption, e)).as_bytes()).await?;
+                        Box::new(payload::Zeros)
+                    }
+                };
+                (Direction::Download, Some(source))
+            } else {
+                (Direction::Upload, None)
+            };
+            let spec = TestSpec::new(&state, &cmd, direction, target, omit).with_hello(&hello);
+            let mut transport = UdsTransport { stream: &mut stream, state: &state, session, source, leftovers: false };
+            transport::run_test(&mut transport, &state, &spec).await?;
+            draining = transport.leftovers;
+        } else if cmd.verb == "RUN" {
+            stream.write_all(format!("{}\n", runs::reply(&state, &cmd)).as_bytes()).await?;
+        } else if cmd.verb == "COMPARE" {
+            stream.write_all(format!("{}\n", runs::compare_reply(&state, &cmd)).as_bytes()).await?;
+        } else if cmd.verb == "RESULT_KEY" {
+            stream.write_all(format!("{}\n", signing::reply(state.result_signer.as_ref())).as_bytes()).await?;
+        } else if cmd.verb == "CAPS" {
+            stream.write_all(format!("{}\n", state.caps()).as_bytes()).await?;
+        } else if cmd.verb == "HELLO" {
+            hello = cmd.meta();
+            stream.write_all(format!("{}\n", state.hello(&cmd)).as_bytes()).await?;
+        } else {
+            let frame = error_frame(ErrorCode::BadCommand, format!("unsupported command on UDS: {}", cmd.verb));
+            stream.write_all(format!("{}\n", frame).as_bytes()).await?;
+        }
+    }
+}
+
+/// `BYTES=` and `OMIT=`; an invalid value gets an ERR frame and no test runs.
+async fn test_options(stream: &mut UnixStream, cmd: &Command) -> anyhow::Result<Option<(Option<u64>, Duration)>> {
+    let target = match cmd.opt("BYTES").map(|v| (v, protocol::parse_byte_count(v))) {
+        Some((v, None)) => return reject(stream, "BYTES", v).await,
+        Some((_, target)) => target,
+        None => None,
+    };
+    let omit = match cmd.opt("OMIT").map(|v| (v, protocol::parse_omit(v))) {
+        Some((v, None)) => return reject(stream, "OMIT", v).await,
+        Some((_, omit)) => omit.unwrap_or_default(),
+        None => Duration::ZERO,
+    };
+    Ok(Some((target, omit)))
+}
+
+async fn reject<T>(stream: &mut UnixStream, key: &str, value: &str) -> anyhow::Result<Option<T>> {
+    stream.write_all(format!("{}\n", error_frame(ErrorCode::InvalidOption, format!("invalid {}={}", key, value))).as_bytes()).await?;
+    Ok(None)
+}
+
+/// A UDS connection as the transport of a throughput test.
+struct UdsTransport<'a> {
+    stream: &'a mut UnixStream,
+    state: &'a ServerState,
+    session: &'a SessionGuard,
+    /// Download payload (`PAYLOAD=`).
+    source: Option<Box<dyn PayloadSource>>,
+    /// Whether the upload closed without END_UPLOAD, so data may still be in flight.
+    leftovers: bool,
+}
+
+impl TestTransport for UdsTransport<'_> {
+    const PROTOCOL: Protocol = Protocol::Uds;
+
+    fn peer(&self) -> SocketAddr {
+        UDS_PEER
+    }
+
+    async fn send_stream(&mut self, spec: &TestSpec) -> anyhow::Result<Streamed> {
+        let Some(source) = self.source.as_mut() else { bail!("no payload for UDS download") };
+        let (config, session) = (&self.state.config, self.session);
+        session.begin(&spec.tenant, Direction::Download);
+        let mut batch = WriteBatch::new(config.tcp_write_slices, config.tcp_write_size);
+        let batch_size = config.tcp_write_slices * config.tcp_write_size;
+        let start = clock::now();
+        let mut sent = 0u64;
+        let mut measured = Measured::new(start, spec.omit);
+        let mut ctl_buf = [0u8; 256];
+        let (mut rd, mut wr) = self.stream.split();
+        while clock::elapsed(start) < spec.window && spec.below_target(sent) {
+            if batch.is_drained() {
+                let limit = spec.target.map_or(batch_size, |t| batch_size.min((t - sent) as usize));
+                batch.refill(source.as_mut(), limit);
+            }
+            let send = async { wr.write_vectored(&batch.slices()).await };
+            // Watch the read side for END_DOWNLOAD or a disconnect while sending.
+            tokio::select! {
+                res = send => match res {
+                    Ok(n) => {
+                        batch.advance(n);
+                        sent += n as u64;
+                        measured.add(n as u64);
+                        session.add_bytes(n as u64);
+                    }
+                    Err(e) => {
+                        println!("[{}] UDS client closed connection during download: {}", spec.tenant, e);
+                        break;
+                    }
+                },
+                _ = session.token().cancelled() => break,
+                // A client that stops reading stalls the send; the window ends regardless.
+                _ = clock::window_end(start, || spec.window, session.token()) => break,
+                res = rd.read(&mut ctl_buf) => match res {
+                    Ok(m) if m > 0 && Command::parse(&String::from_utf8_lossy(&ctl_buf[..m])).verb != "END_DOWNLOAD" => {}
+                    _ => break,
+                },
+            }
+        }
+        session.end();
+        println!("[{}] UDS server finished sending download (~{} bytes)", spec.tenant, sent);
+        Ok(Streamed::finished(measured))
+    }
+
+    async fn recv_stream(&mut self, spec: &TestSpec) -> anyhow::Result<Streamed> {
+        let session = self.session;
+        session.begin(&spec.tenant, Direction::Upload);
+        let mut read_buf = vec![0u8; 256 * 1024];
+        let start = clock::now();
+        let mut received = 0u64;
+        let mut measured = Measured::new(start, spec.omit);
+        let mut end_upload = false;
+        while clock::elapsed(start) < spec.window && spec.below_target(received) {
+            let read = tokio::select! {
+                res = self.stream.read(&mut read_buf) => res,
+                _ = session.token().cancelled() => break,
+                // A silent client sends nothing to wake the read; the window ends regardless.
+                _ = clock::window_end(start, || spec.window, session.token()) => break,
+            };
+            let m = match read {
+                Ok(0) | Err(_) => break,
+                Ok(m) => m,
+            };
+            // Without framing, END_UPLOAD is recognised only as the tail of a chunk.
+            let chunk = read_buf[..m].trim_ascii_end();
+            end_upload = chunk.ends_with(b"END_UPLOAD");
+            let data = if end_upload { (chunk.len() - b"END_UPLOAD".len()) as u64 } else { m as u64 };
+            received += data;
+            measured.add(data);
+            session.add_bytes(data);
+            if end_upload {
+                break;
+            }
+        }
+        self.leftovers = !end_upload;
+        session.end();
+        println!("[{}] UDS server received {} bytes during upload", spec.tenant, received);
+        Ok(Streamed::finished(measured))
+    }
+
+    fn report(&self, result: TestResult) -> TestResult {
+        match &self.source {
+            Some(source) => result.with_payload(source.name()),
+            None => result,
+        }
+    }
+}
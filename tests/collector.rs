@@ -0,0 +1,81 @@
+// proj2-serv/tests/collector.rs
+// The collector keeps results signed by the servers it trusts and refuses the
+// rest, and answers queries over what it kept.
+
+mod common;
+
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+use common::{drain, free_port, Server};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::process::{Child, Command};
+
+const SEED: [u8; 32] = [7; 32];
+
+async fn start_collector(port: u16, public: &str) -> Child {
+    let child = Command::new(env!("CARGO_BIN_EXE_proj2-serv"))
+        .args(["collector", "--port", &port.to_string(), "--trust", public])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("collector starts");
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while TcpStream::connect(("127.0.0.1", port)).await.is_err() {
+        assert!(Instant::now() < deadline, "collector never listened on {}", port);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    child
+}
+
+/// Status line and body of an HTTP request to the collector.
+async fn http(port: u16, method: &str, path: &str, body: &str) -> (String, String) {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    let request = format!("{} {} HTTP/1.1\r\nHost: collector\r\nContent-Length: {}\r\n\r\n{}", method, path, body.len(), body);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    (head.lines().next().unwrap().to_string(), body.to_string())
+}
+
+#[tokio::test]
+async fn collector_keeps_verified_results_and_refuses_tampered_ones() {
+    let public = ed25519_dalek::SigningKey::from_bytes(&SEED).verifying_key();
+    let public: String = public.as_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+    let port = free_port(false);
+    let _collector = start_collector(port, &public).await;
+    let key = std::env::temp_dir().join(format!("proj2-serv-collector-key-{}", std::process::id()));
+    std::fs::write(&key, SEED.iter().map(|b| format!("{:02x}", b)).collect::<String>()).unwrap();
+    let export = format!("http://127.0.0.1:{}/results", port);
+    let server = Server::start(&["--result-key", key.to_str().unwrap(), "--export-results", &export]).await;
+
+    let mut stream = server.tcp_client().await;
+    stream.write_all(b"START_DOWNLOAD TENANT=collected BYTES=1000").await.unwrap();
+    let _ = drain(&mut stream, Instant::now()).await;
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let kept = loop {
+        let (_, body) = http(port, "GET", "/results?tenant=collected", "").await;
+        if !body.is_empty() || Instant::now() > deadline {
+            break body;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    };
+    assert!(kept.starts_with(&format!("signer={} tenant=collected proto=tcp dir=download ", public)), "got {:?}", kept);
+    assert!(kept.contains(" bytes=1000 "), "got {:?}", kept);
+
+    let (status, summary) = http(port, "GET", "/summary", "").await;
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert!(summary.starts_with(&format!("signer={} proto=tcp dir=download results=1 bytes=1000 ", &public[..16])), "got {:?}", summary);
+
+    let (line, sig) = kept.trim().split_once(' ').unwrap().1.rsplit_once(" sig=").unwrap();
+    let forged: Vec<String> = line.replace("bytes=1000", "bytes=9000").split(' ').map(|kv| kv.replacen('=', "\":\"", 1)).collect();
+    let forged = format!("{{\"{}\",\"sig\":\"{}\"}}", forged.join("\",\""), sig);
+    let (status, _) = http(port, "POST", "/results", &forged).await;
+    assert_eq!(status, "HTTP/1.1 403 Forbidden");
+    let (status, _) = http(port, "POST", "/results", "not json").await;
+    assert_eq!(status, "HTTP/1.1 400 Bad Request");
+    std::fs::remove_file(key).unwrap();
+}
@@ -0,0 +1,176 @@
+// proj2-serv/tests/common/mod.rs
+// Harness for the integration tests: a server process on ports of its own, so
+// tests run in parallel, and fake clients speaking the control protocol the
+// way real ones do. Each test file uses only some of the helpers.
+#![allow(dead_code)]
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpSocket, TcpStream, UdpSocket};
+use tokio::process::{Child, Command};
+use tokio::time::timeout;
+
+/// How long a reply may take before a test fails.
+pub const REPLY_TIMEOUT: Duration = Duration::from_secs(3);
+/// The server's test window.
+pub const WINDOW: Duration = Duration::from_secs(5);
+
+pub struct Server {
+    pub tcp: SocketAddr,
+    pub udp: SocketAddr,
+    _child: Child,
+}
+
+impl Server {
+    /// Start the server with `args` on free loopback ports and wait until both
+    /// planes answer.
+    pub async fn start(args: &[&str]) -> Server {
+        let tcp = free_port(false);
+        let udp = free_port(true);
+        let child = Command::new(env!("CARGO_BIN_EXE_proj2-serv"))
+            .args(["--tcp-ports", &tcp.to_string(), "--udp-ports", &udp.to_string(), "--no-beacon"])
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .expect("server binary starts");
+        let server = Server { tcp: loopback(tcp), udp: loopback(udp), _child: child };
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while TcpStream::connect(server.tcp).await.is_err() {
+            assert!(Instant::now() < deadline, "server never listened on {}", server.tcp);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let sock = server.udp_client().await;
+        loop {
+            sock.send(b"CAPS").await.unwrap();
+            if timeout(Duration::from_millis(100), sock.recv(&mut [0u8; 2048])).await.is_ok() {
+                break;
+            }
+            assert!(Instant::now() < deadline, "server never answered on {}", server.udp);
+        }
+        server
+    }
+
+    pub async fn tcp_client(&self) -> TcpStream {
+        TcpStream::connect(self.tcp).await.expect("TCP connect")
+    }
+
+    /// A TCP connection from 127.0.0.`host`, standing in for a client on a
+    /// host of its own; the server runs one test per direction per host.
+    pub async fn tcp_client_from(&self, host: u8) -> TcpStream {
+        let sock = TcpSocket::new_v4().unwrap();
+        sock.bind(SocketAddr::from((Ipv4Addr::new(127, 0, 0, host), 0))).unwrap();
+        sock.connect(self.tcp).await.expect("TCP connect")
+    }
+
+    /// A UDP socket connected to the server's UDP plane.
+    pub async fn udp_client(&self) -> UdpSocket {
+        let sock = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sock.connect(self.udp).await.unwrap();
+        sock
+    }
+
+    /// The `bytes=` of each result recorded for `tenant`, waiting until there
+    /// are `count` of them.
+    pub async fn result_bytes(&self, tenant: &str, count: usize) -> Vec<u64> {
+        self.results(tenant, count)
+            .await
+            .iter()
+            .filter_map(|line| line.split_whitespace().find_map(|kv| kv.strip_prefix("bytes=")))
+            .map(|b| b.parse().unwrap())
+            .collect()
+    }
+
+    /// The result lines recorded for `tenant`, waiting until there are `count`.
+    pub async fn results(&self, tenant: &str, count: usize) -> Vec<String> {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let mut admin = self.tcp_client().await;
+            let reply = request_until(&mut admin, &format!("ADMIN RESULTS TENANT={}", tenant), "END").await;
+            let lines: Vec<String> = reply.lines().filter(|line| line.contains(" bytes=")).map(str::to_string).collect();
+            if lines.len() >= count || Instant::now() > deadline {
+                return lines;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+}
+
+fn loopback(port: u16) -> SocketAddr {
+    SocketAddr::from((Ipv4Addr::LOCALHOST, port))
+}
+
+/// A port the OS just handed out, and so most likely still free.
+pub fn free_port(udp: bool) -> u16 {
+    if udp {
+        std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+    } else {
+        std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+    }
+}
+
+/// Send a command and return the first reply read.
+pub async fn request(stream: &mut TcpStream, cmd: &str) -> String {
+    stream.write_all(cmd.as_bytes()).await.unwrap();
+    let mut buf = vec![0u8; 4096];
+    let n = timeout(REPLY_TIMEOUT, stream.read(&mut buf)).await.unwrap_or_else(|_| panic!("no reply to {:?}", cmd)).unwrap();
+    String::from_utf8_lossy(&buf[..n]).trim().to_string()
+}
+
+/// Send a command and read until a line equal to `last`.
+pub async fn request_until(stream: &mut TcpStream, cmd: &str, last: &str) -> String {
+    stream.write_all(cmd.as_bytes()).await.unwrap();
+    let mut reply = String::new();
+    let mut buf = vec![0u8; 4096];
+    while !reply.lines().any(|line| line == last) {
+        let n = timeout(REPLY_TIMEOUT, stream.read(&mut buf)).await.unwrap_or_else(|_| panic!("no {} after {:?}", last, cmd)).unwrap();
+        assert!(n > 0, "connection closed after {:?}", cmd);
+        reply.push_str(&String::from_utf8_lossy(&buf[..n]));
+    }
+    reply
+}
+
+/// Read until the server stops sending: the bytes, and when the last arrived.
+pub async fn drain(stream: &mut TcpStream, start: Instant) -> (u64, Duration) {
+    let mut buf = vec![0u8; 256 * 1024];
+    let (mut bytes, mut last) = (0u64, Duration::ZERO);
+    while let Ok(Ok(n)) = timeout(Duration::from_millis(500), stream.read(&mut buf)).await {
+        if n == 0 {
+            break;
+        }
+        bytes += n as u64;
+        last = start.elapsed();
+    }
+    (bytes, last)
+}
+
+/// Receive one datagram as text.
+pub async fn recv_text(sock: &UdpSocket) -> String {
+    let mut buf = vec![0u8; 64 * 1024];
+    let n = timeout(REPLY_TIMEOUT, sock.recv(&mut buf)).await.expect("no UDP reply").unwrap();
+    String::from_utf8_lossy(&buf[..n]).to_string()
+}
+
+/// Every datagram arriving until `quiet` passes without one.
+pub async fn recv_all(sock: &UdpSocket, quiet: Duration) -> Vec<Vec<u8>> {
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut out = Vec::new();
+    while let Ok(Ok(n)) = timeout(quiet, sock.recv(&mut buf)).await {
+        out.push(buf[..n].to_vec());
+    }
+    out
+}
+
+/// The `COOKIE=` of an ACK, if it has one.
+pub fn cookie(ack: &str) -> Option<&str> {
+    ack.split_whitespace().find_map(|kv| kv.strip_prefix("COOKIE="))
+}
+
+/// Whether a datagram of a download is flood data rather than an ACK, FIN or PING.
+pub fn is_data(datagram: &[u8]) -> bool {
+    !datagram.starts_with(b"ACK_") && !datagram.starts_with(b"FIN ") && !datagram.starts_with(b"PING ")
+}
@@ -0,0 +1,113 @@
+// proj2-serv/tests/concurrent.rs
+// Several clients at once: sessions keep their own accounting, and the test
+// limit queues or refuses the overflow. Clients that run the same direction
+// together connect from hosts of their own, 127.0.0.2 and up.
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{cookie, drain, is_data, recv_all, recv_text, request, Server};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+#[tokio::test]
+async fn parallel_tcp_downloads_are_accounted_separately() {
+    let server = Server::start(&[]).await;
+    let mut clients = Vec::new();
+    for i in 1..=4u64 {
+        // Each from a host of its own: one host runs one download at a time.
+        let mut stream = server.tcp_client_from(i as u8 + 1).await;
+        clients.push(tokio::spawn(async move {
+            stream.write_all(format!("START_DOWNLOAD TENANT=parallel BYTES={}", i * 1_000_000).as_bytes()).await.unwrap();
+            drain(&mut stream, Instant::now()).await.0
+        }));
+    }
+    let mut received = Vec::new();
+    for client in clients {
+        received.push(client.await.unwrap());
+    }
+    assert_eq!(received, [1_000_000, 2_000_000, 3_000_000, 4_000_000]);
+    let mut recorded = server.result_bytes("parallel", 4).await;
+    recorded.sort();
+    assert_eq!(recorded, received);
+}
+
+#[tokio::test]
+async fn tcp_and_udp_sessions_run_side_by_side() {
+    let server = Server::start(&[]).await;
+    let mut stream = server.tcp_client().await;
+    stream.write_all(b"START_UPLOAD TENANT=mixed BYTES=1000000").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let sock = server.udp_client().await;
+    sock.send(b"START_DOWNLOAD TENANT=mixed BYTES=50000").await.unwrap();
+    let ack = recv_text(&sock).await;
+    sock.send(format!("CONFIRM COOKIE={}", cookie(&ack).unwrap()).as_bytes()).await.unwrap();
+    stream.write_all(&vec![0u8; 1_000_000]).await.unwrap();
+    let data: u64 = recv_all(&sock, Duration::from_millis(500))
+        .await
+        .iter()
+        .filter(|d| is_data(d))
+        .map(|d| d.len() as u64)
+        .sum();
+    assert_eq!(data, 50_000);
+    let mut recorded = server.result_bytes("mixed", 2).await;
+    recorded.sort();
+    assert_eq!(recorded, [50_000, 1_000_000]);
+}
+
+#[tokio::test]
+async fn compare_sets_tcp_and_udp_of_a_run_side_by_side() {
+    let server = Server::start(&[]).await;
+    let mut stream = server.tcp_client().await;
+    stream.write_all(b"START_DOWNLOAD TENANT=cmp RUN=both BYTES=500000").await.unwrap();
+    assert_eq!(drain(&mut stream, Instant::now()).await.0, 500_000);
+    let sock = server.udp_client().await;
+    sock.send(b"START_DOWNLOAD TENANT=cmp RUN=both BYTES=50000").await.unwrap();
+    let ack = recv_text(&sock).await;
+    sock.send(format!("CONFIRM COOKIE={}", cookie(&ack).unwrap()).as_bytes()).await.unwrap();
+    recv_all(&sock, Duration::from_millis(500)).await;
+    server.results("cmp", 2).await;
+
+    let reply = request(&mut stream, "COMPARE both TENANT=cmp").await;
+    assert!(reply.starts_with("COMPARE name=both download_tcp_streams=1 "), "{}", reply);
+    assert!(reply.contains(" download_udp_streams=1 "), "{}", reply);
+    assert!(reply.contains(" download_udp_over_tcp="), "{}", reply);
+    assert!(!reply.contains("upload_"), "{}", reply);
+    let reply = request(&mut stream, "COMPARE missing TENANT=cmp").await;
+    assert!(reply.starts_with("ERR "), "{}", reply);
+}
+
+#[tokio::test]
+async fn tests_beyond_the_limit_queue_then_refuse() {
+    let server = Server::start(&["--max-tests", "1", "--queue", "1"]).await;
+    let mut running = server.tcp_client_from(2).await;
+    running.write_all(b"START_DOWNLOAD").await.unwrap();
+    let mut first = [0u8; 1];
+    running.read_exact(&mut first).await.unwrap();
+
+    let mut queued = server.tcp_client_from(3).await;
+    let reply = request(&mut queued, "START_DOWNLOAD TENANT=queued BYTES=100000").await;
+    assert!(reply.starts_with("QUEUED 1"), "got {:?}", reply);
+    let mut refused = server.tcp_client_from(4).await;
+    let reply = request(&mut refused, "START_DOWNLOAD").await;
+    assert!(reply.starts_with("ERR BUSY"), "got {:?}", reply);
+
+    // Freeing the slot lets the queued test run.
+    running.write_all(b"END_DOWNLOAD").await.unwrap();
+    assert_eq!(server.result_bytes("queued", 1).await, [100_000]);
+}
+
+#[tokio::test]
+async fn status_lists_every_running_session() {
+    let server = Server::start(&[]).await;
+    let mut downloads = Vec::new();
+    for host in 2..5 {
+        let mut stream = server.tcp_client_from(host).await;
+        stream.write_all(b"START_DOWNLOAD").await.unwrap();
+        downloads.push(stream);
+    }
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let mut admin = server.tcp_client().await;
+    let status = common::request_until(&mut admin, "STATUS", "END").await;
+    assert_eq!(status.lines().filter(|line| line.contains("dir=download")).count(), 3, "STATUS:\n{}", status);
+}
@@ -0,0 +1,23 @@
+// proj2-serv/tests/conformance.rs
+// The conformance subcommand passes every check against this server.
+
+mod common;
+
+use common::Server;
+use tokio::process::Command;
+
+#[tokio::test]
+async fn every_check_passes_against_this_server() {
+    let server = Server::start(&[]).await;
+    let output = Command::new(env!("CARGO_BIN_EXE_proj2-serv"))
+        .args(["conformance", "--tcp-port", &server.tcp.port().to_string(), "--udp-port", &server.udp.port().to_string()])
+        .output()
+        .await
+        .expect("conformance runs");
+    let matrix = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", matrix);
+    assert!(matrix.contains(" 0 failed"), "{}", matrix);
+    for check in ["tcp.hello", "tcp.resume", "tcp.admin", "udp.owd", "udp.capacity_probe", "udp.extend", "run.compare"] {
+        assert!(matrix.lines().any(|line| line.starts_with(check) && line.contains(" PASS ")), "{} missing from\n{}", check, matrix);
+    }
+}
@@ -0,0 +1,56 @@
+// proj2-serv/tests/dashboard.rs
+// The dashboard serves its embedded page, refuses paths outside it, and
+// streams session snapshots.
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{free_port, Server};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+async fn connect(port: u16) -> TcpStream {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        match TcpStream::connect(("127.0.0.1", port)).await {
+            Ok(stream) => return stream,
+            Err(_) if Instant::now() < deadline => tokio::time::sleep(Duration::from_millis(20)).await,
+            Err(e) => panic!("dashboard never listened on {}: {}", port, e),
+        }
+    }
+}
+
+/// Head and body of a GET to the dashboard.
+async fn get(port: u16, path: &str) -> (String, String) {
+    let mut stream = connect(port).await;
+    stream.write_all(format!("GET {} HTTP/1.1\r\nHost: dashboard\r\n\r\n", path).as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    (head.to_string(), body.to_string())
+}
+
+#[tokio::test]
+async fn embedded_page_and_events_are_served() {
+    let port = free_port(false);
+    let _server = Server::start(&["--dashboard-port", &port.to_string()]).await;
+
+    let (head, body) = get(port, "/").await;
+    assert!(head.starts_with("HTTP/1.1 200 OK") && head.contains("Content-Type: text/html"), "got {:?}", head);
+    assert!(body.contains("/events"), "page does not subscribe to /events");
+    assert_eq!(get(port, "/index.html").await.1, body);
+    assert!(get(port, "/missing.js").await.0.starts_with("HTTP/1.1 404"));
+    assert!(get(port, "/../dashboard.rs").await.0.starts_with("HTTP/1.1 404"));
+
+    let mut events = connect(port).await;
+    events.write_all(b"GET /events HTTP/1.1\r\nHost: dashboard\r\n\r\n").await.unwrap();
+    let mut seen = Vec::new();
+    let mut buf = [0u8; 4096];
+    while !String::from_utf8_lossy(&seen).contains("data: [") {
+        let n = tokio::time::timeout(Duration::from_secs(3), events.read(&mut buf)).await.expect("a snapshot within 3s").unwrap();
+        assert!(n > 0, "event stream closed");
+        seen.extend_from_slice(&buf[..n]);
+    }
+    assert!(String::from_utf8_lossy(&seen).contains("text/event-stream"));
+}
@@ -0,0 +1,219 @@
+// proj2-serv/tests/deadlines.rs
+// Window deadlines under tokio's paused time: a window ends exactly when its
+// length says, EXTEND moves the end, a kill ends it early, and an upload
+// window counts what arrived up to its deadline and nothing after. The
+// runtime skips ahead whenever every task is waiting, so the five-second
+// windows here take no real time. These check the clock and ledger the planes
+// build on; udp.rs drives the real server past a short window as well. A TCP
+// peer that stays connected but silent, before or after resuming on a new
+// connection, is read the way the TCP plane reads it and still ends on time.
+
+#[allow(dead_code)]
+#[path = "../src/clock.rs"]
+mod clock;
+#[allow(dead_code)]
+#[path = "../src/ledger.rs"]
+mod ledger;
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use ledger::{Account, Ledger, Recorded};
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+const WINDOW: Duration = Duration::from_secs(5);
+
+/// A window's length, as the session's window holds it for EXTEND.
+#[derive(Clone)]
+struct Length(Arc<AtomicU64>);
+
+impl Length {
+    fn new(length: Duration) -> Self {
+        Length(Arc::new(AtomicU64::new(length.as_millis() as u64)))
+    }
+
+    fn get(&self) -> Duration {
+        Duration::from_millis(self.0.load(Ordering::Relaxed))
+    }
+
+    fn set(&self, length: Duration) {
+        self.0.store(length.as_millis() as u64, Ordering::Relaxed);
+    }
+}
+
+struct Upload {
+    id: u64,
+    started: Instant,
+    length: Length,
+    total: u64,
+}
+
+impl Account for Upload {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn deadline(&self) -> Instant {
+        self.started + self.length.get()
+    }
+
+    fn target(&self) -> Option<u64> {
+        None
+    }
+
+    fn count(&mut self, len: usize, copies: usize) -> u64 {
+        self.total += (len * copies) as u64;
+        self.total
+    }
+}
+
+fn client() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 40000))
+}
+
+/// Open an upload window and close it at its deadline, as the UDP plane does;
+/// the returned task yields what the window counted.
+fn open_upload(uploads: &Arc<Mutex<Ledger<Upload>>>, length: Length) -> tokio::task::JoinHandle<Option<u64>> {
+    let started = clock::now();
+    let window = Upload { id: 1, started, length: length.clone(), total: 0 };
+    assert!(uploads.lock().unwrap().open(client(), window).is_none());
+    let uploads = uploads.clone();
+    tokio::spawn(async move {
+        clock::window_end(started, || length.get(), &CancellationToken::new()).await;
+        uploads.lock().unwrap().close(1).map(|(_, w)| w.total)
+    })
+}
+
+#[tokio::test(start_paused = true)]
+async fn window_ends_exactly_at_its_length() {
+    let started = clock::now();
+    assert!(clock::window_end(started, || WINDOW, &CancellationToken::new()).await);
+    assert_eq!(clock::elapsed(started), WINDOW);
+}
+
+#[tokio::test(start_paused = true)]
+async fn extend_moves_the_end_while_waiting() {
+    let started = clock::now();
+    let length = Length::new(WINDOW);
+    let extend = length.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        extend.set(Duration::from_secs(8));
+    });
+    assert!(clock::window_end(started, || length.get(), &CancellationToken::new()).await);
+    assert_eq!(clock::elapsed(started), Duration::from_secs(8));
+}
+
+#[tokio::test(start_paused = true)]
+async fn kill_ends_the_window_early() {
+    let started = clock::now();
+    let cancel = CancellationToken::new();
+    let kill = cancel.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        kill.cancel();
+    });
+    assert!(!clock::window_end(started, || WINDOW, &cancel).await);
+    assert_eq!(clock::elapsed(started), Duration::from_secs(2));
+}
+
+#[tokio::test(start_paused = true)]
+async fn datagrams_after_the_deadline_are_not_counted() {
+    let uploads = Arc::new(Mutex::new(Ledger::default()));
+    let closed = open_upload(&uploads, Length::new(WINDOW));
+    for _ in 0..10 {
+        assert!(matches!(uploads.lock().unwrap().record(client(), 1000, 1, clock::now()), Recorded::Counted));
+    }
+    // Late datagrams racing the deadline task find the window past its deadline.
+    tokio::time::advance(WINDOW + Duration::from_millis(300)).await;
+    for _ in 0..10 {
+        let recorded = uploads.lock().unwrap().record(client(), 1000, 1, clock::now());
+        assert!(matches!(recorded, Recorded::Late | Recorded::NoWindow));
+    }
+    assert_eq!(closed.await.unwrap(), Some(10_000));
+    // A late END_UPLOAD finds no window.
+    assert!(uploads.lock().unwrap().end(client()).is_none());
+}
+
+#[tokio::test(start_paused = true)]
+async fn a_datagram_at_the_deadline_counts() {
+    let uploads = Arc::new(Mutex::new(Ledger::default()));
+    let started = clock::now();
+    let length = Length::new(WINDOW);
+    assert!(uploads.lock().unwrap().open(client(), Upload { id: 1, started, length, total: 0 }).is_none());
+    tokio::time::advance(WINDOW).await;
+    assert!(matches!(uploads.lock().unwrap().record(client(), 1000, 1, clock::now()), Recorded::Counted));
+    tokio::time::advance(Duration::from_millis(1)).await;
+    assert!(matches!(uploads.lock().unwrap().record(client(), 1000, 1, clock::now()), Recorded::Late));
+}
+
+#[tokio::test(start_paused = true)]
+async fn extended_upload_counts_until_its_new_deadline() {
+    let uploads = Arc::new(Mutex::new(Ledger::default()));
+    let length = Length::new(WINDOW);
+    let closed = open_upload(&uploads, length.clone());
+    tokio::time::advance(Duration::from_secs(4)).await;
+    length.set(Duration::from_secs(10));
+    tokio::time::advance(Duration::from_secs(3)).await;
+    // Past the original deadline, inside the extended one.
+    assert!(matches!(uploads.lock().unwrap().record(client(), 1000, 1, clock::now()), Recorded::Counted));
+    assert_eq!(closed.await.unwrap(), Some(1000));
+}
+
+/// A connected loopback pair: the client's end and the server's.
+async fn tcp_pair(listener: &TcpListener) -> (TcpStream, TcpStream) {
+    let client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+    let (server, _) = listener.accept().await.unwrap();
+    (client, server)
+}
+
+/// Read an upload as the TCP plane does, moving to resuming connections, until
+/// the window ends; the bytes read.
+async fn read_upload(mut stream: TcpStream, resumes: &mut mpsc::Receiver<TcpStream>, started: Instant, length: Length) -> u64 {
+    let cancel = CancellationToken::new();
+    let mut buf = [0u8; 1024];
+    let mut total = 0;
+    loop {
+        tokio::select! {
+            read = stream.read(&mut buf) => match read {
+                Ok(0) | Err(_) => panic!("the peer stayed connected"),
+                Ok(n) => total += n as u64,
+            },
+            Some(moved) = resumes.recv() => stream = moved,
+            _ = clock::window_end(started, || length.get(), &cancel) => return total,
+        }
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn silent_tcp_peer_ends_at_the_window() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let (_client, server) = tcp_pair(&listener).await;
+    let (_resume, mut resumes) = mpsc::channel(1);
+    let started = clock::now();
+    assert_eq!(read_upload(server, &mut resumes, started, Length::new(WINDOW)).await, 0);
+    assert_eq!(clock::elapsed(started), WINDOW);
+}
+
+#[tokio::test(start_paused = true)]
+async fn silent_tcp_peer_after_a_resume_ends_at_the_window() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let (_first, server) = tcp_pair(&listener).await;
+    let (second, resumed) = tcp_pair(&listener).await;
+    let (resume, mut resumes) = mpsc::channel(1);
+    tokio::spawn(async move {
+        // The client moves to a new connection and goes quiet there too.
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        resume.send(resumed).await.unwrap();
+        tokio::time::sleep(WINDOW).await;
+        drop(second);
+    });
+    let started = clock::now();
+    assert_eq!(read_upload(server, &mut resumes, started, Length::new(WINDOW)).await, 0);
+    assert_eq!(clock::elapsed(started), WINDOW);
+}
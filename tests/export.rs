@@ -0,0 +1,60 @@
+// proj2-serv/tests/export.rs
+// Results reach an MQTT sink: a minimal broker accepts the exporter's session
+// and receives each result as a QoS 0 PUBLISH on the configured topic.
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{drain, free_port, Server};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Read one MQTT packet: its first byte and body.
+async fn read_packet(conn: &mut TcpStream) -> (u8, Vec<u8>) {
+    let kind = conn.read_u8().await.unwrap();
+    let (mut len, mut shift) = (0usize, 0);
+    loop {
+        let byte = conn.read_u8().await.unwrap();
+        len |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    let mut body = vec![0u8; len];
+    conn.read_exact(&mut body).await.unwrap();
+    (kind, body)
+}
+
+#[tokio::test]
+async fn results_are_published_to_mqtt() {
+    let port = free_port(false);
+    let broker = TcpListener::bind(("127.0.0.1", port)).await.unwrap();
+    let sink = format!("mqtt://127.0.0.1:{}/speed/results", port);
+    let server = Server::start(&["--export-results", &sink]).await;
+
+    let (mut conn, _) = tokio::time::timeout(Duration::from_secs(5), broker.accept()).await.expect("exporter connects").unwrap();
+    let (kind, _) = read_packet(&mut conn).await;
+    assert_eq!(kind >> 4, 1, "expected CONNECT");
+    conn.write_all(&[0x20, 2, 0, 0]).await.unwrap();
+
+    let mut stream = server.tcp_client().await;
+    stream.write_all(b"START_DOWNLOAD TENANT=mqtt BYTES=1000").await.unwrap();
+    let _ = drain(&mut stream, Instant::now()).await;
+    let (kind, body) = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let packet = read_packet(&mut conn).await;
+            if packet.0 >> 4 == 3 {
+                return packet;
+            }
+        }
+    })
+    .await
+    .expect("a PUBLISH within 5s");
+    assert_eq!(kind & 0x06, 0, "published above QoS 0");
+    let topic_len = u16::from_be_bytes([body[0], body[1]]) as usize;
+    assert_eq!(&body[2..2 + topic_len], b"speed/results");
+    let payload = String::from_utf8_lossy(&body[2 + topic_len..]);
+    assert!(payload.contains(r#""tenant":"mqtt""#) && payload.contains(r#""bytes":1000"#), "got {:?}", payload);
+}
@@ -0,0 +1,103 @@
+// proj2-serv/tests/eyeballs.rs
+// Happy Eyeballs races under tokio's paused time, with stand-in connects that
+// take a set time or fail: IPv6 wins while it is quick, IPv4 starts after the
+// attempt delay or as soon as IPv6 fails, and the loser's handshake time is
+// filled in once it settles.
+
+#[allow(dead_code)]
+#[path = "../src/eyeballs.rs"]
+mod eyeballs;
+
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+const V6: &str = "[2001:db8::1]:8080";
+const V4: &str = "192.0.2.1:8080";
+
+/// How a stand-in connect to one family goes.
+#[derive(Clone, Copy)]
+enum Dial {
+    Connects(Duration),
+    Fails(Duration),
+    Hangs,
+}
+
+/// Race V6 and V4 with each family's connect behaving as given; the stream is
+/// the address that connected.
+async fn race(ipv6: Dial, ipv4: Dial) -> anyhow::Result<(SocketAddr, Duration, Option<eyeballs::Racing<SocketAddr>>)> {
+    let addrs: Vec<SocketAddr> = vec![V6.parse().unwrap(), V4.parse().unwrap()];
+    eyeballs::race(&addrs, move |peer: SocketAddr| async move {
+        match if peer.is_ipv6() { ipv6 } else { ipv4 } {
+            Dial::Connects(after) => {
+                tokio::time::sleep(after).await;
+                Ok(peer)
+            }
+            Dial::Fails(after) => {
+                tokio::time::sleep(after).await;
+                Err(io::Error::from(io::ErrorKind::ConnectionRefused))
+            }
+            Dial::Hangs => std::future::pending().await,
+        }
+    })
+    .await
+}
+
+fn ms(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+#[tokio::test(start_paused = true)]
+async fn quick_ipv6_wins_and_ipv4_still_reports() {
+    let start = Instant::now();
+    let (peer, connect, racing) = race(Dial::Connects(ms(100)), Dial::Connects(ms(5))).await.unwrap();
+    assert_eq!((peer, connect), (V6.parse().unwrap(), ms(100)));
+    assert_eq!(start.elapsed(), ms(100), "IPv4 cannot win before its attempt delay");
+    let race = racing.unwrap().settle().await;
+    assert_eq!(race, eyeballs::Race { winner: "ipv6", ipv6: Some(ms(100)), ipv4: Some(ms(5)) });
+    assert_eq!(race.to_string(), "happy_eyeballs=ipv6 he_ipv6_connect_ms=100.00 he_ipv4_connect_ms=5.00 he_margin_ms=-95.00");
+}
+
+#[tokio::test(start_paused = true)]
+async fn slow_ipv6_loses_to_ipv4_after_the_attempt_delay() {
+    let start = Instant::now();
+    let (peer, connect, racing) = race(Dial::Connects(ms(1000)), Dial::Connects(ms(10))).await.unwrap();
+    assert_eq!((peer, connect), (V4.parse().unwrap(), ms(10)));
+    assert_eq!(start.elapsed(), ms(260), "IPv4 starts 250 ms after IPv6");
+    let race = racing.unwrap().settle().await;
+    assert_eq!(race, eyeballs::Race { winner: "ipv4", ipv6: Some(ms(1000)), ipv4: Some(ms(10)) });
+    assert!(race.to_string().ends_with(" he_margin_ms=990.00"), "got {}", race);
+}
+
+#[tokio::test(start_paused = true)]
+async fn failed_ipv6_starts_ipv4_at_once() {
+    let start = Instant::now();
+    let (peer, _, racing) = race(Dial::Fails(ms(20)), Dial::Connects(ms(10))).await.unwrap();
+    assert_eq!(peer, V4.parse().unwrap());
+    assert_eq!(start.elapsed(), ms(30), "IPv4 waited out the attempt delay after IPv6 failed");
+    let race = racing.unwrap().settle().await;
+    assert_eq!(race.to_string(), "happy_eyeballs=ipv4 he_ipv6_connect_ms=- he_ipv4_connect_ms=10.00");
+}
+
+#[tokio::test(start_paused = true)]
+async fn hung_ipv6_times_out_and_a_failed_ipv4_fails_the_race() {
+    let start = Instant::now();
+    let error = race(Dial::Hangs, Dial::Fails(ms(10))).await.err().expect("neither family connects");
+    assert_eq!(start.elapsed(), ms(2000), "the race ends when the IPv6 attempt times out");
+    assert!(format!("{:#}", error).contains("connect timed out"), "got {:#}", error);
+}
+
+#[tokio::test(start_paused = true)]
+async fn single_family_targets_do_not_race() {
+    let addrs: Vec<SocketAddr> = vec![V4.parse().unwrap()];
+    let (peer, connect, racing) = eyeballs::race(&addrs, |peer: SocketAddr| async move {
+        tokio::time::sleep(ms(40)).await;
+        Ok::<_, io::Error>(peer)
+    })
+    .await
+    .unwrap();
+    assert_eq!((peer, connect), (V4.parse().unwrap(), ms(40)));
+    assert!(racing.is_none());
+}
@@ -0,0 +1,42 @@
+// proj2-serv/tests/fragments.rs
+// How many IP packets a UDP download's datagram takes on a path, which the
+// result reports as fragmentation risk: one per datagram while it fits the
+// MTU with its headers, then as many as the fragment payloads, multiples of 8
+// bytes, need.
+
+#[allow(dead_code)]
+#[path = "../src/sockopt.rs"]
+mod sockopt;
+
+use std::net::SocketAddr;
+
+fn v4() -> SocketAddr {
+    SocketAddr::from(([192, 0, 2, 1], 9000))
+}
+
+fn v6() -> SocketAddr {
+    SocketAddr::from(([0x2001, 0xdb8, 0, 0, 0, 0, 0, 1], 9000))
+}
+
+#[test]
+fn datagram_filling_the_mtu_is_not_fragmented() {
+    assert_eq!(sockopt::fragments(1400, v4(), 1500), 1);
+    assert_eq!(sockopt::fragments(1472, v4(), 1500), 1);
+    assert_eq!(sockopt::fragments(1452, v6(), 1500), 1);
+    assert_eq!(sockopt::fragments(65_507, v4(), 65_536), 1);
+}
+
+#[test]
+fn one_byte_over_the_mtu_takes_two_fragments() {
+    assert_eq!(sockopt::fragments(1473, v4(), 1500), 2);
+    assert_eq!(sockopt::fragments(1453, v6(), 1500), 2);
+}
+
+#[test]
+fn jumbo_datagram_on_an_ethernet_path() {
+    // 9008 bytes of UDP in fragments of 1480 (IPv4) or 1448 (IPv6).
+    assert_eq!(sockopt::fragments(9000, v4(), 1500), 7);
+    assert_eq!(sockopt::fragments(9000, v6(), 1500), 7);
+    assert_eq!(sockopt::fragments(9000, v4(), 9000), 2);
+    assert_eq!(sockopt::fragments(8972, v4(), 9000), 1);
+}
@@ -0,0 +1,76 @@
+// proj2-serv/tests/grpc.rs
+// The gRPC admin service: sessions can be listed and killed, limits read and
+// changed, and metrics and results fetched; with a token, calls without it
+// are refused.
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{drain, free_port, Server};
+use tokio::io::AsyncWriteExt;
+use tonic::transport::Channel;
+use tonic::{Code, Request};
+
+mod proto {
+    tonic::include_proto!("proj2serv.admin.v1");
+}
+
+use proto::admin_client::AdminClient;
+use proto::{GetLimitsRequest, GetMetricsRequest, KillSessionRequest, ListResultsRequest, ListSessionsRequest, SetLimitRequest};
+
+async fn connect(port: u16) -> AdminClient<Channel> {
+    AdminClient::connect(format!("http://127.0.0.1:{}", port)).await.expect("gRPC connect")
+}
+
+#[tokio::test]
+async fn sessions_limits_metrics_and_results() {
+    let port = free_port(false);
+    let server = Server::start(&["--grpc-port", &port.to_string()]).await;
+    let mut admin = connect(port).await;
+
+    let mut stream = server.tcp_client().await;
+    stream.write_all(b"START_DOWNLOAD TENANT=grpc").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let sessions = admin.list_sessions(ListSessionsRequest { tenant: Some("grpc".to_string()) }).await.unwrap().into_inner().sessions;
+    assert_eq!(sessions.len(), 1, "got {:?}", sessions);
+    assert_eq!((sessions[0].protocol.as_str(), sessions[0].direction.as_str()), ("tcp", "download"));
+    admin.kill_session(KillSessionRequest { id: sessions[0].id }).await.unwrap();
+    let start = Instant::now();
+    let _ = drain(&mut stream, start).await;
+    assert!(start.elapsed() < Duration::from_secs(2), "killed download ran on");
+    let missing = admin.kill_session(KillSessionRequest { id: u64::MAX }).await.unwrap_err();
+    assert_eq!(missing.code(), Code::NotFound);
+
+    let limits = admin.set_limit(SetLimitRequest { name: "max_test_duration".to_string(), value: "30".to_string() }).await.unwrap().into_inner();
+    assert_eq!(limits.max_test_duration_s, 30);
+    assert_eq!(admin.get_limits(GetLimitsRequest {}).await.unwrap().into_inner().max_test_duration_s, 30);
+    let bad = admin.set_limit(SetLimitRequest { name: "no_such_limit".to_string(), value: "1".to_string() }).await.unwrap_err();
+    assert_eq!(bad.code(), Code::InvalidArgument);
+
+    server.results("grpc", 1).await;
+    let results = admin.list_results(ListResultsRequest { tenant: Some("grpc".to_string()) }).await.unwrap().into_inner().results;
+    assert_eq!(results.len(), 1);
+    assert!(results[0].bytes > 0 && results[0].line.starts_with("tenant=grpc proto=tcp dir=download "), "got {:?}", results[0]);
+    let metrics = admin.get_metrics(GetMetricsRequest { tenant: Some("grpc".to_string()) }).await.unwrap().into_inner();
+    assert_eq!(metrics.tenants.len(), 1);
+    assert_eq!((metrics.tenants[0].sessions_started, metrics.tenants[0].sessions_finished), (1, 1));
+}
+
+#[tokio::test]
+async fn calls_without_the_token_are_refused() {
+    let token = std::env::temp_dir().join(format!("proj2-serv-grpc-token-{}", std::process::id()));
+    std::fs::write(&token, "s3cret\n").unwrap();
+    let port = free_port(false);
+    let _server = Server::start(&["--grpc-port", &port.to_string(), "--grpc-token", token.to_str().unwrap()]).await;
+    let mut admin = connect(port).await;
+    let refused = admin.get_limits(GetLimitsRequest {}).await.unwrap_err();
+    assert_eq!(refused.code(), Code::Unauthenticated);
+    let mut wrong = Request::new(GetLimitsRequest {});
+    wrong.metadata_mut().insert("authorization", "Bearer guess".parse().unwrap());
+    assert_eq!(admin.get_limits(wrong).await.unwrap_err().code(), Code::Unauthenticated);
+    let mut right = Request::new(GetLimitsRequest {});
+    right.metadata_mut().insert("authorization", "Bearer s3cret".parse().unwrap());
+    assert!(admin.get_limits(right).await.unwrap().into_inner().max_test_duration_s > 0);
+    std::fs::remove_file(token).unwrap();
+}
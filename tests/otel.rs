@@ -0,0 +1,79 @@
+// proj2-serv/tests/otel.rs
+// OTLP export: a test started with TRACEPARENT= reaches a collector as
+// `proj2.test` and its phase spans, in the client's trace.
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{drain, free_port, Server};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+const PARENT_ID: &str = "00f067aa0ba902b7";
+
+/// Accept OTLP/HTTP posts on `listener`, passing on each path and body.
+async fn collector(listener: TcpListener, posts: mpsc::UnboundedSender<(String, String)>) {
+    loop {
+        let (conn, _) = listener.accept().await.unwrap();
+        let posts = posts.clone();
+        tokio::spawn(async move {
+            let mut conn = BufReader::new(conn);
+            loop {
+                let mut request = String::new();
+                if conn.read_line(&mut request).await.unwrap_or(0) == 0 {
+                    return;
+                }
+                let mut length = 0;
+                loop {
+                    let mut header = String::new();
+                    conn.read_line(&mut header).await.unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = header.split_once(':')
+                        && name.eq_ignore_ascii_case("content-length")
+                    {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0u8; length];
+                conn.read_exact(&mut body).await.unwrap();
+                conn.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await.unwrap();
+                let path = request.split(' ').nth(1).unwrap_or_default().to_string();
+                let _ = posts.send((path, String::from_utf8_lossy(&body).into_owned()));
+            }
+        });
+    }
+}
+
+#[tokio::test]
+async fn test_spans_join_the_client_trace() {
+    let port = free_port(false);
+    let listener = TcpListener::bind(("127.0.0.1", port)).await.unwrap();
+    let (tx, mut posts) = mpsc::unbounded_channel();
+    tokio::spawn(collector(listener, tx));
+    let server = Server::start(&["--otlp-endpoint", &format!("http://127.0.0.1:{}/otel", port)]).await;
+
+    let mut stream = server.tcp_client().await;
+    let start = format!("START_DOWNLOAD TENANT=traced BYTES=1000 TRACEPARENT=00-{}-{}-01", TRACE_ID, PARENT_ID);
+    stream.write_all(start.as_bytes()).await.unwrap();
+    let _ = drain(&mut stream, Instant::now()).await;
+
+    let mut spans = String::new();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !["handshake", "transfer", "finalize"].iter().all(|phase| spans.contains(&format!(r#""name":"{}""#, phase))) {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let (path, body) = tokio::time::timeout(left, posts.recv()).await.expect("spans within 10s").unwrap();
+        if path == "/otel/v1/traces" {
+            // The SDK pretty-prints; compare without whitespace.
+            spans.extend(body.split_whitespace());
+        }
+    }
+    assert!(spans.contains(r#""name":"proj2.test""#), "got {}", spans);
+    assert!(spans.contains(&format!(r#""traceId":"{}""#, TRACE_ID)), "got {}", spans);
+    assert!(spans.contains(&format!(r#""parentSpanId":"{}""#, PARENT_ID)), "got {}", spans);
+    assert!(spans.contains(r#""stringValue":"traced""#), "got {}", spans);
+}
@@ -0,0 +1,52 @@
+// proj2-serv/tests/schedule.rs
+// Scheduled tests against a second server: every result carries the time the
+// target took to resolve, and TCP ones the handshake time of their connection;
+// a test that never connected has no handshake to report, and none reports a
+// TLS handshake, which is out of scope.
+
+mod common;
+
+use common::{free_port, Server};
+
+/// The value of `key=` in a result line.
+fn field<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    line.split_whitespace().find_map(|kv| kv.strip_prefix(key)?.strip_prefix('='))
+}
+
+fn millis(line: &str, key: &str) -> f64 {
+    let value = field(line, key).unwrap_or_else(|| panic!("no {}= in {:?}", key, line));
+    value.parse().unwrap_or_else(|_| panic!("{}={} is not a number of ms", key, value))
+}
+
+#[tokio::test]
+async fn results_carry_dns_and_connect_times() {
+    let target = Server::start(&[]).await;
+    let schedule = std::env::temp_dir().join(format!("proj2-serv-schedule-{}", std::process::id()));
+    let jobs = [
+        format!("tcp-down every=60 target=127.0.0.1:{} proto=tcp dir=download duration=1", target.tcp.port()),
+        // An upload, as the target runs one download at a time for this host.
+        format!("udp-up every=60 target=127.0.0.1:{} proto=udp dir=upload duration=1", target.udp.port()),
+        format!("refused every=60 target=127.0.0.1:{} proto=tcp dir=download duration=1", free_port(false)),
+    ];
+    std::fs::write(&schedule, jobs.join("\n")).unwrap();
+    let agent = Server::start(&["--schedule", schedule.to_str().unwrap()]).await;
+    let results = agent.results("scheduler", jobs.len()).await;
+    std::fs::remove_file(schedule).unwrap();
+    let result = |run: &str| results.iter().find(|line| field(line, "run") == Some(run)).unwrap_or_else(|| panic!("no {} result in {:?}", run, results));
+
+    let tcp = result("tcp-down");
+    assert!(millis(tcp, "bytes") > 0.0 && field(tcp, "aborted").is_none(), "got {:?}", tcp);
+    assert!(millis(tcp, "dns_ms") >= 0.0);
+    assert!(millis(tcp, "connect_ms") >= 0.0);
+    assert_eq!(field(tcp, "tls_ms"), None, "no TLS handshake is timed: {:?}", tcp);
+
+    let udp = result("udp-up");
+    assert!(millis(udp, "bytes") > 0.0 && field(udp, "aborted").is_none(), "got {:?}", udp);
+    assert!(millis(udp, "dns_ms") >= 0.0);
+    assert_eq!(field(udp, "connect_ms"), None, "UDP has no handshake: {:?}", udp);
+
+    let refused = result("refused");
+    assert_eq!(field(refused, "aborted"), Some("error"), "got {:?}", refused);
+    assert!(millis(refused, "dns_ms") >= 0.0);
+    assert_eq!(field(refused, "connect_ms"), None, "nothing connected: {:?}", refused);
+}
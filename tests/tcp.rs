@@ -0,0 +1,408 @@
+// proj2-serv/tests/tcp.rs
+// TCP plane: downloads and uploads end where they should and are recorded,
+// file transfers refuse a second upload of a name in progress, and the control
+// channel survives bad input.
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{drain, recv_text, request, Server, WINDOW};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+#[tokio::test]
+async fn download_runs_for_the_window() {
+    let server = Server::start(&[]).await;
+    let mut stream = server.tcp_client().await;
+    let start = Instant::now();
+    stream.write_all(b"START_DOWNLOAD TENANT=dlwindow").await.unwrap();
+    let (bytes, last) = drain(&mut stream, start).await;
+    assert!(bytes > 0, "no download data");
+    assert!(last >= WINDOW - Duration::from_millis(500), "download stopped early at {:?}", last);
+    assert!(last < WINDOW + Duration::from_secs(2), "download ran until {:?}", last);
+    assert_eq!(server.result_bytes("dlwindow", 1).await.len(), 1);
+}
+
+#[tokio::test]
+async fn download_stops_at_byte_target() {
+    let server = Server::start(&[]).await;
+    let mut stream = server.tcp_client().await;
+    stream.write_all(b"START_DOWNLOAD TENANT=dlbytes BYTES=3000000").await.unwrap();
+    let (bytes, _) = drain(&mut stream, Instant::now()).await;
+    assert_eq!(bytes, 3_000_000);
+    assert_eq!(server.result_bytes("dlbytes", 1).await, [3_000_000]);
+}
+
+#[tokio::test]
+async fn user_paced_download_keeps_to_its_rate() {
+    let server = Server::start(&[]).await;
+    let mut stream = server.tcp_client().await;
+    let start = Instant::now();
+    // 1 MiB at 8 Mibit/s: a second.
+    stream.write_all(b"START_DOWNLOAD TENANT=paced BYTES=1M RATE=8M PACING=user").await.unwrap();
+    let (bytes, last) = drain(&mut stream, start).await;
+    assert_eq!(bytes, 1 << 20);
+    assert!(last >= Duration::from_millis(900), "1 MiB arrived in {:?}", last);
+    let result = &server.results("paced", 1).await[0];
+    assert!(result.contains(" rate_bps=8388608 pacing=user"), "got {:?}", result);
+}
+
+#[tokio::test]
+async fn end_download_stops_the_stream() {
+    let server = Server::start(&[]).await;
+    let mut stream = server.tcp_client().await;
+    let start = Instant::now();
+    stream.write_all(b"START_DOWNLOAD").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    stream.write_all(b"END_DOWNLOAD").await.unwrap();
+    let (_, last) = drain(&mut stream, start).await;
+    assert!(last < WINDOW - Duration::from_secs(1), "download continued until {:?}", last);
+}
+
+#[tokio::test]
+async fn stalled_clients_end_at_the_window() {
+    let server = Server::start(&[]).await;
+    let start = Instant::now();
+    // Both stay connected: the uploader sends nothing, the downloader reads nothing.
+    let mut upload = server.tcp_client().await;
+    upload.write_all(b"START_UPLOAD TENANT=stalled").await.unwrap();
+    let mut download = server.tcp_client().await;
+    download.write_all(b"START_DOWNLOAD TENANT=stalled").await.unwrap();
+    let results = server.results("stalled", 2).await;
+    assert_eq!(results.len(), 2, "got {:?}", results);
+    assert!(start.elapsed() < WINDOW + Duration::from_secs(2), "windows ended after {:?}", start.elapsed());
+    drop((upload, download));
+}
+
+#[tokio::test]
+async fn second_test_in_a_direction_from_the_same_host_is_busy() {
+    let server = Server::start(&[]).await;
+    let mut running = server.tcp_client().await;
+    running.write_all(b"START_DOWNLOAD").await.unwrap();
+    running.read_exact(&mut [0u8; 1]).await.unwrap();
+    // Another connection, and the UDP plane from another port, are the same host.
+    let mut second = server.tcp_client().await;
+    let reply = request(&mut second, "START_DOWNLOAD").await;
+    assert!(reply.starts_with("ERR BUSY a download test is already running for 127.0.0.1"), "got {:?}", reply);
+    let sock = server.udp_client().await;
+    sock.send(b"START_DOWNLOAD").await.unwrap();
+    assert!(recv_text(&sock).await.starts_with("ERR BUSY"), "UDP download was not refused");
+    // The other direction, and another host, are free.
+    second.write_all(b"START_UPLOAD TENANT=otherway BYTES=1000").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    second.write_all(&[0u8; 1000]).await.unwrap();
+    assert_eq!(server.result_bytes("otherway", 1).await, [1000]);
+    let mut other = server.tcp_client_from(2).await;
+    other.write_all(b"START_DOWNLOAD BYTES=1000").await.unwrap();
+    assert_eq!(drain(&mut other, Instant::now()).await.0, 1000);
+}
+
+#[tokio::test]
+async fn upload_counts_every_byte_up_to_target() {
+    let server = Server::start(&[]).await;
+    let mut stream = server.tcp_client().await;
+    stream.write_all(b"START_UPLOAD TENANT=upbytes BYTES=2000000").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let chunk = vec![0u8; 100_000];
+    for _ in 0..20 {
+        stream.write_all(&chunk).await.unwrap();
+    }
+    assert_eq!(server.result_bytes("upbytes", 1).await, [2_000_000]);
+}
+
+#[tokio::test]
+async fn end_upload_closes_the_test_early() {
+    let server = Server::start(&[]).await;
+    let mut stream = server.tcp_client().await;
+    stream.write_all(b"START_UPLOAD TENANT=upend").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    stream.write_all(&vec![0u8; 500_000]).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let start = Instant::now();
+    stream.write_all(b"END_UPLOAD").await.unwrap();
+    assert_eq!(server.result_bytes("upend", 1).await, [500_000]);
+    assert!(start.elapsed() < WINDOW, "upload ran on after END_UPLOAD");
+}
+
+#[tokio::test]
+async fn unknown_command_keeps_the_connection() {
+    let server = Server::start(&[]).await;
+    let mut stream = server.tcp_client().await;
+    let reply = request(&mut stream, "NOT_A_COMMAND").await;
+    assert!(reply.starts_with("ERR BAD_COMMAND"), "got {:?}", reply);
+    let reply = request(&mut stream, "CAPS").await;
+    assert!(reply.starts_with("CAPS"), "got {:?}", reply);
+}
+
+#[tokio::test]
+async fn invalid_option_is_refused_by_name() {
+    let server = Server::start(&[]).await;
+    let mut stream = server.tcp_client().await;
+    let reply = request(&mut stream, "START_DOWNLOAD CCA=__no_such_cca__").await;
+    assert!(reply.starts_with("ERR"), "got {:?}", reply);
+    assert!(reply.contains("CCA"), "error does not name the option: {:?}", reply);
+}
+
+#[tokio::test]
+async fn cca_is_put_back_after_the_test() {
+    let server = Server::start(&[]).await;
+    let mut stream = server.tcp_client().await;
+    let cca = |result: &str| result.split_whitespace().find_map(|kv| kv.strip_prefix("cca=")).unwrap_or_default().to_string();
+    stream.write_all(b"START_DOWNLOAD TENANT=ccabefore BYTES=1000").await.unwrap();
+    drain(&mut stream, Instant::now()).await;
+    let default = cca(&server.results("ccabefore", 1).await[0]);
+    let allowed = std::fs::read_to_string("/proc/sys/net/ipv4/tcp_allowed_congestion_control").unwrap_or_default();
+    let Some(other) = allowed.split_whitespace().find(|c| *c != default) else { return };
+    stream.write_all(format!("START_DOWNLOAD TENANT=ccaset BYTES=1000 CCA={}", other).as_bytes()).await.unwrap();
+    drain(&mut stream, Instant::now()).await;
+    assert_eq!(cca(&server.results("ccaset", 1).await[0]), other);
+    stream.write_all(b"START_DOWNLOAD TENANT=ccaafter BYTES=1000").await.unwrap();
+    drain(&mut stream, Instant::now()).await;
+    assert_eq!(cca(&server.results("ccaafter", 1).await[0]), default);
+}
+
+#[tokio::test]
+async fn hello_negotiates_version_and_features() {
+    let server = Server::start(&[]).await;
+    let mut stream = server.tcp_client().await;
+    let reply = request(&mut stream, "HELLO VERSION=99 FEATURES=resume,no-such-feature").await;
+    assert!(reply.starts_with("HELLO version=2 features=resume "), "got {:?}", reply);
+    let reply = request(&mut stream, "HELLO VERSION=0").await;
+    assert!(reply.starts_with("ERR UNSUPPORTED_OPTION"), "got {:?}", reply);
+}
+
+#[tokio::test]
+async fn hello_metadata_is_stored_with_later_results() {
+    let server = Server::start(&[]).await;
+    let mut stream = server.tcp_client().await;
+    let reply = request(&mut stream, "HELLO VERSION=2 AGENT=speedapp/4.1 META=device:phone,site:lab-3,bad=tag:x").await;
+    assert!(reply.starts_with("HELLO "), "got {:?}", reply);
+    stream.write_all(b"START_DOWNLOAD TENANT=meta BYTES=1000").await.unwrap();
+    let _ = drain(&mut stream, Instant::now()).await;
+    stream.write_all(b"START_DOWNLOAD TENANT=meta BYTES=2000 AGENT=cli/1.0").await.unwrap();
+    let _ = drain(&mut stream, Instant::now()).await;
+    let results = server.results("meta", 2).await;
+    assert!(results[0].contains(" agent=speedapp/4.1 meta_device=phone meta_site=lab-3"), "got {:?}", results[0]);
+    assert!(!results[0].contains("bad"), "got {:?}", results[0]);
+    assert!(results[1].contains(" agent=cli/1.0 meta_device=phone meta_site=lab-3"), "got {:?}", results[1]);
+}
+
+#[tokio::test]
+async fn compressed_download_is_a_zstd_stream_of_the_payload() {
+    use std::io::Write;
+
+    let server = Server::start(&[]).await;
+    let mut stream = server.tcp_client().await;
+    stream.write_all(b"START_DOWNLOAD TENANT=zstd BYTES=200000 PAYLOAD=compressible RATIO=8 COMPRESS=zstd").await.unwrap();
+    let mut wire = Vec::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    while let Ok(Ok(n)) = tokio::time::timeout(Duration::from_millis(500), stream.read(&mut buf)).await {
+        if n == 0 {
+            break;
+        }
+        wire.extend_from_slice(&buf[..n]);
+    }
+    assert_eq!(wire.len(), 200_000);
+    let mut decoder = zstd::stream::write::Decoder::new(Vec::new()).unwrap();
+    decoder.write_all(&wire).unwrap();
+    decoder.flush().unwrap();
+    let payload = decoder.into_inner();
+    assert!(payload.len() > 1_000_000, "decoded only {} bytes", payload.len());
+    assert!(payload.chunks(4096).all(|block| block[512..].iter().all(|&b| b == 0)));
+
+    let result = &server.results("zstd", 1).await[0];
+    let ratio: f64 = result.split_whitespace().find_map(|kv| kv.strip_prefix("compression_ratio=")).unwrap().parse().unwrap();
+    assert!(result.contains(" payload=compressible ") && result.contains(" compression=zstd "), "got {:?}", result);
+    assert!(ratio > 5.0, "got {:?}", result);
+}
+
+#[tokio::test]
+async fn framed_upload_counts_payload_that_reads_as_commands() {
+    let server = Server::start(&[]).await;
+    let mut stream = server.tcp_client().await;
+    stream.write_all(b"START_UPLOAD TENANT=framed FRAMED=1").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let payload = b"START_DOWNLOAD\nEND_UPLOAD\nDATA 5\n".repeat(1000);
+    for _ in 0..10 {
+        stream.write_all(format!("DATA {}\n", payload.len()).as_bytes()).await.unwrap();
+        stream.write_all(&payload).await.unwrap();
+    }
+    stream.write_all(b"END_UPLOAD\n").await.unwrap();
+    assert_eq!(server.result_bytes("framed", 1).await, [10 * payload.len() as u64]);
+    let reply = request(&mut stream, "CAPS").await;
+    assert!(reply.starts_with("CAPS "), "got {:?}", reply);
+}
+
+#[tokio::test]
+async fn framed_leftovers_after_the_target_are_skipped() {
+    let server = Server::start(&[]).await;
+    let mut stream = server.tcp_client().await;
+    stream.write_all(b"START_UPLOAD TENANT=framedleft FRAMED=1 BYTES=1000").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    stream.write_all(b"DATA 1000\n").await.unwrap();
+    stream.write_all(&[0u8; 1000]).await.unwrap();
+    assert_eq!(server.result_bytes("framedleft", 1).await, [1000]);
+    // A frame still in flight when the target closed the window, then a command.
+    let reply = request(&mut stream, "DATA 14\nSTART_DOWNLOADCAPS\n").await;
+    assert!(reply.starts_with("CAPS "), "got {:?}", reply);
+}
+
+#[tokio::test]
+async fn unframed_leftovers_after_the_target_are_not_commands() {
+    let server = Server::start(&[]).await;
+    let mut stream = server.tcp_client().await;
+    stream.write_all(b"START_UPLOAD TENANT=unframedleft BYTES=100000").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    stream.write_all(&[0u8; 100_000]).await.unwrap();
+    assert_eq!(server.result_bytes("unframedleft", 1).await, [100_000]);
+    // Payload still in flight that happens to read as a command.
+    stream.write_all(b"START_DOWNLOAD TENANT=leaked").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let reply = request(&mut stream, "CAPS").await;
+    assert!(reply.starts_with("CAPS "), "got {:?}", reply.chars().take(40).collect::<String>());
+}
+
+#[tokio::test]
+async fn priority_is_set_and_reported() {
+    let server = Server::start(&[]).await;
+    let mut stream = server.tcp_client().await;
+    stream.write_all(b"START_DOWNLOAD TENANT=prio BYTES=100000 PRIORITY=3").await.unwrap();
+    let (bytes, _) = drain(&mut stream, Instant::now()).await;
+    assert_eq!(bytes, 100_000);
+    let result = &server.results("prio", 1).await[0];
+    assert!(result.contains(" priority=3"), "got {:?}", result);
+}
+
+#[tokio::test]
+async fn server_priority_applies_to_every_test() {
+    let server = Server::start(&["--socket-priority", "5"]).await;
+    let mut stream = server.tcp_client().await;
+    stream.write_all(b"START_DOWNLOAD TENANT=serverprio BYTES=1000").await.unwrap();
+    let _ = drain(&mut stream, Instant::now()).await;
+    let result = &server.results("serverprio", 1).await[0];
+    assert!(result.contains(" priority=5"), "got {:?}", result);
+}
+
+#[tokio::test]
+async fn mark_outside_client_marks_is_refused() {
+    let server = Server::start(&[]).await;
+    let mut stream = server.tcp_client().await;
+    let reply = request(&mut stream, "START_DOWNLOAD TENANT=nomark BYTES=1000 MARK=7").await;
+    assert!(reply.starts_with("ERR UNAUTHORIZED MARK=7"), "got {:?}", reply);
+    let result = &server.results("nomark", 1).await[0];
+    assert!(!result.contains(" mark="), "got {:?}", result);
+}
+
+#[tokio::test]
+async fn results_are_signed_with_the_result_key() {
+    let key = std::env::temp_dir().join(format!("proj2-serv-result-key-{}", std::process::id()));
+    std::fs::write(&key, format!("{}\n", "07".repeat(32))).unwrap();
+    let server = Server::start(&["--result-key", key.to_str().unwrap()]).await;
+    let mut stream = server.tcp_client().await;
+    let reply = request(&mut stream, "RESULT_KEY").await;
+    let public = reply.strip_prefix("RESULT_KEY ed25519 ").unwrap_or_else(|| panic!("got {:?}", reply));
+    let public: [u8; 32] = hex(public).try_into().unwrap();
+    let public = ed25519_dalek::VerifyingKey::from_bytes(&public).unwrap();
+
+    stream.write_all(b"START_DOWNLOAD TENANT=signed BYTES=1000").await.unwrap();
+    let _ = drain(&mut stream, Instant::now()).await;
+    let result = &server.results("signed", 1).await[0];
+    let (line, sig) = result.rsplit_once(" sig=").unwrap_or_else(|| panic!("unsigned: {:?}", result));
+    let sig = ed25519_dalek::Signature::from_slice(&hex(sig)).unwrap();
+    public.verify_strict(line.as_bytes(), &sig).unwrap();
+    let tampered = line.replace("bytes=1000", "bytes=9000");
+    assert!(public.verify_strict(tampered.as_bytes(), &sig).is_err());
+    std::fs::remove_file(key).unwrap();
+}
+
+#[tokio::test]
+async fn concurrent_uploads_of_one_file_are_refused() {
+    let dir = std::env::temp_dir().join(format!("proj2-serv-files-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let server = Server::start(&["--file-dir", dir.to_str().unwrap()]).await;
+    let mut first = server.tcp_client().await;
+    assert_eq!(request(&mut first, "SEND_FILE same.bin SIZE=2000").await, "OK SEND_FILE");
+    first.write_all(&[1u8; 1000]).await.unwrap();
+    let mut second = server.tcp_client().await;
+    let reply = request(&mut second, "SEND_FILE same.bin SIZE=2000").await;
+    assert!(reply.starts_with("ERR BUSY"), "got {:?}", reply);
+    first.write_all(&[1u8; 1000]).await.unwrap();
+    let mut buf = [0u8; 64];
+    let n = tokio::time::timeout(common::REPLY_TIMEOUT, first.read(&mut buf)).await.unwrap().unwrap();
+    assert_eq!(String::from_utf8_lossy(&buf[..n]).trim(), "OK bytes=2000");
+    assert_eq!(std::fs::read(dir.join("same.bin")).unwrap(), [1u8; 2000]);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+fn hex(s: &str) -> Vec<u8> {
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+}
+
+#[tokio::test]
+async fn results_carry_the_clients_country_and_network() {
+    let db = std::env::temp_dir().join(format!("proj2-serv-geoip-{}.mmdb", std::process::id()));
+    std::fs::write(&db, loopback_mmdb()).unwrap();
+    let server = Server::start(&["--geoip", db.to_str().unwrap()]).await;
+    let mut stream = server.tcp_client().await;
+    stream.write_all(b"START_DOWNLOAD TENANT=geo BYTES=1000").await.unwrap();
+    let _ = drain(&mut stream, Instant::now()).await;
+    let result = &server.results("geo", 1).await[0];
+    assert!(result.contains(" country=ZZ asn=64512 as_org=Loopback_Test_Network_Operations"), "got {:?}", result);
+    std::fs::remove_file(db).unwrap();
+}
+
+/// A MaxMind database placing 127.0.0.0/8 in country ZZ and AS 64512.
+fn loopback_mmdb() -> Vec<u8> {
+    fn control(kind: u8, size: usize, out: &mut Vec<u8>) {
+        let (kind, ext) = if kind > 7 { (0, Some(kind - 7)) } else { (kind, None) };
+        if size < 29 {
+            out.push(kind << 5 | size as u8);
+        } else {
+            out.push(kind << 5 | 29);
+        }
+        out.extend(ext);
+        if size >= 29 {
+            out.push((size - 29) as u8);
+        }
+    }
+    fn string(s: &str, out: &mut Vec<u8>) {
+        control(2, s.len(), out);
+        out.extend_from_slice(s.as_bytes());
+    }
+    fn uint(n: u32, out: &mut Vec<u8>) {
+        control(6, 4, out);
+        out.extend_from_slice(&n.to_be_bytes());
+    }
+    const NODES: u32 = 8;
+    let mut out = Vec::new();
+    for (i, bit) in [0, 1, 1, 1, 1, 1, 1, 1].into_iter().enumerate() {
+        let next = if i < 7 { i as u32 + 1 } else { NODES + 16 };
+        let records = if bit == 0 { [next, NODES] } else { [NODES, next] };
+        for record in records {
+            out.extend_from_slice(&record.to_be_bytes()[1..]);
+        }
+    }
+    out.extend_from_slice(&[0; 16]);
+    control(7, 3, &mut out);
+    string("autonomous_system_number", &mut out);
+    uint(64512, &mut out);
+    string("autonomous_system_organization", &mut out);
+    string("Loopback Test Network Operations", &mut out);
+    string("country", &mut out);
+    control(7, 1, &mut out);
+    string("iso_code", &mut out);
+    string("ZZ", &mut out);
+    out.extend_from_slice(b"\xab\xcd\xefMaxMind.com");
+    control(7, 9, &mut out);
+    for (key, value) in [("binary_format_major_version", 2), ("binary_format_minor_version", 0), ("build_epoch", 0), ("ip_version", 4), ("node_count", NODES), ("record_size", 24)] {
+        string(key, &mut out);
+        uint(value, &mut out);
+    }
+    string("database_type", &mut out);
+    string("Test-Loopback", &mut out);
+    string("description", &mut out);
+    control(7, 0, &mut out);
+    string("languages", &mut out);
+    control(11, 0, &mut out);
+    out
+}
@@ -0,0 +1,535 @@
+// proj2-serv/tests/udp.rs
+// UDP plane: the start handshakes, including lost ACKs and clients that never
+// confirm, one test per address unless an upload and a download both ask for
+// BIDIR=1, replies to commands the server does not know, ECN counts of uploads,
+// latency under load, staircase downloads, overhead probes, echo round trips,
+// the socket snapshot, accounting that ignores datagrams after the deadline
+// and starts signed with the control key. The deadline arithmetic itself is
+// also covered under paused time in deadlines.rs.
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{cookie, is_data, recv_all, recv_text, request_until, Server};
+
+#[tokio::test]
+async fn unknown_command_gets_bad_command() {
+    let server = Server::start(&[]).await;
+    let sock = server.udp_client().await;
+    sock.send(b"NOT_A_COMMAND").await.unwrap();
+    let reply = recv_text(&sock).await;
+    assert!(reply.starts_with("ERR BAD_COMMAND"), "got {:?}", reply);
+}
+
+#[tokio::test]
+async fn upload_acks_are_resent_while_the_client_is_silent() {
+    let server = Server::start(&["--udp-ack-retries", "2"]).await;
+    let sock = server.udp_client().await;
+    sock.send(b"START_UPLOAD").await.unwrap();
+    let replies = recv_all(&sock, Duration::from_millis(600)).await;
+    let acks = replies.iter().filter(|d| d.as_slice() == b"ACK_UPLOAD").count();
+    // A burst of three, and two more bursts as the client sends nothing.
+    assert_eq!(acks, 9, "replies: {:?}", replies.iter().map(|d| String::from_utf8_lossy(d).to_string()).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn handshake_survives_a_lost_ack_burst() {
+    let server = Server::start(&["--udp-ack-retries", "3"]).await;
+    let sock = server.udp_client().await;
+    sock.send(b"START_UPLOAD TENANT=lostack HANDSHAKE=1").await.unwrap();
+    // Drop the first burst on the floor, then confirm off a resent one.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let _ = recv_all(&sock, Duration::from_millis(20)).await;
+    let ack = recv_text(&sock).await;
+    assert!(ack.starts_with("ACK_UPLOAD"), "got {:?}", ack);
+    let confirm = match cookie(&ack) {
+        Some(cookie) => format!("CONFIRM COOKIE={}", cookie),
+        None => "CONFIRM".to_string(),
+    };
+    sock.send(confirm.as_bytes()).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    for _ in 0..50 {
+        sock.send(&[0u8; 1000]).await.unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    sock.send(b"END_UPLOAD").await.unwrap();
+    let replies = recv_all(&sock, Duration::from_millis(500)).await;
+    assert!(replies.iter().any(|d| d.as_slice() == b"ACK_END_UPLOAD bytes=50000"), "no ACK_END_UPLOAD bytes=50000");
+}
+
+#[tokio::test]
+async fn download_never_starts_without_confirm() {
+    let server = Server::start(&[]).await;
+    let sock = server.udp_client().await;
+    sock.send(b"START_DOWNLOAD").await.unwrap();
+    let replies = recv_all(&sock, Duration::from_secs(3)).await;
+    assert!(!replies.is_empty(), "no ACK_DOWNLOAD");
+    for datagram in &replies {
+        assert!(datagram.starts_with(b"ACK_DOWNLOAD"), "unconfirmed download sent {} bytes of data", datagram.len());
+    }
+}
+
+#[tokio::test]
+async fn confirmed_download_delivers_byte_target() {
+    let server = Server::start(&[]).await;
+    let sock = server.udp_client().await;
+    sock.send(b"START_DOWNLOAD TENANT=udpbytes BYTES=50000").await.unwrap();
+    let ack = recv_text(&sock).await;
+    let cookie = cookie(&ack).expect("ACK_DOWNLOAD carries a cookie");
+    sock.send(format!("CONFIRM COOKIE={}", cookie).as_bytes()).await.unwrap();
+    let data: u64 = recv_all(&sock, Duration::from_millis(500))
+        .await
+        .iter()
+        .filter(|d| is_data(d))
+        .map(|d| d.len() as u64)
+        .sum();
+    assert_eq!(data, 50_000);
+    assert_eq!(server.result_bytes("udpbytes", 1).await, [50_000]);
+}
+
+#[tokio::test]
+async fn wrong_cookie_is_refused() {
+    let server = Server::start(&[]).await;
+    let sock = server.udp_client().await;
+    sock.send(b"START_DOWNLOAD").await.unwrap();
+    let _ack = recv_text(&sock).await;
+    sock.send(b"CONFIRM COOKIE=0000000000000000").await.unwrap();
+    let replies = recv_all(&sock, Duration::from_millis(500)).await;
+    assert!(replies.iter().any(|d| d.starts_with(b"ERR NOT_FOUND")), "wrong cookie was not refused");
+    assert!(replies.iter().all(|d| d.starts_with(b"ACK_DOWNLOAD") || d.starts_with(b"ERR")), "download started on a wrong cookie");
+}
+
+#[tokio::test]
+async fn datagrams_after_the_deadline_are_not_counted() {
+    // A BYTES= window is bounded by --max-test-duration, here one second.
+    let server = Server::start(&["--max-test-duration", "1"]).await;
+    let sock = server.udp_client().await;
+    let start = Instant::now();
+    sock.send(b"START_UPLOAD TENANT=late BYTES=1G").await.unwrap();
+    let _ = recv_all(&sock, Duration::from_millis(100)).await;
+    for _ in 0..10 {
+        sock.send(&[0u8; 1000]).await.unwrap();
+    }
+    tokio::time::sleep_until((start + Duration::from_millis(1300)).into()).await;
+    for _ in 0..10 {
+        sock.send(&[0u8; 1000]).await.unwrap();
+    }
+    assert_eq!(server.result_bytes("late", 1).await, [10_000]);
+    sock.send(b"END_UPLOAD").await.unwrap();
+    let replies = recv_all(&sock, Duration::from_millis(500)).await;
+    assert!(replies.iter().any(|d| d.as_slice() == b"ACK_END_UPLOAD bytes=0"), "late END_UPLOAD found a window");
+}
+
+#[tokio::test]
+async fn second_start_from_the_same_address_is_busy() {
+    let server = Server::start(&[]).await;
+    let sock = server.udp_client().await;
+    sock.send(b"START_UPLOAD").await.unwrap();
+    let _ = recv_all(&sock, Duration::from_millis(100)).await;
+    sock.send(b"START_UPLOAD BYTES=1000").await.unwrap();
+    let replies = recv_all(&sock, Duration::from_millis(300)).await;
+    assert!(replies.iter().any(|d| d.starts_with(b"ERR BUSY")), "second upload was not refused");
+}
+
+#[tokio::test]
+async fn second_start_from_another_port_is_busy() {
+    let server = Server::start(&[]).await;
+    let sock = server.udp_client().await;
+    sock.send(b"START_UPLOAD").await.unwrap();
+    let _ = recv_all(&sock, Duration::from_millis(100)).await;
+    let other = server.udp_client().await;
+    other.send(b"START_UPLOAD BYTES=1000").await.unwrap();
+    let replies = recv_all(&other, Duration::from_millis(300)).await;
+    assert!(replies.iter().any(|d| d.starts_with(b"ERR BUSY a upload test is already running for 127.0.0.1")), "upload from a new port was not refused");
+}
+
+#[tokio::test]
+async fn repeated_start_upload_is_acked_without_a_reset() {
+    let server = Server::start(&[]).await;
+    let sock = server.udp_client().await;
+    sock.send(b"START_UPLOAD TENANT=again").await.unwrap();
+    let _ = recv_all(&sock, Duration::from_millis(100)).await;
+    for _ in 0..20 {
+        sock.send(&[0u8; 1000]).await.unwrap();
+    }
+    sock.send(b"START_UPLOAD TENANT=again").await.unwrap();
+    let replies = recv_all(&sock, Duration::from_millis(300)).await;
+    assert!(replies.iter().any(|d| d.as_slice() == b"ACK_UPLOAD"), "repeated START_UPLOAD was not ACKed");
+    assert!(!replies.iter().any(|d| d.starts_with(b"ERR")), "repeated START_UPLOAD was refused");
+    for _ in 0..10 {
+        sock.send(&[0u8; 1000]).await.unwrap();
+    }
+    sock.send(b"END_UPLOAD").await.unwrap();
+    let replies = recv_all(&sock, Duration::from_millis(300)).await;
+    assert!(replies.iter().any(|d| d.as_slice() == b"ACK_END_UPLOAD bytes=30000"), "repeated START_UPLOAD reset the count");
+    assert_eq!(server.result_bytes("again", 1).await, [30_000]);
+}
+
+#[tokio::test]
+async fn repeated_start_download_gets_the_same_cookie() {
+    let server = Server::start(&[]).await;
+    let sock = server.udp_client().await;
+    sock.send(b"START_DOWNLOAD BYTES=50000").await.unwrap();
+    let first = recv_text(&sock).await;
+    let _ = recv_all(&sock, Duration::from_millis(100)).await;
+    sock.send(b"START_DOWNLOAD BYTES=50000").await.unwrap();
+    let again = recv_text(&sock).await;
+    assert_eq!(again, first);
+    sock.send(format!("CONFIRM COOKIE={}", cookie(&first).expect("ACK_DOWNLOAD carries a cookie")).as_bytes()).await.unwrap();
+    let data: u64 = recv_all(&sock, Duration::from_millis(500))
+        .await
+        .iter()
+        .filter(|d| is_data(d))
+        .map(|d| d.len() as u64)
+        .sum();
+    assert_eq!(data, 50_000);
+}
+
+#[tokio::test]
+async fn upload_waiting_for_confirm_holds_the_address() {
+    let server = Server::start(&[]).await;
+    let sock = server.udp_client().await;
+    sock.send(b"START_UPLOAD HANDSHAKE=1").await.unwrap();
+    let _ = recv_all(&sock, Duration::from_millis(100)).await;
+    sock.send(b"START_UPLOAD").await.unwrap();
+    let replies = recv_all(&sock, Duration::from_millis(300)).await;
+    assert!(replies.iter().any(|d| d.starts_with(b"ERR BUSY")), "upload stacked on one awaiting CONFIRM");
+}
+
+#[tokio::test]
+async fn opposite_direction_is_busy_without_bidir() {
+    let server = Server::start(&[]).await;
+    let sock = server.udp_client().await;
+    sock.send(b"START_UPLOAD BIDIR=1").await.unwrap();
+    let _ = recv_all(&sock, Duration::from_millis(100)).await;
+    sock.send(b"START_DOWNLOAD").await.unwrap();
+    let replies = recv_all(&sock, Duration::from_millis(300)).await;
+    assert!(
+        replies.iter().any(|d| d.starts_with(b"ERR BUSY a upload test is already running")),
+        "download started beside an upload: {:?}",
+        replies.iter().map(|d| String::from_utf8_lossy(d).to_string()).collect::<Vec<_>>()
+    );
+}
+
+#[tokio::test]
+async fn bidir_runs_an_upload_and_a_download_together() {
+    let server = Server::start(&[]).await;
+    let sock = server.udp_client().await;
+    sock.send(b"START_UPLOAD TENANT=bidir BIDIR=1").await.unwrap();
+    let _ = recv_all(&sock, Duration::from_millis(100)).await;
+    sock.send(b"START_DOWNLOAD TENANT=bidir BIDIR=1 BYTES=50000").await.unwrap();
+    let ack = recv_text(&sock).await;
+    let cookie = cookie(&ack).expect("ACK_DOWNLOAD carries a cookie");
+    sock.send(format!("CONFIRM COOKIE={}", cookie).as_bytes()).await.unwrap();
+    for _ in 0..20 {
+        sock.send(&[0u8; 1000]).await.unwrap();
+    }
+    let data: u64 = recv_all(&sock, Duration::from_millis(500))
+        .await
+        .iter()
+        .filter(|d| is_data(d))
+        .map(|d| d.len() as u64)
+        .sum();
+    assert_eq!(data, 50_000);
+    sock.send(b"END_UPLOAD").await.unwrap();
+    let replies = recv_all(&sock, Duration::from_millis(300)).await;
+    assert!(replies.iter().any(|d| d.as_slice() == b"ACK_END_UPLOAD bytes=20000"), "upload miscounted beside the download");
+}
+
+#[tokio::test]
+async fn download_ends_with_fin_of_what_was_sent() {
+    let server = Server::start(&[]).await;
+    let sock = server.udp_client().await;
+    sock.send(b"START_DOWNLOAD BYTES=50000").await.unwrap();
+    let ack = recv_text(&sock).await;
+    let cookie = cookie(&ack).expect("ACK_DOWNLOAD carries a cookie");
+    sock.send(format!("CONFIRM COOKIE={}", cookie).as_bytes()).await.unwrap();
+    let mut datagrams = 0;
+    let fin = loop {
+        let reply = recv_text(&sock).await;
+        if reply.starts_with("FIN ") {
+            break reply;
+        }
+        datagrams += !reply.starts_with("ACK_") as usize;
+    };
+    assert_eq!(fin, format!("FIN 50000 PACKETS={}", datagrams));
+    sock.send(b"FIN_ACK").await.unwrap();
+    // What is left of the burst in flight, then nothing.
+    let _ = recv_all(&sock, Duration::from_millis(100)).await;
+    let after = recv_all(&sock, Duration::from_millis(600)).await;
+    assert!(after.is_empty(), "FIN resent after FIN_ACK");
+}
+
+#[tokio::test]
+async fn fin_is_resent_until_acknowledged() {
+    let server = Server::start(&[]).await;
+    let sock = server.udp_client().await;
+    sock.send(b"START_DOWNLOAD BYTES=10000").await.unwrap();
+    let ack = recv_text(&sock).await;
+    let cookie = cookie(&ack).expect("ACK_DOWNLOAD carries a cookie");
+    sock.send(format!("CONFIRM COOKIE={}", cookie).as_bytes()).await.unwrap();
+    let fins = recv_all(&sock, Duration::from_millis(500)).await.iter().filter(|d| d.starts_with(b"FIN ")).count();
+    assert!(fins > 3, "only {} FIN datagrams without a FIN_ACK", fins);
+}
+
+#[tokio::test]
+async fn jumbo_datagrams_are_sent_whole() {
+    let server = Server::start(&[]).await;
+    let sock = server.udp_client().await;
+    sock.send(b"START_DOWNLOAD TENANT=jumbo BYTES=200000 DATAGRAM=60000").await.unwrap();
+    let ack = recv_text(&sock).await;
+    assert!(ack.contains(" MTU="), "ACK_DOWNLOAD does not report the path MTU: {:?}", ack);
+    sock.send(format!("CONFIRM COOKIE={}", cookie(&ack).expect("ACK_DOWNLOAD carries a cookie")).as_bytes()).await.unwrap();
+    let sizes: Vec<usize> = recv_all(&sock, Duration::from_millis(500)).await.iter().filter(|d| is_data(d)).map(Vec::len).collect();
+    assert_eq!(sizes, [60_000, 60_000, 60_000, 20_000]);
+    let result = &server.results("jumbo", 1).await[0];
+    assert!(result.contains(" datagram=60000 mtu="), "got {:?}", result);
+}
+
+#[tokio::test]
+async fn datagram_over_the_server_limit_is_refused() {
+    let server = Server::start(&["--udp-max-datagram", "1500"]).await;
+    let sock = server.udp_client().await;
+    sock.send(b"START_DOWNLOAD BYTES=10000 DATAGRAM=9000").await.unwrap();
+    let replies = recv_all(&sock, Duration::from_millis(100)).await;
+    assert!(replies.iter().any(|d| d.starts_with(b"ERR UNSUPPORTED_OPTION DATAGRAM=9000")), "DATAGRAM=9000 was not refused");
+    let ack = replies.iter().map(|d| String::from_utf8_lossy(d).to_string()).find(|d| d.starts_with("ACK_DOWNLOAD")).expect("no ACK_DOWNLOAD");
+    sock.send(format!("CONFIRM COOKIE={}", cookie(&ack).expect("ACK_DOWNLOAD carries a cookie")).as_bytes()).await.unwrap();
+    let largest = recv_all(&sock, Duration::from_millis(500)).await.iter().filter(|d| is_data(d)).map(Vec::len).max();
+    assert_eq!(largest, Some(1400));
+}
+
+#[tokio::test]
+async fn jumbo_upload_datagrams_are_counted_whole() {
+    let server = Server::start(&[]).await;
+    let sock = server.udp_client().await;
+    sock.send(b"START_UPLOAD").await.unwrap();
+    let _ = recv_all(&sock, Duration::from_millis(100)).await;
+    for _ in 0..5 {
+        sock.send(&[0u8; 65_507]).await.unwrap();
+    }
+    sock.send(b"END_UPLOAD").await.unwrap();
+    let replies = recv_all(&sock, Duration::from_millis(300)).await;
+    assert!(replies.iter().any(|d| d.as_slice() == b"ACK_END_UPLOAD bytes=327535"), "jumbo datagrams cut short");
+}
+
+#[tokio::test]
+async fn priority_gives_a_download_its_own_socket() {
+    let server = Server::start(&[]).await;
+    // Not connected: the data comes from the session's own port.
+    let sock = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    sock.send_to(b"START_DOWNLOAD TENANT=udpprio BYTES=20000 PRIORITY=2", server.udp).await.unwrap();
+    let mut buf = vec![0u8; 64 * 1024];
+    let (n, _) = tokio::time::timeout(Duration::from_secs(3), sock.recv_from(&mut buf)).await.expect("no ACK_DOWNLOAD").unwrap();
+    let ack = String::from_utf8_lossy(&buf[..n]).to_string();
+    assert!(ack.starts_with("ACK_DOWNLOAD PORT="), "got {:?}", ack);
+    sock.send_to(format!("CONFIRM COOKIE={}", cookie(&ack).expect("ACK_DOWNLOAD carries a cookie")).as_bytes(), server.udp).await.unwrap();
+    let mut data = 0;
+    while let Ok(Ok((n, _))) = tokio::time::timeout(Duration::from_millis(500), sock.recv_from(&mut buf)).await {
+        if is_data(&buf[..n]) {
+            data += n;
+        }
+    }
+    assert_eq!(data, 20_000);
+    let result = &server.results("udpprio", 1).await[0];
+    assert!(result.contains(" priority=2") && result.contains(" connected=1"), "got {:?}", result);
+}
+
+#[tokio::test]
+async fn upload_counts_ce_marked_datagrams() {
+    let server = Server::start(&[]).await;
+    let sock = server.udp_client().await;
+    sock.send(b"START_UPLOAD TENANT=udpecn").await.unwrap();
+    let _ = recv_all(&sock, Duration::from_millis(100)).await;
+    // As an AQM would leave them: CE in the low bits of the TOS byte.
+    socket2::SockRef::from(&sock).set_tos_v4(0b11).unwrap();
+    for _ in 0..20 {
+        sock.send(&[0u8; 1000]).await.unwrap();
+    }
+    socket2::SockRef::from(&sock).set_tos_v4(0).unwrap();
+    sock.send(b"END_UPLOAD").await.unwrap();
+    let _ = recv_all(&sock, Duration::from_millis(300)).await;
+    let result = &server.results("udpecn", 1).await[0];
+    if cfg!(target_os = "linux") {
+        assert!(result.contains(" ecn_ce=20 "), "got {:?}", result);
+    }
+}
+
+#[tokio::test]
+async fn bloat_download_grades_latency_under_load() {
+    let server = Server::start(&[]).await;
+    let sock = server.udp_client().await;
+    let _ = socket2::SockRef::from(&sock).set_recv_buffer_size(4 << 20);
+    sock.send(b"START_DOWNLOAD TENANT=bloat BLOAT=1").await.unwrap();
+    let ack = recv_text(&sock).await;
+    assert!(ack.contains(" BLOAT_IDLE_MS=1000"), "got {:?}", ack);
+    sock.send(format!("CONFIRM COOKIE={}", cookie(&ack).expect("ACK_DOWNLOAD carries a cookie")).as_bytes()).await.unwrap();
+    // Answer every ping through the idle second and half a second of flood.
+    let end = tokio::time::Instant::now() + Duration::from_millis(1500);
+    let mut buf = vec![0u8; 64 * 1024];
+    while let Ok(Ok(n)) = tokio::time::timeout_at(end, sock.recv(&mut buf)).await {
+        if let Some(seq) = buf[..n].strip_prefix(b"PING ") {
+            sock.send(&[b"PONG ", seq].concat()).await.unwrap();
+        }
+    }
+    sock.send(b"END_DOWNLOAD").await.unwrap();
+    let _ = recv_all(&sock, Duration::from_millis(300)).await;
+    let result = &server.results("bloat", 1).await[0];
+    assert!(result.contains(" rtt_idle_ms=") && result.contains(" rtt_loaded_ms=") && result.contains(" bloat_grade="), "got {:?}", result);
+}
+
+#[tokio::test]
+async fn ramp_download_reports_loss_per_step() {
+    let server = Server::start(&[]).await;
+    let sock = server.udp_client().await;
+    sock.send(b"START_DOWNLOAD TENANT=ramp RAMP=1,2 STEP=1").await.unwrap();
+    let ack = recv_text(&sock).await;
+    sock.send(format!("CONFIRM COOKIE={}", cookie(&ack).expect("ACK_DOWNLOAD carries a cookie")).as_bytes()).await.unwrap();
+    let mut received = [0u64; 2];
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = tokio::time::timeout(Duration::from_secs(3), sock.recv(&mut buf)).await.expect("no FIN").unwrap();
+        let datagram = String::from_utf8_lossy(&buf[..n]);
+        if datagram.starts_with("FIN ") {
+            break;
+        }
+        if let Some(step) = datagram.strip_prefix("RAMP ").and_then(|h| h.split(' ').next()) {
+            received[step.parse::<usize>().unwrap()] += 1;
+        }
+    }
+    // 1400-byte datagrams: 89 a second at 1 Mbit/s, 179 at 2.
+    assert!(received[0] > 80 && received[1] > 170, "received {:?}", received);
+    sock.send(b"FIN_ACK").await.unwrap();
+    sock.send(format!("RAMP_REPORT {}:0,{}:0", received[0], received[1]).as_bytes()).await.unwrap();
+    let replies = recv_all(&sock, Duration::from_millis(300)).await;
+    assert!(replies.iter().any(|d| d.as_slice() == b"ACK_RAMP_REPORT"), "no ACK_RAMP_REPORT");
+    let result = &server.results("ramp", 1).await[0];
+    assert!(result.contains(" ramp_mbps=1,2 ramp_step_s=1 ") && result.contains(" ramp_loss_pct=0.00,0.00 ") && result.contains(" ramp_knee_mbps=2"), "got {:?}", result);
+}
+
+#[tokio::test]
+async fn overhead_download_estimates_per_packet_overhead() {
+    let server = Server::start(&[]).await;
+    let sock = server.udp_client().await;
+    sock.send(b"START_DOWNLOAD TENANT=overhead OVERHEAD=200,500,800 STEP=1").await.unwrap();
+    let ack = recv_text(&sock).await;
+    sock.send(format!("CONFIRM COOKIE={}", cookie(&ack).expect("ACK_DOWNLOAD carries a cookie")).as_bytes()).await.unwrap();
+    let mut sizes = [0usize; 3];
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = tokio::time::timeout(Duration::from_secs(3), sock.recv(&mut buf)).await.expect("no FIN").unwrap();
+        let datagram = String::from_utf8_lossy(&buf[..n]);
+        if datagram.starts_with("FIN ") {
+            break;
+        }
+        if let Some(step) = datagram.strip_prefix("RAMP ").and_then(|h| h.split(' ').next()) {
+            sizes[step.parse::<usize>().unwrap()] = n;
+        }
+    }
+    assert_eq!(sizes, [200, 500, 800]);
+    sock.send(b"FIN_ACK").await.unwrap();
+    // What a 1 MB/s link costing 100 bytes a datagram would have delivered.
+    sock.send(b"RAMP_REPORT 3333:0,1667:0,1111:0").await.unwrap();
+    let replies = recv_all(&sock, Duration::from_millis(300)).await;
+    let reply = replies.iter().map(|d| String::from_utf8_lossy(d).into_owned()).find(|d| d.starts_with("OVERHEAD ")).expect("no OVERHEAD reply");
+    assert!(reply.contains("pkt_overhead_bytes=100 encap_bytes=72 "), "got {:?}", reply);
+    let result = &server.results("overhead", 1).await[0];
+    assert!(result.contains(" overhead_sizes=200,500,800 overhead_step_s=1 ") && result.contains(" pkt_overhead_bytes=100 "), "got {:?}", result);
+}
+
+#[tokio::test]
+async fn socket_snapshot_lists_the_udp_socket_and_running_downloads() {
+    let server = Server::start(&[]).await;
+    let sock = server.udp_client().await;
+    sock.send(b"START_DOWNLOAD TENANT=sockets").await.unwrap();
+    let ack = recv_text(&sock).await;
+    sock.send(format!("CONFIRM COOKIE={}", cookie(&ack).expect("ACK_DOWNLOAD carries a cookie")).as_bytes()).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let mut admin = server.tcp_client().await;
+    let reply = request_until(&mut admin, "ADMIN SOCKETS", "END").await;
+    assert!(reply.lines().any(|l| l.starts_with("socket=udp rx_queued=") && l.contains(" drops=")), "got {:?}", reply);
+    let session = reply.lines().find(|l| l.contains(" tenant=sockets ")).unwrap_or_else(|| panic!("no download session in {:?}", reply));
+    assert!(session.contains(" proto=udp ") && session.contains(" would_block=") && session.ends_with(" send_errors=0"), "got {:?}", session);
+}
+
+#[tokio::test]
+async fn echo_results_carry_round_trip_percentiles() {
+    let server = Server::start(&[]).await;
+    let sock = server.udp_client().await;
+    sock.send(b"START_ECHO TENANT=echo").await.unwrap();
+    assert_eq!(recv_text(&sock).await, "ACK_ECHO");
+    // A client that waits for each echo, a millisecond of turnaround apart.
+    for _ in 0..20 {
+        sock.send(&[7u8; 64]).await.unwrap();
+        assert_eq!(recv_text(&sock).await.len(), 64);
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    sock.send(b"END_ECHO").await.unwrap();
+    assert_eq!(recv_text(&sock).await, "ACK_END_ECHO requests=20");
+    let result = &server.results("echo", 1).await[0];
+    assert!(result.contains(" requests=20 ") && result.contains(" jitter_p999_us="), "got {:?}", result);
+    let p50: u64 = result.split_whitespace().find_map(|kv| kv.strip_prefix("rtt_p50_us=")).unwrap().parse().unwrap();
+    assert!(p50 >= 1000, "got {:?}", result);
+}
+
+#[tokio::test]
+async fn signed_starts_are_checked_against_the_control_key() {
+    use hmac::{Hmac, Mac};
+    let key = std::env::temp_dir().join(format!("proj2-serv-control-key-{}", std::process::id()));
+    std::fs::write(&key, "0123456789abcdef0123\n").unwrap();
+    let server = Server::start(&["--control-key", key.to_str().unwrap()]).await;
+    let ts = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+    let signed = format!("START_UPLOAD TENANT=signed NONCE=00112233445566778899aabbccddeeff TS={}", ts);
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(b"0123456789abcdef0123").unwrap();
+    mac.update(signed.as_bytes());
+    let hex: String = mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+    let command = format!("{} MAC={}", signed, hex);
+
+    let unsigned = server.udp_client().await;
+    unsigned.send(b"START_UPLOAD TENANT=signed").await.unwrap();
+    assert!(recv_text(&unsigned).await.starts_with("ERR UNAUTHORIZED"));
+    let tampered = server.udp_client().await;
+    tampered.send(command.replace("TENANT=signed", "TENANT=forged").as_bytes()).await.unwrap();
+    assert!(recv_text(&tampered).await.starts_with("ERR UNAUTHORIZED"));
+    let sock = server.udp_client().await;
+    sock.send(command.as_bytes()).await.unwrap();
+    let reply = recv_text(&sock).await;
+    assert!(reply.starts_with("ACK_UPLOAD"), "got {:?}", reply);
+    let replayed = server.udp_client().await;
+    replayed.send(command.as_bytes()).await.unwrap();
+    let reply = recv_text(&replayed).await;
+    assert!(reply.starts_with("ERR UNAUTHORIZED") && reply.contains("replayed NONCE"), "got {:?}", reply);
+    std::fs::remove_file(key).unwrap();
+}
+
+#[tokio::test]
+async fn retransmitted_signed_start_is_acked_without_a_token() {
+    use hmac::{Hmac, Mac};
+    let key = std::env::temp_dir().join(format!("proj2-serv-control-key-retry-{}", std::process::id()));
+    std::fs::write(&key, "0123456789abcdef0123\n").unwrap();
+    // One token, so a retransmit that cost one would be refused.
+    let server = Server::start(&["--control-key", key.to_str().unwrap(), "--control-burst", "1", "--control-rate", "1"]).await;
+    let ts = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+    let sign = |nonce: &str| {
+        let signed = format!("START_UPLOAD TENANT=resigned NONCE={} TS={}", nonce, ts);
+        let mut mac = Hmac::<sha2::Sha256>::new_from_slice(b"0123456789abcdef0123").unwrap();
+        mac.update(signed.as_bytes());
+        let hex: String = mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+        format!("{} MAC={}", signed, hex)
+    };
+
+    let sock = server.udp_client().await;
+    sock.send(sign("00112233445566778899aabbccddeeff").as_bytes()).await.unwrap();
+    assert_eq!(recv_text(&sock).await, "ACK_UPLOAD");
+    // The same datagram again, then the retry re-signed with a fresh nonce.
+    for retry in [sign("00112233445566778899aabbccddeeff"), sign("ffeeddccbbaa99887766554433221100")] {
+        sock.send(retry.as_bytes()).await.unwrap();
+        let reply = recv_text(&sock).await;
+        assert_eq!(reply, "ACK_UPLOAD", "retransmit of {:?}", retry);
+    }
+    // A forged retransmit is still checked, and charged.
+    sock.send(sign("ffeeddccbbaa99887766554433221100").replace("MAC=", "MAC=00").as_bytes()).await.unwrap();
+    assert!(recv_text(&sock).await.starts_with("ERR BUSY"));
+    std::fs::remove_file(key).unwrap();
+}
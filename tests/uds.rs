@@ -0,0 +1,54 @@
+// proj2-serv/tests/uds.rs
+// UDS plane: a test whose client goes quiet still ends at its window, and
+// upload data still in flight after the target is not read as commands.
+
+mod common;
+
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use common::{Server, WINDOW};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+
+/// A socket path of this test's own.
+fn socket_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("proj2-serv-{}-{}.sock", name, std::process::id()))
+}
+
+#[tokio::test]
+async fn stalled_clients_end_at_the_window() {
+    let path = socket_path("stalled");
+    let server = Server::start(&["--uds", path.to_str().unwrap()]).await;
+    let start = Instant::now();
+    // Both stay connected: the uploader sends nothing, the downloader reads nothing.
+    let mut upload = UnixStream::connect(&path).await.unwrap();
+    upload.write_all(b"START_UPLOAD TENANT=udsstalled").await.unwrap();
+    let mut download = UnixStream::connect(&path).await.unwrap();
+    download.write_all(b"START_DOWNLOAD TENANT=udsstalled").await.unwrap();
+    let results = server.results("udsstalled", 2).await;
+    assert_eq!(results.len(), 2, "got {:?}", results);
+    assert!(start.elapsed() < WINDOW + Duration::from_secs(2), "windows ended after {:?}", start.elapsed());
+    drop((upload, download));
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn unframed_leftovers_after_the_target_are_not_commands() {
+    let path = socket_path("leftovers");
+    let server = Server::start(&["--uds", path.to_str().unwrap()]).await;
+    let mut stream = UnixStream::connect(&path).await.unwrap();
+    stream.write_all(b"START_UPLOAD TENANT=udsleft BYTES=100000").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    stream.write_all(&[0u8; 100_000]).await.unwrap();
+    assert_eq!(server.result_bytes("udsleft", 1).await, [100_000]);
+    // Payload still in flight that happens to read as a command.
+    stream.write_all(b"START_DOWNLOAD TENANT=udsleaked").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    stream.write_all(b"CAPS").await.unwrap();
+    let mut buf = vec![0u8; 4096];
+    let n = tokio::time::timeout(Duration::from_secs(2), stream.read(&mut buf)).await.expect("a reply to CAPS").unwrap();
+    let reply = String::from_utf8_lossy(&buf[..n]);
+    assert!(reply.starts_with("CAPS "), "got {:?}", reply.chars().take(40).collect::<String>());
+    let _ = std::fs::remove_file(path);
+}
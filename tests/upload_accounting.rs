@@ -0,0 +1,253 @@
+// proj2-serv/tests/upload_accounting.rs
+// The upload ledger under arbitrary interleavings of START_UPLOAD, data, END,
+// deadlines, EXTEND, resumes and idle sweeps, checked against a model of
+// what each window should have counted: every window opened is closed
+// exactly once, with the bytes that arrived while it was open and before its
+// deadline. Cases are proptest operation sequences, so a failure is shrunk to
+// a short sequence and saved in upload_accounting.proptest-regressions, which
+// proptest replays first on the next run.
+
+#[allow(dead_code)]
+#[path = "../src/ledger.rs"]
+mod ledger;
+
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use ledger::{Account, Ledger, Recorded};
+use proptest::prelude::*;
+use proptest::sample::Index;
+
+const CASES: u32 = 2000;
+const STEPS: usize = 200;
+const CLIENTS: u16 = 4;
+
+/// One thing that can happen to the ledger; clients are indices below CLIENTS.
+#[derive(Debug, Clone)]
+enum Op {
+    /// START_UPLOAD, possibly repeated for an address that has a window.
+    Start { client: u16, deadline_ms: u64, target: Option<u64> },
+    /// A datagram, from a client with or without a window.
+    Datagram { client: u16, len: usize, copies: usize },
+    /// Time passes.
+    Wait { ms: u64 },
+    /// END_UPLOAD.
+    End { client: u16 },
+    /// A deadline task fires, for a window that may already be closed.
+    Deadline { window: Index },
+    /// EXTEND moves a deadline on.
+    Extend { window: Index, ms: u64 },
+    /// RESUME moves a window to another address, unless that one is busy.
+    Resume { window: Index, to: u16 },
+    /// The idle sweep closes the windows whose id has this parity.
+    Sweep { parity: u64 },
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    let client = || 0..CLIENTS;
+    prop_oneof![
+        15 => (client(), 50..550u64, prop::option::weighted(1.0 / 3.0, 1..=20_000u64))
+            .prop_map(|(client, deadline_ms, target)| Op::Start { client, deadline_ms, target }),
+        50 => (client(), 1..=1500usize, prop::bool::weighted(0.25))
+            .prop_map(|(client, len, twice)| Op::Datagram { client, len, copies: 1 + twice as usize }),
+        10 => (0..200u64).prop_map(|ms| Op::Wait { ms }),
+        5 => client().prop_map(|client| Op::End { client }),
+        8 => any::<Index>().prop_map(|window| Op::Deadline { window }),
+        4 => (any::<Index>(), 0..300u64).prop_map(|(window, ms)| Op::Extend { window, ms }),
+        5 => (any::<Index>(), client()).prop_map(|(window, to)| Op::Resume { window, to }),
+        3 => (0..2u64).prop_map(|parity| Op::Sweep { parity }),
+    ]
+}
+
+struct Window {
+    id: u64,
+    /// Shared with the driver, as the session's window is with EXTEND.
+    deadline: Rc<Cell<Instant>>,
+    target: Option<u64>,
+    total: u64,
+}
+
+impl Account for Window {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn deadline(&self) -> Instant {
+        self.deadline.get()
+    }
+
+    fn target(&self) -> Option<u64> {
+        self.target
+    }
+
+    fn count(&mut self, len: usize, copies: usize) -> u64 {
+        self.total += (len * copies) as u64;
+        self.total
+    }
+}
+
+/// What the model expects of one window.
+struct Expected {
+    addr: SocketAddr,
+    deadline: Instant,
+    target: Option<u64>,
+    total: u64,
+    extend: Rc<Cell<Instant>>,
+}
+
+#[derive(Default)]
+struct Model {
+    open: BTreeMap<u64, Expected>,
+    /// Window id -> total it was closed with.
+    closed: HashMap<u64, u64>,
+    next_id: u64,
+}
+
+impl Model {
+    fn at(&self, addr: SocketAddr) -> Option<u64> {
+        self.open.iter().find(|(_, w)| w.addr == addr).map(|(id, _)| *id)
+    }
+
+    /// Record that the ledger handed back `window`, checking it was the one
+    /// expected and had counted what it should.
+    fn close(&mut self, window: Window, why: &str) -> Result<(), String> {
+        let Some(expected) = self.open.remove(&window.id) else {
+            return Err(format!("{}: window {} closed again (first with {:?} bytes)", why, window.id, self.closed.get(&window.id)));
+        };
+        if window.total != expected.total {
+            return Err(format!("{}: window {} closed with {} bytes, expected {}", why, window.id, window.total, expected.total));
+        }
+        self.closed.insert(window.id, window.total);
+        Ok(())
+    }
+}
+
+fn client(index: u16) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 40000 + index))
+}
+
+fn pick(index: &Index, items: &[u64]) -> Option<u64> {
+    (!items.is_empty()).then(|| *index.get(items))
+}
+
+fn run_case(ops: &[Op]) -> Result<(), String> {
+    let mut ledger: Ledger<Window> = Ledger::default();
+    let mut model = Model::default();
+    let mut now = Instant::now();
+    for (step, op) in ops.iter().enumerate() {
+        let at = |e: String| format!("step {}: {}", step, e);
+        match *op {
+            Op::Start { client: index, deadline_ms, target } => {
+                let addr = client(index);
+                model.next_id += 1;
+                let id = model.next_id;
+                let deadline = now + Duration::from_millis(deadline_ms);
+                let extend = Rc::new(Cell::new(deadline));
+                let displaced = ledger.open(addr, Window { id, deadline: extend.clone(), target, total: 0 });
+                match (displaced, model.at(addr)) {
+                    (Some(window), Some(_)) => model.close(window, "displaced by START").map_err(at)?,
+                    (None, None) => {}
+                    (got, want) => return Err(at(format!("START displaced {:?}, expected {:?}", got.map(|w| w.id), want))),
+                }
+                model.open.insert(id, Expected { addr, deadline, target, total: 0, extend });
+            }
+            Op::Datagram { client: index, len, copies } => {
+                let addr = client(index);
+                let expected = model.at(addr);
+                match ledger.record(addr, len, copies, now) {
+                    Recorded::NoWindow if expected.is_none() => {}
+                    Recorded::Late if expected.is_some_and(|id| now > model.open[&id].deadline) => {}
+                    Recorded::Counted | Recorded::Complete(_) if expected.is_none_or(|id| now > model.open[&id].deadline) => {
+                        return Err(at(format!("datagram from {} counted into {:?} past its deadline or with no window", addr, expected)));
+                    }
+                    Recorded::Counted => {
+                        let w = model.open.get_mut(&expected.unwrap()).unwrap();
+                        w.total += (len * copies) as u64;
+                        if w.target.is_some_and(|t| w.total >= t) {
+                            return Err(at(format!("window {} reached its target but stayed open", expected.unwrap())));
+                        }
+                    }
+                    Recorded::Complete(window) => {
+                        let w = model.open.get_mut(&window.id).unwrap();
+                        w.total += (len * copies) as u64;
+                        if w.target.is_none_or(|t| w.total < t) {
+                            return Err(at(format!("window {} closed short of its target", window.id)));
+                        }
+                        model.close(window, "target").map_err(at)?;
+                    }
+                    Recorded::NoWindow | Recorded::Late => return Err(at(format!("datagram from {} misaccounted; window {:?}", addr, expected))),
+                }
+            }
+            Op::Wait { ms } => now += Duration::from_millis(ms),
+            Op::End { client: index } => {
+                let addr = client(index);
+                match (ledger.end(addr), model.at(addr)) {
+                    (Some(window), Some(_)) => model.close(window, "END").map_err(at)?,
+                    (None, None) => {}
+                    (got, want) => return Err(at(format!("END closed {:?}, expected {:?}", got.map(|w| w.id), want))),
+                }
+            }
+            Op::Deadline { ref window } => {
+                let ids: Vec<u64> = (1..=model.next_id).collect();
+                let Some(id) = pick(window, &ids) else { continue };
+                match (ledger.close(id), model.open.contains_key(&id)) {
+                    (Some((_, window)), true) => model.close(window, "deadline").map_err(at)?,
+                    (None, false) => {}
+                    (got, _) => return Err(at(format!("deadline task for {} closed {:?}", id, got.map(|(_, w)| w.id)))),
+                }
+            }
+            Op::Extend { ref window, ms } => {
+                let ids: Vec<u64> = model.open.keys().copied().collect();
+                let Some(id) = pick(window, &ids) else { continue };
+                let w = model.open.get_mut(&id).unwrap();
+                w.deadline += Duration::from_millis(ms);
+                w.extend.set(w.deadline);
+            }
+            Op::Resume { ref window, to } => {
+                let ids: Vec<u64> = model.open.keys().copied().collect();
+                let Some(id) = pick(window, &ids) else { continue };
+                let to = client(to);
+                let (from, _) = ledger.find(|w| w.id == id).ok_or_else(|| at(format!("open window {} missing", id)))?;
+                let busy = model.at(to).is_some_and(|other| other != id);
+                match ledger.relocate(from, to) {
+                    Err(()) if busy => {}
+                    Ok(Some(window)) if !busy && window.id == id => model.open.get_mut(&id).unwrap().addr = to,
+                    _ => return Err(at(format!("resuming {} from {} to {} misbehaved (busy: {})", id, from, to, busy))),
+                }
+            }
+            Op::Sweep { parity } => {
+                for (addr, window, ()) in ledger.sweep(|w| (w.id % 2 == parity).then_some(())) {
+                    if model.at(addr) != Some(window.id) {
+                        return Err(at(format!("sweep closed {} under {}, expected it elsewhere", window.id, addr)));
+                    }
+                    model.close(window, "sweep").map_err(at)?;
+                }
+            }
+        }
+    }
+    // Every deadline task eventually fires; nothing may be left open.
+    for id in 1..=model.next_id {
+        if let Some((_, window)) = ledger.close(id) {
+            model.close(window, "final deadline")?;
+        }
+    }
+    if let Some(id) = model.open.keys().next() {
+        return Err(format!("window {} was never closed", id));
+    }
+    if model.closed.len() as u64 != model.next_id {
+        return Err(format!("{} windows opened, {} closed", model.next_id, model.closed.len()));
+    }
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(CASES))]
+
+    #[test]
+    fn every_window_closes_once_with_its_own_bytes(ops in prop::collection::vec(op(), 1..=STEPS)) {
+        run_case(&ops).map_err(TestCaseError::fail)?;
+    }
+}